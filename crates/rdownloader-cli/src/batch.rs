@@ -0,0 +1,112 @@
+// --- 批量下载 ---
+// 多个 URL 并发下载，每个文件在 MultiProgress 中拥有自己的进度条。
+
+use crate::json::{json_options, tee_options};
+use crate::socket::ProgressSocket;
+use futures_util::{stream, StreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use rdownloader::{
+    download_with, DownloadError, DownloadEvent, DownloadOptions, DownloadSummary, EventCallback,
+};
+use std::path::Path;
+use std::sync::Arc;
+
+/// 从 URL 列表文件中读取 URL：每行一个，忽略空行和以 `#` 开头的注释行
+pub fn read_url_file(path: &Path) -> std::io::Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+/// 并发下载所有 URL，同时进行的下载任务不超过 `jobs` 个。
+///
+/// 单个 URL 失败不会中止其他下载，返回值按 `urls` 的顺序给出每个 URL 的结果。
+/// `json` 为 `true` 时不显示进度条，每个下载的事件都以 JSON 行输出。
+/// 提供 `socket` 时每个下载的事件还会写到进度套接字。
+pub async fn download_all(
+    urls: &[String],
+    output_dir: Option<String>,
+    options: &DownloadOptions,
+    jobs: usize,
+    json: bool,
+    socket: Option<&Arc<ProgressSocket>>,
+) -> Vec<Result<DownloadSummary, DownloadError>> {
+    let multi = if options.show_progress && !json {
+        MultiProgress::new()
+    } else {
+        MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+    };
+
+    let mut results: Vec<(usize, Result<DownloadSummary, DownloadError>)> =
+        stream::iter(urls.iter().enumerate())
+            .map(|(i, url)| {
+                // 进度条在任务真正开始时才加入，等待中的 URL 不占用终端行
+                let bar = multi.add(new_bar(url));
+                let options = if json {
+                    json_options(url, options)
+                } else {
+                    DownloadOptions {
+                        progress_bar: Some(bar.clone()),
+                        // 各个下载的状态信息会打乱多个进度条的显示，只在进度条上方显示警告
+                        on_event: options
+                            .on_event
+                            .as_ref()
+                            .map(|_| warning_callback(bar.clone())),
+                        show_progress: false,
+                        ..options.clone()
+                    }
+                };
+                let options = match socket {
+                    Some(socket) => tee_options(url, &options, socket.clone()),
+                    None => options,
+                };
+                let options = DownloadOptions {
+                    // 多个下载的数据块图会相互穿插，无法阅读
+                    on_chunk_progress: None,
+                    ..options
+                };
+                let output = output_dir.clone();
+                async move {
+                    let result = download_with(url, output, &options).await;
+                    match &result {
+                        Ok(_) => bar.finish_with_message("下载完成"),
+                        Err(_) => bar.abandon_with_message("下载失败"),
+                    }
+                    (i, result)
+                }
+            })
+            .buffer_unordered(jobs)
+            .collect()
+            .await;
+
+    results.sort_by_key(|(i, _)| *i);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+fn new_bar(url: &str) -> ProgressBar {
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                "{prefix:30!} [{bar:30.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}) {msg}",
+            )
+            .unwrap()
+            .progress_chars("->-"),
+    );
+    let name = url.rsplit('/').find(|s| !s.is_empty()).unwrap_or(url);
+    bar.set_prefix(name.to_string());
+    bar
+}
+
+/// 在进度条上方显示该下载的警告，其他事件忽略
+fn warning_callback(bar: ProgressBar) -> EventCallback {
+    EventCallback::new(move |event| {
+        if let DownloadEvent::Warning(message) = event {
+            bar.println(format!("警告: {}: {}", bar.prefix(), message));
+        }
+    })
+}
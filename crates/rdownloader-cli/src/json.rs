@@ -0,0 +1,211 @@
+// --- JSON 输出 ---
+// `--json` 模式下不显示进度条和状态文字，每个事件以一行 JSON 写到标准输出 (换行分隔的 JSON)。
+// 每个事件都带有 `url` 字段，批量下载时可以据此区分不同的任务。
+// 同样的事件也可以通过 `--progress-socket` 写到其他输出目标 (见 socket.rs)，此时终端上的显示保持不变。
+
+use indicatif::{ProgressBar, ProgressStyle};
+use rdownloader::{
+    DownloadError, DownloadEvent, DownloadOptions, DownloadPlan, EventCallback, ProbeTotal,
+    ProgressCallback,
+};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// 相邻两个 progress 事件的最小间隔，下载完成时的最后一个事件不受限制
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// JSON 事件的输出目标，每次调用写入完整的一行
+pub trait Sink: Send + Sync {
+    fn send(&self, event: &Value);
+}
+
+/// 标准输出：多个下载任务共用标准输出，整行写入时持有锁，避免不同事件相互穿插
+struct Stdout;
+
+impl Sink for Stdout {
+    fn send(&self, event: &Value) {
+        let mut stdout = std::io::stdout().lock();
+        let _ = writeln!(stdout, "{}", event);
+        let _ = stdout.flush();
+    }
+}
+
+/// 输出一行事件到标准输出
+fn emit(event: Value) {
+    Stdout.send(&event);
+}
+
+/// 返回将 `url` 的探测、进度、状态和完成信息输出为 JSON 事件的下载选项
+pub fn json_options(url: &str, options: &DownloadOptions) -> DownloadOptions {
+    let stdout: Arc<dyn Sink> = Arc::new(Stdout);
+    DownloadOptions {
+        on_event: Some(event_callback(url.to_string(), stdout.clone())),
+        on_progress: Some(progress_callback(url.to_string(), stdout)),
+        show_progress: false,
+        ..options.clone()
+    }
+}
+
+/// 在 `options` 已有的回调之外，把 `url` 的事件同时写到 `sink`。
+///
+/// 原本由库绘制的默认进度条会因为设置了进度回调而隐藏，这里换成由调用方提供的同样样式的进度条
+pub fn tee_options(url: &str, options: &DownloadOptions, sink: Arc<dyn Sink>) -> DownloadOptions {
+    let events = event_callback(url.to_string(), sink.clone());
+    let progress = progress_callback(url.to_string(), sink);
+    let progress_bar = match &options.progress_bar {
+        None if options.show_progress && options.on_progress.is_none() => Some(terminal_bar()),
+        bar => bar.clone(),
+    };
+    DownloadOptions {
+        on_event: Some(match options.on_event.clone() {
+            Some(existing) => EventCallback::new(move |event| {
+                existing.emit(event);
+                events.emit(event);
+            }),
+            None => events,
+        }),
+        on_progress: Some(match options.on_progress.clone() {
+            Some(existing) => ProgressCallback::new(move |downloaded, total| {
+                existing.report(downloaded, total);
+                progress.report(downloaded, total);
+            }),
+            None => progress,
+        }),
+        progress_bar,
+        ..options.clone()
+    }
+}
+
+/// 与库的默认进度条相同的样式，文件大小未知时进度条保持为空
+fn terminal_bar() -> ProgressBar {
+    let bar = ProgressBar::new_spinner();
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+            .unwrap()
+            .progress_chars("->-"),
+    );
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
+
+fn event_callback(url: String, sink: Arc<dyn Sink>) -> EventCallback {
+    EventCallback::new(move |event| {
+        sink.send(&match event {
+            DownloadEvent::Probed {
+                resolved_url,
+                size,
+                supports_range,
+                supports_resume,
+            } => json!({
+                "event": "probe",
+                "url": url,
+                "resolved_url": resolved_url,
+                "size": size,
+                "supports_range": supports_range,
+                "supports_resume": supports_resume,
+            }),
+            DownloadEvent::Status(message) => json!({
+                "event": "status",
+                "url": url,
+                "message": message,
+            }),
+            DownloadEvent::Warning(message) => json!({
+                "event": "warning",
+                "url": url,
+                "message": message,
+            }),
+            DownloadEvent::Finished(summary) => json!({
+                "event": "done",
+                "url": url,
+                "path": summary.path.display().to_string(),
+                "total_size": summary.total_size,
+                "bytes_downloaded": summary.bytes_downloaded,
+                "resumed": summary.resumed,
+                "multipart": summary.multipart,
+                "skipped": summary.skipped,
+                "elapsed_secs": summary.elapsed.as_secs_f64(),
+                "average_speed": summary.average_speed(),
+                "peak_speed": summary.peak_speed,
+                "chunks": summary.chunks,
+                "retries": summary.retries,
+                "restart_reason": summary.restart_reason,
+            }),
+        })
+    })
+}
+
+fn progress_callback(url: String, sink: Arc<dyn Sink>) -> ProgressCallback {
+    let last_emit: Mutex<Option<Instant>> = Mutex::new(None);
+    ProgressCallback::new(move |downloaded, total| {
+        let finished = total == Some(downloaded);
+        // 锁里只有上次输出的时间，某次回调 panic 后仍可以继续使用
+        let mut last = last_emit.lock().unwrap_or_else(PoisonError::into_inner);
+        if !finished && last.is_some_and(|at| at.elapsed() < PROGRESS_INTERVAL) {
+            return;
+        }
+        *last = Some(Instant::now());
+        sink.send(&json!({
+            "event": "progress",
+            "url": url,
+            "downloaded": downloaded,
+            "total": total,
+        }));
+    })
+}
+
+/// 输出下载失败事件，`kind` 为错误类别的简短标识，`message` 为完整的错误信息
+pub fn emit_error(url: &str, error: &DownloadError) {
+    emit(error_event(url, error));
+}
+
+/// 下载失败事件，除了标准输出也会写到 `--progress-socket`
+pub fn error_event(url: &str, error: &DownloadError) -> Value {
+    json!({
+        "event": "error",
+        "url": url,
+        "kind": error.kind(),
+        "message": error.to_string(),
+    })
+}
+
+/// 输出 `--dry-run` 的结果，`mode` 为 `multipart`、`sequential` 或 `stream`
+pub fn emit_plan(url: &str, plan: &DownloadPlan) {
+    emit(json!({
+        "event": "plan",
+        "url": url,
+        "path": plan.path.display().to_string(),
+        "exists": plan.path.exists(),
+        "resolved_url": plan.probe.resolved_url,
+        "size": plan.probe.size,
+        "mode": plan.mode.as_str(),
+        "supports_range": plan.probe.supports_range,
+        "supports_resume": plan.probe.supports_resume,
+        "content_length": plan.probe.content_length,
+        "content_range": plan.probe.content_range,
+        "etag": plan.probe.etag,
+        "content_type": plan.probe.content_type,
+    }));
+}
+
+/// 多个 URL 的试运行结束后输出合计事件，有文件大小未知时 `size` 为 `null`
+pub fn emit_plan_total(total: &ProbeTotal) {
+    emit(json!({
+        "event": "plan_total",
+        "files": total.files,
+        "size": total.size(),
+        "known_size": total.known_size,
+        "unknown_sizes": total.unknown,
+    }));
+}
+
+/// 批量下载结束后输出汇总事件
+pub fn emit_summary(succeeded: usize, failed: usize) {
+    emit(json!({
+        "event": "summary",
+        "succeeded": succeeded,
+        "failed": failed,
+    }));
+}
@@ -1,20 +1,575 @@
-use clap::Parser;
-use rdownloader::download;
+mod batch;
+mod json;
+mod socket;
+
+use batch::{download_all, read_url_file};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use indicatif::HumanBytes;
+use json::Sink;
+use rdownloader::{
+    download_to_writer, download_with, plan, Auth, CancellationToken, Checksum,
+    ChunkProgressCallback, ChunkReport, ChunkStatus, ConnectionLimit, DownloadError, DownloadEvent,
+    DownloadMode, DownloadOptions, DownloadPlan, DownloadSummary, EventCallback, HttpVersion,
+    IpVersion, OverwritePolicy, ProbeTotal, ResumeMode, TransferMode, DEFAULT_USER_AGENT,
+};
+use rdownloader_utils::{parse_header, parse_size, validate_output_template};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use socket::ProgressSocket;
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// 要下载的文件的 URL
-    url: String,
+    /// 要下载的文件的 URL，可以指定多个 (多个 URL 会并发下载)
+    #[arg(value_name = "URL", required_unless_present = "input_file")]
+    urls: Vec<String>,
+
+    /// 从文件中读取要下载的 URL，每行一个 (忽略空行和以 # 开头的行)
+    #[arg(short, long, value_name = "FILE")]
+    input_file: Option<PathBuf>,
+
+    /// 下载多个 URL 时同时进行的下载任务数
+    #[arg(short, long, value_name = "N", default_value_t = 3, value_parser = parse_jobs)]
+    jobs: usize,
 
-    /// 输出路径 (可以是一个完整的文件路径，或一个目录)
+    /// 所有下载任务合计同时进行的请求数上限，与 --jobs 和 --connections 同时生效
+    #[arg(long, value_name = "N", value_parser = parse_connections)]
+    max_connections: Option<usize>,
+
+    /// 输出路径 (可以是一个完整的文件路径，或一个目录)，"-" 表示写入标准输出。
+    /// 下载多个 URL 时总是视为目录
     #[arg(short, long, value_name = "PATH")]
     output: Option<String>,
 
     /// 指定 log4rs 配置文件的路径
     #[arg(short = 'c', long, value_name = "FILE")]
     log_conf: Option<PathBuf>,
+
+    /// 多线程模式下每个数据块的大小，例如 4M、16M
+    #[arg(long, value_name = "SIZE", default_value = "1M", value_parser = parse_chunk_size)]
+    chunk_size: u64,
+
+    /// 数据块从 --chunk-size 开始逐块翻倍，直到该大小，例如 --chunk-size 256K --max-chunk-size 8M
+    #[arg(long, value_name = "SIZE", value_parser = parse_chunk_size)]
+    max_chunk_size: Option<u64>,
+
+    /// 多线程下载时至少划分的数据块数 (默认等于 --connections)，不足时缩小数据块，但不小于 256K；1 表示总是使用 --chunk-size
+    #[arg(long, value_name = "N", value_parser = parse_min_chunks)]
+    min_chunks: Option<usize>,
+
+    /// 多线程模式下的并发连接数
+    #[arg(long, value_name = "N", default_value_t = DownloadOptions::default().concurrency, value_parser = parse_connections)]
+    connections: usize,
+
+    /// 启用自适应并发：从 N 个连接开始，顺利时逐步增加到 --connections，出错或遇到 429 时减半
+    #[arg(long, value_name = "N", value_parser = parse_connections)]
+    initial_connections: Option<usize>,
+
+    /// 单个数据块的最大尝试次数 (包含第一次请求)，失败后按指数退避重试
+    #[arg(long, value_name = "N", default_value_t = DownloadOptions::default().chunk_max_attempts, value_parser = clap::value_parser!(u32).range(1..))]
+    chunk_attempts: u32,
+
+    /// 探测请求遇到网络错误或 HTTP 错误后的最大重试次数 (不含第一次请求)
+    #[arg(long, value_name = "N", default_value_t = DownloadOptions::default().probe_retries)]
+    retries: u32,
+
+    /// 探测请求第一次重试前等待的秒数，之后每次翻倍
+    #[arg(long, value_name = "SECS", default_value_t = DownloadOptions::default().probe_retry_delay.as_secs())]
+    retry_delay: u64,
+
+    /// 探测请求两次重试之间最多等待的秒数
+    #[arg(long, value_name = "SECS", default_value_t = DownloadOptions::default().probe_max_retry_delay.as_secs())]
+    retry_max_delay: u64,
+
+    /// 随机缩短探测请求的重试等待时间 (最多一半)，避免大量客户端同时重试
+    #[arg(long)]
+    retry_jitter: bool,
+
+    /// 遇到任何错误都重试，包括 403、404 等通常不会自行恢复的 HTTP 错误
+    #[arg(long)]
+    retry_all_errors: bool,
+
+    /// 限制最大下载速度 (每秒字节数)，例如 500K、2M
+    #[arg(long, value_name = "SIZE", value_parser = parse_max_speed)]
+    max_speed: Option<u64>,
+
+    /// 允许下载的最大文件大小，例如 500M、4G，超过时中止下载
+    #[arg(long, value_name = "SIZE", value_parser = parse_max_size)]
+    max_size: Option<u64>,
+
+    /// 只下载文件中的一段字节 (包含两端)，例如 0-1048575
+    #[arg(long, value_name = "START-END", value_parser = parse_byte_range)]
+    range: Option<RangeInclusive<u64>>,
+
+    /// 下载完成后校验文件摘要，格式为 sha256:<hex> 或 md5:<hex>
+    #[arg(long, value_name = "ALGO:HEX")]
+    checksum: Option<Checksum>,
+
+    /// 附加到所有请求上的请求头，格式为 "Key: Value"，可重复指定
+    #[arg(short = 'H', long = "header", value_name = "KEY: VALUE", value_parser = parse_header)]
+    headers: Vec<(HeaderName, HeaderValue)>,
+
+    /// 通过代理服务器下载，例如 http://host:port (默认读取 HTTP_PROXY/HTTPS_PROXY 环境变量)
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// 发送给下载地址所在主机的 cookie，格式为 name=value，可以重复指定
+    #[arg(long = "cookie", value_name = "NAME=VALUE", value_parser = parse_cookie)]
+    cookies: Vec<String>,
+
+    /// 从 Netscape 格式的 cookie 文件 (例如浏览器导出的 cookies.txt) 加载 cookie
+    #[arg(long, value_name = "PATH")]
+    cookie_file: Option<PathBuf>,
+
+    /// 额外信任的 CA 证书 (PEM 格式)，用于使用私有 CA 的服务器
+    #[arg(long, value_name = "PEM")]
+    cacert: Option<PathBuf>,
+
+    /// 不校验服务器的 TLS 证书 (不安全，仅用于测试环境)
+    #[arg(short = 'k', long)]
+    insecure: bool,
+
+    /// 所有请求使用的 User-Agent
+    #[arg(long, value_name = "UA", default_value = DEFAULT_USER_AGENT, value_parser = parse_user_agent)]
+    user_agent: String,
+
+    /// 安静模式：不显示进度条和状态信息，只输出错误
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// 跳过下载前的磁盘剩余空间检查 (适用于支持稀疏文件的文件系统)
+    #[arg(long)]
+    no_space_check: bool,
+
+    /// 不校验各个数据块的 Content-Type 是否与探测时一致
+    #[arg(long)]
+    no_content_check: bool,
+
+    /// 建立连接的超时秒数，0 表示不限制
+    #[arg(long, value_name = "SECS", default_value_t = default_secs(DownloadOptions::default().connect_timeout))]
+    connect_timeout: u64,
+
+    /// 读取超时秒数：超过该时长没有收到数据时重试当前数据块，0 表示不限制
+    #[arg(long, value_name = "SECS", default_value_t = default_secs(DownloadOptions::default().read_timeout))]
+    read_timeout: u64,
+
+    /// 空闲连接在连接池中保留的秒数，0 表示一直保留
+    #[arg(long, value_name = "SECS", default_value_t = default_secs(DownloadOptions::default().pool_idle_timeout))]
+    pool_idle_timeout: u64,
+
+    /// TCP keep-alive 探测间隔秒数，0 表示不开启
+    #[arg(long, value_name = "SECS", default_value_t = default_secs(DownloadOptions::default().tcp_keepalive))]
+    tcp_keepalive: u64,
+
+    /// 整个下载任务的最长秒数，超时后保存进度并退出，0 表示不限制
+    #[arg(long, value_name = "SECS", default_value_t = 0)]
+    timeout: u64,
+
+    /// HTTP Basic 认证，格式为 user:pass (省略 ":pass" 时密码为空)
+    #[arg(long, value_name = "USER:PASS", value_parser = parse_basic_auth, conflicts_with = "bearer")]
+    user: Option<Auth>,
+
+    /// Bearer 令牌认证，以 "Authorization: Bearer <TOKEN>" 附加到所有请求上
+    #[arg(long, value_name = "TOKEN", value_parser = parse_bearer)]
+    bearer: Option<Auth>,
+
+    /// 最多跟随的重定向次数
+    #[arg(long, value_name = "N", default_value_t = DownloadOptions::default().max_redirects)]
+    max_redirects: usize,
+
+    /// 不跟随任何重定向，服务器返回 3xx 时直接报错
+    #[arg(long, conflicts_with = "max_redirects")]
+    no_redirects: bool,
+
+    /// 直接使用 HTTP/2 连接，所有数据块复用同一个连接 (服务器必须支持 HTTP/2)
+    #[arg(long, conflicts_with = "http1_only")]
+    http2: bool,
+
+    /// 只使用 HTTP/1.1，每个并发的数据块占用一个连接
+    #[arg(long)]
+    http1_only: bool,
+
+    /// 只通过 IPv4 连接服务器
+    #[arg(short = '4', long, conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// 只通过 IPv6 连接服务器
+    #[arg(short = '6', long)]
+    ipv6: bool,
+
+    /// 从指定的本地地址发起连接，例如 192.168.1.10
+    #[arg(long, value_name = "ADDR")]
+    interface: Option<std::net::IpAddr>,
+
+    /// 文件大于该大小时才启用多线程模式，例如 512K、50M
+    #[arg(long, value_name = "SIZE", default_value = "1M", value_parser = parse_min_multipart_size)]
+    min_multipart_size: u64,
+
+    /// 强制使用单线程模式，忽略文件大小
+    #[arg(long, conflicts_with = "multi")]
+    single: bool,
+
+    /// 只要服务器支持 Range 请求就使用多线程模式，忽略文件大小
+    #[arg(long)]
+    multi: bool,
+
+    /// 同一文件的镜像地址，可重复指定。主地址不可用或数据块多次失败时依次改用镜像
+    #[arg(long = "mirror", value_name = "URL")]
+    mirrors: Vec<String>,
+
+    /// 存在未完成的下载时尝试续传 (默认行为)
+    #[arg(long = "continue", overrides_with_all = ["no_continue", "require_continue"])]
+    continue_: bool,
+
+    /// 忽略并删除未完成的下载进度，从头开始下载
+    #[arg(long, overrides_with_all = ["continue_", "require_continue"])]
+    no_continue: bool,
+
+    /// 必须续传：没有可以续传的进度时报错退出，而不是从头下载
+    #[arg(long, overrides_with_all = ["continue_", "no_continue"])]
+    require_continue: bool,
+
+    /// 目标文件已存在时跳过下载 (视为成功)
+    #[arg(long, conflicts_with = "overwrite")]
+    no_clobber: bool,
+
+    /// 目标文件已存在时重新下载并覆盖
+    #[arg(long)]
+    overwrite: bool,
+
+    /// 目标文件已存在时，只有服务器上的文件比它新 (If-Modified-Since) 才重新下载并覆盖
+    #[arg(long, conflicts_with = "no_clobber")]
+    if_newer: bool,
+
+    /// 目标文件已存在时，只有服务器上文件的 ETag 与该值不同 (If-None-Match) 才重新下载并覆盖
+    #[arg(long, value_name = "ETAG", conflicts_with = "no_clobber")]
+    etag: Option<String>,
+
+    /// 每秒在标准错误输出上打印一次数据块状态图，用于排查卡住或反复失败的数据块
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// 输出文件名模板，例如 "{date}/{filename}"，相对于 -o 指定的目录 (默认为当前目录)。
+    /// 可用占位符: {filename} {host} {date} {ext}
+    #[arg(long, value_name = "TEMPLATE", value_parser = parse_output_template)]
+    output_template: Option<String>,
+
+    /// 以换行分隔的 JSON 事件 (probe、progress、status、done、error) 代替进度条和状态信息输出到标准输出
+    #[arg(long)]
+    json: bool,
+
+    /// 把与 --json 相同的事件同时写到 Unix 域套接字或命名管道，供其他进程显示进度 (路径不存在时创建套接字)
+    #[arg(long, value_name = "PATH")]
+    progress_socket: Option<PathBuf>,
+
+    /// 存放 .rdownload 状态文件的目录 (默认放在目标文件旁边，目标目录不可写时使用系统缓存目录)
+    #[arg(long, value_name = "DIR")]
+    state_dir: Option<PathBuf>,
+
+    /// 下载期间存放 .part 文件和状态文件的目录，完成后移动到最终路径 (可以跨文件系统)
+    #[arg(long, value_name = "DIR")]
+    temp_dir: Option<PathBuf>,
+
+    /// 不创建 .rdownload 状态文件 (中断后无法续传，下次运行会从头下载)
+    #[arg(long, conflicts_with = "state_dir")]
+    no_state: bool,
+
+    /// 不自动解压服务器以 gzip、deflate 或 br 编码发送的数据，按原样保存
+    #[arg(long)]
+    no_decompress: bool,
+
+    /// 多线程下载时通过内存映射写入文件，适合很大的文件；无法映射时自动改用普通写入
+    #[arg(long)]
+    mmap: bool,
+
+    /// 流式下载写入文件时的缓冲区大小，例如 64K、1M；为 0 时不缓冲
+    #[arg(long, value_name = "SIZE", default_value = "256K", value_parser = parse_write_buffer)]
+    write_buffer: usize,
+
+    /// 续传前重新读取已下载的数据块并校验哈希，只跳过完好的数据块 (较慢，默认关闭)
+    #[arg(long)]
+    verify_resume: bool,
+
+    /// 下载完成后解压 .gz/.tgz 文件，保存为去掉压缩扩展名的文件
+    #[arg(long = "decompress")]
+    unpack: bool,
+
+    /// 解压成功后删除下载的压缩文件
+    #[arg(long, requires = "unpack")]
+    remove_compressed: bool,
+
+    /// 只探测不下载：显示保存路径、文件大小、下载方式、ETag 和 Content-Type，不写入任何文件
+    #[arg(long)]
+    dry_run: bool,
+}
+
+fn parse_cookie(s: &str) -> Result<String, String> {
+    match s.split_once('=') {
+        Some((name, _)) if !name.trim().is_empty() && !s.contains(';') => Ok(s.to_string()),
+        _ => Err("cookie 的格式应为 name=value，每个 --cookie 只能指定一个".to_string()),
+    }
+}
+
+fn parse_user_agent(s: &str) -> Result<String, String> {
+    HeaderValue::from_str(s).map_err(|_| "User-Agent 含有无法用于请求头的字符".to_string())?;
+    Ok(s.to_string())
+}
+
+fn parse_basic_auth(s: &str) -> Result<Auth, String> {
+    let auth = Auth::basic(s);
+    auth.header_value()?;
+    Ok(auth)
+}
+
+fn parse_bearer(s: &str) -> Result<Auth, String> {
+    let auth = Auth::Bearer(s.to_string());
+    auth.header_value()?;
+    Ok(auth)
+}
+
+/// 将秒数转换为超时时长，0 表示不限制
+fn timeout_secs(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// 与 [`timeout_secs`] 相反，用于在帮助信息中显示库的默认超时
+fn default_secs(timeout: Option<Duration>) -> u64 {
+    timeout.map_or(0, |t| t.as_secs())
+}
+
+fn parse_connections(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("并发连接数必须大于等于 1".into()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("无法解析并发连接数 '{}'", s)),
+    }
+}
+
+fn parse_min_chunks(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("数据块数必须大于等于 1".into()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("无法解析数据块数 '{}'", s)),
+    }
+}
+
+fn parse_jobs(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(0) => Err("同时下载的任务数必须大于等于 1".into()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("无法解析任务数 '{}'", s)),
+    }
+}
+
+fn parse_max_speed(s: &str) -> Result<u64, String> {
+    match parse_size(s) {
+        Some(0) => Err("最大下载速度必须大于 0".into()),
+        Some(speed) => Ok(speed),
+        None => Err(format!("无法解析速度 '{}'，示例: 500K、2M", s)),
+    }
+}
+
+fn parse_max_size(s: &str) -> Result<u64, String> {
+    parse_size(s).ok_or_else(|| format!("无法解析大小 '{}'，示例: 500M、4G", s))
+}
+
+fn parse_byte_range(s: &str) -> Result<RangeInclusive<u64>, String> {
+    let invalid = || format!("无法解析字节范围 '{}'，示例: 0-1048575", s);
+    let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+    let start: u64 = start.trim().parse().map_err(|_| invalid())?;
+    let end: u64 = end.trim().parse().map_err(|_| invalid())?;
+    if start > end {
+        return Err(format!("字节范围 '{}' 的起点不能大于终点", s));
+    }
+    Ok(start..=end)
+}
+
+fn parse_output_template(s: &str) -> Result<String, String> {
+    validate_output_template(s)?;
+    Ok(s.to_string())
+}
+
+fn parse_write_buffer(s: &str) -> Result<usize, String> {
+    parse_size(s)
+        .and_then(|size| usize::try_from(size).ok())
+        .ok_or_else(|| format!("无法解析大小 '{}'，示例: 64K、1M", s))
+}
+
+fn parse_min_multipart_size(s: &str) -> Result<u64, String> {
+    parse_size(s).ok_or_else(|| format!("无法解析大小 '{}'，示例: 512K、50M", s))
+}
+
+fn parse_chunk_size(s: &str) -> Result<u64, String> {
+    match parse_size(s) {
+        Some(0) => Err("数据块大小必须大于 0".into()),
+        Some(size) => Ok(size),
+        None => Err(format!("无法解析大小 '{}'，示例: 4M、16M、512K", s)),
+    }
+}
+
+/// 数据块状态图的最大宽度，数据块更多时每个字符代表相邻的若干个数据块
+const CHUNK_MAP_WIDTH: usize = 64;
+
+/// 将数据块快照压缩成一行状态图：`#` 已完成，`>` 下载中，`.` 等待中，`x` 失败
+fn chunk_map(reports: &[ChunkReport]) -> String {
+    let per_cell = reports.len().div_ceil(CHUNK_MAP_WIDTH).max(1);
+    let map: String = reports
+        .chunks(per_cell)
+        .map(|cell| {
+            let has = |status| cell.iter().any(|r| r.status == status);
+            if has(ChunkStatus::Failed) {
+                'x'
+            } else if has(ChunkStatus::InFlight) {
+                '>'
+            } else if has(ChunkStatus::Pending) {
+                '.'
+            } else {
+                '#'
+            }
+        })
+        .collect();
+    let count = |status| reports.iter().filter(|r| r.status == status).count();
+    let mut line = format!(
+        "[{}] 完成 {}/{}，下载中 {}，失败 {}",
+        map,
+        count(ChunkStatus::Done),
+        reports.len(),
+        count(ChunkStatus::InFlight),
+        count(ChunkStatus::Failed)
+    );
+    // 列出尚未完成却已重试过的数据块，它们通常就是下载卡住的原因
+    let retried: Vec<String> = reports
+        .iter()
+        .filter(|r| r.status != ChunkStatus::Done && r.attempts > 1)
+        .take(3)
+        .map(|r| format!("{}-{} (第 {} 次)", r.start, r.end, r.attempts))
+        .collect();
+    if !retried.is_empty() {
+        line.push_str(&format!("，重试中: {}", retried.join(", ")));
+    }
+    line
+}
+
+/// 在终端显示库上报的状态信息和警告。
+///
+/// 状态信息写到标准输出，`show_status` 为 `false` 时 (例如下载内容本身写到标准输出) 不显示；
+/// 警告总是写到标准错误。
+fn terminal_events(show_status: bool) -> EventCallback {
+    EventCallback::new(move |event| match event {
+        DownloadEvent::Status(message) if show_status => println!("{}", message),
+        DownloadEvent::Warning(message) => eprintln!("警告: {}", message),
+        // 跳过的下载已经有状态信息说明原因，没有可统计的数据
+        DownloadEvent::Finished(summary) if show_status && !summary.skipped => {
+            println!("{}", summary_line(summary))
+        }
+        _ => {}
+    })
+}
+
+/// 下载结束时的统计信息：下载量、用时、平均和峰值速度、数据块数和重试次数
+fn summary_line(summary: &DownloadSummary) -> String {
+    format!(
+        "下载完成: {}，用时 {:.1} 秒，平均速度 {}/s，峰值速度 {}/s，{} 个数据块，重试 {} 次",
+        HumanBytes(summary.bytes_downloaded),
+        summary.elapsed.as_secs_f64(),
+        HumanBytes(summary.average_speed()),
+        HumanBytes(summary.peak_speed),
+        summary.chunks,
+        summary.retries
+    )
+}
+
+/// 下载被 Ctrl-C 中断时的退出码 (128 + SIGINT)，与下载失败区分开
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// 下载 (批量下载时任意一个 URL) 或试运行的探测失败时的退出码
+const EXIT_FAILED: i32 = 1;
+
+/// 以 `code` 退出进程。`std::process::exit` 不会运行析构函数，先释放 `resources`
+/// (进度套接字及持有它的下载选项)，自己创建的套接字文件才会被删除
+fn exit_with(code: i32, resources: impl Sized) -> ! {
+    drop(resources);
+    std::process::exit(code)
+}
+
+/// 第一次按下 Ctrl-C 时取消下载：不再启动新的数据块，正在写入的数据块写完后最后保存一次状态文件，
+/// 下载函数随后返回取消错误。再次按下 Ctrl-C 时立即退出
+fn cancel_on_ctrl_c() -> CancellationToken {
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    tokio::spawn(async move {
+        // 两次 Ctrl-C 必须由同一个监听器接收：每次调用 tokio::signal::ctrl_c 都会立即看到已经收到的那一次
+        #[cfg(unix)]
+        let listener = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt());
+        #[cfg(windows)]
+        let listener = tokio::signal::windows::ctrl_c();
+        let Ok(mut interrupts) = listener else {
+            return;
+        };
+        if interrupts.recv().await.is_none() {
+            return;
+        }
+        eprintln!("\n正在停止下载并保存进度，再次按 Ctrl-C 立即退出...");
+        cancel.cancel();
+        if interrupts.recv().await.is_some() {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+    });
+    token
+}
+
+/// 下载失败时也通知 `--progress-socket` 的读取方
+fn send_error(socket: Option<&Arc<ProgressSocket>>, url: &str, error: &DownloadError) {
+    if let Some(socket) = socket {
+        socket.send(&json::error_event(url, error));
+    }
+}
+
+/// 以文字形式显示多个 URL 的 `--dry-run` 结果的合计
+fn print_plan_total(total: &ProbeTotal) {
+    match total.unknown {
+        0 => println!("合计: {} 个文件，共 {} 字节", total.files, total.known_size),
+        unknown => println!(
+            "合计: {} 个文件，已知大小共 {} 字节 ({} 个文件大小未知)",
+            total.files, total.known_size, unknown
+        ),
+    }
+}
+
+/// 以文字形式显示 `--dry-run` 的结果
+fn print_plan(url: &str, plan: &DownloadPlan) {
+    let or_none = |value: &Option<String>| value.clone().unwrap_or_else(|| "无".into());
+    println!("{}", url);
+    println!("  保存路径: {}", plan.path.display());
+    match plan.probe.size {
+        Some(size) => println!("  文件大小: {} 字节", size),
+        None => println!("  文件大小: 未知"),
+    }
+    println!(
+        "  下载方式: {}",
+        match plan.mode {
+            TransferMode::Multipart => "多线程",
+            TransferMode::Sequential => "单线程",
+            TransferMode::Stream => "流式 (大小未知，不支持续传)",
+        }
+    );
+    println!(
+        "  支持续传: {}",
+        match (plan.probe.supports_resume, plan.probe.supports_range) {
+            (true, _) => "是",
+            (false, true) => "否 (服务器声明支持 Range，但没有返回 206)",
+            (false, false) => "否",
+        }
+    );
+    println!("  ETag: {}", or_none(&plan.probe.etag));
+    println!("  Content-Type: {}", or_none(&plan.probe.content_type));
+    if plan.path.exists() {
+        println!("  注意: 文件已存在");
+    }
 }
 
 fn setup_logger(config_path: Option<PathBuf>) -> Result<(), Box<dyn std::error::Error>> {
@@ -25,18 +580,350 @@ fn setup_logger(config_path: Option<PathBuf>) -> Result<(), Box<dyn std::error::
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+
+    let mut urls = std::mem::take(&mut args.urls);
+    if let Some(input_file) = &args.input_file {
+        match read_url_file(input_file) {
+            Ok(listed) => urls.extend(listed),
+            Err(e) => Args::command()
+                .error(
+                    ErrorKind::Io,
+                    format!("无法读取 URL 列表文件 '{}': {}", input_file.display(), e),
+                )
+                .exit(),
+        }
+    }
+    if urls.is_empty() {
+        Args::command()
+            .error(ErrorKind::MissingRequiredArgument, "没有要下载的 URL")
+            .exit();
+    }
+    let batch = urls.len() > 1;
+    if batch && args.output.as_deref() == Some("-") {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "下载多个 URL 时不能写入标准输出 (-o -)",
+            )
+            .exit();
+    }
+    if args.output_template.is_some() && args.output.as_deref() == Some("-") {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "写入标准输出 (-o -) 时不能使用 --output-template",
+            )
+            .exit();
+    }
+    if args.dry_run && args.output.as_deref() == Some("-") {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "写入标准输出 (-o -) 时不能使用 --dry-run",
+            )
+            .exit();
+    }
+    if args.json && args.output.as_deref() == Some("-") {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "写入标准输出 (-o -) 时不能使用 --json",
+            )
+            .exit();
+    }
+    if batch && !args.mirrors.is_empty() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--mirror 只能用于单个 URL 的下载",
+            )
+            .exit();
+    }
+    if batch && args.checksum.is_some() {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--checksum 只能用于单个 URL 的下载",
+            )
+            .exit();
+    }
 
     // 初始化日志记录器
     if let Err(e) = setup_logger(args.log_conf) {
         eprintln!("错误：无法初始化日志记录器: {}. 日志功能将不可用。", e);
     }
 
+    let socket = args.progress_socket.as_deref().map(|path| {
+        ProgressSocket::open(path).unwrap_or_else(|e| {
+            Args::command()
+                .error(
+                    ErrorKind::Io,
+                    format!("无法打开进度套接字 '{}': {}", path.display(), e),
+                )
+                .exit()
+        })
+    });
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in args.headers {
+        headers.append(name, value);
+    }
+
+    // "-o -" 将内容写入标准输出，便于通过管道交给其他程序处理
+    let to_stdout = args.output.as_deref() == Some("-");
+
+    let options = DownloadOptions {
+        chunk_size: args.chunk_size,
+        max_chunk_size: args.max_chunk_size,
+        min_chunks: args.min_chunks,
+        concurrency: args.connections,
+        initial_concurrency: args.initial_connections,
+        mode: if args.single {
+            DownloadMode::Single
+        } else if args.multi {
+            DownloadMode::Multi
+        } else {
+            DownloadMode::Auto
+        },
+        min_multipart_size: args.min_multipart_size,
+        mirrors: args.mirrors,
+        on_chunk_progress: args
+            .verbose
+            .then(|| ChunkProgressCallback::new(|reports| eprintln!("{}", chunk_map(reports)))),
+        // 库只通过日志和事件上报状态，由命令行决定如何显示；--json 模式会为每个 URL 换成 JSON 事件
+        on_event: (!args.quiet && !args.json).then(|| terminal_events(!to_stdout)),
+        overwrite: if args.no_clobber {
+            OverwritePolicy::Skip
+        } else if args.overwrite {
+            OverwritePolicy::Overwrite
+        } else {
+            OverwritePolicy::Error
+        },
+        if_newer: args.if_newer,
+        if_none_match: args.etag,
+        resume: if args.no_continue {
+            ResumeMode::Restart
+        } else if args.require_continue {
+            ResumeMode::Require
+        } else {
+            ResumeMode::Auto
+        },
+        checksum: args.checksum,
+        headers,
+        chunk_max_attempts: args.chunk_attempts,
+        probe_retries: args.retries,
+        probe_retry_delay: Duration::from_secs(args.retry_delay),
+        probe_max_retry_delay: Duration::from_secs(args.retry_max_delay),
+        probe_retry_jitter: args.retry_jitter,
+        retry_all_errors: args.retry_all_errors,
+        max_speed: args.max_speed,
+        // 所有下载共用同一组名额，批量下载时逐个克隆选项也不会增加总数
+        connection_limit: args.max_connections.map(ConnectionLimit::new),
+        max_size: args.max_size,
+        byte_range: args.range,
+        proxy: args.proxy,
+        user_agent: Some(args.user_agent),
+        cookies: args.cookies,
+        cookie_file: args.cookie_file,
+        ca_cert: args.cacert,
+        insecure: args.insecure,
+        // 写入标准输出时，进度条会混入数据流，因此总是关闭
+        show_progress: !args.quiet && !to_stdout,
+        skip_space_check: args.no_space_check,
+        skip_content_check: args.no_content_check,
+        connect_timeout: timeout_secs(args.connect_timeout),
+        read_timeout: timeout_secs(args.read_timeout),
+        http_version: if args.http2 {
+            HttpVersion::Http2
+        } else if args.http1_only {
+            HttpVersion::Http1
+        } else {
+            HttpVersion::Auto
+        },
+        ip_version: if args.ipv4 {
+            IpVersion::V4
+        } else if args.ipv6 {
+            IpVersion::V6
+        } else {
+            IpVersion::Any
+        },
+        local_address: args.interface,
+        pool_idle_timeout: timeout_secs(args.pool_idle_timeout),
+        tcp_keepalive: timeout_secs(args.tcp_keepalive),
+        timeout: timeout_secs(args.timeout),
+        auth: args.user.or(args.bearer),
+        max_redirects: if args.no_redirects {
+            0
+        } else {
+            args.max_redirects
+        },
+        output_template: args.output_template,
+        state_dir: args.state_dir,
+        temp_dir: args.temp_dir,
+        no_state: args.no_state,
+        decompress: !args.no_decompress,
+        mmap: args.mmap,
+        write_buffer_size: args.write_buffer,
+        cancel: Some(cancel_on_ctrl_c()),
+        verify_resume: args.verify_resume,
+        unpack: args.unpack,
+        remove_compressed: args.remove_compressed,
+        ..Default::default()
+    };
+
+    // 多个 URL 时输出路径总是视为目录
+    let output = if batch {
+        args.output.map(|dir| {
+            if dir.ends_with('/') {
+                dir
+            } else {
+                format!("{}/", dir)
+            }
+        })
+    } else {
+        args.output
+    };
+
+    if args.dry_run {
+        let mut total = ProbeTotal::default();
+        let mut failed = false;
+        for url in &urls {
+            let result = if args.json {
+                plan(url, output.clone(), &json::json_options(url, &options)).await
+            } else {
+                plan(url, output.clone(), &options).await
+            };
+            match &result {
+                Ok(plan) => total.add(&plan.probe),
+                Err(_) => failed = true,
+            }
+            match result {
+                Ok(plan) if args.json => json::emit_plan(url, &plan),
+                Ok(plan) => print_plan(url, &plan),
+                Err(e) if args.json => json::emit_error(url, &e),
+                Err(e) => eprintln!("探测失败 {}: {}", url, e),
+            }
+        }
+        // 多个 URL 时在开始下载前给出合计大小
+        if batch && args.json {
+            json::emit_plan_total(&total);
+        } else if batch {
+            print_plan_total(&total);
+        }
+        if failed {
+            exit_with(EXIT_FAILED, socket);
+        }
+        return Ok(());
+    }
+
+    if batch {
+        let output_dir = output;
+        let results = download_all(
+            &urls,
+            output_dir,
+            &options,
+            args.jobs,
+            args.json,
+            socket.as_ref(),
+        )
+        .await;
+        let mut failed = 0;
+        for (url, result) in urls.iter().zip(&results) {
+            if let Err(e) = result {
+                failed += 1;
+                log::error!("下载失败 {}: {}", url, e);
+                send_error(socket.as_ref(), url, e);
+                if args.json {
+                    json::emit_error(url, e);
+                } else {
+                    eprintln!("下载失败 {}: {}", url, e);
+                }
+            }
+        }
+        log::info!(
+            "批量下载结束: 成功 {} 个，失败 {} 个",
+            urls.len() - failed,
+            failed
+        );
+        if args.json {
+            json::emit_summary(urls.len() - failed, failed);
+        } else if !args.quiet || failed > 0 {
+            eprintln!(
+                "批量下载结束: 成功 {} 个，失败 {} 个",
+                urls.len() - failed,
+                failed
+            );
+        }
+        if results
+            .iter()
+            .any(|result| result.as_ref().is_err_and(|e| e.is_cancelled()))
+        {
+            if !args.json {
+                eprintln!("下载已中断，进度已保存。使用相同的命令重新运行即可续传。");
+            }
+            exit_with(EXIT_INTERRUPTED, socket);
+        }
+        if failed > 0 {
+            exit_with(EXIT_FAILED, socket);
+        }
+        return Ok(());
+    }
+
     // --- 调用高级 API ---
-    // 所有复杂的逻辑都被封装在 rdownloader::download 函数中
-    match download(&args.url, args.output).await {
-        Ok(_) => log::info!("\n下载任务成功完成!"),
-        Err(e) => log::error!("\n下载任务失败: {:?}", e),
+    // 所有复杂的逻辑都被封装在 rdownloader::download_with 函数中
+    let url = &urls[0];
+    let options = if args.json {
+        json::json_options(url, &options)
+    } else {
+        options
+    };
+    let options = match &socket {
+        Some(socket) => json::tee_options(url, &options, socket.clone()),
+        None => options,
+    };
+    // 写入标准输出时没有保存路径，也就没有下载结果的统计信息
+    let result = if to_stdout {
+        download_to_writer(url, &mut std::io::stdout().lock(), &options)
+            .await
+            .map(|_| None)
+    } else {
+        download_with(url, output, &options).await.map(Some)
+    };
+    if let Err(e) = &result {
+        send_error(socket.as_ref(), url, e);
+    }
+    match result {
+        Ok(None) => log::info!("\n下载任务成功完成!"),
+        Ok(Some(summary)) => log::info!(
+            "\n下载任务成功完成! 本次下载 {} / {} 字节，用时 {:.1} 秒",
+            summary.bytes_downloaded,
+            summary.total_size,
+            summary.elapsed.as_secs_f64()
+        ),
+        Err(e) if e.is_cancelled() => {
+            log::info!("下载任务被中断");
+            if args.json {
+                json::emit_error(url, &e);
+            } else if to_stdout {
+                // 写入标准输出的下载无法续传
+                eprintln!("下载已中断。");
+            } else {
+                eprintln!("下载已中断，进度已保存。使用相同的命令重新运行即可续传。");
+            }
+            exit_with(EXIT_INTERRUPTED, (options, socket));
+        }
+        Err(e) => {
+            log::error!("\n下载任务失败: {}", e);
+            if args.json {
+                json::emit_error(url, &e);
+            } else {
+                // 错误信息在安静模式下同样需要让用户看到
+                eprintln!("下载任务失败: {}", e);
+            }
+            // 回调中也持有进度套接字，两者都释放后才会删除套接字文件
+            exit_with(EXIT_FAILED, (options, socket));
+        }
     }
 
     Ok(())
@@ -0,0 +1,150 @@
+// --- 进度套接字 ---
+// `--progress-socket` 把与 `--json` 相同格式的事件写到 Unix 域套接字或命名管道，供托盘程序、
+// 编辑器插件等其他进程显示下载进度。读取方断开或套接字被删除时只丢弃这个连接，下载照常进行。
+
+use crate::json::Sink;
+use serde_json::Value;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+#[cfg(unix)]
+use std::time::Duration;
+
+/// 单次写入的最长等待时间，读取方长时间不读取时断开它，而不是拖慢下载
+#[cfg(unix)]
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// 事件的读取方，可能有多个 (自己创建的套接字允许多个进程同时连接)
+pub struct ProgressSocket {
+    readers: Mutex<Vec<Box<dyn Write + Send>>>,
+    /// 由本进程创建的套接字文件，退出时删除
+    created: Option<PathBuf>,
+}
+
+impl ProgressSocket {
+    /// 打开 `path` 上的进度输出：
+    ///
+    /// - 已有进程在监听的 Unix 域套接字：直接连接
+    /// - 命名管道 (FIFO)：在后台等待读取方打开管道，之前的事件不会保留
+    /// - 路径不存在 (或是没有进程监听的旧套接字)：创建套接字，接受任意个读取方连接
+    ///
+    /// 非 Unix 平台上把 `path` 当作已经存在的命名管道 (如 `\\.\pipe\rdownloader`) 打开写入
+    pub fn open(path: &Path) -> io::Result<Arc<Self>> {
+        #[cfg(unix)]
+        {
+            open_unix(path)
+        }
+        #[cfg(not(unix))]
+        {
+            let pipe = std::fs::OpenOptions::new().write(true).open(path)?;
+            Ok(Arc::new(ProgressSocket {
+                readers: Mutex::new(vec![Box::new(pipe)]),
+                created: None,
+            }))
+        }
+    }
+
+    fn add_reader(&self, reader: Box<dyn Write + Send>) {
+        self.readers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(reader);
+    }
+}
+
+#[cfg(unix)]
+fn open_unix(path: &Path) -> io::Result<Arc<ProgressSocket>> {
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    let file_type = std::fs::metadata(path).map(|meta| meta.file_type());
+    if file_type.as_ref().is_ok_and(|t| t.is_fifo()) {
+        let socket = Arc::new(ProgressSocket {
+            readers: Mutex::new(Vec::new()),
+            created: None,
+        });
+        // 以写方式打开 FIFO 会一直阻塞到有读取方为止，不能让下载等待它
+        let pipe = path.to_path_buf();
+        let weak = Arc::downgrade(&socket);
+        std::thread::spawn(
+            move || match std::fs::OpenOptions::new().write(true).open(&pipe) {
+                Ok(file) => {
+                    if let Some(socket) = weak.upgrade() {
+                        socket.add_reader(Box::new(file));
+                    }
+                }
+                Err(e) => log::warn!("无法打开进度管道 '{}': {}", pipe.display(), e),
+            },
+        );
+        return Ok(socket);
+    }
+
+    if file_type.as_ref().is_ok_and(|t| t.is_socket()) {
+        match UnixStream::connect(path) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                return Ok(Arc::new(ProgressSocket {
+                    readers: Mutex::new(vec![Box::new(stream)]),
+                    created: None,
+                }));
+            }
+            // 上次运行留下的套接字文件，没有进程在监听，换成新建的套接字
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => std::fs::remove_file(path)?,
+            Err(e) => return Err(e),
+        }
+    } else if file_type.is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "path exists and is neither a socket nor a named pipe",
+        ));
+    }
+
+    let listener = UnixListener::bind(path)?;
+    let socket = Arc::new(ProgressSocket {
+        readers: Mutex::new(Vec::new()),
+        created: Some(path.to_path_buf()),
+    });
+    let weak = Arc::downgrade(&socket);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Some(socket) = weak.upgrade() else {
+                return;
+            };
+            match stream {
+                Ok(stream) if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_ok() => {
+                    socket.add_reader(Box::new(stream))
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("接受进度套接字连接失败: {}", e),
+            }
+        }
+    });
+    Ok(socket)
+}
+
+impl Sink for ProgressSocket {
+    fn send(&self, event: &Value) {
+        let line = format!("{}\n", event);
+        let mut readers = self.readers.lock().unwrap_or_else(PoisonError::into_inner);
+        readers.retain_mut(|reader| {
+            match reader
+                .write_all(line.as_bytes())
+                .and_then(|_| reader.flush())
+            {
+                Ok(()) => true,
+                Err(e) => {
+                    log::warn!("进度套接字的读取方已断开，不再向它发送事件: {}", e);
+                    false
+                }
+            }
+        });
+    }
+}
+
+impl Drop for ProgressSocket {
+    fn drop(&mut self) {
+        if let Some(path) = &self.created {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
@@ -0,0 +1,525 @@
+// --- FTP 下载 ---
+// 支持 ftp:// 和 ftps:// (隐式 TLS，默认端口 990) 地址。FTP 不便于多连接分块，
+// 因此总是以被动模式单连接下载：通过 SIZE 得到文件大小，通过 REST 从 .part 文件的末尾继续。
+// 只用到登录、SIZE、MDTM、被动模式、REST 和 RETR 几条命令，所以没有引入 suppaftp 等 FTP 库：
+// 这个客户端直接建立在 tokio 和 HTTP 下载已经在用的 native-tls 之上，超时、取消、限速、
+// 大小限制和 .part 文件的处理都与 HTTP 下载共用同一套选项和实现。
+
+use crate::{BackendFuture, DispatchError, Downloader, Probe};
+use percent_encoding::percent_decode_str;
+use rdownloader_http::{
+    DownloadError, DownloadEvent, DownloadSummary, HttpOptions, ResumeMode, copy_from_reader,
+    download_from_reader, receive_from_reader, resolve_part_path,
+};
+use rdownloader_utils::{parse_mdtm, sanitize_filename};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_native_tls::TlsConnector;
+
+/// 控制连接和数据连接：明文的 TCP 连接或其上的 TLS 连接
+trait FtpStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> FtpStream for T {}
+
+/// `url` 是否为 FTP 地址
+pub(crate) fn is_ftp(url: &str) -> bool {
+    url.starts_with("ftp://") || url.starts_with("ftps://")
+}
+
+/// 从 FTP 地址中解析出的连接信息
+struct Target {
+    host: String,
+    port: u16,
+    tls: bool,
+    user: String,
+    password: String,
+    /// 服务器上的文件路径 (已解码)。开头的 `/` 已去掉，相对于登录后的目录；
+    /// 绝对路径需要写成 `ftp://host/%2Fpub/file`
+    path: String,
+}
+
+impl Target {
+    fn parse(url: &str) -> Result<Self, DispatchError> {
+        let parsed = reqwest::Url::parse(url)
+            .map_err(|e| DispatchError::Ftp(format!("invalid URL '{}': {}", url, e)))?;
+        let tls = parsed.scheme() == "ftps";
+        let host = parsed
+            .host_str()
+            .filter(|host| !host.is_empty())
+            .ok_or_else(|| DispatchError::Ftp(format!("no host in '{}'", url)))?;
+        let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+        let path = decode(parsed.path().strip_prefix('/').unwrap_or(parsed.path()));
+        if path.is_empty() || path.ends_with('/') {
+            return Err(DispatchError::Ftp(format!(
+                "'{}' does not name a file",
+                url
+            )));
+        }
+        Ok(Target {
+            // IPv6 地址在 URL 中带有方括号，连接时需要去掉
+            host: host.trim_matches(['[', ']']).to_string(),
+            port: parsed.port().unwrap_or(if tls { 990 } else { 21 }),
+            tls,
+            user: match parsed.username() {
+                "" => "anonymous".to_string(),
+                user => decode(user),
+            },
+            password: parsed
+                .password()
+                .map_or_else(|| "anonymous@".to_string(), decode),
+            path,
+        })
+    }
+
+    /// 服务器上的文件名，与 HTTP 下载从 URL 路径推断的文件名一样经过清理
+    fn filename(&self) -> Option<String> {
+        self.path
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .map(sanitize_filename)
+    }
+}
+
+/// 已登录的控制连接
+struct Control {
+    stream: BufReader<Box<dyn FtpStream>>,
+    /// ftps:// 时数据连接同样使用 TLS
+    tls: Option<TlsConnector>,
+    host: String,
+    /// 控制连接的对端地址，被动模式的数据连接总是连到这里
+    peer: IpAddr,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+}
+
+impl Control {
+    /// 连接服务器、登录并切换到二进制模式
+    async fn connect(target: &Target, options: &HttpOptions) -> Result<Self, DispatchError> {
+        let tcp = connect_tcp((target.host.as_str(), target.port), options.connect_timeout)
+            .await
+            .map_err(|e| {
+                DispatchError::Ftp(format!(
+                    "could not connect to {}:{}: {}",
+                    target.host, target.port, e
+                ))
+            })?;
+        let peer = tcp.peer_addr().map_err(connection_error)?.ip();
+        let tls = match target.tls {
+            true => Some(TlsConnector::from(
+                native_tls::TlsConnector::builder()
+                    .danger_accept_invalid_certs(options.insecure)
+                    .danger_accept_invalid_hostnames(options.insecure)
+                    .build()
+                    .map_err(tls_error)?,
+            )),
+            false => None,
+        };
+        let handshake_timeout = options.connect_timeout.or(options.read_timeout);
+        let stream = secure(tls.as_ref(), &target.host, handshake_timeout, tcp).await?;
+        let mut control = Control {
+            stream: BufReader::new(stream),
+            tls,
+            host: target.host.clone(),
+            peer,
+            connect_timeout: options.connect_timeout,
+            read_timeout: options.read_timeout,
+        };
+
+        let (code, message) = control.reply().await?;
+        if code != 220 {
+            return Err(DispatchError::Ftp(format!(
+                "the server refused the connection: {}",
+                message
+            )));
+        }
+        let (code, message) = control.send(&format!("USER {}", target.user)).await?;
+        match code {
+            230 => {}
+            331 | 332 => {
+                control
+                    .command(&format!("PASS {}", target.password), &[202, 230])
+                    .await?;
+            }
+            _ => return Err(rejected("USER", &message)),
+        }
+        control.command("TYPE I", &[200]).await?;
+        if target.tls {
+            // 要求数据连接同样加密
+            control.command("PBSZ 0", &[200]).await?;
+            control.command("PROT P", &[200]).await?;
+        }
+        Ok(control)
+    }
+
+    /// 读取一个完整的响应。多行响应以 `123-` 开头，到以 `123 ` 开头的行结束
+    async fn reply(&mut self) -> Result<(u16, String), DispatchError> {
+        let first = self.line().await?;
+        let code = first
+            .get(..3)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| DispatchError::Ftp(format!("malformed reply: {:?}", first)))?;
+        let mut message = first.clone();
+        if first.as_bytes().get(3) == Some(&b'-') {
+            let last = format!("{} ", code);
+            loop {
+                let line = self.line().await?;
+                message.push('\n');
+                message.push_str(&line);
+                if line.starts_with(&last) {
+                    break;
+                }
+            }
+        }
+        log::debug!("FTP< {}", message);
+        Ok((code, message))
+    }
+
+    async fn line(&mut self) -> Result<String, DispatchError> {
+        let mut line = String::new();
+        let read = match self.read_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, self.stream.read_line(&mut line))
+                .await
+                .map_err(|_| DownloadError::ReadTimeout(timeout))?,
+            None => self.stream.read_line(&mut line).await,
+        }
+        .map_err(connection_error)?;
+        if read == 0 {
+            return Err(DispatchError::Ftp(
+                "the server closed the control connection".into(),
+            ));
+        }
+        Ok(line.trim_end().to_string())
+    }
+
+    /// 发送一条命令并返回响应码和响应内容
+    async fn send(&mut self, command: &str) -> Result<(u16, String), DispatchError> {
+        // 日志中不记录密码
+        match command.strip_prefix("PASS ") {
+            Some(_) => log::debug!("FTP> PASS ****"),
+            None => log::debug!("FTP> {}", command),
+        }
+        let stream = self.stream.get_mut();
+        stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await
+            .map_err(connection_error)?;
+        stream.flush().await.map_err(connection_error)?;
+        self.reply().await
+    }
+
+    /// 发送一条命令，响应码不在 `expected` 中时返回包含服务器响应的错误
+    async fn command(&mut self, command: &str, expected: &[u16]) -> Result<String, DispatchError> {
+        let (code, message) = self.send(command).await?;
+        if !expected.contains(&code) {
+            // 错误信息中只保留命令名，不泄露密码
+            let verb = command.split(' ').next().unwrap_or(command);
+            return Err(rejected(verb, &message));
+        }
+        Ok(message)
+    }
+
+    /// 文件大小。服务器不支持 SIZE 命令时返回 `None`，之后按大小未知的流式下载处理
+    async fn size(&mut self, path: &str) -> Result<Option<u64>, DispatchError> {
+        let (code, message) = self.send(&format!("SIZE {}", path)).await?;
+        Ok((code == 213)
+            .then(|| message.get(4..)?.trim().parse().ok())
+            .flatten())
+    }
+
+    /// 文件的修改时间。服务器不支持 MDTM 命令时返回 `None`
+    async fn modified(&mut self, path: &str) -> Result<Option<SystemTime>, DispatchError> {
+        let (code, message) = self.send(&format!("MDTM {}", path)).await?;
+        Ok((code == 213)
+            .then(|| parse_mdtm(message.get(4..)?.trim()))
+            .flatten())
+    }
+
+    /// 以被动模式打开数据连接，优先使用 EPSV，服务器不支持时改用 PASV。
+    /// 返回的是未加密的连接：ftps:// 的服务器要收到 RETR 之后才在数据连接上开始 TLS 握手，
+    /// 因此 RETR 被接受之后再调用 [`Control::secure_data`]
+    async fn open_data(&mut self) -> Result<TcpStream, DispatchError> {
+        let port = match self.send("EPSV").await? {
+            (229, message) => parse_epsv(&message),
+            _ => parse_pasv(&self.command("PASV", &[227]).await?),
+        }
+        .ok_or_else(|| DispatchError::Ftp("malformed passive mode reply".into()))?;
+        // 被动模式响应中的地址可能是服务器的内网地址，总是连接控制连接的对端地址
+        connect_tcp((self.peer, port), self.connect_timeout)
+            .await
+            .map_err(connection_error)
+    }
+
+    /// ftps:// 时为数据连接完成 TLS 握手
+    async fn secure_data(&mut self, tcp: TcpStream) -> Result<Box<dyn FtpStream>, DispatchError> {
+        let timeout = self.connect_timeout.or(self.read_timeout);
+        secure(self.tls.as_ref(), &self.host, timeout, tcp).await
+    }
+
+    /// 数据连接关闭后，服务器在控制连接上确认传输是否完整
+    async fn finish_transfer(&mut self) -> Result<(), DispatchError> {
+        match self.reply().await? {
+            (226 | 250, _) => Ok(()),
+            (_, message) => Err(DispatchError::Ftp(format!(
+                "the transfer was not completed: {}",
+                message
+            ))),
+        }
+    }
+
+    /// 结束会话。文件已经下载完成，QUIT 失败不影响结果
+    async fn quit(mut self) {
+        if let Err(e) = self.send("QUIT").await {
+            log::debug!("FTP QUIT 失败: {}", e);
+        }
+    }
+}
+
+/// 建立 TCP 连接，超过 `timeout` 仍未连上时返回 [`std::io::ErrorKind::TimedOut`]
+async fn connect_tcp(
+    addr: impl ToSocketAddrs,
+    timeout: Option<Duration>,
+) -> std::io::Result<TcpStream> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, TcpStream::connect(addr))
+            .await
+            .unwrap_or_else(|_| {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    format!("connection timed out after {:?}", timeout),
+                ))
+            }),
+        None => TcpStream::connect(addr).await,
+    }
+}
+
+/// 有 `tls` 时在 `tcp` 上完成 TLS 握手，否则原样返回。握手超过 `timeout`
+/// (连接超时，没有设置时为读取超时) 时报错，服务器迟迟不开始握手时不会一直等待
+async fn secure(
+    tls: Option<&TlsConnector>,
+    host: &str,
+    timeout: Option<Duration>,
+    tcp: TcpStream,
+) -> Result<Box<dyn FtpStream>, DispatchError> {
+    let Some(connector) = tls else {
+        return Ok(Box::new(tcp));
+    };
+    let handshake = connector.connect(host, tcp);
+    let stream = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, handshake)
+            .await
+            .map_err(|_| {
+                DispatchError::Ftp(format!("TLS handshake timed out after {:?}", timeout))
+            })?,
+        None => handshake.await,
+    }
+    .map_err(tls_error)?;
+    Ok(Box::new(stream))
+}
+
+fn connection_error(e: std::io::Error) -> DispatchError {
+    DispatchError::Ftp(format!("connection error: {}", e))
+}
+
+fn tls_error(e: native_tls::Error) -> DispatchError {
+    DispatchError::Ftp(format!("TLS error: {}", e))
+}
+
+fn rejected(command: &str, message: &str) -> DispatchError {
+    DispatchError::Ftp(format!("{} was rejected: {}", command, message))
+}
+
+/// 解析 `229 Entering Extended Passive Mode (|||6446|)` 中的端口
+fn parse_epsv(message: &str) -> Option<u16> {
+    let start = message.find('(')?;
+    let end = message[start..].find(')')? + start;
+    message[start + 1..end].split('|').nth(3)?.parse().ok()
+}
+
+/// 解析 `227 Entering Passive Mode (h1,h2,h3,h4,p1,p2)` 中的端口，地址部分被忽略
+fn parse_pasv(message: &str) -> Option<u16> {
+    let numbers: Vec<u32> = message
+        .get(4..)?
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|n| n.parse().ok())
+        .collect();
+    match numbers[..] {
+        [.., p1, p2] if numbers.len() >= 6 => u16::try_from(p1 * 256 + p2).ok(),
+        _ => None,
+    }
+}
+
+/// `ftp://` 和 `ftps://` 地址的下载后端
+pub(crate) struct FtpDownloader;
+
+impl Downloader for FtpDownloader {
+    fn probe<'a>(&'a self, url: &'a str, options: &'a HttpOptions) -> BackendFuture<'a, Probe> {
+        Box::pin(probe(url, options))
+    }
+
+    /// 下载时总是重新登录。调用方已有探测结果时直接使用其中的大小，不再发送 SIZE
+    fn download<'a>(
+        &'a self,
+        url: &'a str,
+        probe: Option<Probe>,
+        path: &'a Path,
+        options: &'a HttpOptions,
+    ) -> BackendFuture<'a, DownloadSummary> {
+        Box::pin(download(url, probe, path, options))
+    }
+}
+
+/// 登录并查询文件大小，得到与 HTTP 探测相同形式的结果。
+/// FTP 下载不能分块，`supports_range` 和 `supports_resume` 总是为 `false` (续传通过 `REST` 进行)
+async fn probe(url: &str, options: &HttpOptions) -> Result<Probe, DispatchError> {
+    let target = Target::parse(url)?;
+    let mut control = Control::connect(&target, options).await?;
+    let size = control.size(&target.path).await?;
+    control.quit().await;
+    Ok(Probe {
+        resolved_url: url.to_string(),
+        size,
+        supports_range: false,
+        supports_resume: false,
+        content_length: None,
+        content_range: None,
+        etag: None,
+        last_modified: None,
+        content_type: None,
+        filename: target.filename(),
+    })
+}
+
+/// 下载 FTP 文件到 `path`。已有 .part 文件时发送 `REST` 从其末尾继续，
+/// 服务器拒绝或文件在上次下载之后被修改过时从头下载
+async fn download(
+    url: &str,
+    probe: Option<Probe>,
+    path: &Path,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DispatchError> {
+    let target = Target::parse(url)?;
+    if options.byte_range.is_some() {
+        return Err(DispatchError::Ftp(
+            "downloading a byte range is not supported over FTP".into(),
+        ));
+    }
+    status!(
+        options,
+        "连接 FTP 服务器 {}:{} ...",
+        target.host,
+        target.port
+    );
+    let mut control = Control::connect(&target, options).await?;
+    let size = match probe {
+        Some(probe) => probe.size,
+        None => control.size(&target.path).await?,
+    };
+    if let Some(on_event) = &options.on_event {
+        on_event.emit(&DownloadEvent::Probed {
+            resolved_url: url.to_string(),
+            size,
+            supports_range: false,
+            supports_resume: false,
+        });
+    }
+    if let (Some(size), Some(limit)) = (size, options.max_size)
+        && size > limit
+    {
+        return Err(DownloadError::TooLarge { size, limit }.into());
+    }
+
+    let part_path = resolve_part_path(path, options);
+    let part_len = std::fs::metadata(&part_path).map_or(0, |m| m.len());
+    let mut offset = match options.resume {
+        ResumeMode::Restart => 0,
+        _ => part_len,
+    };
+    if size.is_some_and(|size| offset > size) {
+        status!(options, "已下载的部分比服务器上的文件大，从头下载。");
+        offset = 0;
+    }
+    // 服务器上的文件晚于 .part 文件被修改时，续传会把新旧两个版本拼在一起。
+    // 比较的是服务器和本机两边的时钟，偏差很大时可能误判；服务器不支持 MDTM 时只能依靠大小判断
+    if offset > 0
+        && let Some(modified) = control.modified(&target.path).await?
+        && std::fs::metadata(&part_path)
+            .and_then(|m| m.modified())
+            .is_ok_and(|part_modified| modified > part_modified)
+    {
+        if options.resume == ResumeMode::Require {
+            return Err(DownloadError::CannotResume(
+                "the file on the server was modified after the partial download".into(),
+            )
+            .into());
+        }
+        warning!(options, "服务器上的文件在上次下载之后被修改过，从头下载。");
+        offset = 0;
+    }
+    if offset == 0 && options.resume == ResumeMode::Require {
+        return Err(DownloadError::CannotResume("no partial download was found".into()).into());
+    }
+    if offset > 0 && size == Some(offset) {
+        // 上次已经收到全部数据，只是没有完成最后的校验和重命名
+        control.quit().await;
+        return Ok(download_from_reader(tokio::io::empty(), path, size, offset, options).await?);
+    }
+
+    // REST 必须紧挨着 RETR 发送，因此先建立数据连接
+    let data = control.open_data().await?;
+    if offset > 0 {
+        match control.send(&format!("REST {}", offset)).await? {
+            (350, _) => status!(options, "从第 {} 字节继续下载。", offset),
+            (_, message) if options.resume == ResumeMode::Require => {
+                return Err(DownloadError::CannotResume(format!(
+                    "the server rejected REST: {}",
+                    message
+                ))
+                .into());
+            }
+            (_, message) => {
+                warning!(options, "服务器不支持断点续传 ({})，从头下载。", message);
+                offset = 0;
+            }
+        }
+    }
+    control
+        .command(&format!("RETR {}", target.path), &[125, 150])
+        .await?;
+    let data = control.secure_data(data).await?;
+    // 大小已知时 receive_from_reader 会在数据不完整时报错并保留 .part 文件；
+    // 大小未知时只能依靠服务器在传输结束后的确认，因此确认之后才校验和重命名
+    let received = receive_from_reader(data, path, size, offset, options).await?;
+    control.finish_transfer().await?;
+    control.quit().await;
+    Ok(received.finish(options).await?)
+}
+
+/// 将 FTP 文件按顺序写入 `writer`，不做续传。与下载到文件时一样遵守限速、读取超时、取消和大小限制
+pub(crate) async fn download_to_writer<W: Write + ?Sized>(
+    url: &str,
+    writer: &mut W,
+    options: &HttpOptions,
+) -> Result<(), DispatchError> {
+    let target = Target::parse(url)?;
+    let mut control = Control::connect(&target, options).await?;
+    // 大小只用于进度显示和提前拒绝过大的文件，服务器不支持 SIZE 时照常下载
+    let size = control.size(&target.path).await?;
+    if let (Some(size), Some(limit)) = (size, options.max_size)
+        && size > limit
+    {
+        return Err(DownloadError::TooLarge { size, limit }.into());
+    }
+    let data = control.open_data().await?;
+    control
+        .command(&format!("RETR {}", target.path), &[125, 150])
+        .await?;
+    let data = control.secure_data(data).await?;
+    copy_from_reader(data, writer, size, options).await?;
+    control.finish_transfer().await?;
+    control.quit().await;
+    Ok(())
+}
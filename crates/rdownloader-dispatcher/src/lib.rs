@@ -1,10 +1,28 @@
-use rdownloader_http::{download_multipart, download_sequential};
-use reqwest::Client;
-use reqwest::header::{ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG};
+pub use rdownloader_http::{
+    CancellationToken, ChunkProgressCallback, ChunkReport, ChunkStatus, ConnectionLimit,
+    DownloadEvent, DownloadMode, DownloadSummary, EventCallback, HttpOptions, OverwritePolicy,
+    PauseHandle, ProgressBar, ProgressCallback, ResumeMode,
+};
+use rdownloader_http::{
+    DownloadError, copy_from_reader, download_multipart, download_range, download_sequential,
+    download_to_writer, resolve_state_path, unpack_gzip, unpacked_path,
+};
+use reqwest::header::{
+    ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE,
+    CONTENT_TYPE, ETAG, HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::{Client, StatusCode};
 // 修正导入路径，直接从 rdownloader_utils 导入
-use rdownloader_utils::parse_content_range;
-use std::path::Path;
-use std::time::Duration;
+use rdownloader_utils::{
+    content_encoding, content_range_len, file_url_path, http_date, is_retryable_status,
+    parse_content_disposition, parse_content_range, retry_after, retry_delay, sanitize_filename,
+    target_headers,
+};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub enum DispatchError {
@@ -14,6 +32,67 @@ pub enum DispatchError {
     UnsupportedProtocol(String),
     BuildError(reqwest::Error),
     DownloadFailed(String),
+    FileExists(PathBuf), // 最终文件已经存在，且覆盖策略为 OverwritePolicy::Error
+    Ftp(String),         // FTP 服务器拒绝了命令、返回了无法识别的响应或控制连接中断
+    Backend(Box<dyn std::error::Error + Send + Sync>), // 自定义的下载后端 (见 Downloader) 返回的错误
+}
+
+impl fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DispatchError::Http(e) => write!(f, "{}", e),
+            DispatchError::Network(e) => write!(f, "network error while probing URL: {}", e),
+            DispatchError::HttpError(status) => write!(f, "HTTP {} while probing URL", status),
+            DispatchError::UnsupportedProtocol(url) => write!(
+                f,
+                "unsupported protocol in '{}': only http://, https://, ftp://, ftps:// and file:// URLs are supported",
+                url
+            ),
+            DispatchError::BuildError(e) => write!(f, "could not build the HTTP request: {}", e),
+            DispatchError::DownloadFailed(msg) => write!(f, "download failed: {}", msg),
+            DispatchError::FileExists(path) => write!(
+                f,
+                "'{}' already exists; pass --overwrite to replace it or --no-clobber to keep it",
+                path.display()
+            ),
+            DispatchError::Ftp(msg) => write!(f, "FTP error: {}", msg),
+            DispatchError::Backend(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl DispatchError {
+    /// 下载是否因调用方触发取消令牌而中止
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, DispatchError::Http(DownloadError::Cancelled))
+    }
+
+    /// 错误类别的简短标识，下载阶段的错误沿用 [`DownloadError::kind`]
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DispatchError::Http(e) => e.kind(),
+            DispatchError::Network(_) => "network",
+            DispatchError::HttpError(_) => "http_status",
+            DispatchError::UnsupportedProtocol(_) => "unsupported_protocol",
+            DispatchError::BuildError(_) => "request",
+            DispatchError::DownloadFailed(_) => "download_failed",
+            DispatchError::FileExists(_) => "file_exists",
+            DispatchError::Ftp(_) => "ftp",
+            DispatchError::Backend(_) => "backend",
+        }
+    }
+}
+
+impl std::error::Error for DispatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DispatchError::Http(e) => e.source(),
+            DispatchError::Network(e) => Some(e),
+            DispatchError::BuildError(e) => Some(e),
+            DispatchError::Backend(e) => e.source(),
+            _ => None,
+        }
+    }
 }
 
 impl From<rdownloader_http::DownloadError> for DispatchError {
@@ -31,106 +110,1047 @@ impl From<reqwest::Error> for DispatchError {
     }
 }
 
-// --- 可配置参数 ---
-const MIN_SIZE_FOR_MULTIPART: u64 = 1 * 1024 * 1024; // 1MB
-const PROBE_MAX_RETRIES: u32 = 3;
-const PROBE_INITIAL_BACKOFF_SECS: u64 = 1;
+/// 以 `info` 级别记录状态信息，有事件回调时同时作为 [`DownloadEvent::Status`] 上报。
+/// 库本身不向终端打印状态，如何显示由调用方决定
+macro_rules! status {
+    ($options:expr, $($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        log::info!("{}", message);
+        if let Some(on_event) = &$options.on_event {
+            on_event.emit(&DownloadEvent::Status(message));
+        }
+    }};
+}
 
-pub async fn dispatch(client: &Client, url: &str, path: &Path) -> Result<(), DispatchError> {
-    if !url.starts_with("http://") && !url.starts_with("https://") {
-        return Err(DispatchError::UnsupportedProtocol(url.to_string()));
+/// 以 `warn` 级别记录需要用户注意的情况，有事件回调时同时作为 [`DownloadEvent::Warning`] 上报
+macro_rules! warning {
+    ($options:expr, $($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        log::warn!("{}", message);
+        if let Some(on_event) = &$options.on_event {
+            on_event.emit(&DownloadEvent::Warning(message));
+        }
+    }};
+}
+
+mod ftp;
+
+/// [`Downloader`] 的方法返回的 future
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, DispatchError>> + Send + 'a>>;
+
+/// 一类地址 (协议) 的下载后端。
+///
+/// [`dispatch_probed`] 通过 [`downloader_for`] 按地址的协议选择后端，处理完覆盖策略等与协议无关的部分后，
+/// 再交给后端探测和下载。实现这个 trait 并调用 [`dispatch_with`] 即可接入新的协议，
+/// 或者在测试中替换掉网络访问。
+pub trait Downloader: Send + Sync {
+    /// 探测 `url`，得到文件大小、是否支持分块下载和服务器建议的文件名等信息
+    fn probe<'a>(&'a self, url: &'a str, options: &'a HttpOptions) -> BackendFuture<'a, Probe>;
+
+    /// 将 `url` 下载到 `path`。`probe` 是调用方已有的探测结果，为 `None` 时由后端自行探测。
+    /// 调用前已经按 [`HttpOptions::overwrite`] 处理过已存在的最终文件
+    fn download<'a>(
+        &'a self,
+        url: &'a str,
+        probe: Option<Probe>,
+        path: &'a Path,
+        options: &'a HttpOptions,
+    ) -> BackendFuture<'a, DownloadSummary>;
+
+    /// 是否支持条件下载 ([`HttpOptions::if_newer`] 和 [`HttpOptions::if_none_match`])。
+    /// 支持时，最终文件已存在的条件下载由后端决定是否跳过，[`OverwritePolicy::Error`] 不会报错
+    fn supports_conditions(&self) -> bool {
+        false
     }
+}
 
-    let mut last_error: Option<DispatchError> = None;
+/// HTTP 和 HTTPS 地址的下载后端：按探测结果选择多线程或单线程下载，支持镜像和条件下载
+#[derive(Debug, Clone)]
+pub struct HttpDownloader {
+    client: Client,
+}
 
-    // --- 探测重试循环 (实现了指数退避) ---
-    // 考虑到 CDN 等网络环境可能返回临时性错误，我们在此处加入重试逻辑以提高稳定性。
-    for attempt in 1..=PROBE_MAX_RETRIES {
-        println!("发送探测请求 (尝试 {}/{}) ...", attempt, PROBE_MAX_RETRIES);
-        let probe_res = client.get(url).header("Range", "bytes=0-1").send().await?;
-
-        // 如果请求成功 (2xx) 或作为部分内容响应 (206)，则认为探测成功
-        if probe_res.status().is_success() || probe_res.status() == 206 {
-            let headers = probe_res.headers();
-            // 提取 ETag 用于后续的文件一致性校验
-            let etag = headers
-                .get(ETAG)
-                .and_then(|v| v.to_str().ok())
-                .map(|s| s.to_string());
-            // 提取 Content-Type 用于后续数据块的内容校验，防止静默的 HTML 错误页面
-            let content_type = headers
-                .get(CONTENT_TYPE)
-                .and_then(|v| v.to_str().ok())
-                .map(|s| s.to_string());
-
-            // 优先通过 Content-Range 判断，这是最可靠的方式
-            if let Some(range_str) = headers.get(CONTENT_RANGE).and_then(|v| v.to_str().ok()) {
-                if let Some(size) = parse_content_range(range_str) {
-                    if size > MIN_SIZE_FOR_MULTIPART {
-                        println!("探测成功 (Content-Range): 文件较大，启动多线程模式。");
-                        return Ok(
-                            download_multipart(client, url, path, size, etag, content_type).await?,
-                        );
-                    } else {
-                        println!("将使用单线程模式 (文件较小)。");
-                        return Ok(download_sequential(
-                            client,
-                            url,
-                            path,
-                            Some(size),
-                            etag,
-                            content_type,
-                        )
-                        .await?);
+impl HttpDownloader {
+    pub fn new(client: Client) -> Self {
+        HttpDownloader { client }
+    }
+}
+
+impl Downloader for HttpDownloader {
+    fn probe<'a>(&'a self, url: &'a str, options: &'a HttpOptions) -> BackendFuture<'a, Probe> {
+        Box::pin(probe(&self.client, url, url, options))
+    }
+
+    fn download<'a>(
+        &'a self,
+        url: &'a str,
+        probe: Option<Probe>,
+        path: &'a Path,
+        options: &'a HttpOptions,
+    ) -> BackendFuture<'a, DownloadSummary> {
+        Box::pin(async move {
+            let conditions = conditional_headers(path, options)?;
+            let probe = if conditions.is_empty() {
+                probe
+            } else {
+                // 调用方之前的探测没有附带条件请求头，需要带上条件重新探测一次
+                status!(options, "本地文件已存在，向服务器确认文件是否有更新...");
+                match probe_with(&self.client, url, url, &conditions, options).await {
+                    Ok(probe) => Some(probe),
+                    Err(DispatchError::HttpError(StatusCode::NOT_MODIFIED)) => {
+                        status!(options, "服务器上的文件没有变化，跳过下载。");
+                        return skipped(path);
                     }
+                    Err(e) if !e.is_cancelled() && !options.mirrors.is_empty() => None,
+                    Err(e) => return Err(e),
                 }
+            };
+            download_restarting(&self.client, url, path, probe, options).await
+        })
+    }
+
+    fn supports_conditions(&self) -> bool {
+        true
+    }
+}
+
+/// `file://` 地址的下载后端。探测结果来自文件的元数据，数据块从本地文件读取，
+/// 分块、续传和进度与 HTTP 下载共用同一套实现
+struct FileDownloader {
+    client: Client,
+}
+
+impl Downloader for FileDownloader {
+    fn probe<'a>(&'a self, url: &'a str, _options: &'a HttpOptions) -> BackendFuture<'a, Probe> {
+        Box::pin(async move {
+            let source = file_url_path(url)
+                .ok_or_else(|| DispatchError::UnsupportedProtocol(url.to_string()))?;
+            Probe::from_local(url, &source)
+        })
+    }
+
+    fn download<'a>(
+        &'a self,
+        url: &'a str,
+        probe: Option<Probe>,
+        path: &'a Path,
+        options: &'a HttpOptions,
+    ) -> BackendFuture<'a, DownloadSummary> {
+        Box::pin(download_restarting(&self.client, url, path, probe, options))
+    }
+}
+
+/// 按地址的协议选择下载后端：支持 `http://`、`https://`、`ftp://`、`ftps://` 和 `file://`，
+/// 其他协议返回 [`DispatchError::UnsupportedProtocol`]
+pub fn downloader_for(client: &Client, url: &str) -> Result<Box<dyn Downloader>, DispatchError> {
+    if file_url_path(url).is_some() {
+        Ok(Box::new(FileDownloader {
+            client: client.clone(),
+        }))
+    } else if ftp::is_ftp(url) {
+        Ok(Box::new(ftp::FtpDownloader))
+    } else if url.starts_with("http://") || url.starts_with("https://") {
+        Ok(Box::new(HttpDownloader::new(client.clone())))
+    } else {
+        Err(DispatchError::UnsupportedProtocol(url.to_string()))
+    }
+}
+
+pub async fn dispatch(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DispatchError> {
+    dispatch_probed(client, url, path, None, options).await
+}
+
+/// 与 [`dispatch`] 相同，但可以传入调用方已经通过 [`probe_url`] 得到的探测结果，
+/// 避免在解析文件名之后再重复发送一次探测请求。
+pub async fn dispatch_probed(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    probe: Option<Probe>,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DispatchError> {
+    let downloader = downloader_for(client, url)?;
+    dispatch_with(downloader.as_ref(), url, path, probe, options).await
+}
+
+/// 与 [`dispatch_probed`] 相同，但使用指定的下载后端，而不是按地址的协议选择
+pub async fn dispatch_with(
+    downloader: &dyn Downloader,
+    url: &str,
+    path: &Path,
+    probe: Option<Probe>,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DispatchError> {
+    let started = Instant::now();
+    let conditional =
+        downloader.supports_conditions() && (options.if_newer || options.if_none_match.is_some());
+    // 在发出任何网络请求之前检查最终文件。未完成的下载只有 .part 和状态文件，不受影响。
+    // 条件下载时由服务器决定是否需要重新下载，文件有变化时直接覆盖
+    let existing = path.is_file();
+    if existing {
+        match options.overwrite {
+            OverwritePolicy::Error if !conditional => {
+                return Err(DispatchError::FileExists(path.to_path_buf()));
+            }
+            OverwritePolicy::Overwrite => {
+                status!(
+                    options,
+                    "文件 {} 已存在，将重新下载并覆盖。",
+                    path.display()
+                );
+            }
+            _ => {}
+        }
+    }
+    // 解压得到的文件同样遵守覆盖策略，已经存在时不必再下载压缩文件
+    let unpacked = options
+        .unpack
+        .then(|| unpacked_path(path))
+        .flatten()
+        .filter(|target| target.is_file());
+    if let Some(target) = &unpacked
+        && options.overwrite == OverwritePolicy::Error
+        && !conditional
+    {
+        return Err(DispatchError::FileExists(target.clone()));
+    }
+    let mut summary = match unpacked {
+        Some(target) if options.overwrite == OverwritePolicy::Skip => {
+            status!(options, "文件 {} 已存在，跳过下载。", target.display());
+            skipped(&target)?
+        }
+        _ if existing && options.overwrite == OverwritePolicy::Skip => {
+            status!(options, "文件 {} 已存在，跳过下载。", path.display());
+            skipped(path)?
+        }
+        _ => {
+            let mut summary = downloader.download(url, probe, path, options).await?;
+            if options.unpack && !summary.skipped {
+                unpack_download(&mut summary, options).await?;
+            }
+            summary
+        }
+    };
+    // 总用时包括探测和回退重试
+    summary.elapsed = started.elapsed();
+    emit_finished(&summary, options);
+    Ok(summary)
+}
+
+/// 按 [`HttpOptions::unpack`] 解压下载完成的文件，成功后 `summary.path` 指向解压得到的文件。
+/// 不是支持解压的格式时保持原样
+async fn unpack_download(
+    summary: &mut DownloadSummary,
+    options: &HttpOptions,
+) -> Result<(), DispatchError> {
+    let Some(target) = unpacked_path(&summary.path) else {
+        warning!(
+            options,
+            "{} 不是支持解压的格式 (目前支持 .gz 和 .tgz)，保持原样。",
+            summary.path.display()
+        );
+        return Ok(());
+    };
+    status!(
+        options,
+        "解压 {} 到 {} ...",
+        summary.path.display(),
+        target.display()
+    );
+    unpack_gzip(&summary.path, &target).await?;
+    if options.remove_compressed {
+        std::fs::remove_file(&summary.path).map_err(DownloadError::from)?;
+    }
+    summary.path = target;
+    Ok(())
+}
+
+/// 探测并下载。文件在下载过程中被修改时，旧的探测结果 (大小、ETag) 已经失效，需要重新探测一次。
+/// 重新探测后总大小仍与数据块响应不一致 (例如动态生成的文件) 时不再重试，直接返回错误
+async fn download_restarting(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    probe: Option<Probe>,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DispatchError> {
+    match probe_and_download(client, url, path, probe, options).await {
+        Err(DispatchError::Http(
+            e @ (DownloadError::ResourceChanged | DownloadError::TotalSizeChanged { .. }),
+        )) => {
+            status!(options, "服务器上的文件已发生变化，重新探测并从头下载。");
+            let mut summary = probe_and_download(client, url, path, None, options).await?;
+            summary.restart_reason.get_or_insert_with(|| e.to_string());
+            Ok(summary)
+        }
+        result => result,
+    }
+}
+
+/// 保留已有文件、不做下载时的结果
+fn skipped(path: &Path) -> Result<DownloadSummary, DispatchError> {
+    Ok(DownloadSummary {
+        path: path.to_path_buf(),
+        total_size: std::fs::metadata(path).map_err(DownloadError::from)?.len(),
+        bytes_downloaded: 0,
+        resumed: false,
+        multipart: false,
+        skipped: true,
+        elapsed: Duration::ZERO,
+        peak_speed: 0,
+        chunks: 0,
+        retries: 0,
+        restart_reason: None,
+    })
+}
+
+/// 条件下载的请求头。最终文件不存在或没有设置条件时返回空的 [`HeaderMap`]
+fn conditional_headers(path: &Path, options: &HttpOptions) -> Result<HeaderMap, DispatchError> {
+    let mut headers = HeaderMap::new();
+    if !path.is_file() {
+        return Ok(headers);
+    }
+    if let Some(etag) = &options.if_none_match {
+        let value = HeaderValue::from_str(etag).map_err(|_| {
+            DownloadError::InvalidOption(format!("invalid ETag for If-None-Match: {:?}", etag))
+        })?;
+        headers.insert(IF_NONE_MATCH, value);
+    }
+    if options.if_newer {
+        let modified = std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(DownloadError::from)?;
+        // HTTP 日期只包含 ASCII 字符，总能作为请求头
+        headers.insert(
+            IF_MODIFIED_SINCE,
+            HeaderValue::from_str(&http_date(modified)).unwrap(),
+        );
+    }
+    Ok(headers)
+}
+
+fn emit_finished(summary: &DownloadSummary, options: &HttpOptions) {
+    if let Some(on_event) = &options.on_event {
+        on_event.emit(&DownloadEvent::Finished(summary.clone()));
+    }
+}
+
+/// 将下载内容写入任意 [`Write`] (例如标准输出)。
+///
+/// 输出不是普通文件时无法随机写入，因此不做探测，直接以单线程流式下载，也不支持续传。
+pub async fn dispatch_to_writer<W: Write + ?Sized>(
+    client: &Client,
+    url: &str,
+    writer: &mut W,
+    options: &HttpOptions,
+) -> Result<(), DispatchError> {
+    check_protocol(url)?;
+    if options.byte_range.is_some() {
+        return Err(DispatchError::DownloadFailed(
+            "a byte range can only be downloaded to a file".into(),
+        ));
+    }
+    if let Some(source) = file_url_path(url) {
+        // 与下载到文件时一样逐段异步读取，限速、进度、超时和取消照常生效
+        let file = tokio::fs::File::open(source)
+            .await
+            .map_err(DownloadError::from)?;
+        let len = file.metadata().await.map_err(DownloadError::from)?.len();
+        return Ok(copy_from_reader(file, writer, Some(len), options).await?);
+    }
+    if ftp::is_ftp(url) {
+        return ftp::download_to_writer(url, writer, options).await;
+    }
+    Ok(download_to_writer(client, url, writer, options).await?)
+}
+
+/// 探测响应中与下载方式相关的信息
+#[derive(Debug, Clone)]
+pub struct Probe {
+    /// 跟随重定向之后的最终地址
+    pub resolved_url: String,
+    /// 从 Content-Range 或 Content-Length 得到的文件总大小，无法确定时为 `None`
+    pub size: Option<u64>,
+    /// 服务器是否声明支持 Range 请求 (`Accept-Ranges: bytes` 或返回了 Content-Range)
+    pub supports_range: bool,
+    /// 是否确认可以续传和分块下载：带 Range 的探测请求得到了 `206` 响应和有效的 Content-Range。
+    /// 有些服务器声明支持 Range，却总是返回 `200` 和完整内容，这时只有 `supports_range` 为 `true`。
+    /// 调度器只根据这个字段选择下载方式
+    pub supports_resume: bool,
+    /// 探测响应中原样的 Content-Length 和 Content-Range，用于排查两者不一致的情况 (见 [`Probe::length_mismatch`])
+    pub content_length: Option<u64>,
+    pub content_range: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_type: Option<String>,
+    /// 从 Content-Disposition 得到的文件名 (已清理，可以直接拼接到输出目录上)
+    pub filename: Option<String>,
+}
+
+impl Probe {
+    /// `file://` 地址的探测结果直接来自文件的元数据。本地文件总是可以按任意范围读取，
+    /// 修改时间作为 Last-Modified 记入状态文件，源文件在两次运行之间被修改时不会续传
+    fn from_local(url: &str, source: &Path) -> Result<Self, DispatchError> {
+        let metadata = std::fs::metadata(source).map_err(DownloadError::from)?;
+        if !metadata.is_file() {
+            return Err(DispatchError::DownloadFailed(format!(
+                "'{}' is not a regular file",
+                source.display()
+            )));
+        }
+        Ok(Probe {
+            resolved_url: url.to_string(),
+            size: Some(metadata.len()),
+            supports_range: true,
+            supports_resume: true,
+            content_length: None,
+            content_range: None,
+            etag: None,
+            last_modified: metadata.modified().ok().map(http_date),
+            content_type: None,
+            filename: source
+                .file_name()
+                .map(|name| sanitize_filename(&name.to_string_lossy())),
+        })
+    }
+
+    fn from_response(res: &reqwest::Response) -> Self {
+        let headers = res.headers();
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string())
+        };
+        // 优先通过 Content-Range 判断，这是最可靠的方式；返回了 Content-Range 说明服务器支持 Range 请求
+        let content_range_size = header(CONTENT_RANGE).and_then(|v| parse_content_range(&v));
+        // 如果 Content-Range 不可用，则回退到 Content-Length + Accept-Ranges 的组合。
+        // 206 响应的 Content-Length 只是本次片段的长度 (例如总大小未知的 `bytes 0-1/*`)，不能当作文件大小。
+        // 响应经过内容编码时，Content-Range 和 Content-Length 都不可信，只能流式下载
+        let (size, supports_range) = match content_range_size {
+            _ if content_encoding(headers).is_some() => (None, false),
+            Some(size) => (Some(size), true),
+            None if res.status() != StatusCode::PARTIAL_CONTENT => (
+                header(CONTENT_LENGTH).and_then(|v| v.parse::<u64>().ok()),
+                headers.get(ACCEPT_RANGES).is_some_and(|v| v == "bytes"),
+            ),
+            None => (None, false),
+        };
+        // 只有 Range 请求才会得到 206，单凭 Accept-Ranges 不能确认服务器真的会按范围返回数据
+        let supports_resume = supports_range
+            && content_range_size.is_some()
+            && res.status() == StatusCode::PARTIAL_CONTENT;
+        let mut probe = Probe {
+            // reqwest 会自动跟随重定向，数据块请求需要发往最终地址，
+            // 否则短期有效的重定向目标 (如预签名地址) 可能在下载过程中指向不同的资源
+            resolved_url: res.url().to_string(),
+            size,
+            supports_range,
+            supports_resume,
+            content_length: header(CONTENT_LENGTH).and_then(|v| v.parse::<u64>().ok()),
+            content_range: header(CONTENT_RANGE),
+            // 提取 ETag 用于后续的文件一致性校验
+            etag: header(ETAG),
+            // 没有 ETag 的服务器通常会提供 Last-Modified，作为续传时的备用校验依据
+            last_modified: header(LAST_MODIFIED),
+            // 提取 Content-Type 用于后续数据块的内容校验，防止静默的 HTML 错误页面
+            content_type: header(CONTENT_TYPE),
+            // Content-Disposition 来自服务器，不可信，必须清理后才能使用
+            filename: headers
+                .get(CONTENT_DISPOSITION)
+                .and_then(|v| parse_content_disposition(&String::from_utf8_lossy(v.as_bytes())))
+                .map(|name| sanitize_filename(&name)),
+        };
+        // 有些代理会改写其中一个响应头。大小仍以 Content-Range 为准，但差距较大时
+        // 说明按范围返回的数据不可信，不使用多线程和续传
+        if probe.has_large_length_mismatch() {
+            probe.supports_resume = false;
+        }
+        probe
+    }
+
+    /// Content-Length 与 Content-Range 给出的范围长度不一致时，返回两者相差的字节数。
+    /// 无法确定文件大小时 (包括响应经过内容编码、Content-Length 是编码后的长度) 不做比较
+    pub fn length_mismatch(&self) -> Option<u64> {
+        self.size?;
+        let expected = content_range_len(self.content_range.as_deref()?)?;
+        let actual = self.content_length?;
+        (expected != actual).then(|| expected.abs_diff(actual))
+    }
+
+    /// 不一致的程度超过文件大小的 1%
+    fn has_large_length_mismatch(&self) -> bool {
+        self.length_mismatch()
+            .is_some_and(|diff| diff.saturating_mul(100) > self.size.unwrap_or(0))
+    }
+}
+
+/// 根据探测结果和选项决定的下载方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferMode {
+    /// 多线程分块下载
+    Multipart,
+    /// 单线程下载，大小已知，可以续传
+    Sequential,
+    /// 大小未知，单线程流式下载
+    Stream,
+}
+
+impl TransferMode {
+    /// 简短的英文标识，便于脚本处理 (例如 `--json` 输出)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransferMode::Multipart => "multipart",
+            TransferMode::Sequential => "sequential",
+            TransferMode::Stream => "stream",
+        }
+    }
+}
+
+impl Probe {
+    /// 按 `options` 下载时会使用的方式，与 [`dispatch_probed`] 的选择一致。
+    /// 下载过程中发现服务器实际上忽略了 Range 请求时，调度器仍可能回退到单线程模式
+    pub fn transfer_mode(&self, options: &HttpOptions) -> TransferMode {
+        match self.size {
+            None => TransferMode::Stream,
+            Some(size) if sequential_reason(size, self.supports_resume, options).is_none() => {
+                TransferMode::Multipart
             }
+            Some(_) => TransferMode::Sequential,
+        }
+    }
+}
+
+/// 探测主地址 (带重试)，得到文件大小、Range 支持、校验信息和服务器建议的文件名。
+///
+/// 结果可以交给 [`dispatch_probed`]，使文件名解析和下载共用同一次探测请求。
+pub async fn probe_url(
+    client: &Client,
+    url: &str,
+    options: &HttpOptions,
+) -> Result<Probe, DispatchError> {
+    downloader_for(client, url)?.probe(url, options).await
+}
+
+async fn probe(
+    client: &Client,
+    url: &str,
+    target: &str,
+    options: &HttpOptions,
+) -> Result<Probe, DispatchError> {
+    probe_with(client, url, target, &HeaderMap::new(), options).await
+}
+
+fn check_protocol(url: &str) -> Result<(), DispatchError> {
+    if !url.starts_with("http://")
+        && !url.starts_with("https://")
+        && !ftp::is_ftp(url)
+        && file_url_path(url).is_none()
+    {
+        return Err(DispatchError::UnsupportedProtocol(url.to_string()));
+    }
+    Ok(())
+}
 
-            // 如果 Content-Range 不可用，则回退到 Content-Length + Accept-Ranges 的组合
-            if let Some(size_str) = headers.get(CONTENT_LENGTH).and_then(|v| v.to_str().ok()) {
-                if let Ok(size) = size_str.parse::<u64>() {
-                    if headers.get(ACCEPT_RANGES).map_or(false, |v| v == "bytes")
-                        && size > MIN_SIZE_FOR_MULTIPART
-                    {
-                        println!(
-                            "探测成功 (Content-Length): 文件较大且服务器支持并发，启动多线程模式。"
-                        );
-                        return Ok(
-                            download_multipart(client, url, path, size, etag, content_type).await?,
-                        );
-                    } else {
-                        println!("将使用单线程模式 (服务器不支持并发或文件较小)。");
-                        return Ok(download_sequential(
-                            client,
-                            url,
-                            path,
-                            Some(size),
-                            etag,
-                            content_type,
-                        )
-                        .await?);
+async fn probe_and_download(
+    client: &Client,
+    url: &str,
+    path: &Path,
+    probe_result: Option<Probe>,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DispatchError> {
+    let candidates: Vec<&str> = std::iter::once(url)
+        .chain(options.mirrors.iter().map(String::as_str))
+        .collect();
+    for candidate in &candidates {
+        check_protocol(candidate)?;
+        if ftp::is_ftp(candidate) {
+            // 镜像需要按范围请求数据块，FTP 只能整个文件顺序下载
+            return Err(DispatchError::Ftp(format!(
+                "'{}' cannot be used as a mirror",
+                candidate
+            )));
+        }
+    }
+
+    // 依次探测主地址和各个镜像，使用第一个探测成功的地址。调用方已经探测过主地址时直接使用其结果
+    let mut last_error = None;
+    let mut probed = probe_result.map(|probe| (0, probe));
+    if probed.is_none() {
+        for (i, candidate) in candidates.iter().enumerate() {
+            match probe(client, url, candidate, options).await {
+                Ok(probe) => {
+                    probed = Some((i, probe));
+                    break;
+                }
+                Err(e) if e.is_cancelled() => return Err(e),
+                Err(e) => {
+                    if i + 1 < candidates.len() {
+                        warning!(options, "探测 {} 失败: {}，尝试下一个镜像。", candidate, e);
                     }
+                    last_error = Some(e);
                 }
             }
+        }
+    }
+    let Some((chosen, probe)) = probed else {
+        // 如果所有重试都失败了，返回最后一次遇到的错误
+        return Err(last_error
+            .unwrap_or_else(|| DispatchError::DownloadFailed("all probe attempts failed".into())));
+    };
+    if let Some(on_event) = &options.on_event {
+        on_event.emit(&DownloadEvent::Probed {
+            resolved_url: probe.resolved_url.clone(),
+            size: probe.size,
+            supports_range: probe.supports_range,
+            supports_resume: probe.supports_resume,
+        });
+    }
+    match probe.length_mismatch() {
+        Some(_) if probe.has_large_length_mismatch() => warning!(
+            options,
+            "探测响应的 Content-Length ({}) 与 Content-Range ({}) 相差较大，以 Content-Range 给出的大小为准，改用单线程模式。",
+            probe.content_length.unwrap_or_default(),
+            probe.content_range.as_deref().unwrap_or_default()
+        ),
+        Some(_) => warning!(
+            options,
+            "探测响应的 Content-Length ({}) 与 Content-Range ({}) 不一致，以 Content-Range 为准。",
+            probe.content_length.unwrap_or_default(),
+            probe.content_range.as_deref().unwrap_or_default()
+        ),
+        None if probe.supports_range && !probe.supports_resume => warning!(
+            options,
+            "服务器声明支持 Range 请求，但对探测请求返回了完整内容，将按不支持 Range 处理。"
+        ),
+        None => {}
+    }
 
-            // --- 降级处理 ---
-            // 如果以上所有方法都无法确定文件大小，则降级到不支持断点续传的单线程流式下载。
-            println!("警告: 无法从服务器响应头中确定文件总大小。");
-            return Ok(download_sequential(client, url, path, None, etag, content_type).await?);
-        } else {
-            // 如果服务器返回明确的错误，记录下来
-            last_error = Some(DispatchError::HttpError(probe_res.status()));
+    // 只下载一段时不需要选择下载方式，也不使用镜像
+    if let Some(range) = &options.byte_range {
+        if !probe.supports_resume {
+            return Err(DownloadError::RangeNotSupported.into());
+        }
+        status!(options, "只下载第 {}-{} 字节。", range.start(), range.end());
+        return Ok(download_range(
+            client,
+            url,
+            &probe.resolved_url,
+            path,
+            range.clone(),
+            probe.size,
+            probe.etag,
+            options,
+        )
+        .await?);
+    }
+
+    let Some(size) = probe.size else {
+        // --- 降级处理 ---
+        // 如果以上所有方法都无法确定文件大小，则降级到不支持断点续传的单线程流式下载。
+        warning!(options, "无法从服务器响应头中确定文件总大小。");
+        return Ok(download_sequential(
+            client,
+            url,
+            &probe.resolved_url,
+            path,
+            None,
+            probe.etag,
+            probe.last_modified,
+            probe.content_type,
+            options,
+        )
+        .await?);
+    };
+
+    if let Some(limit) = options.max_size
+        && size > limit
+    {
+        return Err(DownloadError::TooLarge { size, limit }.into());
+    }
+
+    // 其余地址作为数据块失败时的备用来源，只保留与所选地址一致的镜像
+    let mut options = options.clone();
+    options.mirrors = if probe.supports_resume {
+        let others: Vec<&str> = candidates
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != chosen)
+            .map(|(_, candidate)| *candidate)
+            .collect();
+        matching_mirrors(client, url, &others, size, &probe.etag, &options).await?
+    } else {
+        Vec::new()
+    };
+    let options = &options;
+
+    let sequential_reason = sequential_reason(size, probe.supports_resume, options);
+    if sequential_reason.is_none() {
+        status!(options, "探测成功: 服务器支持并发，启动多线程模式。");
+        download_multipart_with_fallback(
+            client,
+            url,
+            &probe.resolved_url,
+            path,
+            size,
+            probe.etag,
+            probe.last_modified,
+            probe.content_type,
+            options,
+        )
+        .await
+    } else {
+        if !probe.supports_resume
+            && !options.no_state
+            && resolve_state_path(path, url, options).exists()
+        {
+            // 之前的多线程下载进度依赖 Range 请求，无法继续使用
+            warning!(
+                options,
+                "检测到未完成的下载，但服务器已不再支持 Range 请求，将丢弃已下载的部分并从头开始。"
+            );
+        }
+        status!(
+            options,
+            "将使用单线程模式 ({})。",
+            sequential_reason.unwrap_or_default()
+        );
+        download_sequential_with_fallback(
+            client,
+            url,
+            &probe.resolved_url,
+            path,
+            size,
+            probe.etag,
+            probe.last_modified,
+            probe.content_type,
+            options,
+        )
+        .await
+    }
+}
+
+/// 探测请求依次尝试的 Range 形式。部分服务器拒绝 2 字节的范围，或者只接受特定形式的 Range，
+/// 这时改用单字节范围，最后不带 Range 直接请求 (只读取响应头，不读取响应体)
+const PROBE_RANGES: [Option<&str>; 3] = [Some("bytes=0-1"), Some("bytes=0-0"), None];
+
+/// 探测被拒绝时是否值得换一种 Range 形式再试。文件不存在或需要认证时，换一种形式也不会成功；
+/// 429 限流与 Range 无关，立即换一种形式再请求只会加重限流，交给重试循环按 Retry-After 等待
+fn retry_probe_without_range(status: StatusCode) -> bool {
+    status.is_client_error()
+        && !matches!(
+            status,
+            StatusCode::UNAUTHORIZED
+                | StatusCode::NOT_FOUND
+                | StatusCode::PROXY_AUTHENTICATION_REQUIRED
+                | StatusCode::GONE
+                | StatusCode::TOO_MANY_REQUESTS
+        )
+}
+
+/// 发送探测请求：先请求 `Range: bytes=0-1`，服务器以 4xx 拒绝时依次改用 [`PROBE_RANGES`] 中的其他形式，
+/// 返回第一个没有被拒绝的响应 (都被拒绝时返回最后一个)。
+/// `url` 是用户给出的主地址，发往其他来源的请求不携带认证信息。
+async fn send_probe(
+    client: &Client,
+    url: &str,
+    target: &str,
+    conditions: &HeaderMap,
+    options: &HttpOptions,
+) -> Result<reqwest::Response, DispatchError> {
+    let mut res =
+        send_probe_with_range(client, url, target, conditions, PROBE_RANGES[0], options).await?;
+    for range in &PROBE_RANGES[1..] {
+        if !retry_probe_without_range(res.status()) {
+            break;
         }
+        status!(
+            options,
+            "服务器拒绝了探测请求 (HTTP {})，改用 {} 重新探测。",
+            res.status(),
+            range.unwrap_or("不带 Range 的请求")
+        );
+        res = send_probe_with_range(client, url, target, conditions, *range, options).await?;
+    }
+    Ok(res)
+}
+
+/// 发送一次探测请求，`range` 为 `None` 时不带 Range 请求头。
+/// 探测请求同样需要响应取消令牌，否则在连接停滞时无法及时中止
+async fn send_probe_with_range(
+    client: &Client,
+    url: &str,
+    target: &str,
+    conditions: &HeaderMap,
+    range: Option<&str>,
+    options: &HttpOptions,
+) -> Result<reqwest::Response, DispatchError> {
+    let mut probe = client
+        .get(target)
+        .headers(target_headers(url, target, &options.headers))
+        .headers(conditions.clone())
+        // 与数据块请求一致，要求不做内容编码，否则响应头中的大小描述的是压缩后的数据
+        .header(ACCEPT_ENCODING, "identity");
+    if let Some(range) = range {
+        probe = probe.header("Range", range);
+    }
+    let probe = probe.send();
+    Ok(match &options.cancel {
+        Some(token) => tokio::select! {
+            _ = token.cancelled() => return Err(DownloadError::Cancelled.into()),
+            res = probe => res?,
+        },
+        None => probe.await?,
+    })
+}
+
+/// 带重试的探测请求，`conditions` 为附加的条件请求头。
+/// 服务器返回 `304 Not Modified` 时不重试，直接返回 [`DispatchError::HttpError`]
+async fn probe_with(
+    client: &Client,
+    url: &str,
+    target: &str,
+    conditions: &HeaderMap,
+    options: &HttpOptions,
+) -> Result<Probe, DispatchError> {
+    if let Some(source) = file_url_path(target) {
+        status!(options, "读取本地文件 {} 的信息...", source.display());
+        return Probe::from_local(target, &source);
+    }
+    let max_attempts = options.probe_retries.saturating_add(1);
+
+    // --- 探测重试循环 (实现了指数退避) ---
+    // 考虑到 CDN 等网络环境可能返回临时性错误 (非 2xx 响应、连接被重置、超时)，
+    // 我们在此处加入重试逻辑以提高稳定性。
+    let mut attempt = 1;
+    loop {
+        status!(
+            options,
+            "发送探测请求 (尝试 {}/{}) ...",
+            attempt,
+            max_attempts
+        );
+        let (error, retry_after) = match send_probe(client, url, target, conditions, options).await
+        {
+            // 如果请求成功 (2xx) 或作为部分内容响应 (206)，则认为探测成功
+            Ok(res) if res.status().is_success() => return Ok(Probe::from_response(&res)),
+            Ok(res) if res.status() == StatusCode::NOT_MODIFIED => {
+                return Err(DispatchError::HttpError(res.status()));
+            }
+            // 404 等明确的错误重试也不会成功，立即返回；暂时性的错误 (429、5xx 等) 记录下来稍后重试
+            Ok(res) if !options.retry_all_errors && !is_retryable_status(res.status()) => {
+                return Err(DispatchError::HttpError(res.status()));
+            }
+            Ok(res) => (
+                DispatchError::HttpError(res.status()),
+                retry_after(res.status(), res.headers()),
+            ),
+            // DNS 失败、连接被重置和超时同样可能是暂时的；
+            // 取消、无法构造的请求和超出重定向限制重试也不会有不同的结果
+            Err(DispatchError::Network(e)) if !e.is_redirect() => (DispatchError::Network(e), None),
+            Err(e) => return Err(e),
+        };
 
         // 如果还未到最大重试次数，则等待一段时间后重试
-        if attempt < PROBE_MAX_RETRIES {
-            // 指数退避： 1s, 2s, 4s, ...
-            let backoff_secs = PROBE_INITIAL_BACKOFF_SECS * 2_u64.pow(attempt - 1);
-            println!("探测失败，将在 {} 秒后重试...", backoff_secs);
-            tokio::time::sleep(Duration::from_secs(backoff_secs)).await;
+        if attempt >= max_attempts {
+            return Err(error);
+        }
+        let delay = retry_delay(
+            options.probe_retry_delay,
+            attempt,
+            options.probe_max_retry_delay,
+            options.probe_retry_jitter,
+        );
+        // 服务器通过 Retry-After 要求的等待时间比退避时间长时以服务器为准
+        let delay = retry_after.map_or(delay, |wait| wait.max(delay));
+        warning!(
+            options,
+            "探测失败: {}，将在 {:.1} 秒后重试...",
+            error,
+            delay.as_secs_f64()
+        );
+        match &options.cancel {
+            Some(token) => tokio::select! {
+                _ = token.cancelled() => return Err(DownloadError::Cancelled.into()),
+                _ = tokio::time::sleep(delay) => {}
+            },
+            None => tokio::time::sleep(delay).await,
+        }
+        attempt += 1;
+    }
+}
+
+/// 逐个探测候选镜像，只返回支持 Range 且大小、ETag 与主文件都一致的镜像 (跟随重定向后的地址)。
+/// 探测失败或不一致的镜像会被跳过，不影响下载本身。
+async fn matching_mirrors(
+    client: &Client,
+    url: &str,
+    candidates: &[&str],
+    size: u64,
+    etag: &Option<String>,
+    options: &HttpOptions,
+) -> Result<Vec<String>, DispatchError> {
+    let mut mirrors = Vec::new();
+    for candidate in candidates {
+        let probe = match send_probe(client, url, candidate, &HeaderMap::new(), options).await {
+            Ok(res) if res.status().is_success() => Probe::from_response(&res),
+            Ok(res) => {
+                warning!(
+                    options,
+                    "镜像 {} 返回 HTTP {}，已跳过。",
+                    candidate,
+                    res.status()
+                );
+                continue;
+            }
+            Err(e) if e.is_cancelled() => return Err(e),
+            Err(e) => {
+                warning!(options, "镜像 {} 探测失败: {}，已跳过。", candidate, e);
+                continue;
+            }
+        };
+        if probe.supports_resume && probe.size == Some(size) && probe.etag == *etag {
+            mirrors.push(probe.resolved_url);
+        } else {
+            warning!(
+                options,
+                "镜像 {} 与主文件不一致 (大小、ETag 或 Range 支持不同)，已跳过。",
+                candidate
+            );
+        }
+    }
+    Ok(mirrors)
+}
+
+/// 根据下载模式、文件大小和服务器能力决定是否使用多线程。
+/// 返回 `None` 表示使用多线程，否则返回使用单线程的原因。
+fn sequential_reason(
+    size: u64,
+    supports_range: bool,
+    options: &HttpOptions,
+) -> Option<&'static str> {
+    match options.mode {
+        DownloadMode::Single => Some("已指定单线程模式"),
+        _ if !supports_range => Some("服务器不支持并发"),
+        DownloadMode::Multi => None,
+        DownloadMode::Auto if size > options.min_multipart_size => None,
+        DownloadMode::Auto => Some("文件较小"),
+    }
+}
+
+/// 以多线程模式下载；如果发现服务器实际上忽略了 Range 请求，则自动回退到单线程模式从头下载。
+#[allow(clippy::too_many_arguments)]
+async fn download_multipart_with_fallback(
+    client: &Client,
+    url: &str,
+    resolved_url: &str,
+    path: &Path,
+    size: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content_type: Option<String>,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DispatchError> {
+    match download_multipart(
+        client,
+        url,
+        resolved_url,
+        path,
+        size,
+        etag.clone(),
+        last_modified.clone(),
+        content_type.clone(),
+        options,
+    )
+    .await
+    {
+        Err(DownloadError::RangeNotSupported) => {
+            warning!(
+                options,
+                "服务器忽略了 Range 请求并返回完整文件，回退到单线程模式重新下载。"
+            );
+            download_sequential_with_fallback(
+                client,
+                url,
+                resolved_url,
+                path,
+                size,
+                etag,
+                last_modified,
+                content_type,
+                options,
+            )
+            .await
+        }
+        Err(DownloadError::ContentEncoded(encoding)) => {
+            stream_encoded(client, url, resolved_url, path, &encoding, options).await
         }
+        result => Ok(result?),
     }
+}
+
+/// 以单线程模式下载已知大小的文件；如果服务器对 Range 响应做了内容编码，则改为流式下载整个文件。
+#[allow(clippy::too_many_arguments)]
+async fn download_sequential_with_fallback(
+    client: &Client,
+    url: &str,
+    resolved_url: &str,
+    path: &Path,
+    size: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content_type: Option<String>,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DispatchError> {
+    match download_sequential(
+        client,
+        url,
+        resolved_url,
+        path,
+        Some(size),
+        etag,
+        last_modified,
+        content_type,
+        options,
+    )
+    .await
+    {
+        Err(DownloadError::ContentEncoded(encoding)) => {
+            stream_encoded(client, url, resolved_url, path, &encoding, options).await
+        }
+        result => Ok(result?),
+    }
+}
 
-    // 如果所有重试都失败了，返回最后一次遇到的错误
-    Err(last_error.unwrap_or_else(|| DispatchError::DownloadFailed("所有探测尝试均失败。".into())))
+/// 服务器坚持对 Range 响应使用内容编码时，字节偏移和文件大小都不可靠，只能按普通请求流式下载整个文件
+async fn stream_encoded(
+    client: &Client,
+    url: &str,
+    resolved_url: &str,
+    path: &Path,
+    encoding: &str,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DispatchError> {
+    warning!(
+        options,
+        "服务器对 Range 响应使用了 {} 编码，改为流式下载整个文件 (不支持断点续传)。",
+        encoding
+    );
+    Ok(download_sequential(
+        client,
+        url,
+        resolved_url,
+        path,
+        None,
+        None,
+        None,
+        None,
+        options,
+    )
+    .await?)
 }
@@ -0,0 +1,1576 @@
+use rdownloader_dispatcher::{
+    BackendFuture, CancellationToken, DispatchError, DownloadEvent, DownloadMode, DownloadSummary,
+    Downloader, EventCallback, HttpOptions, OverwritePolicy, Probe, TransferMode, dispatch,
+    dispatch_to_writer, dispatch_with, probe_url,
+};
+use rdownloader_utils::{get_part_path, get_state_path};
+use reqwest::Client;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+#[tokio::test]
+async fn custom_headers_are_sent_on_probe_and_download() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Referer", "https://example.com/"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"hello".to_vec()))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hello.txt");
+    let url = format!("{}/hello.txt", server.uri());
+    let mut options = HttpOptions::default();
+    options
+        .headers
+        .insert("Referer", "https://example.com/".parse().unwrap());
+
+    dispatch(&Client::new(), &url, &path, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn falls_back_to_sequential_when_server_ignores_range() {
+    // 大于多线程阈值 (1MB)，且声称支持 Range，但实际总是返回 200 和完整文件
+    let body: Vec<u8> = (0..2 * 1024 * 1024 + 123)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Accept-Ranges", "bytes")
+                .set_body_bytes(body.clone()),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big.bin");
+    let url = format!("{}/big.bin", server.uri());
+    let (callback, events) = recording_events();
+    let options = HttpOptions {
+        quiet: true,
+        on_event: Some(callback),
+        ..HttpOptions::default()
+    };
+
+    // 只有 Accept-Ranges 而没有 206 响应，不能确认可以续传
+    let probe = probe_url(&Client::new(), &url, &options).await.unwrap();
+    assert!(probe.supports_range);
+    assert!(!probe.supports_resume);
+    assert_eq!(probe.transfer_mode(&options), TransferMode::Sequential);
+
+    let summary = dispatch(&Client::new(), &url, &path, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(!summary.multipart);
+    // 探测之后直接单线程下载，不会先发出注定失败的数据块请求
+    assert_eq!(server.received_requests().await.unwrap().len(), 3);
+    // 事件中分别报告声明的和实际确认的能力
+    assert!(events.lock().unwrap().contains(&DownloadEvent::Probed {
+        resolved_url: url.clone(),
+        size: Some(body.len() as u64),
+        supports_range: true,
+        supports_resume: false,
+    }));
+}
+
+/// 像改写了响应头的代理一样：Range 请求得到 206 和 `bytes 0-1/总大小`，响应体却是 `ranged_body`
+struct MislabeledRangeResponder {
+    body: Vec<u8>,
+    ranged_body: Vec<u8>,
+}
+
+impl Respond for MislabeledRangeResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        if request.headers.contains_key("Range") {
+            ResponseTemplate::new(206)
+                .insert_header(
+                    "Content-Range",
+                    format!("bytes 0-1/{}", self.body.len()).as_str(),
+                )
+                .set_body_bytes(self.ranged_body.clone())
+        } else {
+            ResponseTemplate::new(200).set_body_bytes(self.body.clone())
+        }
+    }
+}
+
+#[tokio::test]
+async fn conflicting_content_length_and_content_range_fall_back_to_sequential() {
+    let body: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/full.bin"))
+        .respond_with(MislabeledRangeResponder {
+            body: body.clone(),
+            ranged_body: body.clone(),
+        })
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/extra.bin"))
+        .respond_with(MislabeledRangeResponder {
+            body: body.clone(),
+            ranged_body: b"abc".to_vec(),
+        })
+        .mount(&server)
+        .await;
+
+    // 只差 1 个字节：以 Content-Range 为准，仍然可以多线程下载
+    let url = format!("{}/extra.bin", server.uri());
+    let probe = probe_url(&Client::new(), &url, &HttpOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(probe.size, Some(body.len() as u64));
+    assert_eq!(probe.content_length, Some(3));
+    assert_eq!(
+        probe.content_range.as_deref(),
+        Some(format!("bytes 0-1/{}", body.len()).as_str())
+    );
+    assert_eq!(probe.length_mismatch(), Some(1));
+    assert!(probe.supports_resume);
+
+    // 206 响应带着整个文件：Range 响应不可信，改用单线程下载
+    let url = format!("{}/full.bin", server.uri());
+    let probe = probe_url(&Client::new(), &url, &HttpOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(probe.size, Some(body.len() as u64));
+    assert_eq!(probe.length_mismatch(), Some(body.len() as u64 - 2));
+    assert!(!probe.supports_resume);
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("full.bin");
+    let (callback, events) = recording_events();
+    let options = HttpOptions {
+        on_event: Some(callback),
+        quiet: true,
+        ..HttpOptions::default()
+    };
+    let summary = dispatch(&Client::new(), &url, &file, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&file).unwrap(), body);
+    assert!(!summary.multipart);
+    assert!(events.lock().unwrap().iter().any(|e| matches!(
+        e,
+        DownloadEvent::Warning(msg) if msg.contains("Content-Length") && msg.contains("单线程")
+    )));
+}
+
+/// 第一次请求 (探测) 看到的是旧版本，之后服务器上的文件被替换为新版本。
+/// 按 Range 和 If-Range 语义响应，超出新文件大小的范围返回 416。
+struct ChangingResponder {
+    old_body: Vec<u8>,
+    new_body: Vec<u8>,
+    /// 是否在响应中带上 ETag ("v1"/"v2")
+    with_etag: bool,
+    calls: AtomicUsize,
+}
+
+impl ChangingResponder {
+    fn new(old_body: Vec<u8>, new_body: Vec<u8>, with_etag: bool) -> Self {
+        ChangingResponder {
+            old_body,
+            new_body,
+            with_etag,
+            calls: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Respond for ChangingResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let (body, etag) = if self.calls.fetch_add(1, Ordering::SeqCst) == 0 {
+            (&self.old_body, "\"v1\"")
+        } else {
+            (&self.new_body, "\"v2\"")
+        };
+        let range = request
+            .headers
+            .get("Range")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("bytes="))
+            .and_then(|v| v.split_once('-'))
+            .and_then(|(start, end)| {
+                Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+            });
+        let stale = request
+            .headers
+            .get("If-Range")
+            .is_some_and(|v| v.as_bytes() != etag.as_bytes());
+        let response = match range {
+            Some((start, _)) if !stale && start >= body.len() => ResponseTemplate::new(416)
+                .insert_header("Content-Range", format!("bytes */{}", body.len()).as_str()),
+            Some((start, end)) if !stale => {
+                let end = end.min(body.len() - 1);
+                ResponseTemplate::new(206)
+                    .insert_header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", start, end, body.len()).as_str(),
+                    )
+                    .set_body_bytes(body[start..=end].to_vec())
+            }
+            _ => ResponseTemplate::new(200).set_body_bytes(body.clone()),
+        };
+        if self.with_etag {
+            response.insert_header("ETag", etag)
+        } else {
+            response
+        }
+    }
+}
+
+#[tokio::test]
+async fn restarts_when_file_changes_during_download() {
+    let old_body: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let new_body: Vec<u8> = old_body.iter().map(|b| b.wrapping_add(1)).collect();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ChangingResponder::new(old_body, new_body.clone(), true))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big.bin");
+    let url = format!("{}/big.bin", server.uri());
+    let options = HttpOptions {
+        quiet: true,
+        ..HttpOptions::default()
+    };
+
+    dispatch(&Client::new(), &url, &path, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), new_body);
+}
+
+#[tokio::test]
+async fn restarts_when_stale_ranges_return_416() {
+    // 探测时文件为 3MB，随后缩小到 1.5MB 且没有 ETag，超出范围的数据块请求得到 416
+    let old_body: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let new_body: Vec<u8> = (0..3 * 512 * 1024).map(|i| (i % 241) as u8).collect();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ChangingResponder::new(old_body, new_body.clone(), false))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shrunk.bin");
+    let url = format!("{}/shrunk.bin", server.uri());
+    let options = HttpOptions {
+        quiet: true,
+        ..HttpOptions::default()
+    };
+
+    dispatch(&Client::new(), &url, &path, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), new_body);
+}
+
+/// 每次响应都报告比上一次多一个字节的总大小，模拟动态生成、大小不断变化的文件
+struct GrowingResponder {
+    calls: AtomicUsize,
+}
+
+impl Respond for GrowingResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let total = 2 * 1024 * 1024 + self.calls.fetch_add(1, Ordering::SeqCst);
+        let (start, end) = request
+            .headers
+            .get("Range")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("bytes="))
+            .and_then(|v| v.split_once('-'))
+            .and_then(|(start, end)| {
+                Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+            })
+            .unwrap();
+        let end = end.min(total - 1);
+        ResponseTemplate::new(206)
+            .insert_header(
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, end, total).as_str(),
+            )
+            .set_body_bytes(vec![0u8; end - start + 1])
+    }
+}
+
+#[tokio::test]
+async fn persistent_total_size_mismatch_is_reported() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(GrowingResponder {
+            calls: AtomicUsize::new(0),
+        })
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("live.bin");
+    let url = format!("{}/live.bin", server.uri());
+    let options = HttpOptions {
+        quiet: true,
+        ..HttpOptions::default()
+    };
+
+    // 重新探测一次后大小仍然对不上，不再继续重试
+    let err = dispatch(&Client::new(), &url, &path, &options)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), "size_changed", "{:?}", err);
+    assert!(!path.exists());
+    assert!(!get_part_path(&path).exists());
+}
+
+#[tokio::test]
+async fn resume_falls_back_to_sequential_when_range_is_no_longer_supported() {
+    let body: Vec<u8> = (0..2 * 1024 * 1024 + 100)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    let total = body.len() as u64;
+    // 服务器现在只返回完整文件，也不再声明 Accept-Ranges
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("big.bin");
+    let url = format!("{}/big.bin", server.uri());
+
+    // 上一次多线程下载完成了第一个数据块后中断
+    let mb = 1024 * 1024;
+    let state = serde_json::json!({
+        "version": 1,
+        "url": url,
+        "total_size": total,
+        "etag": null,
+        "last_modified": null,
+        "chunks": [
+            { "start": 0, "end": mb - 1, "completed": true },
+            { "start": mb, "end": 2 * mb - 1, "completed": false },
+            { "start": 2 * mb, "end": total - 1, "completed": false }
+        ]
+    });
+    std::fs::write(get_state_path(&path), state.to_string()).unwrap();
+    let mut part = vec![0xFFu8; mb as usize];
+    part.resize(total as usize, 0);
+    std::fs::write(get_part_path(&path), part).unwrap();
+
+    let options = HttpOptions {
+        quiet: true,
+        ..HttpOptions::default()
+    };
+    dispatch(&Client::new(), &url, &path, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(!get_state_path(&path).exists());
+}
+
+#[tokio::test]
+async fn chunks_are_requested_from_redirect_target() {
+    let body: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let server = MockServer::start().await;
+    // 只有探测请求会经过重定向，之后的数据块请求直接发往最终地址
+    Mock::given(method("GET"))
+        .and(path("/start"))
+        .respond_with(
+            ResponseTemplate::new(302)
+                .insert_header("Location", format!("{}/signed", server.uri()).as_str()),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/signed"))
+        .respond_with(ChangingResponder::new(body.clone(), body.clone(), false))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.bin");
+    let url = format!("{}/start", server.uri());
+    let options = HttpOptions {
+        chunk_size: 512 * 1024,
+        ..HttpOptions::default()
+    };
+
+    dispatch(&Client::new(), &url, &file, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&file).unwrap(), body);
+}
+
+/// 以给定的选项下载 `size` 字节的文件，返回服务器收到的请求数 (包括探测请求)
+async fn count_requests(size: usize, options: HttpOptions) -> usize {
+    let body: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ChangingResponder::new(body.clone(), body.clone(), false))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    dispatch(&Client::new(), &url, &file, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&file).unwrap(), body);
+    server.received_requests().await.unwrap().len()
+}
+
+#[tokio::test]
+async fn download_mode_overrides_size_threshold() {
+    let small = 8 * 1024;
+    let large = 2 * 1024 * 1024;
+    let options = |mode, min_multipart_size| HttpOptions {
+        chunk_size: small as u64 / 4,
+        mode,
+        min_multipart_size,
+        ..HttpOptions::default()
+    };
+    let default_threshold = HttpOptions::default().min_multipart_size;
+
+    // 自动模式：小文件单线程 (探测 + 1 个请求)，大文件多线程
+    assert_eq!(
+        count_requests(small, options(DownloadMode::Auto, default_threshold)).await,
+        2
+    );
+    assert_eq!(
+        count_requests(small, options(DownloadMode::Auto, 1024)).await,
+        5
+    );
+    // 强制模式忽略大小阈值
+    assert_eq!(
+        count_requests(small, options(DownloadMode::Multi, default_threshold)).await,
+        5
+    );
+    assert_eq!(
+        count_requests(large, options(DownloadMode::Single, default_threshold)).await,
+        2
+    );
+}
+
+/// 对 Range 请求返回总大小未知的 206 (`bytes 0-1/*`)，对普通请求返回完整内容
+struct UnknownTotalResponder {
+    body: Vec<u8>,
+}
+
+impl Respond for UnknownTotalResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        if request.headers.contains_key("Range") {
+            ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 0-1/*")
+                .insert_header("Accept-Ranges", "bytes")
+                .set_body_bytes(self.body[..2].to_vec())
+        } else {
+            ResponseTemplate::new(200).set_body_bytes(self.body.clone())
+        }
+    }
+}
+
+#[tokio::test]
+async fn unknown_total_in_content_range_streams_whole_file() {
+    let body: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(UnknownTotalResponder { body: body.clone() })
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    // 探测响应的 Content-Length (2) 不是文件大小，不能据此只下载 2 个字节
+    dispatch(&Client::new(), &url, &file, &HttpOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&file).unwrap(), body);
+}
+
+#[tokio::test]
+async fn unreachable_primary_falls_over_to_mirror() {
+    let body: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+    let mirror = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ChangingResponder::new(body.clone(), body.clone(), false))
+        .mount(&mirror)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.bin");
+    let options = HttpOptions {
+        mirrors: vec![format!("{}/file.bin", mirror.uri())],
+        ..HttpOptions::default()
+    };
+
+    // 端口 1 上没有服务，主地址的探测会立即失败
+    dispatch(
+        &Client::new(),
+        "http://127.0.0.1:1/file.bin",
+        &file,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&file).unwrap(), body);
+}
+
+#[tokio::test]
+async fn mismatched_mirror_is_skipped() {
+    let body: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    // 主地址只有探测请求成功，所有数据块请求都失败
+    let primary = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=0-1"))
+        .respond_with(ChangingResponder::new(body.clone(), body.clone(), false))
+        .with_priority(1)
+        .mount(&primary)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&primary)
+        .await;
+    // 大小不同的镜像只会收到一次探测请求
+    let other = body[..1024 * 1024].to_vec();
+    let mismatched = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ChangingResponder::new(other.clone(), other, false))
+        .expect(1)
+        .mount(&mismatched)
+        .await;
+    let matching = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ChangingResponder::new(body.clone(), body.clone(), false))
+        .mount(&matching)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", primary.uri());
+    let options = HttpOptions {
+        chunk_size: 512 * 1024,
+        chunk_max_attempts: 1,
+        mirrors: vec![
+            format!("{}/file.bin", mismatched.uri()),
+            format!("{}/file.bin", matching.uri()),
+        ],
+        ..HttpOptions::default()
+    };
+
+    dispatch(&Client::new(), &url, &file, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&file).unwrap(), body);
+}
+
+/// 探测请求正常，但对其余 Range 请求都使用 gzip 编码；不带 Range 的请求返回未编码的完整文件
+struct GzipRangeResponder {
+    body: Vec<u8>,
+    /// 探测请求 (`bytes=0-1`) 是否也使用 gzip 编码
+    encode_probe: bool,
+}
+
+impl Respond for GzipRangeResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let range = request.headers.get("Range").and_then(|v| v.to_str().ok());
+        match range {
+            None => ResponseTemplate::new(200).set_body_bytes(self.body.clone()),
+            Some("bytes=0-1") if !self.encode_probe => ResponseTemplate::new(206)
+                .insert_header(
+                    "Content-Range",
+                    format!("bytes 0-1/{}", self.body.len()).as_str(),
+                )
+                .set_body_bytes(self.body[..2].to_vec()),
+            // 模拟的压缩数据，长度与请求的范围不一致
+            Some(_) => ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 0-9/10")
+                .insert_header("Content-Encoding", "gzip")
+                .set_body_bytes(vec![0x1f, 0x8b, 0x08, 0x00, 0, 0, 0, 0, 0, 0]),
+        }
+    }
+}
+
+#[tokio::test]
+async fn gzip_range_responses_fall_back_to_streaming() {
+    let body: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    for encode_probe in [false, true] {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(GzipRangeResponder {
+                body: body.clone(),
+                encode_probe,
+            })
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file.bin");
+        let url = format!("{}/file.bin", server.uri());
+
+        dispatch(&Client::new(), &url, &file, &HttpOptions::default())
+            .await
+            .unwrap();
+
+        assert_eq!(std::fs::read(&file).unwrap(), body, "{}", encode_probe);
+    }
+}
+
+#[tokio::test]
+async fn existing_file_follows_overwrite_policy() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"new".to_vec()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.txt");
+    let url = format!("{}/file.txt", server.uri());
+    std::fs::write(&file, b"old").unwrap();
+    let options = |overwrite| HttpOptions {
+        overwrite,
+        ..HttpOptions::default()
+    };
+
+    // 默认报错，且不发出任何请求
+    let result = dispatch(
+        &Client::new(),
+        &url,
+        &file,
+        &options(OverwritePolicy::Error),
+    )
+    .await;
+    assert!(matches!(result, Err(DispatchError::FileExists(_))));
+    dispatch(&Client::new(), &url, &file, &options(OverwritePolicy::Skip))
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&file).unwrap(), b"old");
+    assert!(server.received_requests().await.unwrap().is_empty());
+
+    dispatch(
+        &Client::new(),
+        &url,
+        &file,
+        &options(OverwritePolicy::Overwrite),
+    )
+    .await
+    .unwrap();
+    assert_eq!(std::fs::read(&file).unwrap(), b"new");
+}
+
+#[tokio::test]
+async fn part_file_does_not_count_as_existing() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"data".to_vec()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.txt");
+    let url = format!("{}/file.txt", server.uri());
+    std::fs::write(get_part_path(&file), b"partial").unwrap();
+
+    dispatch(&Client::new(), &url, &file, &HttpOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&file).unwrap(), b"data");
+}
+
+/// 收集所有事件的回调
+fn recording_events() -> (EventCallback, Arc<Mutex<Vec<DownloadEvent>>>) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink = events.clone();
+    let callback = EventCallback::new(move |event| sink.lock().unwrap().push(event.clone()));
+    (callback, events)
+}
+
+#[tokio::test]
+async fn structured_events_replace_status_output() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"hello".to_vec()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hello.txt");
+    let url = format!("{}/hello.txt", server.uri());
+    let (callback, events) = recording_events();
+    let options = HttpOptions {
+        on_event: Some(callback),
+        ..HttpOptions::default()
+    };
+
+    let summary = dispatch(&Client::new(), &url, &path, &options)
+        .await
+        .unwrap();
+
+    let events = events.lock().unwrap();
+    assert!(events.contains(&DownloadEvent::Probed {
+        resolved_url: url.clone(),
+        size: Some(5),
+        supports_range: false,
+        supports_resume: false,
+    }));
+    assert!(
+        events
+            .iter()
+            .any(|e| matches!(e, DownloadEvent::Status(msg) if msg.contains("单线程")))
+    );
+    assert_eq!(events.last(), Some(&DownloadEvent::Finished(summary)));
+}
+
+#[tokio::test]
+async fn fallbacks_are_reported_as_warnings() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(UnknownTotalResponder {
+            body: b"hello".to_vec(),
+        })
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hello.txt");
+    let url = format!("{}/hello.txt", server.uri());
+    let (callback, events) = recording_events();
+    let options = HttpOptions {
+        on_event: Some(callback),
+        ..HttpOptions::default()
+    };
+
+    dispatch(&Client::new(), &url, &path, &options)
+        .await
+        .unwrap();
+
+    // 无法确定文件大小需要用户注意，与普通的状态信息分开上报
+    let events = events.lock().unwrap();
+    assert!(
+        events
+            .iter()
+            .any(|e| matches!(e, DownloadEvent::Warning(msg) if msg.contains("文件总大小")))
+    );
+    assert!(
+        !events
+            .iter()
+            .any(|e| matches!(e, DownloadEvent::Status(msg) if msg.contains("文件总大小")))
+    );
+}
+
+#[tokio::test]
+async fn summary_describes_the_download() {
+    let body: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ChangingResponder::new(body.clone(), body.clone(), false))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let summary = dispatch(&Client::new(), &url, &path, &HttpOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(summary.path, path);
+    assert_eq!(summary.total_size, body.len() as u64);
+    assert_eq!(summary.bytes_downloaded, body.len() as u64);
+    assert!(summary.multipart && !summary.resumed && !summary.skipped);
+
+    // 再次下载时文件已存在，按 Skip 策略跳过
+    let options = HttpOptions {
+        overwrite: OverwritePolicy::Skip,
+        ..HttpOptions::default()
+    };
+    let summary = dispatch(&Client::new(), &url, &path, &options)
+        .await
+        .unwrap();
+    assert!(summary.skipped);
+    assert_eq!(summary.bytes_downloaded, 0);
+    assert_eq!(summary.total_size, body.len() as u64);
+}
+
+#[tokio::test]
+async fn failed_download_reports_error_kind_without_finished_event() {
+    let server = MockServer::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    std::fs::write(&path, b"old").unwrap();
+    let (callback, events) = recording_events();
+    let options = HttpOptions {
+        on_event: Some(callback),
+        ..HttpOptions::default()
+    };
+
+    let err = dispatch(&Client::new(), &server.uri(), &path, &options)
+        .await
+        .unwrap_err();
+
+    assert_eq!(err.kind(), "file_exists");
+    assert!(events.lock().unwrap().is_empty());
+}
+
+/// 直接关闭前 `drops` 个连接，之后对所有请求返回 "hello" 的服务器
+async fn dropping_server(drops: usize) -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        for i in 0.. {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 4096];
+            let _ = socket.read(&mut request).await;
+            if i >= drops {
+                let _ = socket
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+                    )
+                    .await;
+            }
+        }
+    });
+    format!("http://{}/hello.txt", addr)
+}
+
+fn fast_retries(retries: u32) -> HttpOptions {
+    HttpOptions {
+        probe_retries: retries,
+        probe_retry_delay: Duration::from_millis(10),
+        ..HttpOptions::default()
+    }
+}
+
+#[tokio::test]
+async fn probe_retries_after_connection_errors() {
+    let url = dropping_server(2).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hello.txt");
+
+    dispatch(&Client::new(), &url, &path, &fast_retries(2))
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+
+    // 重试次数不够时返回最后一次的网络错误
+    let url = dropping_server(2).await;
+    let err = dispatch(
+        &Client::new(),
+        &url,
+        &dir.path().join("b"),
+        &fast_retries(1),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DispatchError::Network(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn probe_retries_are_configurable() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(4)
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let options = HttpOptions {
+        probe_max_retry_delay: Duration::from_millis(20),
+        probe_retry_jitter: true,
+        ..fast_retries(3)
+    };
+
+    let err = dispatch(
+        &Client::new(),
+        &server.uri(),
+        &dir.path().join("f"),
+        &options,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DispatchError::HttpError(s) if s == 503));
+}
+
+#[tokio::test]
+async fn probe_falls_back_to_single_byte_range() {
+    let body: Vec<u8> = (0..2 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let server = MockServer::start().await;
+    // 只拒绝 2 字节的探测范围，其他 Range 请求正常处理
+    Mock::given(header("Range", "bytes=0-1"))
+        .respond_with(ResponseTemplate::new(416))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ChangingResponder::new(body.clone(), body.clone(), false))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("picky.bin");
+    let url = format!("{}/picky.bin", server.uri());
+    let summary = dispatch(&Client::new(), &url, &path, &fast_retries(0))
+        .await
+        .unwrap();
+
+    assert!(summary.multipart);
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[tokio::test]
+async fn probe_falls_back_to_plain_get_when_ranges_are_rejected() {
+    let server = MockServer::start().await;
+    for range in ["bytes=0-1", "bytes=0-0"] {
+        Mock::given(header("Range", range))
+            .respond_with(ResponseTemplate::new(400))
+            .expect(1)
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"no ranges here".to_vec()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("plain.txt");
+    let url = format!("{}/plain.txt", server.uri());
+    let summary = dispatch(&Client::new(), &url, &path, &fast_retries(0))
+        .await
+        .unwrap();
+
+    assert_eq!(summary.total_size, 14);
+    assert_eq!(std::fs::read(&path).unwrap(), b"no ranges here");
+}
+
+#[tokio::test]
+async fn missing_file_is_probed_only_once() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let err = dispatch(
+        &Client::new(),
+        &format!("{}/missing.bin", server.uri()),
+        &dir.path().join("missing.bin"),
+        &fast_retries(0),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DispatchError::HttpError(s) if s == 404));
+}
+
+/// 第一次探测返回带 `Retry-After` 的 `status`，之后正常提供文件；返回下载所用的时间
+async fn download_after_retry_after(status: u16, retry_after: &str) -> Duration {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(status).insert_header("Retry-After", retry_after))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes("hello"))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("hello.txt");
+    let started = std::time::Instant::now();
+    dispatch(
+        &Client::new(),
+        &format!("{}/hello.txt", server.uri()),
+        &path,
+        &fast_retries(1),
+    )
+    .await
+    .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"hello");
+    started.elapsed()
+}
+
+#[tokio::test]
+async fn probe_waits_for_retry_after_seconds() {
+    assert!(download_after_retry_after(429, "1").await >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn probe_waits_for_retry_after_date() {
+    // HTTP 日期只精确到秒，2 秒后的日期至少需要等待 1 秒
+    let later = rdownloader_utils::http_date(std::time::SystemTime::now() + Duration::from_secs(2));
+    assert!(download_after_retry_after(503, &later).await >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn not_found_does_not_trigger_probe_retries() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let started = std::time::Instant::now();
+    let options = HttpOptions {
+        probe_retry_delay: Duration::from_secs(5),
+        ..fast_retries(3)
+    };
+    let err = dispatch(
+        &Client::new(),
+        &format!("{}/missing.bin", server.uri()),
+        &dir.path().join("missing.bin"),
+        &options,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DispatchError::HttpError(s) if s == 404));
+    // 没有进入退避等待
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn retry_all_errors_retries_not_found() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(404))
+        .expect(3)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = HttpOptions {
+        retry_all_errors: true,
+        ..fast_retries(2)
+    };
+    let err = dispatch(
+        &Client::new(),
+        &format!("{}/missing.bin", server.uri()),
+        &dir.path().join("missing.bin"),
+        &options,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DispatchError::HttpError(s) if s == 404));
+}
+
+#[tokio::test]
+async fn redirect_loop_is_not_retried() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(302).insert_header("Location", "/loop"))
+        .expect(3)
+        .mount(&server)
+        .await;
+    let client = Client::builder()
+        .redirect(reqwest::redirect::Policy::limited(2))
+        .build()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+
+    let err = dispatch(
+        &client,
+        &server.uri(),
+        &dir.path().join("f"),
+        &fast_retries(3),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DispatchError::Network(ref e) if e.is_redirect()));
+}
+
+#[tokio::test]
+async fn not_modified_skips_existing_file() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("If-None-Match", "\"v1\""))
+        .respond_with(ResponseTemplate::new(304))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    std::fs::write(&path, b"old").unwrap();
+    let options = HttpOptions {
+        if_none_match: Some("\"v1\"".into()),
+        ..HttpOptions::default()
+    };
+
+    dispatch(&Client::new(), &server.uri(), &path, &options)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"old");
+}
+
+#[tokio::test]
+async fn changed_file_is_downloaded_over_existing_one() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(wiremock::matchers::header_exists("If-Modified-Since"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"new".to_vec()))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"new".to_vec()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    std::fs::write(&path, b"old").unwrap();
+    let options = HttpOptions {
+        if_newer: true,
+        ..HttpOptions::default()
+    };
+
+    dispatch(&Client::new(), &server.uri(), &path, &options)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"new");
+}
+
+#[tokio::test]
+async fn missing_file_is_downloaded_without_conditions() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(wiremock::matchers::header_exists("If-Modified-Since"))
+        .respond_with(ResponseTemplate::new(304))
+        .expect(0)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"new".to_vec()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let options = HttpOptions {
+        if_newer: true,
+        ..HttpOptions::default()
+    };
+
+    dispatch(&Client::new(), &server.uri(), &path, &options)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"new");
+}
+
+#[tokio::test]
+async fn file_larger_than_max_size_is_refused_before_download() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=0-1"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 0-1/5000000")
+                .set_body_bytes(b"ab".to_vec()),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let options = HttpOptions {
+        max_size: Some(1_000_000),
+        ..HttpOptions::default()
+    };
+
+    let err = dispatch(&Client::new(), &server.uri(), &path, &options)
+        .await
+        .unwrap_err();
+
+    assert_eq!(err.kind(), "too_large");
+    assert!(!get_part_path(&path).exists());
+}
+
+fn file_url(path: &std::path::Path) -> String {
+    reqwest::Url::from_file_path(path).unwrap().to_string()
+}
+
+#[tokio::test]
+async fn file_url_is_copied_in_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    let body: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&source, &body).unwrap();
+    let path = dir.path().join("copy.bin");
+    let options = HttpOptions {
+        mode: DownloadMode::Multi,
+        chunk_size: 1024,
+        ..HttpOptions::default()
+    };
+
+    let summary = dispatch(&Client::new(), &file_url(&source), &path, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(summary.multipart);
+    assert_eq!(summary.chunks, 5);
+    assert_eq!(summary.bytes_downloaded, body.len() as u64);
+    assert!(!get_part_path(&path).exists());
+    assert!(!get_state_path(&path).exists());
+}
+
+#[tokio::test]
+async fn local_file_stream_can_be_cancelled() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    std::fs::write(&source, vec![7u8; 2 * 1024 * 1024]).unwrap();
+    let cancel = CancellationToken::new();
+    // 限速让复制持续数秒，取消时还远没有写完
+    let options = HttpOptions {
+        max_speed: Some(256 * 1024),
+        cancel: Some(cancel.clone()),
+        quiet: true,
+        ..HttpOptions::default()
+    };
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        cancel.cancel();
+    });
+
+    let mut out = Vec::new();
+    let err = dispatch_to_writer(&Client::new(), &file_url(&source), &mut out, &options)
+        .await
+        .unwrap_err();
+
+    assert!(err.is_cancelled(), "{}", err);
+    assert!(out.len() < 2 * 1024 * 1024);
+}
+
+#[tokio::test]
+async fn local_file_stream_respects_max_size() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    std::fs::write(&source, vec![7u8; 5000]).unwrap();
+    let options = HttpOptions {
+        max_size: Some(1000),
+        quiet: true,
+        ..HttpOptions::default()
+    };
+
+    let err = dispatch_to_writer(
+        &Client::new(),
+        &file_url(&source),
+        &mut Vec::new(),
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(err.kind(), "too_large");
+}
+
+#[tokio::test]
+async fn missing_local_file_is_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = file_url(&dir.path().join("missing.bin"));
+
+    let err = dispatch(
+        &Client::new(),
+        &url,
+        &dir.path().join("copy.bin"),
+        &HttpOptions::default(),
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(err.kind(), "file");
+}
+
+fn byte_range(range: std::ops::RangeInclusive<u64>) -> HttpOptions {
+    HttpOptions {
+        byte_range: Some(range),
+        ..HttpOptions::default()
+    }
+}
+
+#[tokio::test]
+async fn byte_range_downloads_only_the_requested_bytes() {
+    let body: Vec<u8> = (0..8000).map(|i| (i % 251) as u8).collect();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ChangingResponder::new(body.clone(), body.clone(), false))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("part.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    // 文件足够大时也不会分块
+    let summary = dispatch(&Client::new(), &url, &path, &byte_range(1000..=2999))
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body[1000..3000]);
+    assert_eq!((summary.total_size, summary.chunks), (2000, 1));
+    assert!(!summary.multipart);
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].headers["Range"], "bytes=1000-2999");
+
+    let err = dispatch(
+        &Client::new(),
+        &url,
+        &dir.path().join("beyond.bin"),
+        &byte_range(7000..=8000),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.kind(), "range_out_of_bounds");
+    assert!(err.to_string().contains("8000 bytes"), "{}", err);
+    assert!(!dir.path().join("beyond.bin").exists());
+}
+
+#[tokio::test]
+async fn byte_range_requires_range_support() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![7u8; 4096]))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("part.bin");
+
+    let err = dispatch(
+        &Client::new(),
+        &format!("{}/file.bin", server.uri()),
+        &path,
+        &byte_range(0..=1023),
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(err.kind(), "range_not_supported");
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn byte_range_of_local_file_is_copied() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = dir.path().join("source.bin");
+    let body: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&source, &body).unwrap();
+    let path = dir.path().join("copy.bin");
+
+    dispatch(
+        &Client::new(),
+        &file_url(&source),
+        &path,
+        &byte_range(4000..=4999),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body[4000..]);
+}
+
+/// 提供 `body` 的 gzip 压缩文件 `/data.txt.gz`
+async fn serve_gzip_file(body: &[u8]) -> MockServer {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(body).unwrap();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/data.txt.gz"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(encoder.finish().unwrap()))
+        .mount(&server)
+        .await;
+    server
+}
+
+#[tokio::test]
+async fn downloaded_gzip_file_is_unpacked() {
+    let body = b"hello, unpacked world\n".repeat(100);
+    let server = serve_gzip_file(&body).await;
+    let url = format!("{}/data.txt.gz", server.uri());
+
+    for remove_compressed in [false, true] {
+        let dir = tempfile::tempdir().unwrap();
+        let compressed = dir.path().join("data.txt.gz");
+        let options = HttpOptions {
+            unpack: true,
+            remove_compressed,
+            ..HttpOptions::default()
+        };
+        let summary = dispatch(&Client::new(), &url, &compressed, &options)
+            .await
+            .unwrap();
+
+        let target = dir.path().join("data.txt");
+        assert_eq!(summary.path, target);
+        assert_eq!(std::fs::read(&target).unwrap(), body);
+        assert_eq!(compressed.exists(), !remove_compressed);
+    }
+}
+
+#[tokio::test]
+async fn existing_unpacked_file_follows_overwrite_policy() {
+    let server = serve_gzip_file(b"new").await;
+    let url = format!("{}/data.txt.gz", server.uri());
+    let dir = tempfile::tempdir().unwrap();
+    let compressed = dir.path().join("data.txt.gz");
+    let target = dir.path().join("data.txt");
+    std::fs::write(&target, "old").unwrap();
+    let options = |overwrite| HttpOptions {
+        unpack: true,
+        overwrite,
+        ..HttpOptions::default()
+    };
+
+    let err = dispatch(
+        &Client::new(),
+        &url,
+        &compressed,
+        &options(OverwritePolicy::Error),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DispatchError::FileExists(p) if p == target));
+
+    let summary = dispatch(
+        &Client::new(),
+        &url,
+        &compressed,
+        &options(OverwritePolicy::Skip),
+    )
+    .await
+    .unwrap();
+    assert!(summary.skipped);
+    assert_eq!(summary.path, target);
+    assert!(server.received_requests().await.unwrap().is_empty());
+
+    dispatch(
+        &Client::new(),
+        &url,
+        &compressed,
+        &options(OverwritePolicy::Overwrite),
+    )
+    .await
+    .unwrap();
+    assert_eq!(std::fs::read(&target).unwrap(), b"new");
+}
+
+/// 不访问网络的下载后端，把固定的内容写入目标文件
+struct MockDownloader {
+    body: &'static [u8],
+    downloads: AtomicUsize,
+}
+
+impl Downloader for MockDownloader {
+    fn probe<'a>(&'a self, url: &'a str, _options: &'a HttpOptions) -> BackendFuture<'a, Probe> {
+        Box::pin(async move {
+            Ok(Probe {
+                resolved_url: url.to_string(),
+                size: Some(self.body.len() as u64),
+                supports_range: false,
+                supports_resume: false,
+                content_length: None,
+                content_range: None,
+                etag: None,
+                last_modified: None,
+                content_type: None,
+                filename: None,
+            })
+        })
+    }
+
+    fn download<'a>(
+        &'a self,
+        _url: &'a str,
+        _probe: Option<Probe>,
+        path: &'a std::path::Path,
+        _options: &'a HttpOptions,
+    ) -> BackendFuture<'a, DownloadSummary> {
+        Box::pin(async move {
+            self.downloads.fetch_add(1, Ordering::SeqCst);
+            std::fs::write(path, self.body).unwrap();
+            Ok(DownloadSummary {
+                path: path.to_path_buf(),
+                total_size: self.body.len() as u64,
+                bytes_downloaded: self.body.len() as u64,
+                resumed: false,
+                multipart: false,
+                skipped: false,
+                elapsed: Duration::ZERO,
+                peak_speed: 0,
+                chunks: 1,
+                retries: 0,
+                restart_reason: None,
+            })
+        })
+    }
+}
+
+#[tokio::test]
+async fn custom_backend_is_used_with_overwrite_policy() {
+    let backend = MockDownloader {
+        body: b"mock",
+        downloads: AtomicUsize::new(0),
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("mock.bin");
+    let finished = Arc::new(AtomicUsize::new(0));
+    let options = HttpOptions {
+        overwrite: OverwritePolicy::Skip,
+        on_event: Some({
+            let finished = finished.clone();
+            EventCallback::new(move |event| {
+                if let DownloadEvent::Finished(_) = event {
+                    finished.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        }),
+        ..HttpOptions::default()
+    };
+
+    let summary = dispatch_with(&backend, "mock://file", &path, None, &options)
+        .await
+        .unwrap();
+    assert!(!summary.skipped);
+    assert_eq!(std::fs::read(&path).unwrap(), b"mock");
+
+    // 文件已存在时按覆盖策略跳过，不会调用后端
+    let summary = dispatch_with(&backend, "mock://file", &path, None, &options)
+        .await
+        .unwrap();
+    assert!(summary.skipped);
+    assert_eq!(backend.downloads.load(Ordering::SeqCst), 1);
+    assert_eq!(finished.load(Ordering::SeqCst), 2);
+
+    let err = dispatch(&Client::new(), "mock://file", &path, &options)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), "unsupported_protocol");
+}
@@ -0,0 +1,444 @@
+use rdownloader_dispatcher::{
+    CancellationToken, HttpOptions, ResumeMode, dispatch, dispatch_probed, dispatch_to_writer,
+    probe_url,
+};
+use rdownloader_utils::get_part_path;
+use reqwest::Client;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio_native_tls::TlsAcceptor;
+
+/// 测试服务器的控制连接和数据连接：明文的 TCP 连接或其上的 TLS 连接
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// 自签名的 localhost 证书，客户端需要开启 `insecure`
+fn tls_acceptor() -> TlsAcceptor {
+    let identity = native_tls::Identity::from_pkcs8(
+        include_bytes!("data/localhost-cert.pem"),
+        include_bytes!("data/localhost-key.pem"),
+    )
+    .unwrap();
+    TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap())
+}
+
+/// 有 `tls` 时在 `stream` 上接受 TLS 握手
+async fn accept(tls: Option<&TlsAcceptor>, stream: tokio::net::TcpStream) -> Box<dyn Stream> {
+    match tls {
+        Some(acceptor) => Box::new(acceptor.accept(stream).await.unwrap()),
+        None => Box::new(stream),
+    }
+}
+
+/// 测试服务器偏离正常行为的方式
+#[derive(Clone, Default)]
+struct Quirks {
+    /// 使用隐式 TLS (ftps://)。与 vsftpd 和 ProFTPD 一样，收到 RETR 之后才在数据连接上进行 TLS 握手
+    tls: Option<TlsAcceptor>,
+    /// MDTM 返回的修改时间，为 `None` 时不支持 MDTM
+    mdtm: Option<&'static str>,
+    /// 数据连接关闭后回复 426 而不是 226，表示传输被中止
+    abort_transfer: bool,
+    /// 打开数据连接后不发送任何数据
+    stall_data: bool,
+}
+
+/// 只提供一个文件的 FTP 服务器：匿名登录，支持 SIZE、EPSV、REST 和 RETR。
+/// 返回服务器地址和收到的全部命令
+async fn ftp_server(name: &'static str, body: Vec<u8>) -> (String, Arc<Mutex<Vec<String>>>) {
+    ftp_server_with(name, body, Quirks::default()).await
+}
+
+async fn ftp_server_with(
+    name: &'static str,
+    body: Vec<u8>,
+    quirks: Quirks,
+) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let scheme = if quirks.tls.is_some() { "ftps" } else { "ftp" };
+    let commands = Arc::new(Mutex::new(Vec::new()));
+    let received = commands.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (body, commands, quirks) = (body.clone(), commands.clone(), quirks.clone());
+            tokio::spawn(async move {
+                let stream = accept(quirks.tls.as_ref(), stream).await;
+                let (reader, mut writer) = tokio::io::split(stream);
+                let mut lines = BufReader::new(reader).lines();
+                // 多行的欢迎信息
+                writer
+                    .write_all(b"220-welcome\r\n220 ready\r\n")
+                    .await
+                    .unwrap();
+                let mut data = None;
+                let mut rest = 0;
+                while let Some(line) = lines.next_line().await.unwrap() {
+                    commands.lock().unwrap().push(line.clone());
+                    let (command, arg) = line.split_once(' ').unwrap_or((&line, ""));
+                    let reply = match command {
+                        "USER" => "331 password please".to_string(),
+                        "PASS" => "230 logged in".to_string(),
+                        "TYPE" => "200 binary".to_string(),
+                        "PBSZ" | "PROT" => "200 ok".to_string(),
+                        "SIZE" if arg == name => format!("213 {}", body.len()),
+                        "MDTM" if arg == name && quirks.mdtm.is_some() => {
+                            format!("213 {}", quirks.mdtm.unwrap())
+                        }
+                        "EPSV" => {
+                            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                            let port = listener.local_addr().unwrap().port();
+                            data = Some(listener);
+                            format!("229 Entering Extended Passive Mode (|||{}|)", port)
+                        }
+                        "REST" => {
+                            rest = arg.parse().unwrap();
+                            format!("350 restarting at {}", rest)
+                        }
+                        "RETR" if arg == name => {
+                            writer.write_all(b"150 opening\r\n").await.unwrap();
+                            let (conn, _) = data.take().unwrap().accept().await.unwrap();
+                            let mut conn = accept(quirks.tls.as_ref(), conn).await;
+                            if quirks.stall_data {
+                                tokio::time::sleep(Duration::from_secs(30)).await;
+                            }
+                            conn.write_all(&body[rest..]).await.unwrap();
+                            // TLS 连接需要发送 close_notify 才算正常结束
+                            conn.shutdown().await.unwrap();
+                            drop(conn);
+                            match quirks.abort_transfer {
+                                true => "426 transfer aborted".to_string(),
+                                false => "226 done".to_string(),
+                            }
+                        }
+                        "QUIT" => "221 bye".to_string(),
+                        _ => "550 no such file".to_string(),
+                    };
+                    writer
+                        .write_all(format!("{}\r\n", reply).as_bytes())
+                        .await
+                        .unwrap();
+                }
+            });
+        }
+    });
+    (format!("{}://{}", scheme, addr), received)
+}
+
+fn test_body(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+#[tokio::test]
+async fn ftp_file_is_downloaded() {
+    let body = test_body(300 * 1024);
+    let (server, commands) = ftp_server("pub/data.bin", body.clone()).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.bin");
+
+    let url = format!("{}/pub/data.bin", server);
+    let probe = probe_url(&Client::new(), &url, &HttpOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(probe.size, Some(body.len() as u64));
+    assert_eq!(probe.filename.as_deref(), Some("data.bin"));
+
+    let summary = dispatch(&Client::new(), &url, &path, &HttpOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert_eq!(summary.total_size, body.len() as u64);
+    assert!(!summary.resumed);
+    assert!(!get_part_path(&path).exists());
+    let commands = commands.lock().unwrap();
+    assert!(commands.contains(&"USER anonymous".to_string()));
+    assert!(!commands.iter().any(|c| c.starts_with("REST")));
+}
+
+#[tokio::test]
+async fn ftp_download_resumes_from_part_file() {
+    let body = test_body(100 * 1024);
+    let (server, commands) = ftp_server("data.bin", body.clone()).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.bin");
+    std::fs::write(get_part_path(&path), &body[..40_000]).unwrap();
+
+    let url = format!("{}/data.bin", server);
+    let summary = dispatch(&Client::new(), &url, &path, &HttpOptions::default())
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(summary.resumed);
+    assert_eq!(summary.bytes_downloaded, body.len() as u64 - 40_000);
+    assert!(commands.lock().unwrap().contains(&"REST 40000".to_string()));
+
+    // 要求续传但没有已下载的部分时不会发起下载
+    let other = dir.path().join("other.bin");
+    let options = HttpOptions {
+        resume: ResumeMode::Require,
+        ..HttpOptions::default()
+    };
+    let err = dispatch(&Client::new(), &url, &other, &options)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), "cannot_resume");
+}
+
+#[tokio::test]
+async fn missing_ftp_file_is_reported() {
+    let (server, _) = ftp_server("data.bin", test_body(10)).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("missing.bin");
+
+    let err = dispatch(
+        &Client::new(),
+        &format!("{}/missing.bin", server),
+        &path,
+        &HttpOptions::default(),
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(err.kind(), "ftp");
+    assert!(err.to_string().contains("550"), "{}", err);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn probe_size_is_reused_for_the_download() {
+    let body = test_body(10 * 1024);
+    let (server, commands) = ftp_server("data.bin", body.clone()).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.bin");
+
+    let url = format!("{}/data.bin", server);
+    let options = HttpOptions::default();
+    let probe = probe_url(&Client::new(), &url, &options).await.unwrap();
+    dispatch_probed(&Client::new(), &url, &path, Some(probe), &options)
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    let commands = commands.lock().unwrap();
+    assert_eq!(commands.iter().filter(|c| c.starts_with("SIZE")).count(), 1);
+}
+
+/// 数据已经收全，但服务器没有确认传输完整，不能当作下载成功
+#[tokio::test]
+async fn aborted_transfer_is_not_renamed() {
+    let body = test_body(10 * 1024);
+    let quirks = Quirks {
+        abort_transfer: true,
+        ..Quirks::default()
+    };
+    let (server, _) = ftp_server_with("data.bin", body.clone(), quirks).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.bin");
+
+    let err = dispatch(
+        &Client::new(),
+        &format!("{}/data.bin", server),
+        &path,
+        &HttpOptions::default(),
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(err.kind(), "ftp");
+    assert!(err.to_string().contains("426"), "{}", err);
+    assert!(!path.exists());
+    assert_eq!(std::fs::read(get_part_path(&path)).unwrap(), body);
+}
+
+#[tokio::test]
+async fn part_file_older_than_the_server_file_is_not_resumed() {
+    let body = test_body(10 * 1024);
+    let quirks = Quirks {
+        mdtm: Some("20991231235959"),
+        ..Quirks::default()
+    };
+    let (server, commands) = ftp_server_with("data.bin", body.clone(), quirks).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.bin");
+    // 长度与服务器上的文件相同，没有 MDTM 时会被直接当作已下载完成
+    std::fs::write(get_part_path(&path), vec![0; body.len()]).unwrap();
+
+    let url = format!("{}/data.bin", server);
+    let require = HttpOptions {
+        resume: ResumeMode::Require,
+        ..HttpOptions::default()
+    };
+    let err = dispatch(&Client::new(), &url, &path, &require)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), "cannot_resume");
+
+    let summary = dispatch(&Client::new(), &url, &path, &HttpOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(!summary.resumed);
+    let commands = commands.lock().unwrap();
+    assert!(commands.contains(&"RETR data.bin".to_string()));
+    assert!(!commands.iter().any(|c| c.starts_with("REST")));
+}
+
+#[tokio::test]
+async fn part_file_newer_than_the_server_file_is_resumed() {
+    let body = test_body(10 * 1024);
+    let quirks = Quirks {
+        mdtm: Some("20000101000000.000"),
+        ..Quirks::default()
+    };
+    let (server, commands) = ftp_server_with("data.bin", body.clone(), quirks).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.bin");
+    std::fs::write(get_part_path(&path), &body[..4000]).unwrap();
+
+    let summary = dispatch(
+        &Client::new(),
+        &format!("{}/data.bin", server),
+        &path,
+        &HttpOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(summary.resumed);
+    assert!(commands.lock().unwrap().contains(&"REST 4000".to_string()));
+}
+
+#[tokio::test]
+async fn stalled_data_connection_times_out_when_writing_to_a_stream() {
+    let quirks = Quirks {
+        stall_data: true,
+        ..Quirks::default()
+    };
+    let (server, _) = ftp_server_with("data.bin", test_body(1024), quirks).await;
+    let options = HttpOptions {
+        read_timeout: Some(Duration::from_millis(200)),
+        ..HttpOptions::default()
+    };
+
+    let mut out = Vec::new();
+    let err = dispatch_to_writer(
+        &Client::new(),
+        &format!("{}/data.bin", server),
+        &mut out,
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(err.kind(), "read_timeout");
+    assert!(out.is_empty());
+}
+
+#[tokio::test]
+async fn writing_to_a_stream_can_be_cancelled() {
+    let quirks = Quirks {
+        stall_data: true,
+        ..Quirks::default()
+    };
+    let (server, _) = ftp_server_with("data.bin", test_body(1024), quirks).await;
+    let cancel = CancellationToken::new();
+    let options = HttpOptions {
+        read_timeout: None,
+        cancel: Some(cancel.clone()),
+        ..HttpOptions::default()
+    };
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        cancel.cancel();
+    });
+
+    let err = dispatch_to_writer(
+        &Client::new(),
+        &format!("{}/data.bin", server),
+        &mut Vec::new(),
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(err.is_cancelled(), "{}", err);
+}
+
+#[tokio::test]
+async fn writing_to_a_stream_respects_max_size() {
+    let (server, commands) = ftp_server("data.bin", test_body(10 * 1024)).await;
+    let options = HttpOptions {
+        max_size: Some(1000),
+        ..HttpOptions::default()
+    };
+
+    let mut out = Vec::new();
+    let err = dispatch_to_writer(
+        &Client::new(),
+        &format!("{}/data.bin", server),
+        &mut out,
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert_eq!(err.kind(), "too_large");
+    assert!(out.is_empty());
+    // SIZE 已经说明文件过大，不会再开始传输
+    assert!(
+        !commands
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|c| c.starts_with("RETR"))
+    );
+}
+
+#[tokio::test]
+async fn ftps_data_connection_is_secured_after_retr() {
+    let body = test_body(100 * 1024);
+    let quirks = Quirks {
+        tls: Some(tls_acceptor()),
+        ..Quirks::default()
+    };
+    let (server, commands) = ftp_server_with("data.bin", body.clone(), quirks).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("data.bin");
+    let url = format!("{}/data.bin", server);
+    // 在 RETR 之前就开始数据连接的握手会与服务器互相等待，直到超时
+    let options = HttpOptions {
+        insecure: true,
+        connect_timeout: Some(Duration::from_secs(5)),
+        ..HttpOptions::default()
+    };
+
+    dispatch(&Client::new(), &url, &path, &options)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(commands.lock().unwrap().contains(&"PROT P".to_string()));
+
+    let mut out = Vec::new();
+    dispatch_to_writer(&Client::new(), &url, &mut out, &options)
+        .await
+        .unwrap();
+    assert_eq!(out, body);
+
+    // 默认校验证书，自签名证书被拒绝
+    let err = dispatch(
+        &Client::new(),
+        &url,
+        &dir.path().join("other.bin"),
+        &HttpOptions::default(),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(err.kind(), "ftp");
+    assert!(err.to_string().contains("TLS"), "{}", err);
+}
@@ -1,256 +1,3207 @@
+use bytes::{Bytes, BytesMut};
 use futures_util::{StreamExt, stream};
-use indicatif::{ProgressBar, ProgressStyle};
+pub use indicatif::ProgressBar;
+use indicatif::ProgressStyle;
 use log::debug;
-use reqwest::Client;
-use reqwest::header::CONTENT_TYPE;
+use reqwest::header::{
+    ACCEPT_ENCODING, CONTENT_RANGE, CONTENT_TYPE, ETAG, HeaderMap, HeaderValue, IF_RANGE, RANGE,
+};
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs::{File, OpenOptions};
-use std::io::{Read, Seek, Write};
-use std::path::Path;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio::sync::{Semaphore, SemaphorePermit};
+pub use tokio_util::sync::CancellationToken;
 
 // 修正导入路径，直接从 rdownloader_utils 导入
-use rdownloader_utils::{ChunkState, create_chunks, get_state_path};
+use rdownloader_utils::{
+    Checksum, ChunkState, ChunkStrategy, DEFAULT_CHUNK_SIZE, RateLimiter, chunk_hash,
+    compute_checksum, content_encoding, content_range_start, create_chunks, default_state_dir,
+    dir_is_writable, file_url_path, get_part_path, get_state_path, is_retryable_status,
+    mime_essence, move_file, parse_content_range, read_exact_at, state_file_name, target_headers,
+    temp_base_path, validate_chunks, write_at, write_file_atomic,
+};
+
+/// 多线程模式下默认的并发连接数
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+/// 顺序写入 .part 文件时默认的写缓冲区大小 (256KB)
+pub const DEFAULT_WRITE_BUFFER_SIZE: usize = 256 * 1024;
+/// 单个数据块默认的最大尝试次数 (包含第一次请求)
+pub const DEFAULT_CHUNK_MAX_ATTEMPTS: u32 = 3;
+/// 数据块第一次重试前的默认等待时间，之后每次重试翻倍
+pub const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+/// 探测请求失败后默认的最大重试次数 (不含第一次请求)
+pub const DEFAULT_PROBE_RETRIES: u32 = 2;
+/// 探测请求第一次重试前的默认等待时间，之后每次重试翻倍
+pub const DEFAULT_PROBE_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// 探测请求两次重试之间的默认最长等待时间
+pub const DEFAULT_PROBE_MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// 默认每完成多少个数据块将状态文件写入一次磁盘
+pub const DEFAULT_STATE_SAVE_EVERY: usize = 16;
+/// 状态文件两次写入之间的默认最长间隔
+pub const DEFAULT_STATE_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+/// 默认的读取超时：等待响应头或下一段数据超过该时长即视为连接停滞
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+/// 默认每隔多久报告一次数据块状态
+pub const DEFAULT_CHUNK_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// 自动模式下启用多线程下载的默认文件大小阈值
+pub const DEFAULT_MIN_MULTIPART_SIZE: u64 = 1024 * 1024; // 1MB
+
+/// 存在未完成的下载 (状态文件) 时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResumeMode {
+    /// 状态文件通过校验 (URL、大小、ETag/Last-Modified、数据块布局) 时续传，否则从头下载
+    #[default]
+    Auto,
+    /// 忽略并删除已有的状态文件和 .part 文件，总是从头下载
+    Restart,
+    /// 必须续传：没有可用的状态文件时返回 [`DownloadError::CannotResume`]，并保留已有文件
+    Require,
+}
+
+/// 最终文件已经存在 (已完成的下载，而不是未完成的 .part 文件) 时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverwritePolicy {
+    /// 报错，要求调用方明确选择覆盖或跳过
+    #[default]
+    Error,
+    /// 保留已有文件，跳过下载并视为成功
+    Skip,
+    /// 重新下载并替换已有文件
+    Overwrite,
+}
+
+/// 单线程/多线程模式的选择方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DownloadMode {
+    /// 服务器支持 Range 且文件大于 [`HttpOptions::min_multipart_size`] 时使用多线程
+    #[default]
+    Auto,
+    /// 无论文件大小，总是使用单线程下载 (仍支持断点续传)
+    Single,
+    /// 只要服务器支持 Range 就使用多线程，忽略大小阈值
+    Multi,
+}
+
+/// 下载执行阶段的可调参数
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    /// 多线程模式下每个数据块的大小 (字节)，必须大于 0
+    pub chunk_size: u64,
+    /// 设置后数据块大小从 `chunk_size` 开始逐块翻倍，直到该值 (字节)，
+    /// 见 [`ChunkStrategy::Exponential`]。为 `None` 时所有数据块都是 `chunk_size`
+    pub max_chunk_size: Option<u64>,
+    /// 多线程下载时至少划分的数据块数：文件按 `chunk_size` 划分的块数不足时缩小数据块
+    /// (不小于 [`rdownloader_utils::MIN_CHUNK_SIZE`])，见 [`ChunkStrategy::with_min_chunks`]。
+    /// 为 `None` 时等于 `concurrency`，为 `Some(1)` 时总是使用 `chunk_size`
+    pub min_chunks: Option<usize>,
+    /// 多线程模式下同时进行的数据块请求数，必须大于等于 1。启用自适应并发时为并发数的上限
+    pub concurrency: usize,
+    /// 启用自适应并发：从该并发数开始，数据块顺利完成且吞吐量提高时逐步增加 (最多到 `concurrency`)，
+    /// 出现错误、超时或 429 时减半 (最少为 1)。为 `None` 时始终使用 `concurrency` 个并发请求
+    pub initial_concurrency: Option<usize>,
+    /// 下载完成后需要校验的文件摘要，为 `None` 时跳过校验
+    pub checksum: Option<Checksum>,
+    /// 附加到每一个请求上的自定义请求头 (例如 Authorization、Referer)
+    pub headers: HeaderMap,
+    /// 单个数据块的最大尝试次数 (包含第一次请求)，必须大于等于 1
+    pub chunk_max_attempts: u32,
+    /// 数据块第一次重试前的等待时间，之后按指数退避翻倍
+    pub retry_backoff: Duration,
+    /// 探测请求遇到网络错误或非 2xx 响应后的最大重试次数 (不含第一次请求)
+    pub probe_retries: u32,
+    /// 探测请求第一次重试前的等待时间，之后按指数退避翻倍
+    pub probe_retry_delay: Duration,
+    /// 探测请求重试等待时间的上限
+    pub probe_max_retry_delay: Duration,
+    /// 在 [一半, 全部] 之间随机选取探测请求的重试等待时间，避免大量客户端同时重试同一个 CDN
+    pub probe_retry_jitter: bool,
+    /// 探测请求和数据块请求遇到任何错误都重试，包括 403、404 等通常不会自行恢复的 HTTP 状态。
+    /// 默认只重试网络错误、超时和暂时性的状态码 (见 [`rdownloader_utils::is_retryable_status`])
+    pub retry_all_errors: bool,
+    /// 所有并发请求合计的最大下载速度 (字节/秒)，为 `None` 时不限速
+    pub max_speed: Option<u64>,
+    /// 进度回调。提供时只通过回调上报进度；为 `None` 时在终端显示默认的进度条
+    pub on_progress: Option<ProgressCallback>,
+    /// 调用方提供的进度条，例如加入了 `MultiProgress` 的进度条。提供时代替默认的进度条，
+    /// 不受 `quiet` 和 `on_progress` 影响：下载开始时重置位置和长度，保留它自己的样式
+    pub progress_bar: Option<ProgressBar>,
+    /// 数据块状态回调，用于观察哪些数据块正在下载、已完成或反复失败
+    pub on_chunk_progress: Option<ChunkProgressCallback>,
+    /// 调用 `on_chunk_progress` 的间隔
+    pub chunk_report_interval: Duration,
+    /// 安静模式：不显示进度条。状态信息总是通过 `log` 记录并通过 `on_event` 上报，不受此选项影响
+    pub quiet: bool,
+    /// 每累计完成多少个数据块写入一次状态文件，必须大于等于 1
+    pub state_save_every: usize,
+    /// 有未保存的完成记录时，距上次写入超过该时长也会写入状态文件
+    pub state_save_interval: Duration,
+    /// 取消令牌。触发后不再发起新的数据块请求，已在写入的数据块会写完并保存状态，
+    /// 随后返回 [`DownloadError::Cancelled`]，之后可以再次调用以续传
+    pub cancel: Option<CancellationToken>,
+    /// 暂停控制。暂停期间不再发起新的数据块请求，已在传输的数据块会写完，
+    /// 状态文件立即写入一次；恢复后继续下载剩余的数据块
+    pub pause: Option<PauseHandle>,
+    /// 多个下载共用的连接数上限。每个数据块请求在发出前取得一个名额，读完响应后归还；
+    /// 流式下载在整个传输期间占用一个名额。同一个限制的克隆共享名额，为 `None` 时不限制
+    pub connection_limit: Option<ConnectionLimit>,
+    /// 跳过开始下载前的磁盘剩余空间检查，适用于支持稀疏文件或剩余空间无法准确查询的文件系统
+    pub skip_space_check: bool,
+    /// 不校验各个数据块的 Content-Type 是否与探测时一致，
+    /// 适用于 Content-Type 会合理变化的服务器
+    pub skip_content_check: bool,
+    /// 读取超时 (看门狗)：等待响应头或下一段数据超过该时长时放弃本次请求，
+    /// 数据块会按重试策略重新请求。为 `None` 时不限制
+    pub read_timeout: Option<Duration>,
+    /// 不经过 [`Client`] 的连接 (FTP 的控制连接和数据连接) 的建立超时，为 `None` 时不限制。
+    /// HTTP 请求的连接超时在构建 `Client` 时设置
+    pub connect_timeout: Option<Duration>,
+    /// 不经过 [`Client`] 的 TLS 连接 (FTPS) 不校验服务器证书，只应在测试环境中使用。
+    /// HTTP 请求的证书校验在构建 `Client` 时设置
+    pub insecure: bool,
+    /// 单线程/多线程模式的选择方式，默认根据文件大小自动选择
+    pub mode: DownloadMode,
+    /// 自动模式下，文件大于该大小 (字节) 时才使用多线程
+    pub min_multipart_size: u64,
+    /// 与主地址内容完全相同的镜像地址。数据块在当前地址上用尽重试次数后，依次改用下一个镜像。
+    /// 这里不做一致性校验，调用方 (调度器) 负责只传入大小和 ETag 都一致的镜像
+    pub mirrors: Vec<String>,
+    /// 只下载文件中的这一段字节 (包含两端)，保存的文件内容就是这一段数据。
+    /// 设置后调度器不再选择单线程或多线程模式，而是调用 [`download_range`] 发起一个范围请求
+    pub byte_range: Option<RangeInclusive<u64>>,
+    /// 存在未完成的下载时是否续传，默认在状态文件通过校验时续传
+    pub resume: ResumeMode,
+    /// 最终文件已经存在时的处理方式，默认报错
+    pub overwrite: OverwritePolicy,
+    /// 条件下载：最终文件已存在时，以它的修改时间在探测请求上发送 `If-Modified-Since`，
+    /// 服务器返回 `304 Not Modified` 时跳过下载，否则下载并覆盖该文件
+    pub if_newer: bool,
+    /// 条件下载：最终文件已存在时，在探测请求上发送 `If-None-Match`，处理方式同 `if_newer`
+    pub if_none_match: Option<String>,
+    /// 结构化事件回调，接收探测结果、状态信息、警告和完成事件。库不向终端打印状态信息，
+    /// 需要显示时由调用方在回调中处理
+    pub on_event: Option<EventCallback>,
+    /// 存放状态文件的目录，见 [`resolve_state_path`]。为 `None` 时状态文件放在目标文件旁边
+    pub state_dir: Option<PathBuf>,
+    /// 下载期间存放 .part 文件的目录 (不存在时自动创建)，见 [`resolve_part_path`]。
+    /// 完成后移动到最终路径，不在同一个文件系统上时改为复制后删除。
+    /// 未指定 `state_dir` 时状态文件也放在这里。为 `None` 时 .part 文件放在目标文件旁边
+    pub temp_dir: Option<PathBuf>,
+    /// 不使用状态文件：既不读取、写入，也不删除 `.rdownload`。本次运行仍在内存中跟踪各个数据块，
+    /// 但中断后无法跨运行续传，下次运行会从头下载
+    pub no_state: bool,
+    /// 允许下载的最大文件大小 (字节)。大小已知时在开始下载前检查；大小未知的流式下载在写入
+    /// (解压后的) 数据超过该值时中止并保留已下载的部分。为 `None` 时不限制
+    pub max_size: Option<u64>,
+    /// 流式下载 (大小未知或写入数据流) 时，按 `Content-Encoding` 自动解压 gzip、deflate 和 br，
+    /// 写入解压后的数据。关闭时按原样保存压缩数据。多线程和可续传的下载总是要求不做内容编码
+    pub decompress: bool,
+    /// 多线程下载时通过内存映射写入 .part 文件：预分配完整大小后映射整个文件，各数据块直接复制到
+    /// 映射区域中。无法映射时 (例如文件长度与总大小不一致，或平台不支持) 自动改用按偏移写入
+    pub mmap: bool,
+    /// 流式下载 (大小未知、范围下载和 FTP 等数据流) 顺序写入 .part 文件时的写缓冲区大小 (字节)。
+    /// 响应数据往往按很小的片段到达，缓冲后合并成较大的写入，减少系统调用。
+    /// 记录续传进度前总是先写出缓冲区。为 0 时不缓冲。多线程下载按偏移直接写入，不受影响
+    pub write_buffer_size: usize,
+    /// 续传前重新读取已完成的数据块，与完成时记录的哈希比较，只跳过校验通过的数据块；
+    /// 不一致或没有记录哈希的数据块重新下载。读取整个 .part 文件较慢，默认关闭
+    pub verify_resume: bool,
+    /// 下载完成 (并通过校验和检查) 后，把 `.gz`/`.tgz` 文件解压到去掉压缩扩展名的路径，
+    /// 见 [`unpacked_path`]。与 `decompress` 不同，这里处理的是文件本身的压缩格式
+    pub unpack: bool,
+    /// 解压成功后删除下载的压缩文件，只在 `unpack` 开启时有效
+    pub remove_compressed: bool,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        HttpOptions {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_chunk_size: None,
+            min_chunks: None,
+            concurrency: DEFAULT_CONCURRENCY,
+            initial_concurrency: None,
+            checksum: None,
+            headers: HeaderMap::new(),
+            chunk_max_attempts: DEFAULT_CHUNK_MAX_ATTEMPTS,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+            probe_retries: DEFAULT_PROBE_RETRIES,
+            probe_retry_delay: DEFAULT_PROBE_RETRY_DELAY,
+            probe_max_retry_delay: DEFAULT_PROBE_MAX_RETRY_DELAY,
+            probe_retry_jitter: false,
+            retry_all_errors: false,
+            max_speed: None,
+            on_progress: None,
+            progress_bar: None,
+            on_chunk_progress: None,
+            chunk_report_interval: DEFAULT_CHUNK_REPORT_INTERVAL,
+            quiet: false,
+            state_save_every: DEFAULT_STATE_SAVE_EVERY,
+            state_save_interval: DEFAULT_STATE_SAVE_INTERVAL,
+            cancel: None,
+            pause: None,
+            connection_limit: None,
+            skip_space_check: false,
+            skip_content_check: false,
+            read_timeout: Some(DEFAULT_READ_TIMEOUT),
+            connect_timeout: None,
+            insecure: false,
+            mode: DownloadMode::Auto,
+            min_multipart_size: DEFAULT_MIN_MULTIPART_SIZE,
+            mirrors: Vec::new(),
+            byte_range: None,
+            resume: ResumeMode::Auto,
+            overwrite: OverwritePolicy::Error,
+            if_newer: false,
+            if_none_match: None,
+            on_event: None,
+            state_dir: None,
+            temp_dir: None,
+            no_state: false,
+            max_size: None,
+            decompress: true,
+            mmap: false,
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            verify_resume: false,
+            unpack: false,
+            remove_compressed: false,
+        }
+    }
+}
+
+impl HttpOptions {
+    fn validate(&self) -> Result<(), DownloadError> {
+        if self.chunk_size == 0 {
+            return Err(DownloadError::InvalidOption(
+                "chunk size must be greater than 0".into(),
+            ));
+        }
+        if self.max_chunk_size.is_some_and(|max| max < self.chunk_size) {
+            return Err(DownloadError::InvalidOption(
+                "maximum chunk size must not be smaller than the chunk size".into(),
+            ));
+        }
+        if self.min_chunks == Some(0) {
+            return Err(DownloadError::InvalidOption(
+                "minimum number of chunks must be at least 1".into(),
+            ));
+        }
+        if self.concurrency == 0 {
+            return Err(DownloadError::InvalidOption(
+                "number of connections must be at least 1".into(),
+            ));
+        }
+        if self
+            .initial_concurrency
+            .is_some_and(|start| start == 0 || start > self.concurrency)
+        {
+            return Err(DownloadError::InvalidOption(
+                "initial number of connections must be between 1 and the number of connections"
+                    .into(),
+            ));
+        }
+        if self.chunk_max_attempts == 0 {
+            return Err(DownloadError::InvalidOption(
+                "chunk attempts must be at least 1".into(),
+            ));
+        }
+        if self.max_speed == Some(0) {
+            return Err(DownloadError::InvalidOption(
+                "max speed must be greater than 0".into(),
+            ));
+        }
+        if self.read_timeout == Some(Duration::ZERO) {
+            return Err(DownloadError::InvalidOption(
+                "read timeout must be greater than 0; use None for no limit".into(),
+            ));
+        }
+        if let Some(range) = &self.byte_range
+            && range.is_empty()
+        {
+            return Err(DownloadError::InvalidOption(
+                "the start of the byte range must not be after its end".into(),
+            ));
+        }
+        if self.state_save_every == 0 {
+            return Err(DownloadError::InvalidOption(
+                "state save frequency must be at least 1 chunk".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+    }
+
+    fn rate_limiter(&self) -> Option<Arc<RateLimiter>> {
+        self.max_speed
+            .map(|speed| Arc::new(RateLimiter::new(speed)))
+    }
+
+    /// 由 `chunk_size`、`max_chunk_size` 和 `min_chunks` 确定的 `total_size` 字节文件的数据块划分方式
+    pub fn chunk_strategy(&self, total_size: u64) -> ChunkStrategy {
+        let strategy = match self.max_chunk_size {
+            Some(max) => ChunkStrategy::Exponential {
+                start: self.chunk_size,
+                max,
+            },
+            None => ChunkStrategy::Fixed(self.chunk_size),
+        };
+        let min_chunks = self.min_chunks.unwrap_or(self.concurrency);
+        strategy.with_min_chunks(total_size, min_chunks as u64)
+    }
+}
+
+/// 以 `info` 级别记录状态信息，有事件回调时同时作为 [`DownloadEvent::Status`] 上报。
+/// 库本身不向终端打印状态，如何显示由调用方决定
+macro_rules! status {
+    ($options:expr, $($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        log::info!("{}", message);
+        if let Some(on_event) = &$options.on_event {
+            on_event.emit(&DownloadEvent::Status(message));
+        }
+    }};
+}
+
+/// 以 `warn` 级别记录需要用户注意的情况，有事件回调时同时作为 [`DownloadEvent::Warning`] 上报
+macro_rules! warning {
+    ($options:expr, $($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        log::warn!("{}", message);
+        if let Some(on_event) = &$options.on_event {
+            on_event.emit(&DownloadEvent::Warning(message));
+        }
+    }};
+}
+
+/// 下载过程中的结构化事件，便于调用方 (例如命令行的 `--json` 模式) 代替终端上的状态文字
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadEvent {
+    /// 探测完成，即将开始下载
+    Probed {
+        /// 跟随重定向之后的最终地址
+        resolved_url: String,
+        /// 文件总大小，无法确定时为 `None`
+        size: Option<u64>,
+        /// 服务器是否声明支持 Range 请求 (`Accept-Ranges: bytes` 或返回了 Content-Range)
+        supports_range: bool,
+        /// 是否确认可以分块下载和续传 (带 Range 的探测请求得到了有效的 206 响应)，
+        /// 声明支持 Range 却总是返回 `200` 的服务器只有 `supports_range` 为 `true`
+        supports_resume: bool,
+    },
+    /// 下载过程中的状态信息 (同时以 `info` 级别写入日志)
+    Status(String),
+    /// 需要用户注意但不会中止下载的情况，例如改用较慢的下载方式 (同时以 `warn` 级别写入日志)
+    Warning(String),
+    /// 文件已保存到最终路径 (包括按 [`OverwritePolicy::Skip`] 保留的已有文件)
+    Finished(DownloadSummary),
+}
+
+/// 一次成功下载的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadSummary {
+    /// 最终的保存路径，开启 [`HttpOptions::unpack`] 时为解压得到的文件
+    pub path: PathBuf,
+    /// 文件的总大小 (字节)
+    pub total_size: u64,
+    /// 本次实际下载的字节数，不含续传之前已经下载的部分
+    pub bytes_downloaded: u64,
+    /// 是否从之前保存的进度继续下载
+    pub resumed: bool,
+    /// 是否使用了多线程分块下载
+    pub multipart: bool,
+    /// 文件已存在 (或服务器报告文件没有变化) 而跳过了下载
+    pub skipped: bool,
+    /// 下载用时
+    pub elapsed: Duration,
+    /// 以 1 秒为窗口采样得到的最高下载速度 (字节/秒)，下载不足 1 秒时为平均速度
+    pub peak_speed: u64,
+    /// 本次下载的数据块数，不含续传之前已完成的数据块。大小未知的流式下载计为 1 个，跳过时为 0
+    pub chunks: usize,
+    /// 数据块请求的重试次数 (包括改用镜像后的请求)
+    pub retries: u32,
+    /// 之前保存的进度无法使用、本次从头下载的原因，例如 `"ETag changed from \"a\" to \"b\""`。
+    /// 没有保存的进度或按 [`ResumeMode::Restart`] 主动放弃时为 `None`
+    pub restart_reason: Option<String>,
+}
+
+impl DownloadSummary {
+    /// 本次下载的平均速度 (字节/秒)，按 `bytes_downloaded` 和 `elapsed` 计算
+    pub fn average_speed(&self) -> u64 {
+        bytes_per_sec(self.bytes_downloaded, self.elapsed)
+    }
+}
+
+fn bytes_per_sec(bytes: u64, elapsed: Duration) -> u64 {
+    if elapsed.is_zero() {
+        return 0;
+    }
+    (bytes as f64 / elapsed.as_secs_f64()) as u64
+}
+
+/// 结构化事件回调，回调在下载任务中同步调用，应尽快返回
+#[derive(Clone)]
+pub struct EventCallback(Arc<dyn Fn(&DownloadEvent) + Send + Sync>);
+
+impl EventCallback {
+    pub fn new(callback: impl Fn(&DownloadEvent) + Send + Sync + 'static) -> Self {
+        EventCallback(Arc::new(callback))
+    }
+
+    pub fn emit(&self, event: &DownloadEvent) {
+        (self.0)(event)
+    }
+}
+
+impl fmt::Debug for EventCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventCallback")
+    }
+}
+
+/// 在进程内暂停和恢复下载的控制句柄，克隆得到的句柄共享同一个状态。
+///
+/// 暂停只作用于数据块之间：正在传输的数据块会完整写入，之后的数据块任务原地等待，
+/// 直到调用 [`PauseHandle::resume`] 或下载被取消。
+#[derive(Clone, Debug)]
+pub struct PauseHandle(Arc<tokio::sync::watch::Sender<bool>>);
+
+impl Default for PauseHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PauseHandle {
+    pub fn new() -> Self {
+        PauseHandle(Arc::new(tokio::sync::watch::Sender::new(false)))
+    }
+
+    pub fn pause(&self) {
+        self.0.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.0.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// 暂停期间一直等待，未暂停时立即返回
+    async fn wait_resumed(&self) {
+        let mut receiver = self.0.subscribe();
+        // 发送端由 self 持有，不会在等待期间关闭
+        let _ = receiver.wait_for(|paused| !paused).await;
+    }
+}
+
+/// 在多个同时进行的下载之间限制请求总数的句柄，克隆得到的句柄共享同一组名额。
+///
+/// 例如批量下载 10 个文件、每个文件 8 个并发连接时，总共可能同时打开 80 个连接；
+/// 让所有下载使用同一个 `ConnectionLimit::new(16)` 即可把总数限制在 16 个以内。
+#[derive(Clone, Debug)]
+pub struct ConnectionLimit(Arc<Semaphore>);
+
+impl ConnectionLimit {
+    /// 最多允许 `max_connections` 个请求同时进行，为 0 时按 1 处理
+    pub fn new(max_connections: usize) -> Self {
+        ConnectionLimit(Arc::new(Semaphore::new(max_connections.max(1))))
+    }
+
+    /// 当前空闲的名额数
+    pub fn available(&self) -> usize {
+        self.0.available_permits()
+    }
+
+    /// 等待一个空闲名额，返回的许可在丢弃时归还名额
+    async fn acquire(&self) -> Option<SemaphorePermit<'_>> {
+        // 信号量从不关闭，acquire 不会失败
+        self.0.acquire().await.ok()
+    }
+}
+
+/// 一次多线程下载内部的自适应并发控制 (AIMD)。
+///
+/// 数据块请求在发出前取得一个名额，名额总数就是当前的并发数。每一轮 (一轮开始时的并发数个数据块)
+/// 都顺利完成、且吞吐量高于上一轮时，并发数加 1，直到 `max`；一轮中第一次出现错误时并发数减半，
+/// 最少为 1。减少的名额如果正在使用，就在归还时收回
+struct AdaptiveConcurrency {
+    semaphore: Semaphore,
+    /// 当前的并发数
+    limit: AtomicUsize,
+    max: usize,
+    round: std::sync::Mutex<Round>,
+    on_event: Option<EventCallback>,
+}
+
+/// [`AdaptiveConcurrency`] 当前一轮的统计
+struct Round {
+    /// 本轮需要的数据块结果数
+    target: usize,
+    outcomes: usize,
+    bytes: u64,
+    failed: bool,
+    started: Instant,
+    /// 上一轮的吞吐量 (字节/秒)
+    last_rate: f64,
+    /// 并发数减少后还需收回的名额数
+    debt: usize,
+}
+
+/// [`AdaptiveConcurrency`] 的名额，丢弃时归还 (或在并发数减少后收回)
+struct AdaptivePermit<'a> {
+    permit: Option<SemaphorePermit<'a>>,
+    owner: &'a AdaptiveConcurrency,
+}
+
+impl Drop for AdaptivePermit<'_> {
+    fn drop(&mut self) {
+        let mut round = self
+            .owner
+            .round
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if round.debt > 0
+            && let Some(permit) = self.permit.take()
+        {
+            round.debt -= 1;
+            permit.forget();
+        }
+    }
+}
+
+impl AdaptiveConcurrency {
+    fn new(start: usize, max: usize, on_event: Option<EventCallback>) -> Self {
+        let start = start.clamp(1, max.max(1));
+        AdaptiveConcurrency {
+            semaphore: Semaphore::new(start),
+            limit: AtomicUsize::new(start),
+            max: max.max(1),
+            round: std::sync::Mutex::new(Round {
+                target: start,
+                outcomes: 0,
+                bytes: 0,
+                failed: false,
+                started: Instant::now(),
+                last_rate: 0.0,
+                debt: 0,
+            }),
+            on_event,
+        }
+    }
+
+    async fn acquire(&self) -> AdaptivePermit<'_> {
+        AdaptivePermit {
+            // 信号量从不关闭，acquire 不会失败
+            permit: self.semaphore.acquire().await.ok(),
+            owner: self,
+        }
+    }
+
+    /// 记录一个数据块请求的结果：成功时为收到的字节数，失败时为 `None`
+    fn record(&self, outcome: Option<u64>) {
+        // 状态事件会调用用户的回调，在释放锁之后再发送，回调 panic 时不会让锁中毒
+        if let Some(reduced) = self.update_round(outcome) {
+            status!(self, "数据块请求失败，并发连接数降为 {}。", reduced);
+        }
+    }
+
+    /// 在锁内更新本轮的统计，并发数因失败而减少时返回减少后的值
+    fn update_round(&self, outcome: Option<u64>) -> Option<usize> {
+        let mut round = self.round.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut reduced_to = None;
+        round.outcomes += 1;
+        match outcome {
+            Some(bytes) => round.bytes += bytes,
+            None if !round.failed => {
+                round.failed = true;
+                let limit = self.limit.load(Ordering::SeqCst);
+                let reduced = (limit / 2).max(1);
+                if reduced < limit {
+                    self.limit.store(reduced, Ordering::SeqCst);
+                    let excess = limit - reduced;
+                    round.debt += excess - self.semaphore.forget_permits(excess);
+                    reduced_to = Some(reduced);
+                }
+            }
+            None => {}
+        }
+        if round.outcomes < round.target {
+            return reduced_to;
+        }
+        let rate = round.bytes as f64 / round.started.elapsed().as_secs_f64().max(1e-3);
+        let limit = self.limit.load(Ordering::SeqCst);
+        if !round.failed && rate > round.last_rate && limit < self.max {
+            self.limit.store(limit + 1, Ordering::SeqCst);
+            if round.debt > 0 {
+                round.debt -= 1;
+            } else {
+                self.semaphore.add_permits(1);
+            }
+            debug!("吞吐量提高，并发连接数增加到 {}", limit + 1);
+        }
+        *round = Round {
+            target: self.limit.load(Ordering::SeqCst),
+            outcomes: 0,
+            bytes: 0,
+            failed: false,
+            started: Instant::now(),
+            last_rate: rate,
+            debt: round.debt,
+        };
+        reduced_to
+    }
+}
+
+/// 下载进度回调，参数为 (已下载字节数, 文件总大小)，总大小未知时为 `None`。
+///
+/// 回调会在数据块写入后从下载任务中调用，应尽快返回。
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(u64, Option<u64>) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(u64, Option<u64>) + Send + Sync + 'static) -> Self {
+        ProgressCallback(Arc::new(callback))
+    }
+
+    pub fn report(&self, downloaded: u64, total: Option<u64>) {
+        (self.0)(downloaded, total)
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+/// 单个数据块当前所处的阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkStatus {
+    /// 尚未开始 (包括取消后尚未下载的数据块)
+    Pending,
+    /// 正在下载或等待重试
+    InFlight,
+    /// 已下载并写入磁盘
+    Done,
+    /// 用尽所有重试次数 (和镜像) 后仍然失败
+    Failed,
+}
+
+/// 数据块状态快照中的一项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkReport {
+    pub start: u64,
+    pub end: u64,
+    pub status: ChunkStatus,
+    /// 本次运行中已经发起的请求次数 (包含重试和切换镜像)，续传时已完成的数据块为 0
+    pub attempts: u32,
+}
+
+/// 数据块状态回调，参数为所有数据块按顺序排列的快照。
+/// 下载期间按 [`HttpOptions::chunk_report_interval`] 定期调用，结束时再调用一次。
+#[derive(Clone)]
+pub struct ChunkProgressCallback(Arc<ChunkProgressFn>);
+
+type ChunkProgressFn = dyn Fn(&[ChunkReport]) + Send + Sync;
+
+impl ChunkProgressCallback {
+    pub fn new(callback: impl Fn(&[ChunkReport]) + Send + Sync + 'static) -> Self {
+        ChunkProgressCallback(Arc::new(callback))
+    }
+}
+
+impl fmt::Debug for ChunkProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ChunkProgressCallback")
+    }
+}
+
+/// 记录每个数据块的实时状态，数据块任务之间无锁共享
+struct ChunkTracker {
+    ranges: Vec<(u64, u64)>,
+    status: Vec<AtomicU8>,
+    attempts: Vec<AtomicU32>,
+}
+
+impl ChunkTracker {
+    fn new(chunks: &[ChunkState]) -> Self {
+        ChunkTracker {
+            ranges: chunks
+                .iter()
+                .map(|chunk| (chunk.start, chunk.end))
+                .collect(),
+            status: chunks
+                .iter()
+                .map(|chunk| {
+                    let status = if chunk.completed {
+                        ChunkStatus::Done
+                    } else {
+                        ChunkStatus::Pending
+                    };
+                    AtomicU8::new(status as u8)
+                })
+                .collect(),
+            attempts: chunks.iter().map(|_| AtomicU32::new(0)).collect(),
+        }
+    }
+
+    fn set(&self, i: usize, status: ChunkStatus) {
+        self.status[i].store(status as u8, Ordering::Relaxed);
+    }
+
+    fn record_attempt(&self, i: usize) {
+        self.attempts[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 所有数据块第一次请求之后的请求次数之和
+    fn retries(&self) -> u32 {
+        self.attempts
+            .iter()
+            .map(|attempts| attempts.load(Ordering::Relaxed).saturating_sub(1))
+            .sum()
+    }
+
+    fn snapshot(&self) -> Vec<ChunkReport> {
+        self.ranges
+            .iter()
+            .enumerate()
+            .map(|(i, &(start, end))| ChunkReport {
+                start,
+                end,
+                status: match self.status[i].load(Ordering::Relaxed) {
+                    0 => ChunkStatus::Pending,
+                    1 => ChunkStatus::InFlight,
+                    2 => ChunkStatus::Done,
+                    _ => ChunkStatus::Failed,
+                },
+                attempts: self.attempts[i].load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+/// 速度采样窗口，峰值速度是各个窗口内平均速度的最大值
+const SPEED_SAMPLE_WINDOW: Duration = Duration::from_secs(1);
+
+/// 按固定窗口统计下载速度，记录下载过程中的峰值
+struct SpeedSampler {
+    window_start: Instant,
+    window_bytes: u64,
+    peak: Option<u64>,
+}
+
+impl SpeedSampler {
+    fn new() -> Self {
+        SpeedSampler {
+            window_start: Instant::now(),
+            window_bytes: 0,
+            peak: None,
+        }
+    }
+
+    fn record(&mut self, bytes: u64) {
+        self.window_bytes += bytes;
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= SPEED_SAMPLE_WINDOW {
+            let speed = bytes_per_sec(self.window_bytes, elapsed);
+            self.peak = Some(self.peak.map_or(speed, |peak| peak.max(speed)));
+            self.window_start = Instant::now();
+            self.window_bytes = 0;
+        }
+    }
+
+    /// 还没有完整的采样窗口时 (下载不足 1 秒)，以目前为止的平均速度作为峰值。
+    /// 结束时不足一个窗口的零头时间太短，单独计算的速度波动很大，因此不参与比较
+    fn peak(&self) -> u64 {
+        self.peak
+            .unwrap_or_else(|| bytes_per_sec(self.window_bytes, self.window_start.elapsed()))
+    }
+}
+
+/// 单次下载的进度上报：有回调时调用回调，否则 (非安静模式下) 驱动终端上的 indicatif 进度条
+#[derive(Clone)]
+struct Progress {
+    bar: ProgressBar,
+    callback: Option<ProgressCallback>,
+    downloaded: Arc<AtomicU64>,
+    total: Option<u64>,
+    speed: Arc<Mutex<SpeedSampler>>,
+}
+
+impl Progress {
+    fn new(total: Option<u64>, options: &HttpOptions) -> Self {
+        let callback = options.on_progress.clone();
+        // 隐藏的进度条不会绘制任何内容，包括 spinner 和结束信息
+        let bar = if let Some(bar) = &options.progress_bar {
+            // 回退到单线程重新下载时会再次创建进度，同一个进度条必须从头开始
+            bar.reset();
+            match total {
+                Some(total) => bar.set_length(total),
+                None => bar.unset_length(),
+            }
+            bar.clone()
+        } else if callback.is_some() || options.quiet {
+            ProgressBar::hidden()
+        } else if let Some(total) = total {
+            let bar = ProgressBar::new(total);
+            bar.set_style(ProgressStyle::default_bar().template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})").unwrap().progress_chars("->-"));
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bar
+        } else {
+            let bar = ProgressBar::new_spinner();
+            bar.set_style(
+                ProgressStyle::default_spinner()
+                    .template(
+                        "{spinner:.green} [{elapsed_precise}] {bytes_per_sec} - {bytes} downloaded",
+                    )
+                    .unwrap(),
+            );
+            bar.enable_steady_tick(Duration::from_millis(100));
+            bar
+        };
+        Progress {
+            bar,
+            callback,
+            downloaded: Arc::new(AtomicU64::new(0)),
+            total,
+            speed: Arc::new(Mutex::new(SpeedSampler::new())),
+        }
+    }
+
+    fn inc(&self, bytes: u64) {
+        self.speed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record(bytes);
+        self.inc_resumed(bytes);
+    }
+
+    /// 续传之前已经下载的部分：计入进度，但不参与速度统计
+    fn inc_resumed(&self, bytes: u64) {
+        let downloaded = self.downloaded.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        self.bar.inc(bytes);
+        if let Some(callback) = &self.callback {
+            callback.report(downloaded, self.total);
+        }
+    }
+
+    /// 结束进度显示，返回下载过程中的峰值速度 (字节/秒)
+    fn finish(&self) -> u64 {
+        self.bar.finish_with_message("下载完成");
+        self.speed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .peak()
+    }
+}
+
+/// 状态文件的格式版本，状态文件的字段发生变化时递增，并在 [`load_state`] 中加入对应的迁移
+const STATE_VERSION: u32 = 1;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct DownloadState {
+    version: u32,
     url: String,
     total_size: u64,
     etag: Option<String>,
+    last_modified: Option<String>,
+    /// 探测请求经过重定向后实际指向的地址，数据块请求 (包括续传) 都发往此地址。
+    /// 这是向后兼容的新增字段，旧的状态文件中没有它，读取时为 `None`。
+    #[serde(default)]
+    resolved_url: Option<String>,
     chunks: Vec<ChunkState>,
 }
 
-#[derive(Debug)]
-pub enum DownloadError {
-    NetworkError(reqwest::Error),
-    FileError(std::io::Error),
-    HttpError(reqwest::StatusCode),
-    SpawnError(tokio::task::JoinError),
-    JsonError(serde_json::Error),
-    StateError(String),
-    ChunkDownloadFailed,
-    ContentTypeMismatch, // 当数据块的 Content-Type 与期望不符时返回
+/// 版本 0：引入版本号之前写入的状态文件，没有 `version` 和 `last_modified` 字段
+#[derive(Deserialize)]
+struct DownloadStateV0 {
+    url: String,
+    total_size: u64,
+    etag: Option<String>,
+    chunks: Vec<ChunkState>,
+}
+
+impl From<DownloadStateV0> for DownloadState {
+    fn from(old: DownloadStateV0) -> Self {
+        DownloadState::new(&old.url, old.total_size, old.etag, None, old.chunks)
+    }
+}
+
+impl DownloadState {
+    fn new(
+        url: &str,
+        total_size: u64,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        chunks: Vec<ChunkState>,
+    ) -> Self {
+        DownloadState {
+            version: STATE_VERSION,
+            url: url.to_string(),
+            total_size,
+            etag,
+            last_modified,
+            resolved_url: None,
+            chunks,
+        }
+    }
+
+    /// 判断服务器上的文件是否仍是状态文件记录的那一个。
+    /// 优先比较 ETag；只有双方都没有 ETag 时才退而比较 Last-Modified。
+    fn matches_remote(&self, etag: &Option<String>, last_modified: &Option<String>) -> bool {
+        validators_match(&self.etag, &self.last_modified, etag, last_modified)
+    }
+
+    /// 状态文件不能用于续传当前的远程文件时返回具体原因，可以续传时返回 `None`。
+    /// `part_len` 为 .part 文件的实际长度，文件不存在时为 `None`
+    fn mismatch(
+        &self,
+        url: &str,
+        total_size: u64,
+        etag: &Option<String>,
+        last_modified: &Option<String>,
+        is_multipart: bool,
+        part_len: Option<u64>,
+    ) -> Option<String> {
+        if self.url != url {
+            return Some(format!("URL changed from {} to {}", self.url, url));
+        }
+        if self.total_size != total_size {
+            return Some(format!(
+                "size changed from {} to {} bytes",
+                self.total_size, total_size
+            ));
+        }
+        if !self.matches_remote(etag, last_modified) {
+            return Some(validator_change(
+                &self.etag,
+                &self.last_modified,
+                etag,
+                last_modified,
+            ));
+        }
+        if !validate_chunks(&self.chunks, total_size) {
+            return Some("the saved chunk layout does not cover the file".into());
+        }
+        // 单线程模式只发起一个覆盖整个文件的请求 (服务器可能已不再支持 Range)，
+        // 因此之前多线程下载留下的多个数据块无法在此模式下续传。
+        if !is_multipart && self.chunks.len() > 1 {
+            return Some(
+                "the saved multi-part progress cannot be resumed by a single-connection download"
+                    .into(),
+            );
+        }
+        // 已完成的数据块必须仍在 .part 文件中，例如用户删除或截断了 .part 文件却留下了状态文件
+        let completed_end = self
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.completed)
+            .map(|chunk| chunk.end + 1)
+            .max()
+            .unwrap_or(0);
+        match part_len {
+            None => return Some("the .part file is missing".into()),
+            Some(len) if len < completed_end => {
+                return Some(format!(
+                    "the .part file is shorter ({} bytes) than its completed chunks ({} bytes)",
+                    len, completed_end
+                ));
+            }
+            Some(_) => {}
+        }
+        None
+    }
+}
+
+fn validators_match(
+    saved_etag: &Option<String>,
+    saved_last_modified: &Option<String>,
+    etag: &Option<String>,
+    last_modified: &Option<String>,
+) -> bool {
+    if saved_etag.is_some() || etag.is_some() {
+        saved_etag == etag
+    } else {
+        saved_last_modified == last_modified
+    }
+}
+
+/// 描述 [`validators_match`] 不通过时具体是哪个校验器发生了变化
+fn validator_change(
+    saved_etag: &Option<String>,
+    saved_last_modified: &Option<String>,
+    etag: &Option<String>,
+    last_modified: &Option<String>,
+) -> String {
+    let show = |value: &Option<String>| value.clone().unwrap_or_else(|| "none".into());
+    if saved_etag.is_some() || etag.is_some() {
+        format!("ETag changed from {} to {}", show(saved_etag), show(etag))
+    } else {
+        format!(
+            "Last-Modified changed from {} to {}",
+            show(saved_last_modified),
+            show(last_modified)
+        )
+    }
+}
+
+/// 大小未知的流式下载的续传记录。
+///
+/// 与多线程下载共用状态文件路径，但字段不同：两种状态文件互相读取时都会解析失败，
+/// 按无效状态处理并从头下载，不会被误用。
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct StreamState {
+    version: u32,
+    url: String,
+    /// 续传请求发往的地址 (跟随重定向之后)
+    resolved_url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// 已写入 .part 文件的字节数，续传时从这里继续
+    downloaded: u64,
+}
+
+impl StreamState {
+    /// 记录不能用于续传当前的数据流时返回具体原因，`part_len` 为 .part 文件的实际长度
+    fn mismatch(
+        &self,
+        url: &str,
+        etag: &Option<String>,
+        last_modified: &Option<String>,
+        part_len: u64,
+    ) -> Option<String> {
+        if self.url != url {
+            return Some(format!("URL changed from {} to {}", self.url, url));
+        }
+        if !validators_match(&self.etag, &self.last_modified, etag, last_modified) {
+            return Some(validator_change(
+                &self.etag,
+                &self.last_modified,
+                etag,
+                last_modified,
+            ));
+        }
+        if part_len < self.downloaded {
+            return Some(format!(
+                "the .part file is shorter ({} bytes) than the saved progress ({} bytes)",
+                part_len, self.downloaded
+            ));
+        }
+        None
+    }
+}
+
+fn load_stream_state(state_path: &Path) -> Option<StreamState> {
+    let contents = std::fs::read_to_string(state_path).ok()?;
+    match serde_json::from_str::<StreamState>(&contents) {
+        Ok(state) if state.version == STATE_VERSION => Some(state),
+        Ok(state) => {
+            debug!("无法识别的流式状态文件版本 {}，将从头下载", state.version);
+            None
+        }
+        Err(e) => {
+            debug!("流式状态文件无法解析，将从头下载: {}", e);
+            None
+        }
+    }
+}
+
+fn save_stream_state(state_path: &Path, state: &StreamState) -> std::io::Result<()> {
+    let state_json = serde_json::to_string_pretty(state).map_err(std::io::Error::other)?;
+    write_file_atomic(state_path, state_json.as_bytes())
+}
+
+/// 流式写入 .part 文件，并按 [`HttpOptions::state_save_interval`] 把已写入的字节数记录到状态文件。
+/// `state` 为 `None` 时 (例如响应经过内容编码，字节偏移无法用于续传，或不使用状态文件) 只写入数据。
+/// 写入经过缓冲，保存进度前先写出缓冲区，状态文件记录的字节数不会超过文件中实际的数据
+struct StreamWriter<'a> {
+    file: BufWriter<File>,
+    state: Option<StreamState>,
+    state_path: Option<&'a Path>,
+    save_interval: Duration,
+    last_save: Instant,
+}
+
+impl StreamWriter<'_> {
+    fn save(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        if let (Some(state), Some(state_path)) = (&self.state, self.state_path) {
+            save_stream_state(state_path, state)?;
+        }
+        self.last_save = Instant::now();
+        Ok(())
+    }
+}
+
+impl Write for StreamWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.file.write(buf)?;
+        if let Some(state) = &mut self.state {
+            state.downloaded += written as u64;
+            if self.last_save.elapsed() >= self.save_interval {
+                self.save()?;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[derive(Debug)]
+pub enum DownloadError {
+    NetworkError(reqwest::Error),
+    FileError(std::io::Error),
+    HttpError(reqwest::StatusCode),
+    SpawnError(tokio::task::JoinError),
+    JsonError(serde_json::Error),
+    StateError(String),
+    InvalidOption(String), // 调用方传入的参数不合法
+    ChunkDownloadFailed,
+    ContentTypeMismatch, // 当数据块的 Content-Type 与期望不符时返回
+    ChecksumMismatch {
+        expected: String,
+        actual: String,
+    },
+    RangeNotSupported,      // 服务器忽略了 Range 请求头，对部分数据块返回了完整文件
+    ContentEncoded(String), // 服务器对 Range 响应使用了 Content-Encoding (如 gzip)，字节偏移不再可靠
+    Cancelled,              // 调用方通过取消令牌中止了下载
+    ResourceChanged,        // 下载过程中服务器上的文件发生了变化 (If-Range 校验失败或返回 416)
+    InsufficientSpace {
+        needed: u64,
+        available: u64,
+    }, // 目标磁盘的剩余空间不足以存放整个文件
+    ReadTimeout(Duration),  // 在读取超时内没有收到任何数据，连接可能已停滞
+    SizeMismatch {
+        expected: u64,
+        actual: u64,
+    }, // 所有数据块完成或顺序写入的数据流结束后，磁盘上的文件长度与总大小不一致
+    ChunkMismatch {
+        start: u64,
+        end: u64,
+        reason: String,
+    }, // 数据块响应与请求的范围不符 (起点不同或长度不一致)，该数据块会被重试
+    CannotResume(String),   // 要求续传 (ResumeMode::Require)，但没有可以续传的进度
+    DecodeError(std::io::Error), // 自动解压时压缩数据损坏或不完整
+    TooLarge {
+        size: u64,
+        limit: u64,
+    }, // 文件大小 (流式下载时为已写入的字节数) 超过了 max_size
+    TotalSizeChanged {
+        probed: u64,
+        actual: u64,
+    }, // 数据块响应的 Content-Range 总大小与探测结果不一致
+    RangeOutOfBounds {
+        start: u64,
+        end: u64,
+        size: Option<u64>,
+    }, // 要求下载的字节范围超出了文件末尾
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::NetworkError(e) => write!(f, "network error while downloading: {}", e),
+            DownloadError::FileError(e) => write!(f, "file I/O error: {}", e),
+            DownloadError::HttpError(status) => {
+                write!(f, "HTTP {} while downloading data", status)
+            }
+            DownloadError::SpawnError(e) => write!(f, "download task failed to complete: {}", e),
+            DownloadError::JsonError(e) => {
+                write!(f, "could not read or write the state file: {}", e)
+            }
+            DownloadError::StateError(msg) => write!(f, "invalid download state: {}", msg),
+            DownloadError::InvalidOption(msg) => write!(f, "invalid option: {}", msg),
+            DownloadError::ChunkDownloadFailed => write!(
+                f,
+                "some chunks failed to download; run the same command again to resume"
+            ),
+            DownloadError::ContentTypeMismatch => write!(
+                f,
+                "server returned a different Content-Type for a chunk than for the probe (possibly an error page)"
+            ),
+            DownloadError::RangeNotSupported => write!(
+                f,
+                "server ignored the Range header and returned the whole file; multipart and byte-range downloads are not possible"
+            ),
+            DownloadError::ContentEncoded(encoding) => write!(
+                f,
+                "server applied Content-Encoding '{}' to a range response; byte ranges cannot be used",
+                encoding
+            ),
+            DownloadError::ResourceChanged => write!(
+                f,
+                "the file changed on the server during the download; the partial data was discarded"
+            ),
+            DownloadError::InsufficientSpace { needed, available } => write!(
+                f,
+                "not enough disk space: the download needs {} bytes but only {} bytes are available",
+                needed, available
+            ),
+            DownloadError::SizeMismatch { expected, actual } => write!(
+                f,
+                "downloaded file has the wrong size: expected {} bytes, found {} bytes",
+                expected, actual
+            ),
+            DownloadError::ChunkMismatch { start, end, reason } => write!(
+                f,
+                "the response for bytes {}-{} does not match the requested range: {}",
+                start, end, reason
+            ),
+            DownloadError::CannotResume(reason) => {
+                write!(f, "cannot resume the download: {}", reason)
+            }
+            DownloadError::DecodeError(e) => {
+                write!(f, "could not decompress the response body: {}", e)
+            }
+            DownloadError::TooLarge { size, limit } => write!(
+                f,
+                "the download is larger than the maximum size of {} bytes (at least {} bytes)",
+                limit, size
+            ),
+            DownloadError::RangeOutOfBounds {
+                start,
+                end,
+                size: Some(size),
+            } => write!(
+                f,
+                "the requested byte range {}-{} is outside the file ({} bytes)",
+                start, end, size
+            ),
+            DownloadError::RangeOutOfBounds {
+                start,
+                end,
+                size: None,
+            } => write!(
+                f,
+                "the server cannot satisfy the requested byte range {}-{}",
+                start, end
+            ),
+            DownloadError::TotalSizeChanged { probed, actual } => write!(
+                f,
+                "the server reported a total size of {} bytes for a chunk but {} bytes when probed; the file may be generated on the fly and cannot be downloaded in chunks",
+                actual, probed
+            ),
+            DownloadError::ReadTimeout(timeout) => {
+                write!(
+                    f,
+                    "no data received for {:?}; the connection stalled",
+                    timeout
+                )
+            }
+            DownloadError::Cancelled => write!(
+                f,
+                "download was cancelled; run it again to resume from where it stopped"
+            ),
+            DownloadError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "checksum mismatch: expected {}, got {}; the downloaded .part file was kept for inspection",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl DownloadError {
+    /// 错误类别的简短标识，便于脚本按类别处理错误 (例如 `--json` 输出中的 `kind` 字段)
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DownloadError::NetworkError(_) => "network",
+            DownloadError::FileError(_) => "file",
+            DownloadError::HttpError(_) => "http_status",
+            DownloadError::SpawnError(_) => "task",
+            DownloadError::JsonError(_) => "state_file",
+            DownloadError::StateError(_) => "state",
+            DownloadError::InvalidOption(_) => "invalid_option",
+            DownloadError::ChunkDownloadFailed => "chunks_failed",
+            DownloadError::ContentTypeMismatch => "content_type_mismatch",
+            DownloadError::ChecksumMismatch { .. } => "checksum_mismatch",
+            DownloadError::RangeNotSupported => "range_not_supported",
+            DownloadError::ContentEncoded(_) => "content_encoded",
+            DownloadError::Cancelled => "cancelled",
+            DownloadError::ResourceChanged => "resource_changed",
+            DownloadError::InsufficientSpace { .. } => "insufficient_space",
+            DownloadError::ReadTimeout(_) => "read_timeout",
+            DownloadError::SizeMismatch { .. } => "size_mismatch",
+            DownloadError::ChunkMismatch { .. } => "chunk_mismatch",
+            DownloadError::CannotResume(_) => "cannot_resume",
+            DownloadError::DecodeError(_) => "decode",
+            DownloadError::TooLarge { .. } => "too_large",
+            DownloadError::TotalSizeChanged { .. } => "size_changed",
+            DownloadError::RangeOutOfBounds { .. } => "range_out_of_bounds",
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DownloadError::NetworkError(e) => Some(e),
+            DownloadError::FileError(e) => Some(e),
+            DownloadError::SpawnError(e) => Some(e),
+            DownloadError::JsonError(e) => Some(e),
+            DownloadError::DecodeError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for DownloadError {
+    fn from(err: serde_json::Error) -> Self {
+        DownloadError::JsonError(err)
+    }
+}
+impl From<reqwest::Error> for DownloadError {
+    fn from(err: reqwest::Error) -> Self {
+        DownloadError::NetworkError(err)
+    }
+}
+impl From<std::io::Error> for DownloadError {
+    fn from(err: std::io::Error) -> Self {
+        // SizeLimitWriter 通过 io::Error 报告超出大小限制，在这里还原为专门的错误
+        match err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<SizeLimitExceeded>())
+        {
+            Some(&SizeLimitExceeded { size, limit }) => DownloadError::TooLarge { size, limit },
+            None => DownloadError::FileError(err),
+        }
+    }
+}
+impl From<tokio::task::JoinError> for DownloadError {
+    fn from(err: tokio::task::JoinError) -> Self {
+        DownloadError::SpawnError(err)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn download_multipart(
+    client: &Client,
+    url: &str,
+    resolved_url: &str,
+    path: &Path,
+    total_size: u64,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content_type: Option<String>,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DownloadError> {
+    options.validate()?;
+    run_download(
+        client,
+        url,
+        resolved_url,
+        path,
+        total_size,
+        etag,
+        last_modified,
+        content_type,
+        true,
+        options,
+    )
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn download_sequential(
+    client: &Client,
+    url: &str,
+    resolved_url: &str,
+    path: &Path,
+    total_size: Option<u64>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    content_type: Option<String>,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DownloadError> {
+    options.validate()?;
+    if let Some(size) = total_size {
+        // 如果文件大小已知，则使用支持断点续传的 run_download
+        run_download(
+            client,
+            url,
+            resolved_url,
+            path,
+            size,
+            etag,
+            last_modified,
+            content_type,
+            false,
+            options,
+        )
+        .await
+    } else {
+        download_unknown_size(
+            client,
+            url,
+            resolved_url,
+            path,
+            etag,
+            last_modified,
+            options,
+        )
+        .await
+    }
+}
+
+/// 文件大小未知时的流式下载，尽力支持断点续传：
+/// 状态文件只记录已写入的字节数，续传时发送 `Range: bytes=<已下载>-`，
+/// 服务器以 206 从该位置继续时追加到 .part 文件，否则从头下载。
+async fn download_unknown_size(
+    client: &Client,
+    url: &str,
+    resolved_url: &str,
+    path: &Path,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DownloadError> {
+    let started = Instant::now();
+    let state_path = prepare_state_path(path, url, options)?;
+    let state_path = state_path.as_deref();
+    let part_path = prepare_part_path(path, options)?;
+
+    let saved_state = if options.resume == ResumeMode::Restart {
+        if state_exists(state_path) {
+            status!(options, "忽略已有的下载进度，从头开始下载。");
+        }
+        None
+    } else {
+        state_path.and_then(load_stream_state)
+    };
+    let part_len = std::fs::metadata(&part_path).map_or(0, |m| m.len());
+    let mut restart_reason = saved_state
+        .as_ref()
+        .and_then(|state| state.mismatch(url, &etag, &last_modified, part_len));
+    let resumable = saved_state.filter(|state| restart_reason.is_none() && state.downloaded > 0);
+    if resumable.is_none() && options.resume == ResumeMode::Require {
+        return Err(DownloadError::CannotResume(match &restart_reason {
+            Some(reason) => format!("the saved progress is out of date: {}", reason),
+            None if options.no_state => "saving progress is disabled".into(),
+            None if state_exists(state_path) => "the saved progress could not be read".into(),
+            None => "no saved progress was found".into(),
+        }));
+    }
+    if let Some(reason) = &restart_reason {
+        warning!(
+            options,
+            "之前保存的下载进度已失效 ({})，从头开始下载。",
+            reason
+        );
+    }
+
+    // 流式下载在整个传输期间占用同一个连接
+    let _connection =
+        acquire_connection(options.connection_limit.as_ref(), options.cancel.as_ref()).await?;
+    let mut resumed = None;
+    let res = match resumable {
+        Some(state) => {
+            status!(
+                options,
+                "检测到未完成的流式下载 (已下载 {} 字节)，尝试从断点继续。",
+                state.downloaded
+            );
+            let mut headers = target_headers(url, &state.resolved_url, &options.headers);
+            // 文件已被修改时，服务器会返回完整的新文件而不是 206
+            let validator = state
+                .etag
+                .as_deref()
+                .filter(|etag| !etag.starts_with("W/"))
+                .or(state.last_modified.as_deref());
+            if let Some(value) = validator.and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(IF_RANGE, value);
+            }
+            // 与 download_range 相同：经过内容编码的响应无法按字节偏移续传，要求原样返回
+            let request = client
+                .get(&state.resolved_url)
+                .headers(headers)
+                .header(ACCEPT_ENCODING, "identity")
+                .header(RANGE, format!("bytes={}-", state.downloaded))
+                .send();
+            let res = cancellable(
+                options.cancel.as_ref(),
+                with_read_timeout(options.read_timeout, request),
+            )
+            .await?;
+            let continues = res.status() == StatusCode::PARTIAL_CONTENT
+                && content_encoding(res.headers()).is_none()
+                && res
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(content_range_start)
+                    == Some(state.downloaded);
+            if continues {
+                resumed = Some(state);
+                res
+            } else {
+                if options.resume == ResumeMode::Require {
+                    return Err(DownloadError::CannotResume(format!(
+                        "the server did not continue from byte {} (HTTP {})",
+                        state.downloaded,
+                        res.status()
+                    )));
+                }
+                warning!(
+                    options,
+                    "服务器没有从断点继续 (HTTP {})，从头开始下载。",
+                    res.status()
+                );
+                restart_reason = Some(format!(
+                    "the server did not continue from byte {} (HTTP {})",
+                    state.downloaded,
+                    res.status()
+                ));
+                if res.status() == StatusCode::OK {
+                    // If-Range 校验失败或服务器忽略了 Range 时，响应本身就是完整的文件
+                    res
+                } else {
+                    let headers = target_headers(url, resolved_url, &options.headers);
+                    send_full_request(client, resolved_url, &headers).await?
+                }
+            }
+        }
+        None => {
+            status!(
+                options,
+                "文件大小未知，将执行流式下载 (中断后会尝试从断点继续)。"
+            );
+            let headers = target_headers(url, resolved_url, &options.headers);
+            send_full_request(client, resolved_url, &headers).await?
+        }
+    };
+
+    let (file, state) =
+        match resumed {
+            Some(state) => {
+                let mut file = OpenOptions::new().write(true).open(&part_path)?;
+                // 状态文件之后写入的数据没有记录，丢弃它们，从记录的位置继续
+                file.set_len(state.downloaded)?;
+                file.seek(SeekFrom::Start(state.downloaded))?;
+                (file, Some(state))
+            }
+            None => {
+                let file = File::create(&part_path)?;
+                // 经过内容编码的响应无法按字节偏移续传，不记录进度
+                let state = (state_path.is_some() && content_encoding(res.headers()).is_none())
+                    .then(|| StreamState {
+                        version: STATE_VERSION,
+                        url: url.to_string(),
+                        resolved_url: res.url().to_string(),
+                        etag: etag.clone(),
+                        last_modified: last_modified.clone(),
+                        downloaded: 0,
+                    });
+                if state.is_none() {
+                    remove_state(state_path)?;
+                }
+                (file, state)
+            }
+        };
+    let resumed_from = state.as_ref().map_or(0, |state| state.downloaded);
+    let mut writer = StreamWriter {
+        file: BufWriter::with_capacity(options.write_buffer_size, file),
+        state,
+        state_path,
+        save_interval: options.state_save_interval,
+        last_save: Instant::now(),
+    };
+    writer.save()?;
+
+    // 续传的响应总是未经编码的 (见上面的校验)，只有从头下载时才可能需要解压
+    let encoding = content_encoding(res.headers());
+    let mut decoder = DecodingWriter::new(
+        decoding_for(encoding.as_deref(), options),
+        SizeLimitWriter::new(&mut writer, resumed_from, options.max_size),
+    );
+    let result = stream_response(res, &mut decoder, None, resumed_from, options)
+        .await
+        .and_then(|peak_speed| decoder.finish().map(|_| peak_speed));
+    let peak_speed = match result {
+        Ok(peak_speed) => peak_speed,
+        Err(e) => {
+            // 保存最后的进度，下次运行时从这里继续
+            if let Err(save_error) = writer.save() {
+                debug!("无法保存流式下载的进度: {}", save_error);
+            }
+            return Err(e);
+        }
+    };
+    writer.flush()?;
+    drop(writer);
+    remove_state(state_path)?;
+
+    let total_size = std::fs::metadata(&part_path)?.len();
+    finalize_download(&part_path, path, options).await?;
+    Ok(DownloadSummary {
+        path: path.to_path_buf(),
+        total_size,
+        bytes_downloaded: total_size.saturating_sub(resumed_from),
+        resumed: resumed_from > 0,
+        multipart: false,
+        skipped: false,
+        elapsed: started.elapsed(),
+        peak_speed,
+        chunks: 1,
+        retries: 0,
+        restart_reason,
+    })
+}
+
+/// 只下载 `range` 范围内的字节 (包含两端) 到 `path`，保存的文件内容就是这一段数据。
+///
+/// 对 `resolved_url` 发起一个范围请求 (`file://` 地址直接读取本地文件的这一段)，不分块，
+/// 也不记录续传进度。`total_size` 已知时先检查范围是否超出文件末尾；
+/// 服务器没有按要求的范围响应时返回 [`DownloadError::RangeNotSupported`]
+#[allow(clippy::too_many_arguments)]
+pub async fn download_range(
+    client: &Client,
+    url: &str,
+    resolved_url: &str,
+    path: &Path,
+    range: RangeInclusive<u64>,
+    total_size: Option<u64>,
+    etag: Option<String>,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DownloadError> {
+    options.validate()?;
+    let (start, end) = (*range.start(), *range.end());
+    let out_of_bounds = DownloadError::RangeOutOfBounds {
+        start,
+        end,
+        size: total_size,
+    };
+    if range.is_empty() || total_size.is_some_and(|size| end >= size) {
+        return Err(out_of_bounds);
+    }
+    let len = end - start + 1;
+    if let Some(limit) = options.max_size
+        && len > limit
+    {
+        return Err(DownloadError::TooLarge { size: len, limit });
+    }
+    // 输出文件只包含其中一段，之前为同一路径保存的下载进度已不再适用
+    if !options.no_state {
+        remove_state(Some(&resolve_state_path(path, url, options)))?;
+    }
+
+    if let Some(source) = file_url_path(resolved_url) {
+        let mut file = tokio::fs::File::open(&source).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        return download_from_reader(file.take(len), path, Some(len), 0, options).await;
+    }
+
+    let started = Instant::now();
+    let mut headers = target_headers(url, resolved_url, &options.headers);
+    // 文件在探测之后被修改时，服务器返回完整的新文件而不是 206，下面按不支持 Range 报告
+    if let Some(value) = etag
+        .filter(|etag| !etag.starts_with("W/"))
+        .and_then(|etag| HeaderValue::from_str(&etag).ok())
+    {
+        headers.insert(IF_RANGE, value);
+    }
+    let _connection =
+        acquire_connection(options.connection_limit.as_ref(), options.cancel.as_ref()).await?;
+    let request = client
+        .get(resolved_url)
+        .headers(headers)
+        .header(ACCEPT_ENCODING, "identity")
+        .header(RANGE, format!("bytes={}-{}", start, end))
+        .send();
+    let res = cancellable(
+        options.cancel.as_ref(),
+        with_read_timeout(options.read_timeout, request),
+    )
+    .await?;
+    match res.status() {
+        StatusCode::PARTIAL_CONTENT => {}
+        StatusCode::RANGE_NOT_SATISFIABLE => return Err(out_of_bounds),
+        StatusCode::OK => return Err(DownloadError::RangeNotSupported),
+        status => return Err(DownloadError::HttpError(status)),
+    }
+    if let Some(encoding) = content_encoding(res.headers()) {
+        return Err(DownloadError::ContentEncoded(encoding));
+    }
+    let content_start = res
+        .headers()
+        .get(CONTENT_RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(content_range_start);
+    if content_start != Some(start) {
+        return Err(DownloadError::RangeNotSupported);
+    }
+
+    let part_path = prepare_part_path(path, options)?;
+    let mut file = BufWriter::with_capacity(options.write_buffer_size, File::create(&part_path)?);
+    let peak_speed = stream_response(res, &mut file, Some(len), 0, options).await?;
+    file.flush()?;
+    drop(file);
+    let size = std::fs::metadata(&part_path)?.len();
+    if size != len {
+        return Err(DownloadError::SizeMismatch {
+            expected: len,
+            actual: size,
+        });
+    }
+    finalize_download(&part_path, path, options).await?;
+    Ok(DownloadSummary {
+        path: path.to_path_buf(),
+        total_size: len,
+        bytes_downloaded: len,
+        resumed: false,
+        multipart: false,
+        skipped: false,
+        elapsed: started.elapsed(),
+        peak_speed,
+        chunks: 1,
+        retries: 0,
+        restart_reason: None,
+    })
+}
+
+/// 将按顺序到达的数据流 (例如 FTP 的数据连接) 写入 `path`。
+///
+/// 与流式 HTTP 下载共用 .part 文件、进度显示、限速、读取超时、取消、大小限制和最终的校验与重命名。
+/// `resumed_from` 大于 0 时保留 .part 文件的前 `resumed_from` 字节，`reader` 应从该偏移处开始提供数据。
+/// 已知 `total_size` 而数据流提前结束时保留 .part 文件并返回 [`DownloadError::SizeMismatch`]，
+/// 下次可以从该处继续
+pub async fn download_from_reader<R: AsyncRead + Unpin>(
+    reader: R,
+    path: &Path,
+    total_size: Option<u64>,
+    resumed_from: u64,
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DownloadError> {
+    receive_from_reader(reader, path, total_size, resumed_from, options)
+        .await?
+        .finish(options)
+        .await
+}
+
+/// 已完整写入 .part 文件、尚未校验和重命名的数据流，由 [`receive_from_reader`] 返回
+#[derive(Debug)]
+pub struct ReceivedDownload {
+    part_path: PathBuf,
+    summary: DownloadSummary,
+    started: Instant,
+}
+
+impl ReceivedDownload {
+    /// 校验 .part 文件并重命名为最终路径，返回下载结果
+    pub async fn finish(mut self, options: &HttpOptions) -> Result<DownloadSummary, DownloadError> {
+        finalize_download(&self.part_path, &self.summary.path, options).await?;
+        self.summary.elapsed = self.started.elapsed();
+        Ok(self.summary)
+    }
+}
+
+/// 与 [`download_from_reader`] 相同，但数据流写完后不立即重命名：
+/// 数据来源还需要另外确认传输完整时 (例如 FTP 服务器在控制连接上的 `226` 响应)，
+/// 确认之后再调用 [`ReceivedDownload::finish`]，确认失败时 .part 文件保留下来以便续传
+pub async fn receive_from_reader<R: AsyncRead + Unpin>(
+    reader: R,
+    path: &Path,
+    total_size: Option<u64>,
+    resumed_from: u64,
+    options: &HttpOptions,
+) -> Result<ReceivedDownload, DownloadError> {
+    options.validate()?;
+    let started = Instant::now();
+    let part_path = prepare_part_path(path, options)?;
+    let file = if resumed_from > 0 {
+        let mut file = OpenOptions::new().write(true).open(&part_path)?;
+        file.set_len(resumed_from)?;
+        file.seek(SeekFrom::Start(resumed_from))?;
+        file
+    } else {
+        File::create(&part_path)?
+    };
+    let mut file = BufWriter::with_capacity(options.write_buffer_size, file);
+    let mut writer = SizeLimitWriter::new(&mut file, resumed_from, options.max_size);
+    let progress = Progress::new(total_size, options);
+    progress.inc_resumed(resumed_from);
+    copy_stream(reader, &mut writer, &progress, options).await?;
+    let peak_speed = progress.finish();
+    file.flush()?;
+    drop(file);
+
+    let size = std::fs::metadata(&part_path)?.len();
+    if let Some(expected) = total_size
+        && size != expected
+    {
+        return Err(DownloadError::SizeMismatch {
+            expected,
+            actual: size,
+        });
+    }
+    Ok(ReceivedDownload {
+        part_path,
+        summary: DownloadSummary {
+            path: path.to_path_buf(),
+            total_size: size,
+            bytes_downloaded: size - resumed_from,
+            resumed: resumed_from > 0,
+            multipart: false,
+            skipped: false,
+            elapsed: started.elapsed(),
+            peak_speed,
+            chunks: 1,
+            retries: 0,
+            restart_reason: None,
+        },
+        started,
+    })
+}
+
+/// 将按顺序到达的数据流 (例如 FTP 的数据连接或本地文件) 直接写入任意 [`Write`]，不经过 .part 文件。
+///
+/// 与 [`download_to_writer`] 一样报告进度，并遵守限速、读取超时、取消和大小限制；
+/// `total_size` 仅用于进度显示。不支持校验和
+pub async fn copy_from_reader<R: AsyncRead + Unpin, W: Write + ?Sized>(
+    reader: R,
+    writer: &mut W,
+    total_size: Option<u64>,
+    options: &HttpOptions,
+) -> Result<(), DownloadError> {
+    options.validate()?;
+    if options.checksum.is_some() {
+        return Err(DownloadError::InvalidOption(
+            "checksum verification is not supported when writing to a stream".into(),
+        ));
+    }
+    let progress = Progress::new(total_size, options);
+    let mut writer = SizeLimitWriter::new(writer, 0, options.max_size);
+    copy_stream(reader, &mut writer, &progress, options).await?;
+    progress.finish();
+    writer.flush()?;
+    Ok(())
+}
+
+/// 逐段读取 `reader` 直到结束并写入 `writer`。每次读取都受读取超时和取消的约束，
+/// 开启限速时每一段数据都先获得额度
+async fn copy_stream<R: AsyncRead + Unpin, W: Write>(
+    mut reader: R,
+    writer: &mut W,
+    progress: &Progress,
+    options: &HttpOptions,
+) -> Result<(), DownloadError> {
+    let limiter = options.rate_limiter();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = cancellable(options.cancel.as_ref(), async {
+            match options.read_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, reader.read(&mut buf))
+                    .await
+                    .map_err(|_| DownloadError::ReadTimeout(timeout))?,
+                None => reader.read(&mut buf).await,
+            }
+            .map_err(DownloadError::from)
+        })
+        .await?;
+        if read == 0 {
+            return Ok(());
+        }
+        if let Some(limiter) = &limiter {
+            limiter.acquire(read as u64).await;
+        }
+        writer.write_all(&buf[..read])?;
+        progress.inc(read as u64);
+    }
+}
+
+/// 将下载内容直接写入任意 [`Write`] (例如标准输出或内存缓冲区)，不经过磁盘上的临时文件。
+///
+/// 这种模式只发起一个普通的 GET 请求并按到达顺序写出数据：不支持断点续传、多线程和预分配，
+/// 也不支持校验和 (设置了 `checksum` 时返回 [`DownloadError::InvalidOption`])。
+pub async fn download_to_writer<W: Write + ?Sized>(
+    client: &Client,
+    url: &str,
+    writer: &mut W,
+    options: &HttpOptions,
+) -> Result<(), DownloadError> {
+    options.validate()?;
+    if options.checksum.is_some() {
+        return Err(DownloadError::InvalidOption(
+            "checksum verification is not supported when writing to a stream".into(),
+        ));
+    }
+    let _connection =
+        acquire_connection(options.connection_limit.as_ref(), options.cancel.as_ref()).await?;
+    let res = send_full_request(client, url, &options.headers).await?;
+    let total_size = res.content_length();
+    let encoding = content_encoding(res.headers());
+    let mut decoder = DecodingWriter::new(
+        decoding_for(encoding.as_deref(), options),
+        SizeLimitWriter::new(writer, 0, options.max_size),
+    );
+    stream_response(res, &mut decoder, total_size, 0, options).await?;
+    decoder.finish()?.flush()?;
+    Ok(())
+}
+
+/// 写入的数据超过 [`HttpOptions::max_size`] 时由 [`SizeLimitWriter`] 返回，
+/// 转换为 [`DownloadError`] 时成为 [`DownloadError::TooLarge`]
+#[derive(Debug)]
+struct SizeLimitExceeded {
+    size: u64,
+    limit: u64,
+}
+
+impl fmt::Display for SizeLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "more than {} bytes written", self.limit)
+    }
+}
+
+impl std::error::Error for SizeLimitExceeded {}
+
+/// 统计写入的字节数，超过限制时拒绝写入。放在解压之后，限制的是实际写入磁盘的数据量
+struct SizeLimitWriter<W: Write> {
+    inner: W,
+    written: u64,
+    limit: Option<u64>,
+}
+
+impl<W: Write> SizeLimitWriter<W> {
+    /// `written` 为之前已经写入的字节数 (续传时)
+    fn new(inner: W, written: u64, limit: Option<u64>) -> Self {
+        SizeLimitWriter {
+            inner,
+            written,
+            limit,
+        }
+    }
 }
 
-impl From<serde_json::Error> for DownloadError {
-    fn from(err: serde_json::Error) -> Self {
-        DownloadError::JsonError(err)
+impl<W: Write> Write for SizeLimitWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let size = self.written + buf.len() as u64;
+        if let Some(limit) = self.limit
+            && size > limit
+        {
+            return Err(std::io::Error::other(SizeLimitExceeded { size, limit }));
+        }
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
     }
 }
-impl From<reqwest::Error> for DownloadError {
-    fn from(err: reqwest::Error) -> Self {
-        DownloadError::NetworkError(err)
+
+/// 流式下载时需要解压的编码。未开启 [`HttpOptions::decompress`] 或编码不受支持时返回 `None`，
+/// 数据按原样写入
+fn decoding_for<'a>(encoding: Option<&'a str>, options: &HttpOptions) -> Option<&'a str> {
+    let encoding = encoding?;
+    if !options.decompress {
+        status!(
+            options,
+            "服务器使用了 {} 编码，按原样保存压缩数据。",
+            encoding
+        );
+        None
+    } else if DecodingWriter::<std::io::Sink>::supports(encoding) {
+        status!(options, "服务器使用了 {} 编码，下载时自动解压。", encoding);
+        Some(encoding)
+    } else {
+        warning!(
+            options,
+            "不支持服务器使用的 {} 编码，按原样保存数据。",
+            encoding
+        );
+        None
     }
 }
-impl From<std::io::Error> for DownloadError {
-    fn from(err: std::io::Error) -> Self {
-        DownloadError::FileError(err)
+
+/// 按 `Content-Encoding` 解压写入的数据，再写入内部的 writer
+enum DecodingWriter<W: Write> {
+    Identity(W),
+    Gzip(flate2::write::GzDecoder<W>),
+    Deflate(flate2::write::ZlibDecoder<W>),
+    Brotli(Box<brotli::DecompressorWriter<W>>),
+}
+
+impl<W: Write> DecodingWriter<W> {
+    fn supports(encoding: &str) -> bool {
+        matches!(encoding, "gzip" | "x-gzip" | "deflate" | "br")
+    }
+
+    /// `encoding` 为 `None` 或不受支持时不做任何处理
+    fn new(encoding: Option<&str>, writer: W) -> Self {
+        match encoding {
+            Some("gzip" | "x-gzip") => DecodingWriter::Gzip(flate2::write::GzDecoder::new(writer)),
+            // HTTP 的 deflate 编码是带 zlib 头的 deflate 数据
+            Some("deflate") => DecodingWriter::Deflate(flate2::write::ZlibDecoder::new(writer)),
+            Some("br") => {
+                DecodingWriter::Brotli(Box::new(brotli::DecompressorWriter::new(writer, 64 * 1024)))
+            }
+            _ => DecodingWriter::Identity(writer),
+        }
+    }
+
+    /// 写出剩余的解压数据并校验压缩流已经完整结束，返回内部的 writer
+    fn finish(self) -> Result<W, DownloadError> {
+        let result = match self {
+            DecodingWriter::Identity(writer) => Ok(writer),
+            DecodingWriter::Gzip(decoder) => decoder.finish(),
+            DecodingWriter::Deflate(decoder) => decoder.finish(),
+            DecodingWriter::Brotli(mut decoder) => {
+                decoder.close()?;
+                decoder.into_inner().map_err(|_| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, "incomplete brotli stream")
+                })
+            }
+        };
+        // 解压剩余数据时也可能超出大小限制，此时不算作压缩数据的问题
+        result.map_err(|e| match DownloadError::from(e) {
+            DownloadError::FileError(e) => DownloadError::DecodeError(e),
+            e => e,
+        })
     }
 }
-impl From<tokio::task::JoinError> for DownloadError {
-    fn from(err: tokio::task::JoinError) -> Self {
-        DownloadError::SpawnError(err)
+
+impl<W: Write> Write for DecodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            DecodingWriter::Identity(writer) => writer.write(buf),
+            DecodingWriter::Gzip(decoder) => decoder.write(buf),
+            DecodingWriter::Deflate(decoder) => decoder.write(buf),
+            DecodingWriter::Brotli(decoder) => decoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            DecodingWriter::Identity(writer) => writer.flush(),
+            DecodingWriter::Gzip(decoder) => decoder.flush(),
+            DecodingWriter::Deflate(decoder) => decoder.flush(),
+            DecodingWriter::Brotli(decoder) => decoder.flush(),
+        }
     }
 }
 
-pub async fn download_multipart(
-    client: &Client,
-    url: &str,
-    path: &Path,
-    total_size: u64,
-    etag: Option<String>,
-    content_type: Option<String>,
-) -> Result<(), DownloadError> {
-    run_download(client, url, path, total_size, etag, content_type, true).await
+/// 压缩文件解压后的保存路径：去掉 `.gz` 扩展名，`.tgz` 改为 `.tar`。
+/// 不是支持解压的格式时返回 `None`
+pub fn unpacked_path(path: &Path) -> Option<PathBuf> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "gz" => Some(path.with_extension("")),
+        "tgz" => Some(path.with_extension("tar")),
+        _ => None,
+    }
 }
 
-pub async fn download_sequential(
+/// 将 gzip 压缩的 `path` 解压到 `target`，返回解压后的大小。
+///
+/// 解压数据先写入 `target` 的 .part 文件，完整解压后才重命名，压缩数据损坏时
+/// 返回 [`DownloadError::DecodeError`]，不会留下不完整的文件。多个 gzip 成员依次拼接
+pub async fn unpack_gzip(path: &Path, target: &Path) -> Result<u64, DownloadError> {
+    let (path, target) = (path.to_path_buf(), target.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+        let part_path = get_part_path(&target);
+        let mut decoder =
+            flate2::read::MultiGzDecoder::new(std::io::BufReader::new(File::open(&path)?));
+        let mut output = File::create(&part_path)?;
+        let mut buf = vec![0; 64 * 1024];
+        let mut size = 0;
+        let result = loop {
+            let read = match decoder.read(&mut buf) {
+                Ok(0) => break Ok(()),
+                Ok(read) => read,
+                Err(e) => break Err(DownloadError::DecodeError(e)),
+            };
+            if let Err(e) = output.write_all(&buf[..read]) {
+                break Err(e.into());
+            }
+            size += read as u64;
+        };
+        drop(output);
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&part_path);
+            return Err(e);
+        }
+        std::fs::rename(&part_path, &target)?;
+        Ok(size)
+    })
+    .await?
+}
+
+/// 发起不带 Range 的普通 GET 请求，用于流式下载整个文件
+async fn send_full_request(
     client: &Client,
     url: &str,
-    path: &Path,
+    headers: &HeaderMap,
+) -> Result<reqwest::Response, DownloadError> {
+    let res = client.get(url).headers(headers.clone()).send().await?;
+    if !res.status().is_success() {
+        return Err(DownloadError::HttpError(res.status()));
+    }
+    Ok(res)
+}
+
+/// 读取整个响应体，数据按到达顺序写入 `writer`，返回下载过程中的峰值速度 (字节/秒)。
+/// `total_size` 和 `resumed_from` (续传前已下载的字节数) 仅用于进度显示
+async fn stream_response<W: Write + ?Sized>(
+    mut res: reqwest::Response,
+    writer: &mut W,
     total_size: Option<u64>,
-    etag: Option<String>,
-    content_type: Option<String>,
-) -> Result<(), DownloadError> {
-    if let Some(size) = total_size {
-        // 如果文件大小已知，则使用支持断点续传的 run_download
-        run_download(client, url, path, size, etag, content_type, false).await
-    } else {
-        // --- 文件大小未知：执行简单的流式下载 ---
-        // 这种模式下不支持断点续传
-        println!("文件大小未知，将执行简单的流式下载 (不支持断点续传)。");
-        let mut res = client.get(url).send().await?;
-        if !res.status().is_success() {
-            return Err(DownloadError::HttpError(res.status()));
-        }
-
-        let pb = ProgressBar::new_spinner();
-        pb.set_style(
-            ProgressStyle::default_spinner()
-                .template(
-                    "{spinner:.green} [{elapsed_precise}] {bytes_per_sec} - {bytes} downloaded",
-                )
-                .unwrap(),
-        );
-        pb.enable_steady_tick(Duration::from_millis(100));
+    resumed_from: u64,
+    options: &HttpOptions,
+) -> Result<u64, DownloadError> {
+    let progress = Progress::new(total_size, options);
+    progress.inc_resumed(resumed_from);
+    let limiter = options.rate_limiter();
+
+    while let Some(chunk) = cancellable(
+        options.cancel.as_ref(),
+        with_read_timeout(options.read_timeout, res.chunk()),
+    )
+    .await?
+    {
+        if let Some(limiter) = &limiter {
+            limiter.acquire(chunk.len() as u64).await;
+        }
+        writer.write_all(&chunk)?;
+        progress.inc(chunk.len() as u64);
+    }
+
+    Ok(progress.finish())
+}
+
+/// 下载 `url` 到 `path` 时使用的状态文件路径。
+///
+/// 指定了 [`HttpOptions::state_dir`] 时放在该目录下，文件名由目标文件名和 "最终路径 + URL"
+/// 的摘要组成 (见 [`state_file_name`])；否则放在目标文件旁边 (`<文件名>.rdownload`)。
+/// 目标目录不可写 (例如只读挂载或配额已满)、旁边也没有已有的状态文件时，改用操作系统的缓存目录。
+pub fn resolve_state_path(path: &Path, url: &str, options: &HttpOptions) -> PathBuf {
+    if let Some(dir) = &options.state_dir {
+        return dir.join(state_file_name(path, url));
+    }
+    if let Some(dir) = &options.temp_dir {
+        return get_state_path(&temp_base_path(dir, path));
+    }
+    let local = get_state_path(path);
+    let dir = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    if local.exists() || dir_is_writable(dir) {
+        return local;
+    }
+    match default_state_dir() {
+        Some(cache) => cache.join(state_file_name(path, url)),
+        None => local,
+    }
+}
+
+/// 下载 `path` 期间数据写入的 .part 文件路径。
+///
+/// 指定了 [`HttpOptions::temp_dir`] 时放在该目录下，文件名见 [`temp_base_path`]；
+/// 否则放在目标文件旁边 (`<文件名>.part`)
+pub fn resolve_part_path(path: &Path, options: &HttpOptions) -> PathBuf {
+    match &options.temp_dir {
+        Some(dir) => get_part_path(&temp_base_path(dir, path)),
+        None => get_part_path(path),
+    }
+}
+
+/// 解析 .part 文件路径，并确保临时目录存在
+fn prepare_part_path(path: &Path, options: &HttpOptions) -> Result<PathBuf, DownloadError> {
+    if let Some(dir) = &options.temp_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    Ok(resolve_part_path(path, options))
+}
+
+/// 解析状态文件路径，并确保其所在的目录存在。设置了 [`HttpOptions::no_state`] 时返回 `None`
+fn prepare_state_path(
+    path: &Path,
+    url: &str,
+    options: &HttpOptions,
+) -> Result<Option<PathBuf>, DownloadError> {
+    if options.no_state {
+        return Ok(None);
+    }
+    let state_path = resolve_state_path(path, url, options);
+    if let Some(parent) = state_path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(Some(state_path))
+}
 
-        let mut file = File::create(path)?;
-        let mut downloaded: u64 = 0;
+/// 状态文件是否存在；不使用状态文件时总是 `false`
+fn state_exists(state_path: Option<&Path>) -> bool {
+    state_path.is_some_and(Path::exists)
+}
+
+/// 删除已有的状态文件；不使用状态文件时什么也不做
+fn remove_state(state_path: Option<&Path>) -> std::io::Result<()> {
+    match state_path {
+        Some(state_path) if state_path.exists() => std::fs::remove_file(state_path),
+        _ => Ok(()),
+    }
+}
 
-        while let Some(chunk) = res.chunk().await? {
-            file.write_all(&chunk)?;
-            downloaded += chunk.len() as u64;
-            pb.set_position(downloaded);
+/// 重新读取 .part 文件中已完成的数据块并与记录的哈希比较。
+///
+/// 不一致、超出文件末尾或没有记录哈希的数据块标记为未完成，返回更新后的数据块列表和被重置的数量
+fn verify_chunks(
+    part_path: &Path,
+    mut chunks: Vec<ChunkState>,
+) -> Result<(Vec<ChunkState>, usize), DownloadError> {
+    let mut file = File::open(part_path)?;
+    let len = file.metadata()?.len();
+    let mut buf = Vec::new();
+    let mut corrupt = 0;
+    for chunk in chunks.iter_mut().filter(|chunk| chunk.completed) {
+        let intact = match chunk.hash {
+            Some(hash) if chunk.end < len => {
+                buf.resize((chunk.end - chunk.start + 1) as usize, 0);
+                file.seek(SeekFrom::Start(chunk.start))?;
+                file.read_exact(&mut buf)?;
+                chunk_hash(&buf) == hash
+            }
+            _ => false,
+        };
+        if !intact {
+            debug!("数据块 {}-{} 校验失败，重新下载", chunk.start, chunk.end);
+            chunk.completed = false;
+            chunk.hash = None;
+            corrupt += 1;
         }
+    }
+    Ok((chunks, corrupt))
+}
 
-        pb.finish_with_message("下载完成");
-        Ok(())
+/// 多线程下载时写入 .part 文件的方式，所有数据块任务共用同一个实例
+enum PartWriter {
+    /// 按偏移写入共享的文件句柄
+    Positional(File),
+    /// 写入整个文件的内存映射，见 [`HttpOptions::mmap`]
+    Mapped(memmap2::MmapRaw),
+}
+
+impl PartWriter {
+    fn open(
+        part_path: &Path,
+        total_size: u64,
+        options: &HttpOptions,
+    ) -> Result<Self, DownloadError> {
+        let file = OpenOptions::new().read(true).write(true).open(part_path)?;
+        if !options.mmap {
+            return Ok(PartWriter::Positional(file));
+        }
+        match Self::map(&file, total_size) {
+            Ok(map) => Ok(PartWriter::Mapped(map)),
+            Err(e) => {
+                warning!(options, "无法使用内存映射写入 ({})，改用普通写入", e);
+                Ok(PartWriter::Positional(file))
+            }
+        }
+    }
+
+    fn map(file: &File, total_size: u64) -> std::io::Result<memmap2::MmapRaw> {
+        // 写入映射中超出文件末尾的部分会触发 SIGBUS，只映射已经预分配到完整大小的文件
+        let len = file.metadata()?.len();
+        if len != total_size {
+            return Err(std::io::Error::other(format!(
+                "file is {} bytes, expected {}",
+                len, total_size
+            )));
+        }
+        if total_size == 0 {
+            return Err(std::io::Error::other("file is empty"));
+        }
+        let len = usize::try_from(total_size)
+            .map_err(|_| std::io::Error::other("file is too large to map"))?;
+        memmap2::MmapOptions::new().len(len).map_raw(file)
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            PartWriter::Positional(file) => write_at(file, offset, data),
+            PartWriter::Mapped(map) => {
+                let start = usize::try_from(offset).unwrap_or(usize::MAX);
+                if start
+                    .checked_add(data.len())
+                    .is_none_or(|end| end > map.len())
+                {
+                    return Err(std::io::Error::other(
+                        "write past the end of the mapped file",
+                    ));
+                }
+                // SAFETY: 范围已确认在映射之内；数据块互不重叠，同一区域只有一个任务写入
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        data.as_ptr(),
+                        map.as_mut_ptr().add(start),
+                        data.len(),
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// 将映射中的修改同步到磁盘 (msync)，按偏移写入时无需额外操作
+    fn flush(&self) -> std::io::Result<()> {
+        match self {
+            PartWriter::Positional(_) => Ok(()),
+            PartWriter::Mapped(map) => map.flush(),
+        }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn run_download(
     client: &Client,
     url: &str,
+    resolved_url: &str,
     path: &Path,
     total_size: u64,
     current_etag: Option<String>,
+    current_last_modified: Option<String>,
     expected_content_type: Option<String>,
     is_multipart: bool,
-) -> Result<(), DownloadError> {
-    let state_path = get_state_path(path);
-    let mut state: DownloadState;
+    options: &HttpOptions,
+) -> Result<DownloadSummary, DownloadError> {
+    let started = Instant::now();
+    let state_path = prepare_state_path(path, url, options)?;
+    // 下载期间数据写入 .part 文件，全部完成后才重命名为最终路径
+    let part_path = prepare_part_path(path, options)?;
     let mut completed_bytes = 0;
 
-    if state_path.exists() {
-        let mut file = File::open(&state_path)?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
-        state = serde_json::from_str(&contents)?;
-        // 核心校验：如果文件大小、URL或ETag任意一个不匹配，则判定为无效状态，从头开始。
-        if state.total_size != total_size || &state.url != url || state.etag != current_etag {
-            if state_path.exists() {
-                std::fs::remove_file(&state_path)?;
-            }
-            if path.exists() {
-                std::fs::remove_file(&path)?;
-            }
-            let chunks = create_chunks(total_size, is_multipart);
-            state = DownloadState {
-                total_size,
-                chunks,
-                url: url.to_string(),
-                etag: current_etag,
-            };
-            let file = File::create(&path)?;
-            file.set_len(total_size)?;
-        } else {
+    let saved_state = match state_path.as_deref() {
+        _ if options.resume == ResumeMode::Restart => {
+            if state_exists(state_path.as_deref()) {
+                status!(options, "忽略已有的下载进度，从头开始下载。");
+            }
+            None
+        }
+        Some(state_path) if state_path.exists() => load_state(state_path)?,
+        _ => None,
+    };
+    // 核心校验：如果文件大小、URL或ETag (没有 ETag 时为 Last-Modified) 任意一个不匹配，
+    // 或者数据块布局无法完整覆盖文件，则判定为无效状态，从头开始。
+    // .part 文件缺失或短于已完成的数据块时，状态文件记录的进度同样无法使用
+    let part_len = std::fs::metadata(&part_path).ok().map(|m| m.len());
+    let restart_reason = saved_state.as_ref().and_then(|state| {
+        state.mismatch(
+            url,
+            total_size,
+            &current_etag,
+            &current_last_modified,
+            is_multipart,
+            part_len,
+        )
+    });
+    let resumable = saved_state.filter(|_| restart_reason.is_none());
+    let mut state = match resumable {
+        Some(mut state) => {
+            if options.verify_resume {
+                let (part_path, chunks) = (part_path.clone(), std::mem::take(&mut state.chunks));
+                let (chunks, corrupt) =
+                    tokio::task::spawn_blocking(move || verify_chunks(&part_path, chunks))
+                        .await??;
+                state.chunks = chunks;
+                if corrupt > 0 {
+                    warning!(
+                        options,
+                        "{} 个已完成的数据块校验失败，将重新下载这些数据块。",
+                        corrupt
+                    );
+                } else {
+                    status!(options, "已完成的数据块校验通过。");
+                }
+            }
             for chunk in &state.chunks {
                 if chunk.completed {
                     completed_bytes += chunk.end - chunk.start + 1;
                 }
             }
+            state
         }
-    } else {
-        let chunks = create_chunks(total_size, is_multipart);
-        state = DownloadState {
-            total_size,
-            chunks,
-            url: url.to_string(),
-            etag: current_etag,
-        };
-        let file = File::create(&path)?;
-        // 预分配文件大小，避免后续多线程写入时频繁调整文件大小
-        file.set_len(total_size)?;
-    }
+        None => {
+            // 要求续传时不能静默地从头开始，保留已有文件交给用户处理
+            if options.resume == ResumeMode::Require {
+                return Err(DownloadError::CannotResume(match &restart_reason {
+                    Some(reason) => format!("the saved progress is out of date: {}", reason),
+                    None if options.no_state => "saving progress is disabled".into(),
+                    None if state_exists(state_path.as_deref()) => {
+                        "the saved progress could not be read".into()
+                    }
+                    None => "no saved progress was found".into(),
+                }));
+            }
+            if let Some(reason) = &restart_reason {
+                warning!(
+                    options,
+                    "之前保存的下载进度已失效 ({})，从头开始下载。",
+                    reason
+                );
+            }
+            remove_state(state_path.as_deref())?;
+            if part_path.exists() {
+                std::fs::remove_file(&part_path)?;
+            }
+            if !options.skip_space_check {
+                ensure_disk_space(&part_path, total_size)?;
+            }
+            let chunks =
+                create_chunks(total_size, is_multipart, options.chunk_strategy(total_size));
+            let file = File::create(&part_path)?;
+            // 预分配文件大小，避免后续多线程写入时频繁调整文件大小，也避免磁盘写满时中途失败
+            preallocate(&file, &part_path, total_size, options)?;
+            DownloadState {
+                resolved_url: Some(resolved_url.to_string()),
+                ..DownloadState::new(url, total_size, current_etag, current_last_modified, chunks)
+            }
+        }
+    };
+    // 所有数据块共用一个文件句柄 (或内存映射)，按偏移直接写入，不必为每个数据块重新打开文件并定位
+    let part_file = Arc::new(PartWriter::open(&part_path, total_size, options)?);
+
+    // 续传时沿用状态文件记录的地址，保证所有数据块都来自同一个位置
+    let target_url = state
+        .resolved_url
+        .clone()
+        .unwrap_or(resolved_url.to_string());
+
+    let progress = Progress::new(Some(total_size), options);
+    progress.inc_resumed(completed_bytes);
+
+    // 限速器在所有数据块任务之间共享，限制的是总吞吐量
+    let limiter = options.rate_limiter();
+    let pending_chunks = state.chunks.clone();
+    let chunk_count = pending_chunks
+        .iter()
+        .filter(|chunk| !chunk.completed)
+        .count();
+
+    // 数据块请求附加 If-Range：如果文件在下载期间被修改，服务器会返回完整的新文件而不是 206，
+    // 从而避免把新旧两个版本的数据拼接在一起。If-Range 只接受强校验器，弱 ETag 不使用。
+    // 镜像与主地址的 ETag 一致，因此同样可以附加 If-Range
+    let if_range = state
+        .etag
+        .as_deref()
+        .filter(|etag| !etag.starts_with("W/"))
+        .and_then(|etag| HeaderValue::from_str(etag).ok());
+    // 数据块的下载来源：主地址在前，镜像依次在后
+    let sources: Arc<Vec<(String, HeaderMap)>> = Arc::new(
+        std::iter::once(target_url.clone())
+            .chain(options.mirrors.iter().cloned())
+            .map(|source| {
+                let mut headers = target_headers(url, &source, &options.headers);
+                if let Some(value) = &if_range {
+                    headers.insert(IF_RANGE, value.clone());
+                }
+                (source, headers)
+            })
+            .collect(),
+    );
+    // 某个数据块切换到镜像后，之后启动的数据块直接从该镜像开始，不必在失效的地址上重复等待
+    let preferred_source = Arc::new(AtomicUsize::new(0));
+
+    // --- 状态持久化 ---
+    // 状态文件由单独的写入线程独占维护：数据块任务在数据落盘后只需发送自己的序号和数据的哈希，
+    // 不必在共享的锁内做序列化和磁盘写入，各个数据块的完成也就不会相互阻塞。
+    // 写入按数量和时间批量进行，所有任务结束后再做最后一次写入。
+    // 暂停时发送 `None` 要求立即写入，暂停期间完成的数据块也会立即写入
+    let (completed_tx, completed_rx) = std::sync::mpsc::channel::<Option<(usize, u64)>>();
+    let state_writer = {
+        let state_path = state_path.clone();
+        let save_every = options.state_save_every;
+        let save_interval = options.state_save_interval;
+        let pause = options.pause.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut unsaved = 0;
+            let mut last_save = Instant::now();
+            loop {
+                // 没有未保存的记录时无需计时，直接等待下一个完成的数据块
+                let received = if unsaved == 0 {
+                    completed_rx
+                        .recv()
+                        .map_err(|_| RecvTimeoutError::Disconnected)
+                } else {
+                    completed_rx.recv_timeout(save_interval.saturating_sub(last_save.elapsed()))
+                };
+                match received {
+                    Ok(Some((i, hash))) => {
+                        state.chunks[i].completed = true;
+                        state.chunks[i].hash = Some(hash);
+                        unsaved += 1;
+                    }
+                    Ok(None) if unsaved == 0 => continue,
+                    Ok(None) | Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                let paused = pause.as_ref().is_some_and(PauseHandle::is_paused);
+                if paused || unsaved >= save_every || last_save.elapsed() >= save_interval {
+                    if let Some(state_path) = &state_path {
+                        save_state(state_path, &state)?;
+                    }
+                    unsaved = 0;
+                    last_save = Instant::now();
+                }
+            }
+            if unsaved > 0
+                && let Some(state_path) = &state_path
+            {
+                save_state(state_path, &state)?;
+            }
+            // 返回是否所有数据块都已完成，取消时可能有数据块从未启动
+            Ok::<bool, DownloadError>(state.chunks.iter().all(|chunk| chunk.completed))
+        })
+    };
+
+    // 每次进入暂停状态时通知写入线程保存一次，暂停期间退出进程也不会丢失已完成的数据块
+    let pause_flusher = options.pause.clone().map(|pause| {
+        let completed_tx = completed_tx.clone();
+        tokio::spawn(async move {
+            let mut receiver = pause.0.subscribe();
+            while receiver.wait_for(|paused| *paused).await.is_ok()
+                && completed_tx.send(None).is_ok()
+                && receiver.wait_for(|paused| !paused).await.is_ok()
+            {}
+        })
+    });
 
-    let pb = ProgressBar::new(total_size);
-    pb.set_style(ProgressStyle::default_bar().template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})").unwrap().progress_chars("->-"));
-    pb.inc(completed_bytes);
-    pb.enable_steady_tick(Duration::from_millis(100));
+    // 每个数据块的实时状态，只用于通过 on_chunk_progress 对外报告，不参与状态文件的持久化
+    let tracker = Arc::new(ChunkTracker::new(&pending_chunks));
+    let reporter = options.on_chunk_progress.clone().map(|callback| {
+        let tracker = tracker.clone();
+        let interval = options.chunk_report_interval;
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                (callback.0)(&tracker.snapshot());
+            }
+        })
+    });
 
-    let state = Arc::new(Mutex::new(state));
+    // 自适应并发只在多线程下载中使用，所有数据块任务共享同一组名额
+    let adaptive = options
+        .initial_concurrency
+        .filter(|_| is_multipart)
+        .map(|start| {
+            Arc::new(AdaptiveConcurrency::new(
+                start,
+                options.concurrency,
+                options.on_event.clone(),
+            ))
+        });
 
-    let tasks = stream::iter(state.lock().unwrap().chunks.clone().into_iter().enumerate())
+    let tasks = stream::iter(pending_chunks.into_iter().enumerate())
         .filter(|(_, chunk)| futures_util::future::ready(!chunk.completed))
+        // 取消之后不再启动新的数据块任务
+        .take_while(|_| futures_util::future::ready(!options.is_cancelled()))
         .map(|(i, chunk)| {
             let client = client.clone();
-            let url = url.to_string();
-            let path = path.to_path_buf();
-            let state_path = state_path.clone();
-            let state_arc = Arc::clone(&state);
-            let pb = pb.clone();
-            let expected_content_type = expected_content_type.clone();
+            let sources = sources.clone();
+            let preferred_source = preferred_source.clone();
+            let part_file = part_file.clone();
+            let completed_tx = completed_tx.clone();
+            let progress = progress.clone();
+            let expected_content_type =
+                (!options.skip_content_check).then(|| expected_content_type.clone());
 
-            tokio::spawn(async move {
-                let range_header = format!("bytes={}-{}", chunk.start, chunk.end);
-                let res = client
-                    .get(&url)
-                    .header("Range", range_header)
-                    .send()
-                    .await?;
-
-                // 必须是 206 Partial Content (多线程) 或 200 OK (单线程) 才是有效响应
-                if res.status() != 206 && res.status() != 200 {
-                    return Err(DownloadError::HttpError(res.status()));
-                }
+            let max_attempts = options.chunk_max_attempts;
+            let retry_all_errors = options.retry_all_errors;
+            let retry_backoff = options.retry_backoff;
+            let limiter = limiter.clone();
+            let cancel = options.cancel.clone();
+            let pause = options.pause.clone();
+            let connection_limit = options.connection_limit.clone();
+            let adaptive = adaptive.clone();
+            let read_timeout = options.read_timeout;
 
-                // --- 内容校验 ---
-                // 检查每个块的 Content-Type 是否与探测时获得的一致。
-                // 这是为了防止服务器返回 206 状态码但响应体是 HTML 错误页面的情况。
-                let chunk_content_type = res
-                    .headers()
-                    .get(CONTENT_TYPE)
-                    .and_then(|v| v.to_str().ok())
-                    .map(|s| s.to_string());
-                if chunk_content_type != expected_content_type {
-                    return Err(DownloadError::ContentTypeMismatch);
-                }
+            let tracker = tracker.clone();
+            let chunk_tracker = tracker.clone();
+            tokio::spawn(async move {
+                let result = async move {
+                    // --- 数据块重试循环 (指数退避) ---
+                    // 数据在完整接收并写入之前不会计入进度条，因此重试不会重复统计字节数。
+                    // 每个来源都有完整的重试次数，用尽后改用下一个镜像
+                    let mut source = preferred_source.load(Ordering::SeqCst);
+                    let mut attempt = 1;
+                    let data = loop {
+                        // 暂停期间不发起请求，重试之间同样会停下来
+                        if let Some(pause) = &pause {
+                            cancellable(cancel.as_ref(), async {
+                                pause.wait_resumed().await;
+                                Ok(())
+                            })
+                            .await?;
+                        }
+                        // 名额只在请求期间占用，重试前的等待不占用，其他下载可以先使用
+                        let slot = match &adaptive {
+                            Some(adaptive) => Some(
+                                cancellable(cancel.as_ref(), async {
+                                    Ok(adaptive.acquire().await)
+                                })
+                                .await?,
+                            ),
+                            None => None,
+                        };
+                        let connection =
+                            acquire_connection(connection_limit.as_ref(), cancel.as_ref()).await?;
+                        chunk_tracker.set(i, ChunkStatus::InFlight);
+                        chunk_tracker.record_attempt(i);
+                        let (url, headers) = &sources[source];
+                        let mut retry_after = None;
+                        let fetched = cancellable(
+                            cancel.as_ref(),
+                            fetch_chunk(
+                                &client,
+                                url,
+                                &chunk,
+                                headers,
+                                total_size,
+                                expected_content_type.as_ref(),
+                                limiter.as_deref(),
+                                read_timeout,
+                                &mut retry_after,
+                            ),
+                        )
+                        .await;
+                        drop(connection);
+                        drop(slot);
+                        if let Some(adaptive) = &adaptive {
+                            match &fetched {
+                                Ok(data) => adaptive.record(Some(data.len() as u64)),
+                                Err(DownloadError::Cancelled) => {}
+                                Err(_) => adaptive.record(None),
+                            }
+                        }
+                        match fetched {
+                            Ok(data) => break data,
+                            // 服务器不支持 Range 是确定性的，取消则是调用方的意图，两者都不应重试
+                            Err(
+                                e @ (DownloadError::RangeNotSupported
+                                | DownloadError::ContentEncoded(_)
+                                | DownloadError::ResourceChanged
+                                | DownloadError::TotalSizeChanged { .. }
+                                | DownloadError::Cancelled),
+                            ) => {
+                                return Err(e);
+                            }
+                            Err(e)
+                                if attempt < max_attempts
+                                    && (retry_all_errors || retryable(&e)) =>
+                            {
+                                let backoff = retry_backoff * 2_u32.pow(attempt - 1);
+                                // 服务器通过 Retry-After 要求的等待时间比退避时间长时以服务器为准
+                                let backoff = retry_after.map_or(backoff, |wait| wait.max(backoff));
+                                debug!(
+                                    "数据块 {}-{} 下载失败 (尝试 {}/{}): {}，将在 {:?} 后重试",
+                                    chunk.start, chunk.end, attempt, max_attempts, e, backoff
+                                );
+                                cancellable(cancel.as_ref(), async {
+                                    tokio::time::sleep(backoff).await;
+                                    Ok(())
+                                })
+                                .await?;
+                                attempt += 1;
+                            }
+                            Err(e) if source + 1 < sources.len() => {
+                                debug!(
+                                    "数据块 {}-{} 在 {} 上多次下载失败: {}，改用镜像 {}",
+                                    chunk.start,
+                                    chunk.end,
+                                    url,
+                                    e,
+                                    sources[source + 1].0
+                                );
+                                source += 1;
+                                attempt = 1;
+                                preferred_source.fetch_max(source, Ordering::SeqCst);
+                            }
+                            Err(e) => return Err(e),
+                        }
+                    };
 
-                let data = res.bytes().await?;
+                    // 将文件写入操作移入 spawn_blocking，因为它是一个同步阻塞操作
+                    tokio::task::spawn_blocking(move || {
+                        part_file.write_at(chunk.start, &data)?;
 
-                // 将文件写入操作移入 spawn_blocking，因为它是一个同步阻塞操作
-                tokio::task::spawn_blocking(move || {
-                    let mut file = OpenOptions::new().write(true).open(&path)?;
-                    file.seek(std::io::SeekFrom::Start(chunk.start))?;
-                    file.write_all(&data)?;
+                        // 数据写入之后才通知写入线程标记完成，保证状态文件不会领先于实际数据
+                        completed_tx
+                            .send(Some((i, chunk_hash(&data))))
+                            .map_err(|_| {
+                                DownloadError::StateError(
+                                    "state writer stopped unexpectedly".into(),
+                                )
+                            })?;
 
-                    // 更新状态文件，这是一个原子操作
-                    let mut state_lock = state_arc.lock().unwrap();
-                    state_lock.chunks[i].completed = true;
-                    let state_json = serde_json::to_string_pretty(&*state_lock)?;
-                    let mut state_file = File::create(&state_path)?;
-                    state_file.write_all(state_json.as_bytes())?;
+                        progress.inc(data.len() as u64);
+                        Ok::<(), DownloadError>(())
+                    })
+                    .await??;
 
-                    pb.inc(data.len() as u64);
                     Ok::<(), DownloadError>(())
-                })
-                .await??;
-
-                Ok::<(), DownloadError>(())
+                }
+                .await;
+                tracker.set(
+                    i,
+                    match &result {
+                        Ok(()) => ChunkStatus::Done,
+                        // 被取消的数据块之后还会续传，仍视为等待中
+                        Err(DownloadError::Cancelled) => ChunkStatus::Pending,
+                        Err(_) => ChunkStatus::Failed,
+                    },
+                );
+                result
             })
         })
-        .buffer_unordered(if is_multipart { 8 } else { 1 });
+        .buffer_unordered(if is_multipart { options.concurrency } else { 1 });
 
     // --- 结果处理 ---
     // 等待所有下载任务完成，并检查是否有任何一个任务失败。
     // 这是为了防止静默的数据损坏：即使只有一个块失败，整个下载也必须被视为失败。
     let results: Vec<_> = tasks.collect().await;
+    // 任务都已结束，同步内存映射中的数据后释放映射，之后才能删除或重命名 .part 文件
+    part_file.flush()?;
+    drop(part_file);
+    if let Some(reporter) = reporter {
+        reporter.abort();
+    }
+    if let Some(pause_flusher) = pause_flusher {
+        pause_flusher.abort();
+        // 等待任务真正结束，释放它持有的发送端
+        let _ = pause_flusher.await;
+    }
+    // 所有任务结束后再报告一次，调用方总能看到最终的状态
+    if let Some(callback) = &options.on_chunk_progress {
+        (callback.0)(&tracker.snapshot());
+    }
+    // 所有发送端都释放后写入线程才会退出，等待它把剩余的完成记录写入状态文件
+    drop(completed_tx);
+    let all_completed = state_writer.await??;
     let mut has_error = false;
+    let mut range_ignored = false;
+    let mut resource_changed = false;
+    let mut content_encoding = None;
+    let mut size_changed = None;
+    let mut cancelled = false;
     for result in results {
+        // 外层是 tokio::spawn 的 JoinError，内层是任务自身返回的下载错误，两者都必须检查
+        let result = result.map_err(DownloadError::from).and_then(|r| r);
         if let Err(e) = result {
             debug!("一个下载任务失败: {:?}", e);
+            range_ignored |= matches!(e, DownloadError::RangeNotSupported);
+            resource_changed |= matches!(e, DownloadError::ResourceChanged);
+            cancelled |= matches!(e, DownloadError::Cancelled);
+            match e {
+                DownloadError::ContentEncoded(encoding) => content_encoding = Some(encoding),
+                e @ DownloadError::TotalSizeChanged { .. } => size_changed = Some(e),
+                _ => {}
+            }
             has_error = true;
         }
     }
 
+    if range_ignored || resource_changed || content_encoding.is_some() || size_changed.is_some() {
+        // 服务器不支持 Range、对 Range 响应做了压缩，或者文件已经变化 (包括总大小变化) 时，
+        // 已下载的分块数据都无法续传。
+        // 清理掉以便调用方从头下载 (改用单线程或流式下载，或重新探测后下载新文件)。
+        remove_state(state_path.as_deref())?;
+        if part_path.exists() {
+            std::fs::remove_file(&part_path)?;
+        }
+        return Err(if resource_changed {
+            DownloadError::ResourceChanged
+        } else if let Some(e) = size_changed {
+            e
+        } else if let Some(encoding) = content_encoding {
+            DownloadError::ContentEncoded(encoding)
+        } else {
+            DownloadError::RangeNotSupported
+        });
+    }
+
+    // 取消时已完成的数据块都已写入磁盘并记录在状态文件中，保留它们以便之后续传。
+    // 取消前刚好全部完成的下载仍会正常收尾。
+    if cancelled || (options.is_cancelled() && !all_completed) {
+        return Err(DownloadError::Cancelled);
+    }
+
     if has_error {
-        eprintln!("\n由于部分数据块下载失败，下载未完成。请重新运行命令以续传。");
+        log::error!("部分数据块下载失败，下载未完成，已保留进度以便续传");
         return Err(DownloadError::ChunkDownloadFailed);
     }
 
-    // 只有当所有块都成功下载后，才删除状态文件，标志着整个任务的成功完成
-    pb.finish_with_message("下载完成");
-    std::fs::remove_file(&state_path)?;
+    // 所有数据块都报告成功后，再确认磁盘上的文件长度，防止静默的截断或短写入。
+    // 不一致时保留 .part 文件以便排查；状态文件已无法反映实际数据，删除后下次会从头下载。
+    let actual = std::fs::metadata(&part_path)?.len();
+    if actual != total_size {
+        remove_state(state_path.as_deref())?;
+        return Err(DownloadError::SizeMismatch {
+            expected: total_size,
+            actual,
+        });
+    }
+
+    // 只有当所有块都成功下载后，才删除状态文件并将 .part 重命名为最终文件，标志着整个任务的成功完成
+    let peak_speed = progress.finish();
+    // 状态文件只在数据块完成时写入，空文件没有任何数据块，因此可能从未创建
+    remove_state(state_path.as_deref())?;
+    finalize_download(&part_path, path, options).await?;
+    Ok(DownloadSummary {
+        path: path.to_path_buf(),
+        total_size,
+        bytes_downloaded: total_size - completed_bytes,
+        resumed: completed_bytes > 0,
+        multipart: is_multipart,
+        skipped: false,
+        elapsed: started.elapsed(),
+        peak_speed,
+        chunks: chunk_count,
+        retries: tracker.retries(),
+        restart_reason,
+    })
+}
+
+/// 检查 `path` 所在磁盘的剩余空间是否足以写入 `needed` 字节。
+///
+/// 文件系统不支持真正的预分配时只能创建稀疏文件，直到写入中途才失败，因此在开始下载前就给出明确的错误。
+fn ensure_disk_space(path: &Path, needed: u64) -> Result<(), DownloadError> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let available = fs2::available_space(dir)?;
+    if available < needed {
+        return Err(DownloadError::InsufficientSpace { needed, available });
+    }
+    Ok(())
+}
+
+/// 为 `file` 预留 `len` 字节的磁盘空间并把文件长度设为 `len`。
+///
+/// 优先真正分配磁盘块 (Linux 上的 posix_fallocate、macOS 上的 F_PREALLOCATE、Windows 上的
+/// FileAllocationInfo)，文件系统不支持时退回到只设置长度的稀疏文件。分配失败时重新检查剩余空间，
+/// 空间确实不足则返回 [`DownloadError::InsufficientSpace`]，而不是等到写入中途才失败。
+fn preallocate(
+    file: &File,
+    path: &Path,
+    len: u64,
+    options: &HttpOptions,
+) -> Result<(), DownloadError> {
+    if len > 0
+        && let Err(e) = fs2::FileExt::allocate(file, len)
+    {
+        if !options.skip_space_check {
+            ensure_disk_space(path, len)?;
+        }
+        debug!(
+            "无法为 {} 预分配磁盘空间 ({})，改用稀疏文件",
+            path.display(),
+            e
+        );
+    }
+    if file.metadata()?.len() != len {
+        file.set_len(len)?;
+    }
+    Ok(())
+}
+
+/// 等待一次网络读取 (响应头或下一段数据)，超过 `timeout` 仍无结果时返回 [`DownloadError::ReadTimeout`]
+async fn with_read_timeout<T>(
+    timeout: Option<Duration>,
+    future: impl Future<Output = reqwest::Result<T>>,
+) -> Result<T, DownloadError> {
+    match timeout {
+        Some(timeout) => match tokio::time::timeout(timeout, future).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(DownloadError::ReadTimeout(timeout)),
+        },
+        None => Ok(future.await?),
+    }
+}
+
+/// 数据块请求的错误是否值得在同一个地址上重试。不值得重试的错误仍会尝试下一个镜像
+fn retryable(error: &DownloadError) -> bool {
+    match error {
+        DownloadError::HttpError(status) => is_retryable_status(*status),
+        _ => true,
+    }
+}
+
+/// 在 `limit` 下等待一个连接名额，没有限制时立即返回。等待期间同样响应取消
+async fn acquire_connection<'a>(
+    limit: Option<&'a ConnectionLimit>,
+    cancel: Option<&CancellationToken>,
+) -> Result<Option<SemaphorePermit<'a>>, DownloadError> {
+    match limit {
+        Some(limit) => cancellable(cancel, async { Ok(limit.acquire().await) }).await,
+        None => Ok(None),
+    }
+}
+
+/// 在取消令牌触发时提前结束 `future` 并返回 [`DownloadError::Cancelled`]
+async fn cancellable<T>(
+    cancel: Option<&CancellationToken>,
+    future: impl Future<Output = Result<T, DownloadError>>,
+) -> Result<T, DownloadError> {
+    match cancel {
+        Some(token) => tokio::select! {
+            _ = token.cancelled() => Err(DownloadError::Cancelled),
+            result = future => result,
+        },
+        None => future.await,
+    }
+}
+
+/// 读取状态文件，并将旧版本的格式迁移为当前版本。
+///
+/// 文件损坏或版本无法识别 (例如由更新版本的程序写入) 时返回 `None`，调用方应丢弃它从头下载。
+fn load_state(state_path: &Path) -> Result<Option<DownloadState>, DownloadError> {
+    let mut contents = String::new();
+    File::open(state_path)?.read_to_string(&mut contents)?;
+    let value: serde_json::Value = match serde_json::from_str(&contents) {
+        Ok(value) => value,
+        Err(e) => {
+            debug!("状态文件无法解析，将从头下载: {}", e);
+            return Ok(None);
+        }
+    };
+    let version = match value.get("version") {
+        None => Some(0),
+        Some(version) => version.as_u64(),
+    };
+    let state = match version {
+        Some(0) => serde_json::from_value::<DownloadStateV0>(value).map(DownloadState::from),
+        Some(v) if v == u64::from(STATE_VERSION) => serde_json::from_value(value),
+        _ => {
+            debug!("无法识别的状态文件版本 {:?}，将从头下载", version);
+            return Ok(None);
+        }
+    };
+    match state {
+        Ok(state) => Ok(Some(state)),
+        Err(e) => {
+            debug!("状态文件字段不完整，将从头下载: {}", e);
+            Ok(None)
+        }
+    }
+}
+
+/// 将当前的下载状态完整写入状态文件
+fn save_state(state_path: &Path, state: &DownloadState) -> Result<(), DownloadError> {
+    // 下载可能在保存途中被中断 (例如 Ctrl-C)，写到一半的状态文件会让续传失败或跳过未完成的数据块
+    let state_json = serde_json::to_string_pretty(state)?;
+    write_file_atomic(state_path, state_json.as_bytes())?;
+    Ok(())
+}
+
+/// 从 `file://` 地址对应的本地文件读取单个数据块。
+///
+/// 本地复制与网络下载共用数据块划分、并发写入、状态文件和进度显示，只有数据的来源不同
+async fn read_local_chunk(
+    source: PathBuf,
+    chunk: &ChunkState,
+    limiter: Option<&RateLimiter>,
+) -> Result<Bytes, DownloadError> {
+    let (start, len) = (chunk.start, (chunk.end - chunk.start + 1) as usize);
+    if let Some(limiter) = limiter {
+        limiter.acquire(len as u64).await;
+    }
+    let read = tokio::task::spawn_blocking(move || {
+        let mut data = vec![0; len];
+        read_exact_at(&File::open(&source)?, start, &mut data).map(|()| data)
+    })
+    .await?;
+    match read {
+        Ok(data) => Ok(Bytes::from(data)),
+        // 源文件在复制过程中变小了，与服务器返回 416 属于同一类情况
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            Err(DownloadError::ResourceChanged)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 请求单个数据块并校验响应，成功时返回该数据块的完整内容。
+/// `expected_content_type` 为 `None` 时不校验 Content-Type。
+/// 服务器以 429 或 503 拒绝请求并给出 `Retry-After` 时，要求的等待时间写入 `retry_after`
+#[allow(clippy::too_many_arguments)]
+async fn fetch_chunk(
+    client: &Client,
+    url: &str,
+    chunk: &ChunkState,
+    headers: &HeaderMap,
+    total_size: u64,
+    expected_content_type: Option<&Option<String>>,
+    limiter: Option<&RateLimiter>,
+    read_timeout: Option<Duration>,
+    retry_after: &mut Option<Duration>,
+) -> Result<Bytes, DownloadError> {
+    if let Some(source) = file_url_path(url) {
+        return read_local_chunk(source, chunk, limiter).await;
+    }
+    let range_header = format!("bytes={}-{}", chunk.start, chunk.end);
+    let request = client
+        .get(url)
+        .headers(headers.clone())
+        // Range 针对的是未经压缩的原始字节，因此明确要求服务器不要对响应做内容编码
+        .header(ACCEPT_ENCODING, "identity")
+        .header("Range", range_header)
+        .send();
+    let mut res = with_read_timeout(read_timeout, request).await?;
+
+    // 416 Range Not Satisfiable 说明请求的范围超出了服务器上文件的实际大小 (例如文件变小了)，
+    // 这与文件在下载过程中被修改是同一类情况：已有的数据和状态都已失效，需要重新探测后从头下载
+    if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        return Err(DownloadError::ResourceChanged);
+    }
+
+    // 必须是 206 Partial Content (多线程) 或 200 OK (单线程) 才是有效响应
+    if res.status() != 206 && res.status() != 200 {
+        *retry_after = rdownloader_utils::retry_after(res.status(), res.headers());
+        return Err(DownloadError::HttpError(res.status()));
+    }
+
+    // 即使要求了 identity，个别服务器仍会压缩响应。此时响应体和 Content-Length 描述的是压缩后的数据，
+    // 按 Range 的偏移写入只会得到损坏的文件
+    if let Some(encoding) = content_encoding(res.headers()) {
+        return Err(DownloadError::ContentEncoded(encoding));
+    }
+
+    // 200 OK 意味着服务器忽略了 Range，返回的是完整文件。
+    // 只有当这个数据块本身就覆盖整个文件时才能接受，否则写入会覆盖到错误的偏移上。
+    let chunk_len = chunk.end - chunk.start + 1;
+    let range_ignored = res.status() == 200 && chunk_len != total_size;
+    // 带了 If-Range 时，返回 200 且 ETag 与之不同说明文件已经变化，而不是服务器不支持 Range
+    if range_ignored
+        && let Some(if_range) = headers.get(IF_RANGE)
+        && res.headers().get(ETAG).is_some_and(|etag| etag != if_range)
+    {
+        return Err(DownloadError::ResourceChanged);
+    }
+    if range_ignored && res.content_length().is_some_and(|len| len > chunk_len) {
+        return Err(DownloadError::RangeNotSupported);
+    }
+
+    // 206 响应的 Content-Range 给出了服务器此刻的文件总大小。与探测得到的大小不一致时，
+    // 按旧的大小分块和预分配只会截断文件或留下空洞
+    if res.status() == StatusCode::PARTIAL_CONTENT
+        && let Some(actual) = res
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_content_range)
+        && actual != total_size
+    {
+        return Err(DownloadError::TotalSizeChanged {
+            probed: total_size,
+            actual,
+        });
+    }
+
+    // 与 download_range 相同：206 响应必须从请求的起点开始，否则数据会写到错误的偏移上
+    if res.status() == StatusCode::PARTIAL_CONTENT {
+        let content_start = res
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(content_range_start);
+        if content_start != Some(chunk.start) {
+            return Err(DownloadError::ChunkMismatch {
+                start: chunk.start,
+                end: chunk.end,
+                reason: match content_start {
+                    Some(actual) => format!("the response starts at byte {}", actual),
+                    None => "the response has no valid Content-Range".into(),
+                },
+            });
+        }
+    }
+
+    // --- 内容校验 ---
+    // 检查每个块的 Content-Type 是否与探测时获得的一致。
+    // 这是为了防止服务器返回 206 状态码但响应体是 HTML 错误页面的情况。
+    // 只比较 MIME 本体，大小写和 charset 等参数的差异不视为不一致。
+    // 不少服务器在范围响应中省略 Content-Type，数据块没有该响应头时视为一致；
+    // 探测响应没有 Content-Type 时不知道应有的类型，也不校验
+    let chunk_content_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(mime_essence);
+    if let (Some(Some(expected)), Some(actual)) = (expected_content_type, chunk_content_type)
+        && mime_essence(expected) != actual
+    {
+        return Err(DownloadError::ContentTypeMismatch);
+    }
+
+    // 逐段读取响应体，以便在开启限速时每一段数据都先获得额度
+    let mut data = BytesMut::with_capacity(chunk_len as usize);
+    while let Some(piece) = with_read_timeout(read_timeout, res.chunk()).await? {
+        if let Some(limiter) = limiter {
+            limiter.acquire(piece.len() as u64).await;
+        }
+        data.extend_from_slice(&piece);
+        // 没有 Content-Length 时只能在读取过程中发现响应体超出了请求的范围
+        if range_ignored && data.len() as u64 > chunk_len {
+            return Err(DownloadError::RangeNotSupported);
+        }
+    }
+    // 响应体比请求的范围短时 (连接提前关闭或服务器只返回了一部分)，若标记为完成，
+    // 缺少的部分会在预分配的文件中留下全零的空洞
+    if data.len() as u64 != chunk_len {
+        return Err(DownloadError::ChunkMismatch {
+            start: chunk.start,
+            end: chunk.end,
+            reason: format!("received {} of {} bytes", data.len(), chunk_len),
+        });
+    }
+    Ok(data.freeze())
+}
+
+/// 对已完整写入的 .part 文件做最终校验，并将其重命名为最终路径。
+///
+/// 校验和不匹配时保留 .part 文件，便于用户检查。
+async fn finalize_download(
+    part_path: &Path,
+    path: &Path,
+    options: &HttpOptions,
+) -> Result<(), DownloadError> {
+    if let Some(checksum) = &options.checksum {
+        let algorithm = checksum.algorithm;
+        let hash_path = part_path.to_path_buf();
+        // 计算摘要需要读取整个文件，放入阻塞线程池以免卡住异步运行时
+        let actual =
+            tokio::task::spawn_blocking(move || compute_checksum(&hash_path, algorithm)).await??;
+        if actual != checksum.expected {
+            return Err(DownloadError::ChecksumMismatch {
+                expected: checksum.expected.clone(),
+                actual,
+            });
+        }
+    }
+    move_file(part_path, path)?;
     Ok(())
 }
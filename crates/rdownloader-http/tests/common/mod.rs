@@ -0,0 +1,164 @@
+#![allow(dead_code)]
+
+use wiremock::{Request, Respond, ResponseTemplate};
+
+/// 生成确定性的测试数据，便于校验每个字节是否落在正确的偏移
+pub fn test_body(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// 支持 `Range: bytes=a-b` 的模拟响应：有 Range 时返回 206 和对应片段，否则返回 200 和完整内容
+pub struct RangeResponder {
+    pub body: Vec<u8>,
+    pub content_type: Option<String>,
+    pub delay: Option<std::time::Duration>,
+}
+
+impl RangeResponder {
+    pub fn new(body: Vec<u8>) -> Self {
+        RangeResponder {
+            body,
+            content_type: None,
+            delay: None,
+        }
+    }
+
+    /// 在每个响应上附加指定的 Content-Type
+    pub fn with_content_type(mut self, content_type: &str) -> Self {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+
+    /// 每个响应都延迟指定时长才返回，用于模拟较慢的下载
+    pub fn with_delay(mut self, delay: std::time::Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+pub fn parse_range(request: &Request) -> Option<(usize, usize)> {
+    let value = request.headers.get("Range")?.to_str().ok()?;
+    let (start, end) = value.strip_prefix("bytes=")?.split_once('-')?;
+    Some((start.parse().ok()?, end.parse().ok()?))
+}
+
+impl Respond for RangeResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let total = self.body.len();
+        let response = match parse_range(request) {
+            Some((start, end)) if start < total => {
+                let end = end.min(total - 1);
+                ResponseTemplate::new(206)
+                    .insert_header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", start, end, total).as_str(),
+                    )
+                    .insert_header("Accept-Ranges", "bytes")
+                    .set_body_bytes(self.body[start..=end].to_vec())
+            }
+            Some(_) => ResponseTemplate::new(416)
+                .insert_header("Content-Range", format!("bytes */{}", total).as_str()),
+            None => ResponseTemplate::new(200)
+                .insert_header("Accept-Ranges", "bytes")
+                .set_body_bytes(self.body.clone()),
+        };
+        let response = match &self.content_type {
+            Some(content_type) => response.insert_header("Content-Type", content_type.as_str()),
+            None => response,
+        };
+        match self.delay {
+            Some(delay) => response.set_delay(delay),
+            None => response,
+        }
+    }
+}
+
+/// 前 `failures` 次请求返回 500，之后的请求交给 [`RangeResponder`] 正常处理
+pub struct FlakyResponder {
+    pub inner: RangeResponder,
+    pub failures: usize,
+    pub calls: std::sync::atomic::AtomicUsize,
+}
+
+impl FlakyResponder {
+    pub fn new(body: Vec<u8>, failures: usize) -> Self {
+        FlakyResponder {
+            inner: RangeResponder::new(body),
+            failures,
+            calls: std::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Respond for FlakyResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let call = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        if call < self.failures {
+            ResponseTemplate::new(500)
+        } else {
+            self.inner.respond(request)
+        }
+    }
+}
+
+/// 带 ETag 并支持 `If-Range` 的模拟响应：`If-Range` 与当前 ETag 不一致时忽略 Range，
+/// 返回 200 和完整的新文件，与真实服务器在文件变化后的行为一致
+pub struct VersionedResponder {
+    pub inner: RangeResponder,
+    pub etag: String,
+}
+
+impl VersionedResponder {
+    pub fn new(body: Vec<u8>, etag: &str) -> Self {
+        VersionedResponder {
+            inner: RangeResponder::new(body),
+            etag: etag.to_string(),
+        }
+    }
+}
+
+impl Respond for VersionedResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let stale = request
+            .headers
+            .get("If-Range")
+            .is_some_and(|value| value.as_bytes() != self.etag.as_bytes());
+        let response = if stale {
+            ResponseTemplate::new(200).set_body_bytes(self.inner.body.clone())
+        } else {
+            self.inner.respond(request)
+        };
+        response.insert_header("ETag", self.etag.as_str())
+    }
+}
+
+/// 前 `stalls` 次请求在 `delay` 之后才响应 (模拟停滞的连接)，之后的请求正常处理
+pub struct StallResponder {
+    pub inner: RangeResponder,
+    pub stalls: usize,
+    pub delay: std::time::Duration,
+    pub calls: std::sync::atomic::AtomicUsize,
+}
+
+impl StallResponder {
+    pub fn new(body: Vec<u8>, stalls: usize, delay: std::time::Duration) -> Self {
+        StallResponder {
+            inner: RangeResponder::new(body),
+            stalls,
+            delay,
+            calls: std::sync::atomic::AtomicUsize::new(0),
+        }
+    }
+}
+
+impl Respond for StallResponder {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let call = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let response = self.inner.respond(request);
+        if call < self.stalls {
+            response.set_delay(self.delay)
+        } else {
+            response
+        }
+    }
+}
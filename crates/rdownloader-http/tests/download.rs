@@ -0,0 +1,2475 @@
+mod common;
+
+use common::{FlakyResponder, RangeResponder, StallResponder, VersionedResponder, test_body};
+use rdownloader_http::{
+    CancellationToken, ChunkProgressCallback, ChunkReport, ChunkStatus, ConnectionLimit,
+    DownloadError, DownloadEvent, EventCallback, HttpOptions, PauseHandle, ProgressBar,
+    ProgressCallback, download_multipart, download_sequential, download_to_writer, unpack_gzip,
+    unpacked_path,
+};
+use rdownloader_utils::{get_part_path, get_state_path};
+use reqwest::Client;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wiremock::matchers::{header, method};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn small_chunks() -> HttpOptions {
+    HttpOptions {
+        chunk_size: 1024,
+        retry_backoff: Duration::from_millis(10),
+        ..HttpOptions::default()
+    }
+}
+
+#[tokio::test]
+async fn zero_byte_file_downloads_successfully() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).insert_header("Content-Length", "0"))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("empty.bin");
+    let url = format!("{}/empty.bin", server.uri());
+
+    download_sequential(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        Some(0),
+        None,
+        None,
+        None,
+        &HttpOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+    assert!(!get_state_path(&path).exists());
+}
+
+#[tokio::test]
+async fn zero_byte_file_in_multipart_mode_makes_no_requests() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(206))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("empty.bin");
+    let url = format!("{}/empty.bin", server.uri());
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        0,
+        None,
+        None,
+        None,
+        &HttpOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+}
+
+#[tokio::test]
+async fn multipart_download_renames_part_file_on_success() {
+    let body = test_body(10 * 1024 + 17);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(!get_part_path(&path).exists());
+    assert!(!get_state_path(&path).exists());
+}
+
+#[tokio::test]
+async fn mmap_download_writes_every_chunk() {
+    let body = test_body(10 * 1024 + 17);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &HttpOptions {
+            mmap: true,
+            ..small_chunks()
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(!get_part_path(&path).exists());
+}
+
+#[tokio::test]
+async fn growing_chunks_are_requested_in_order() {
+    let body = test_body(10 * 1024 + 17);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let summary = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &HttpOptions {
+            max_chunk_size: Some(4096),
+            ..small_chunks()
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    // 1K、2K、4K 之后保持 4K，最后一块为剩余部分
+    assert_eq!(summary.chunks, 4);
+    let mut ranges: Vec<String> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| r.headers["Range"].to_str().unwrap().to_string())
+        .collect();
+    ranges.sort_by_key(|range| {
+        range[6..]
+            .split('-')
+            .next()
+            .unwrap()
+            .parse::<u64>()
+            .unwrap()
+    });
+    assert_eq!(
+        ranges,
+        [
+            "bytes=0-1023",
+            "bytes=1024-3071",
+            "bytes=3072-7167",
+            "bytes=7168-10256"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn small_files_are_split_into_at_least_one_chunk_per_connection() {
+    let body = test_body(2 * 1024 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("{}/file.bin", server.uri());
+
+    let download = |name: &str, options: HttpOptions| {
+        let (url, path) = (url.clone(), dir.path().join(name));
+        let size = body.len() as u64;
+        async move {
+            download_multipart(
+                &Client::new(),
+                &url,
+                &url,
+                &path,
+                size,
+                None,
+                None,
+                None,
+                &HttpOptions {
+                    quiet: true,
+                    ..options
+                },
+            )
+            .await
+            .unwrap()
+        }
+    };
+
+    // 按默认的 1MB 只有 2 块，缩小到 256KB 后每个连接都有数据块可下载
+    let summary = download("default.bin", HttpOptions::default()).await;
+    assert_eq!(summary.chunks, 8);
+    assert_eq!(std::fs::read(dir.path().join("default.bin")).unwrap(), body);
+
+    // 数据块不会小于 256KB
+    let summary = download(
+        "many.bin",
+        HttpOptions {
+            min_chunks: Some(100),
+            ..HttpOptions::default()
+        },
+    )
+    .await;
+    assert_eq!(summary.chunks, 8);
+
+    let summary = download(
+        "fixed.bin",
+        HttpOptions {
+            min_chunks: Some(1),
+            ..HttpOptions::default()
+        },
+    )
+    .await;
+    assert_eq!(summary.chunks, 2);
+    assert_eq!(std::fs::read(dir.path().join("fixed.bin")).unwrap(), body);
+}
+
+#[tokio::test]
+async fn failed_download_never_creates_final_file() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let result = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        None,
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert!(!path.exists());
+    assert!(get_part_path(&path).exists());
+}
+
+#[tokio::test]
+async fn checksum_mismatch_keeps_part_file() {
+    let body = test_body(4096);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        checksum: Some("md5:00000000000000000000000000000000".parse().unwrap()),
+        ..small_chunks()
+    };
+
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, DownloadError::ChecksumMismatch { .. }));
+    assert!(!path.exists());
+    assert_eq!(std::fs::read(get_part_path(&path)).unwrap(), body);
+}
+
+#[tokio::test]
+async fn matching_checksum_completes_download() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"abc".to_vec()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("abc.txt");
+    let url = format!("{}/abc.txt", server.uri());
+    let options = HttpOptions {
+        checksum: Some(
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+                .parse()
+                .unwrap(),
+        ),
+        ..HttpOptions::default()
+    };
+
+    download_sequential(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        None,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"abc");
+}
+
+#[tokio::test]
+async fn custom_headers_are_sent_on_every_chunk_request() {
+    let body = test_body(8 * 1024);
+    let server = MockServer::start().await;
+    // 只有携带正确 Authorization 的请求才会被响应，其余请求得到 404
+    Mock::given(method("GET"))
+        .and(header("Authorization", "Bearer secret"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .expect(8)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let mut options = small_chunks();
+    options
+        .headers
+        .insert("Authorization", "Bearer secret".parse().unwrap());
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        8 * 1024,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[tokio::test]
+async fn flaky_chunks_are_retried_until_they_succeed() {
+    let body = test_body(4 * 1024);
+    let server = MockServer::start().await;
+    // 4 个数据块 + 2 次失败 = 6 次请求
+    Mock::given(method("GET"))
+        .respond_with(FlakyResponder::new(body.clone(), 2))
+        .expect(6)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let summary = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert_eq!((summary.chunks, summary.retries), (4, 2));
+    assert!(summary.peak_speed > 0);
+    assert!(summary.average_speed() > 0);
+}
+
+/// 数据块返回的 206 响应与请求不符：响应体比范围短，或者 Content-Range 从别的位置开始
+#[tokio::test]
+async fn mismatched_partial_responses_are_retried() {
+    let body = test_body(4 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=1024-2047"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 1024-2047/4096")
+                .set_body_bytes(body[1024..1424].to_vec()),
+        )
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=3072-4095"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 0-1023/4096")
+                .set_body_bytes(body[..1024].to_vec()),
+        )
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let summary = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap();
+
+    // 不完整的数据块没有被当作完成，文件中没有全零的空洞
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert_eq!(summary.retries, 2);
+}
+
+#[tokio::test]
+async fn persistently_short_chunk_fails_the_download() {
+    let body = test_body(4 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=1024-2047"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 1024-2047/4096")
+                .set_body_bytes(body[1024..1424].to_vec()),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        chunk_max_attempts: 2,
+        ..small_chunks()
+    };
+
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, DownloadError::ChunkDownloadFailed), "{}", err);
+    assert!(!path.exists());
+    let short_requests = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.headers["Range"] == "bytes=1024-2047")
+        .count();
+    assert_eq!(short_requests, 2);
+}
+
+#[tokio::test]
+async fn connection_limit_is_shared_between_downloads() {
+    let body = test_body(8 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()).with_delay(Duration::from_millis(100)))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let limit = ConnectionLimit::new(2);
+    let options = HttpOptions {
+        connection_limit: Some(limit.clone()),
+        ..small_chunks()
+    };
+    let download = |name: &str| {
+        let path = dir.path().join(name);
+        let url = format!("{}/{}", server.uri(), name);
+        let options = options.clone();
+        async move {
+            download_multipart(
+                &Client::new(),
+                &url,
+                &url,
+                &path,
+                8 * 1024,
+                None,
+                None,
+                None,
+                &options,
+            )
+            .await
+            .unwrap();
+            path
+        }
+    };
+
+    // 两个下载各有 8 个数据块、8 个并发连接，但合计只有 2 个名额：16 个请求至少需要 8 轮
+    let started = std::time::Instant::now();
+    let (a, b) = tokio::join!(download("a.bin"), download("b.bin"));
+    assert!(started.elapsed() >= Duration::from_millis(750));
+    assert_eq!(std::fs::read(a).unwrap(), body);
+    assert_eq!(std::fs::read(b).unwrap(), body);
+    assert_eq!(limit.available(), 2);
+}
+
+/// 按数据块状态快照统计同时进行的请求数的最大值
+fn peak_in_flight(snapshots: &[Vec<ChunkReport>]) -> usize {
+    snapshots
+        .iter()
+        .map(|reports| {
+            reports
+                .iter()
+                .filter(|r| r.status == ChunkStatus::InFlight)
+                .count()
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[tokio::test]
+async fn adaptive_concurrency_grows_up_to_the_limit() {
+    let body = test_body(32 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()).with_delay(Duration::from_millis(30)))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let snapshots = Arc::new(Mutex::new(Vec::new()));
+    let recorded = snapshots.clone();
+    let options = HttpOptions {
+        concurrency: 4,
+        initial_concurrency: Some(1),
+        on_chunk_progress: Some(ChunkProgressCallback::new(move |reports| {
+            recorded.lock().unwrap().push(reports.to_vec());
+        })),
+        chunk_report_interval: Duration::from_millis(5),
+        ..small_chunks()
+    };
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    let snapshots = snapshots.lock().unwrap();
+    // 从 1 个连接开始，逐步增加，但不会超过上限
+    let first_active = snapshots
+        .iter()
+        .find(|reports| reports.iter().any(|r| r.status != ChunkStatus::Pending))
+        .unwrap();
+    assert!(peak_in_flight(std::slice::from_ref(first_active)) <= 1);
+    let peak = peak_in_flight(&snapshots);
+    assert!((2..=4).contains(&peak), "peak concurrency {}", peak);
+}
+
+#[tokio::test]
+async fn adaptive_concurrency_halves_on_errors() {
+    let body = test_body(16 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(FlakyResponder::new(body.clone(), 4))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    let options = HttpOptions {
+        concurrency: 4,
+        initial_concurrency: Some(4),
+        chunk_max_attempts: 5,
+        on_event: Some(EventCallback::new(move |event| {
+            if let DownloadEvent::Status(message) = event {
+                recorded.lock().unwrap().push(message.clone());
+            }
+        })),
+        ..small_chunks()
+    };
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    let events = events.lock().unwrap();
+    let reduced: Vec<_> = events
+        .iter()
+        .filter(|message| message.contains("并发连接数降为"))
+        .collect();
+    assert_eq!(
+        reduced.first().map(|m| m.as_str()),
+        Some("数据块请求失败，并发连接数降为 2。")
+    );
+}
+
+#[tokio::test]
+async fn chunk_fails_after_max_attempts() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        chunk_max_attempts: 2,
+        ..small_chunks()
+    };
+
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        1024,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, DownloadError::ChunkDownloadFailed));
+}
+
+/// 单个数据块返回 `status` 时，该数据块一共被请求的次数
+async fn chunk_attempts_for_status(status: u16, retry_all_errors: bool) -> usize {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(status))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        chunk_max_attempts: 3,
+        retry_all_errors,
+        ..small_chunks()
+    };
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        1024,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DownloadError::ChunkDownloadFailed));
+    server.received_requests().await.unwrap().len()
+}
+
+#[tokio::test]
+async fn chunk_retry_waits_for_retry_after() {
+    let body = test_body(4 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=1024-2047"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let started = std::time::Instant::now();
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    // 退避时间只有 10 毫秒，等待时间来自 Retry-After
+    assert!(started.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn fatal_chunk_status_is_not_retried() {
+    assert_eq!(chunk_attempts_for_status(403, false).await, 1);
+    assert_eq!(chunk_attempts_for_status(429, false).await, 3);
+    assert_eq!(chunk_attempts_for_status(403, true).await, 3);
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn part_file_space_is_allocated_before_chunks_arrive() {
+    use std::os::unix::fs::MetadataExt;
+
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let size = 1024 * 1024;
+    let options = HttpOptions {
+        chunk_max_attempts: 1,
+        chunk_size: 256 * 1024,
+        ..small_chunks()
+    };
+
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        size,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    // 没有任何数据块写入，.part 文件却已经占用了完整的磁盘空间，而不是稀疏文件
+    assert!(matches!(err, DownloadError::ChunkDownloadFailed));
+    let metadata = std::fs::metadata(get_part_path(&path)).unwrap();
+    assert_eq!(metadata.len(), size);
+    assert!(metadata.blocks() * 512 >= size);
+}
+
+#[tokio::test]
+async fn zero_attempts_is_rejected() {
+    let options = HttpOptions {
+        chunk_max_attempts: 0,
+        ..HttpOptions::default()
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let err = download_multipart(
+        &Client::new(),
+        "http://127.0.0.1:9/unused",
+        "http://127.0.0.1:9/unused",
+        &dir.path().join("unused"),
+        1024,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DownloadError::InvalidOption(_)));
+
+    let options = HttpOptions {
+        max_chunk_size: Some(512),
+        ..small_chunks()
+    };
+    let err = download_multipart(
+        &Client::new(),
+        "http://127.0.0.1:9/unused",
+        "http://127.0.0.1:9/unused",
+        &dir.path().join("unused"),
+        1024,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DownloadError::InvalidOption(_)));
+}
+
+#[tokio::test]
+async fn max_speed_throttles_multipart_download() {
+    let body = test_body(10 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        max_speed: Some(20 * 1024),
+        ..small_chunks()
+    };
+
+    let start = std::time::Instant::now();
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    // 10KB 按 20KB/s 限速至少需要约 0.5 秒
+    assert!(start.elapsed() >= Duration::from_millis(450));
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[tokio::test]
+async fn resume_with_gapped_chunk_layout_restarts_from_scratch() {
+    let body = test_body(4 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    // 伪造一个声称全部完成、但中间缺了一段的状态文件，以及全零的 .part 文件
+    let state = serde_json::json!({
+        "url": url,
+        "total_size": 4096,
+        "etag": null,
+        "chunks": [
+            { "start": 0, "end": 1023, "completed": true },
+            { "start": 2048, "end": 4095, "completed": true }
+        ]
+    });
+    std::fs::write(get_state_path(&path), state.to_string()).unwrap();
+    std::fs::write(get_part_path(&path), vec![0u8; 4096]).unwrap();
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        None,
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[tokio::test]
+async fn progress_callback_reports_all_bytes() {
+    let body = test_body(8 * 1024 + 5);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("{}/file.bin", server.uri());
+    let total = body.len() as u64;
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&events);
+    let options = HttpOptions {
+        on_progress: Some(ProgressCallback::new(move |downloaded, total| {
+            sink.lock().unwrap().push((downloaded, total));
+        })),
+        ..small_chunks()
+    };
+
+    let path = dir.path().join("multi.bin");
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        total,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+    {
+        let events = events.lock().unwrap();
+        assert!(events.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert_eq!(events.last(), Some(&(total, Some(total))));
+    }
+
+    // 大小未知的流式下载同样会上报进度，总大小为 None
+    events.lock().unwrap().clear();
+    let path = dir.path().join("stream.bin");
+    download_sequential(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        None,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(events.lock().unwrap().last(), Some(&(total, None)));
+}
+
+#[tokio::test]
+async fn supplied_progress_bar_is_used_even_in_quiet_mode() {
+    let body = test_body(8 * 1024 + 5);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("{}/file.bin", server.uri());
+    let total = body.len() as u64;
+    // 隐藏的进度条同样记录位置和长度，上一次下载留下的位置会在开始时重置
+    let bar = ProgressBar::hidden();
+    bar.set_position(12345);
+    let options = HttpOptions {
+        progress_bar: Some(bar.clone()),
+        quiet: true,
+        ..small_chunks()
+    };
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &dir.path().join("multi.bin"),
+        total,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(bar.position(), total);
+    assert_eq!(bar.length(), Some(total));
+    assert!(bar.is_finished());
+
+    download_sequential(
+        &Client::new(),
+        &url,
+        &url,
+        &dir.path().join("stream.bin"),
+        None,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(bar.position(), total);
+    assert_eq!(bar.length(), None);
+}
+
+#[tokio::test]
+async fn multipart_rejects_server_that_ignores_range() {
+    let body = test_body(4 * 1024);
+    let server = MockServer::start().await;
+    // 无论是否带 Range，始终返回 200 和完整文件
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        None,
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, DownloadError::RangeNotSupported));
+    assert!(!path.exists());
+    assert!(!get_part_path(&path).exists());
+    assert!(!get_state_path(&path).exists());
+}
+
+async fn download_with_content_types(
+    probe: Option<&str>,
+    chunk: Option<&str>,
+    options: &HttpOptions,
+) -> Result<(), DownloadError> {
+    let body = test_body(3 * 1024);
+    let server = MockServer::start().await;
+    let mut responder = RangeResponder::new(body.clone());
+    if let Some(chunk) = chunk {
+        responder = responder.with_content_type(chunk);
+    }
+    Mock::given(method("GET"))
+        .respond_with(responder)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("typed.bin");
+    let url = format!("{}/typed.bin", server.uri());
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        probe.map(String::from),
+        options,
+    )
+    .await?;
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    Ok(())
+}
+
+#[tokio::test]
+async fn content_type_comparison_ignores_case() {
+    download_with_content_types(
+        Some("Application/Octet-Stream"),
+        Some("application/octet-stream"),
+        &small_chunks(),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn content_type_comparison_ignores_charset_suffix() {
+    download_with_content_types(
+        Some("application/octet-stream"),
+        Some("application/octet-stream; charset=binary"),
+        &small_chunks(),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn different_content_type_is_still_rejected() {
+    let err = download_with_content_types(
+        Some("application/octet-stream"),
+        Some("text/html; charset=utf-8"),
+        &small_chunks(),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DownloadError::ChunkDownloadFailed));
+}
+
+#[tokio::test]
+async fn chunks_without_content_type_are_accepted() {
+    download_with_content_types(Some("application/octet-stream"), None, &small_chunks())
+        .await
+        .unwrap();
+}
+
+/// 探测响应没有 Content-Type 时不知道应有的类型，数据块声明的任何类型都接受
+#[tokio::test]
+async fn chunk_type_without_probe_type_is_accepted() {
+    download_with_content_types(None, Some("text/html"), &small_chunks())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn content_check_can_be_disabled() {
+    let options = HttpOptions {
+        skip_content_check: true,
+        ..small_chunks()
+    };
+    download_with_content_types(
+        Some("application/octet-stream"),
+        Some("text/html"),
+        &options,
+    )
+    .await
+    .unwrap();
+}
+
+/// 让第 3 个数据块始终失败，并检查返回后状态文件恰好记录了所有已写入的数据块
+async fn check_state_after_partial_failure(options: HttpOptions) {
+    let body = test_body(16 * 1024);
+    let server = MockServer::start().await;
+    // 只有第 3 个数据块始终失败，其余数据块并发完成
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=2048-3071"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DownloadError::ChunkDownloadFailed));
+
+    // 返回之前所有已完成的数据块都必须已经记录到状态文件中，且数据已落盘
+    let state: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(get_state_path(&path)).unwrap()).unwrap();
+    let part = std::fs::read(get_part_path(&path)).unwrap();
+    let chunks = state["chunks"].as_array().unwrap();
+    assert_eq!(chunks.len(), 16);
+    for chunk in chunks {
+        let start = chunk["start"].as_u64().unwrap() as usize;
+        let end = chunk["end"].as_u64().unwrap() as usize;
+        let completed = chunk["completed"].as_bool().unwrap();
+        assert_eq!(completed, start != 2048, "chunk {}-{}", start, end);
+        if completed {
+            assert_eq!(part[start..=end], body[start..=end]);
+        }
+    }
+}
+
+#[tokio::test]
+async fn next_run_fetches_only_the_failed_chunk() {
+    let body = test_body(8 * 1024);
+    let server = MockServer::start().await;
+    // 第 3 个数据块在第一次运行中始终失败，之后恢复正常
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=2048-3071"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        chunk_max_attempts: 2,
+        ..small_chunks()
+    };
+    let client = Client::new();
+    let download = || {
+        download_multipart(
+            &client,
+            &url,
+            &url,
+            &path,
+            body.len() as u64,
+            None,
+            None,
+            None,
+            &options,
+        )
+    };
+
+    let err = download().await.unwrap_err();
+    assert!(matches!(err, DownloadError::ChunkDownloadFailed));
+    let first_run = server.received_requests().await.unwrap().len();
+
+    let summary = download().await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(summary.resumed);
+    assert_eq!(summary.bytes_downloaded, 1024);
+    let requests = server.received_requests().await.unwrap();
+    let ranges: Vec<_> = requests[first_run..]
+        .iter()
+        .map(|r| {
+            r.headers
+                .get("Range")
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    assert_eq!(ranges, ["bytes=2048-3071"]);
+}
+
+#[tokio::test]
+async fn panicking_callback_fails_download_without_losing_progress() {
+    let body = test_body(8 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let download = |options: HttpOptions| {
+        let (url, path) = (url.clone(), path.clone());
+        async move {
+            download_multipart(
+                &Client::new(),
+                &url,
+                &url,
+                &path,
+                8 * 1024,
+                None,
+                None,
+                None,
+                &options,
+            )
+            .await
+        }
+    };
+
+    // 回调在数据块写入并记录之后才被调用，它的 panic 只让对应的任务失败，不会波及其他任务
+    let err = download(HttpOptions {
+        on_progress: Some(ProgressCallback::new(|downloaded, _| {
+            if downloaded > 4 * 1024 {
+                panic!("progress callback failed");
+            }
+        })),
+        ..small_chunks()
+    })
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DownloadError::ChunkDownloadFailed));
+    assert!(get_state_path(&path).exists());
+
+    download(small_chunks()).await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[tokio::test]
+async fn state_file_records_every_chunk_written_before_failure() {
+    check_state_after_partial_failure(HttpOptions {
+        chunk_max_attempts: 1,
+        state_save_every: 1,
+        ..small_chunks()
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn batched_state_writes_are_flushed_before_returning() {
+    // 批量阈值和时间间隔都不会在下载期间触发，只能依靠最后一次写入
+    check_state_after_partial_failure(HttpOptions {
+        chunk_max_attempts: 1,
+        state_save_every: 1000,
+        state_save_interval: Duration::from_secs(3600),
+        ..small_chunks()
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn zero_state_save_frequency_is_rejected() {
+    let options = HttpOptions {
+        state_save_every: 0,
+        ..HttpOptions::default()
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let err = download_multipart(
+        &Client::new(),
+        "http://127.0.0.1:9/unused",
+        "http://127.0.0.1:9/unused",
+        &dir.path().join("unused"),
+        1024,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(err, DownloadError::InvalidOption(_)));
+}
+
+#[tokio::test]
+async fn cancelled_download_keeps_state_and_resumes() {
+    let body = test_body(8 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()).with_delay(Duration::from_millis(100)))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let cancel = CancellationToken::new();
+    let options = HttpOptions {
+        concurrency: 1,
+        state_save_every: 1,
+        cancel: Some(cancel.clone()),
+        ..small_chunks()
+    };
+
+    // 下载进行到一半时取消
+    let trigger = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        cancel.cancel();
+    });
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        8192,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+    trigger.await.unwrap();
+
+    assert!(matches!(err, DownloadError::Cancelled));
+    assert!(!path.exists());
+    assert!(get_part_path(&path).exists());
+    let state: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(get_state_path(&path)).unwrap()).unwrap();
+    let completed = state["chunks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|chunk| chunk["completed"] == true)
+        .count();
+    assert!(
+        completed > 0 && completed < 8,
+        "completed {} chunks",
+        completed
+    );
+
+    // 不带取消令牌再次调用即可从中断处续传
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        8192,
+        None,
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[tokio::test]
+async fn chunk_requests_carry_if_range() {
+    let body = test_body(4 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("If-Range", "\"v1\""))
+        .respond_with(VersionedResponder::new(body.clone(), "\"v1\""))
+        .expect(4)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        Some("\"v1\"".into()),
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[tokio::test]
+async fn file_changed_between_sessions_discards_partial_data() {
+    let old_body = test_body(4 * 1024);
+    let new_body: Vec<u8> = old_body.iter().map(|b| b.wrapping_add(1)).collect();
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(VersionedResponder::new(new_body, "\"v2\""))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    // 上一次会话按 v1 下载了前两个数据块后中断
+    let state = serde_json::json!({
+        "url": url,
+        "total_size": 4096,
+        "etag": "\"v1\"",
+        "chunks": [
+            { "start": 0, "end": 1023, "completed": true },
+            { "start": 1024, "end": 2047, "completed": true },
+            { "start": 2048, "end": 3071, "completed": false },
+            { "start": 3072, "end": 4095, "completed": false }
+        ]
+    });
+    std::fs::write(get_state_path(&path), state.to_string()).unwrap();
+    let mut part = old_body[..2048].to_vec();
+    part.resize(4096, 0);
+    std::fs::write(get_part_path(&path), part).unwrap();
+
+    // 续传时沿用了旧的 ETag，服务器通过 If-Range 发现文件已变化并返回完整的新文件
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        Some("\"v1\"".into()),
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, DownloadError::ResourceChanged));
+    assert!(!path.exists());
+    assert!(!get_part_path(&path).exists());
+    assert!(!get_state_path(&path).exists());
+}
+
+#[tokio::test]
+async fn total_size_change_between_probe_and_chunks_is_reported() {
+    // 探测时文件为 4096 字节，数据块请求时服务器上的文件已经变成 8192 字节
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(test_body(8192)))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        None,
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(
+        matches!(
+            err,
+            DownloadError::TotalSizeChanged {
+                probed: 4096,
+                actual: 8192
+            }
+        ),
+        "{:?}",
+        err
+    );
+    assert_eq!(err.kind(), "size_changed");
+    assert!(!path.exists());
+    assert!(!get_part_path(&path).exists());
+    assert!(!get_state_path(&path).exists());
+}
+
+/// 伪造一个前两个数据块已完成的状态文件 (没有 ETag，只有 Last-Modified)，
+/// 已完成部分填充 0xFF，以便区分最终文件是续传得到的还是重新下载的
+fn write_last_modified_state(path: &std::path::Path, url: &str, last_modified: &str) {
+    let state = serde_json::json!({
+        "version": 1,
+        "url": url,
+        "total_size": 4096,
+        "etag": null,
+        "last_modified": last_modified,
+        "chunks": [
+            { "start": 0, "end": 1023, "completed": true },
+            { "start": 1024, "end": 2047, "completed": true },
+            { "start": 2048, "end": 3071, "completed": false },
+            { "start": 3072, "end": 4095, "completed": false }
+        ]
+    });
+    std::fs::write(get_state_path(path), state.to_string()).unwrap();
+    let mut part = vec![0xFFu8; 2048];
+    part.resize(4096, 0);
+    std::fs::write(get_part_path(path), part).unwrap();
+}
+
+async fn resume_with_last_modified(stored: &str, current: &str) -> (Vec<u8>, Vec<u8>) {
+    let body = test_body(4 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    write_last_modified_state(&path, &url, stored);
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        None,
+        Some(current.to_string()),
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap();
+
+    (std::fs::read(&path).unwrap(), body)
+}
+
+#[tokio::test]
+async fn resume_uses_last_modified_when_etag_is_absent() {
+    let date = "Wed, 21 Oct 2015 07:28:00 GMT";
+    let (downloaded, body) = resume_with_last_modified(date, date).await;
+    // 已完成的数据块没有被重新下载
+    assert!(downloaded[..2048].iter().all(|&b| b == 0xFF));
+    assert_eq!(downloaded[2048..], body[2048..]);
+}
+
+#[tokio::test]
+async fn changed_last_modified_restarts_download() {
+    let (downloaded, body) = resume_with_last_modified(
+        "Wed, 21 Oct 2015 07:28:00 GMT",
+        "Thu, 22 Oct 2015 09:00:00 GMT",
+    )
+    .await;
+    assert_eq!(downloaded, body);
+}
+
+#[tokio::test]
+async fn changed_etag_restarts_download() {
+    let body = test_body(4 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let state = serde_json::json!({
+        "url": url,
+        "total_size": 4096,
+        "etag": "\"v1\"",
+        "chunks": [
+            { "start": 0, "end": 1023, "completed": true },
+            { "start": 1024, "end": 2047, "completed": true },
+            { "start": 2048, "end": 3071, "completed": false },
+            { "start": 3072, "end": 4095, "completed": false }
+        ]
+    });
+    std::fs::write(get_state_path(&path), state.to_string()).unwrap();
+    std::fs::write(get_part_path(&path), vec![0xFFu8; 4096]).unwrap();
+
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let recorded = warnings.clone();
+    let options = HttpOptions {
+        on_event: Some(EventCallback::new(move |event| {
+            if let DownloadEvent::Warning(message) = event {
+                recorded.lock().unwrap().push(message.clone());
+            }
+        })),
+        ..small_chunks()
+    };
+
+    // 探测得到的 ETag 与状态文件不一致，已下载的部分属于旧文件，必须从头下载
+    let summary = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        Some("\"v2\"".into()),
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(!summary.resumed);
+    assert_eq!(summary.bytes_downloaded, 4096);
+    // 从头下载的原因同时写入结果和警告
+    let reason = "ETag changed from \"v1\" to \"v2\"";
+    assert_eq!(summary.restart_reason.as_deref(), Some(reason));
+    assert!(warnings.lock().unwrap().iter().any(|w| w.contains(reason)));
+}
+
+#[tokio::test]
+async fn stale_range_416_discards_partial_data() {
+    // 状态记录的大小是 8KB，但服务器上的文件已经缩小到 4KB，后半部分的数据块会得到 416
+    let body = test_body(4 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        8192,
+        None,
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, DownloadError::ResourceChanged));
+    assert!(!get_part_path(&path).exists());
+    assert!(!get_state_path(&path).exists());
+}
+
+#[tokio::test]
+async fn insufficient_disk_space_is_reported_before_downloading() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(206))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("huge.bin");
+    let url = format!("{}/huge.bin", server.uri());
+    // 1 EiB，任何测试机器的磁盘都放不下
+    let size = 1u64 << 60;
+
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        size,
+        None,
+        None,
+        None,
+        &HttpOptions::default(),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(
+        err,
+        DownloadError::InsufficientSpace { needed, available } if needed == size && available < size
+    ));
+    assert!(!get_part_path(&path).exists());
+}
+
+#[tokio::test]
+async fn space_check_can_be_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    // 比剩余空间稍大的文件：检查会拒绝，跳过检查后只会创建一个稀疏的 .part 文件
+    let size = fs2::available_space(dir.path()).unwrap() + 1024 * 1024;
+    let options = HttpOptions {
+        skip_space_check: true,
+        chunk_size: size,
+        chunk_max_attempts: 1,
+        ..HttpOptions::default()
+    };
+
+    // 之后的请求会因为连接被拒绝而失败，这里只关心没有报告空间不足
+    let result = download_multipart(
+        &Client::new(),
+        "http://127.0.0.1:9/unused",
+        "http://127.0.0.1:9/unused",
+        &dir.path().join("huge.bin"),
+        size,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await;
+
+    assert!(!matches!(
+        result,
+        Err(DownloadError::InsufficientSpace { .. })
+    ));
+}
+
+#[tokio::test]
+async fn stalled_chunk_is_retried_after_read_timeout() {
+    let body = test_body(2 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(StallResponder::new(
+            body.clone(),
+            1,
+            Duration::from_secs(30),
+        ))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        concurrency: 1,
+        read_timeout: Some(Duration::from_millis(200)),
+        ..small_chunks()
+    };
+
+    let started = std::time::Instant::now();
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        2048,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[tokio::test]
+async fn stalled_chunk_fails_after_max_attempts() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(StallResponder::new(
+            test_body(2 * 1024),
+            usize::MAX,
+            Duration::from_secs(30),
+        ))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        chunk_max_attempts: 2,
+        read_timeout: Some(Duration::from_millis(100)),
+        ..small_chunks()
+    };
+
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        2048,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, DownloadError::ChunkDownloadFailed));
+}
+
+#[tokio::test]
+async fn paused_download_saves_state_and_continues_after_resume() {
+    let body = test_body(8 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()).with_delay(Duration::from_millis(100)))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let pause = PauseHandle::new();
+    let options = HttpOptions {
+        concurrency: 1,
+        // 只靠批量写入的话，暂停期间状态文件不会更新
+        state_save_every: 100,
+        state_save_interval: Duration::from_secs(60),
+        pause: Some(pause.clone()),
+        ..small_chunks()
+    };
+
+    let download = tokio::spawn(async move {
+        download_multipart(
+            &Client::new(),
+            &url,
+            &url,
+            &path,
+            8192,
+            None,
+            None,
+            None,
+            &options,
+        )
+        .await
+    });
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    pause.pause();
+    // 等正在传输的数据块写完
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let requests = server.received_requests().await.unwrap().len();
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // 暂停期间不再发起请求，已完成的数据块已经写入状态文件
+    assert_eq!(server.received_requests().await.unwrap().len(), requests);
+    assert!(!download.is_finished());
+    let state_path = get_state_path(&dir.path().join("file.bin"));
+    let state: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(state_path).unwrap()).unwrap();
+    let completed = state["chunks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|chunk| chunk["completed"] == true)
+        .count();
+    assert_eq!(completed, requests);
+
+    pause.resume();
+    download.await.unwrap().unwrap();
+    assert_eq!(std::fs::read(dir.path().join("file.bin")).unwrap(), body);
+    assert_eq!(server.received_requests().await.unwrap().len(), 8);
+}
+
+#[tokio::test]
+async fn download_to_writer_streams_body_without_touching_disk() {
+    let body = test_body(10 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let url = format!("{}/file.bin", server.uri());
+    let mut output = Vec::new();
+
+    download_to_writer(&Client::new(), &url, &mut output, &small_chunks())
+        .await
+        .unwrap();
+
+    assert_eq!(output, body);
+}
+
+#[tokio::test]
+async fn download_to_writer_rejects_checksum() {
+    let options = HttpOptions {
+        checksum: Some(format!("sha256:{}", "0".repeat(64)).parse().unwrap()),
+        ..HttpOptions::default()
+    };
+    let mut output = Vec::new();
+
+    let err = download_to_writer(
+        &Client::new(),
+        "http://127.0.0.1:9/unused",
+        &mut output,
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, DownloadError::InvalidOption(_)));
+}
+
+#[tokio::test]
+async fn credentials_are_not_sent_to_other_origin() {
+    let body = test_body(4096);
+    let origin = MockServer::start().await;
+    let storage = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&storage)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", origin.uri());
+    let resolved_url = format!("{}/signed/file.bin", storage.uri());
+    let mut options = small_chunks();
+    options
+        .headers
+        .insert("Authorization", "Bearer secret".parse().unwrap());
+    options
+        .headers
+        .insert("Referer", "https://example.com/".parse().unwrap());
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &resolved_url,
+        &path,
+        4096,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    let requests = storage.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 4);
+    for request in requests {
+        assert!(!request.headers.contains_key("Authorization"));
+        // 其他自定义请求头照常发送
+        assert!(request.headers.contains_key("Referer"));
+    }
+}
+
+#[tokio::test]
+async fn failing_chunks_switch_to_mirror() {
+    let body = test_body(4096);
+    let primary = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&primary)
+        .await;
+    let mirror = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&mirror)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", primary.uri());
+    let options = HttpOptions {
+        chunk_max_attempts: 2,
+        concurrency: 1,
+        mirrors: vec![format!("{}/file.bin", mirror.uri())],
+        ..small_chunks()
+    };
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    // 第一个数据块在主地址上用尽重试次数后改用镜像，之后的数据块直接从镜像开始
+    assert_eq!(primary.received_requests().await.unwrap().len(), 2);
+    assert_eq!(mirror.received_requests().await.unwrap().len(), 4);
+}
+
+#[tokio::test]
+async fn gzip_encoded_range_responses_are_rejected() {
+    let server = MockServer::start().await;
+    // 服务器无视 Accept-Encoding: identity，仍然压缩 Range 响应 (响应体只是模拟的压缩数据)
+    Mock::given(method("GET"))
+        .and(header("Accept-Encoding", "identity"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("Content-Encoding", "gzip")
+                .set_body_bytes(vec![0x1f, 0x8b, 0x08, 0x00]),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let result = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        None,
+        None,
+        None,
+        &small_chunks(),
+    )
+    .await;
+
+    assert!(matches!(result, Err(DownloadError::ContentEncoded(ref e)) if e == "gzip"));
+    // 按错误偏移写入的数据不能留作续传
+    assert!(!get_part_path(&path).exists());
+    assert!(!get_state_path(&path).exists());
+}
+
+#[tokio::test]
+async fn chunk_progress_reports_status_and_attempts() {
+    let body = test_body(4096);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=3072-4095"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(FlakyResponder::new(body, 1))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let snapshots = Arc::new(Mutex::new(Vec::new()));
+    let recorded = snapshots.clone();
+    let options = HttpOptions {
+        concurrency: 1,
+        chunk_max_attempts: 2,
+        on_chunk_progress: Some(ChunkProgressCallback::new(move |reports| {
+            recorded.lock().unwrap().push(reports.to_vec());
+        })),
+        chunk_report_interval: Duration::from_millis(5),
+        ..small_chunks()
+    };
+
+    let result = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await;
+    assert!(result.is_err());
+
+    let snapshots = snapshots.lock().unwrap();
+    let last = snapshots.last().unwrap();
+    let statuses: Vec<_> = last.iter().map(|r| r.status).collect();
+    assert_eq!(
+        statuses,
+        [
+            ChunkStatus::Done,
+            ChunkStatus::Done,
+            ChunkStatus::Done,
+            ChunkStatus::Failed
+        ]
+    );
+    // 第一个数据块遇到一次 500 后重试成功，最后一个数据块用尽了 2 次尝试
+    let attempts: Vec<_> = last.iter().map(|r| r.attempts).collect();
+    assert_eq!(attempts, [2, 1, 1, 2]);
+    assert_eq!((last[3].start, last[3].end), (3072, 4095));
+}
+
+#[tokio::test]
+async fn many_small_chunks_complete_and_clean_up_state() {
+    let body = test_body(256 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    // 4096 个数据块：所有完成记录都经过写入线程，不应丢失或阻塞
+    let options = HttpOptions {
+        chunk_size: 64,
+        concurrency: 32,
+        ..HttpOptions::default()
+    };
+
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        body.len() as u64,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(!get_state_path(&path).exists());
+    assert!(!get_part_path(&path).exists());
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// 服务器无视 Accept-Encoding: identity，对大小未知的响应使用了内容编码
+async fn serve_encoded(encoding: &str, body: Vec<u8>) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Encoding", encoding)
+                .set_body_bytes(body),
+        )
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn download_stream(url: &str, options: &HttpOptions) -> Result<Vec<u8>, DownloadError> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    download_sequential(
+        &Client::new(),
+        url,
+        url,
+        &path,
+        None,
+        None,
+        None,
+        None,
+        options,
+    )
+    .await?;
+    assert!(!get_state_path(&path).exists());
+    Ok(std::fs::read(&path).unwrap())
+}
+
+#[tokio::test]
+async fn encoded_streams_are_decompressed() {
+    use std::io::Write;
+    let body = test_body(64 * 1024);
+    let mut deflate = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    deflate.write_all(&body).unwrap();
+    let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+    brotli.write_all(&body).unwrap();
+    let brotli = brotli.into_inner();
+
+    for (encoding, encoded) in [
+        ("gzip", gzip(&body)),
+        ("deflate", deflate.finish().unwrap()),
+        ("br", brotli),
+    ] {
+        let server = serve_encoded(encoding, encoded).await;
+        let url = format!("{}/file.bin", server.uri());
+
+        let output = download_stream(&url, &small_chunks()).await.unwrap();
+        assert!(output == body, "{}", encoding);
+    }
+}
+
+#[tokio::test]
+async fn encoded_stream_is_kept_raw_without_decompress() {
+    let body = test_body(16 * 1024);
+    let encoded = gzip(&body);
+    let server = serve_encoded("gzip", encoded.clone()).await;
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        decompress: false,
+        ..small_chunks()
+    };
+
+    assert_eq!(download_stream(&url, &options).await.unwrap(), encoded);
+}
+
+#[tokio::test]
+async fn truncated_encoded_stream_is_an_error() {
+    let mut encoded = gzip(&test_body(16 * 1024));
+    encoded.truncate(encoded.len() / 2);
+    let server = serve_encoded("gzip", encoded).await;
+    let url = format!("{}/file.bin", server.uri());
+
+    let err = download_stream(&url, &small_chunks()).await.unwrap_err();
+    assert!(matches!(err, DownloadError::DecodeError(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn download_to_writer_decompresses_encoded_body() {
+    let body = test_body(16 * 1024);
+    let server = serve_encoded("gzip", gzip(&body)).await;
+    let url = format!("{}/file.bin", server.uri());
+    let mut output = Vec::new();
+
+    download_to_writer(&Client::new(), &url, &mut output, &small_chunks())
+        .await
+        .unwrap();
+
+    assert_eq!(output, body);
+}
+
+#[tokio::test]
+async fn gzip_files_are_unpacked_next_to_the_download() {
+    assert_eq!(
+        unpacked_path(Path::new("dir/data.tar.gz")),
+        Some("dir/data.tar".into())
+    );
+    assert_eq!(
+        unpacked_path(Path::new("dir/data.TGZ")),
+        Some("dir/data.tar".into())
+    );
+    assert_eq!(unpacked_path(Path::new("dir/data.zip")), None);
+
+    let dir = tempfile::tempdir().unwrap();
+    let body = test_body(100 * 1024);
+    let compressed = dir.path().join("data.bin.gz");
+    // 两个拼接的 gzip 成员
+    let mut two_members = gzip(&body[..1000]);
+    two_members.extend(gzip(&body[1000..]));
+    std::fs::write(&compressed, two_members).unwrap();
+    let target = dir.path().join("data.bin");
+    assert_eq!(
+        unpack_gzip(&compressed, &target).await.unwrap(),
+        body.len() as u64
+    );
+    assert_eq!(std::fs::read(&target).unwrap(), body);
+
+    // 截断的压缩数据不会留下不完整的文件
+    let truncated = gzip(&body);
+    std::fs::write(&compressed, &truncated[..truncated.len() / 2]).unwrap();
+    let target = dir.path().join("broken.bin");
+    let err = unpack_gzip(&compressed, &target).await.unwrap_err();
+    assert!(matches!(err, DownloadError::DecodeError(_)), "{:?}", err);
+    assert!(!target.exists());
+    assert!(!get_part_path(&target).exists());
+}
+
+#[tokio::test]
+async fn unknown_size_stream_stops_at_max_size() {
+    let body = test_body(64 * 1024);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(body))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        max_size: Some(10 * 1024),
+        ..small_chunks()
+    };
+
+    let err = download_sequential(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        None,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(
+        matches!(err, DownloadError::TooLarge { limit, .. } if limit == 10 * 1024),
+        "{:?}",
+        err
+    );
+    assert!(!path.exists());
+    // 已下载的部分保留下来，不超过限制
+    let kept = std::fs::metadata(get_part_path(&path)).unwrap().len();
+    assert!(kept <= 10 * 1024, "{}", kept);
+}
+
+#[tokio::test]
+async fn max_size_applies_to_decompressed_data() {
+    let server = serve_encoded("gzip", gzip(&vec![0; 1024 * 1024])).await;
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        max_size: Some(64 * 1024),
+        ..small_chunks()
+    };
+
+    let err = download_stream(&url, &options).await.unwrap_err();
+    assert!(matches!(err, DownloadError::TooLarge { .. }), "{:?}", err);
+}
@@ -0,0 +1,946 @@
+mod common;
+
+use common::{RangeResponder, test_body};
+use rdownloader_http::{
+    DownloadError, DownloadEvent, DownloadSummary, EventCallback, HttpOptions, ProgressCallback,
+    ResumeMode, download_from_reader, download_multipart, download_sequential, resolve_part_path,
+    resolve_state_path,
+};
+use rdownloader_utils::{chunk_hash, get_part_path, get_state_path};
+use reqwest::Client;
+use std::path::Path;
+use std::time::Duration;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn options() -> HttpOptions {
+    HttpOptions {
+        chunk_size: 1024,
+        chunk_max_attempts: 1,
+        retry_backoff: Duration::from_millis(10),
+        ..HttpOptions::default()
+    }
+}
+
+/// 前两个数据块已完成的数据块布局
+fn half_done_chunks() -> serde_json::Value {
+    serde_json::json!([
+        { "start": 0, "end": 1023, "completed": true },
+        { "start": 1024, "end": 2047, "completed": true },
+        { "start": 2048, "end": 3071, "completed": false },
+        { "start": 3072, "end": 4095, "completed": false }
+    ])
+}
+
+/// 写入状态文件和 .part 文件，已完成部分填充 0xFF，以便区分续传和重新下载
+fn write_state(path: &Path, contents: &str) {
+    std::fs::write(get_state_path(path), contents).unwrap();
+    let mut part = vec![0xFFu8; 2048];
+    part.resize(4096, 0);
+    std::fs::write(get_part_path(path), part).unwrap();
+}
+
+async fn serve(body: &[u8]) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.to_vec()))
+        .mount(&server)
+        .await;
+    server
+}
+
+async fn download(url: &str, path: &Path) -> Result<DownloadSummary, DownloadError> {
+    download_multipart(
+        &Client::new(),
+        url,
+        url,
+        path,
+        4096,
+        None,
+        None,
+        None,
+        &options(),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn v0_state_without_version_is_migrated_and_resumed() {
+    let body = test_body(4096);
+    let server = serve(&body).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let v0 = serde_json::json!({
+        "url": url,
+        "total_size": 4096,
+        "etag": null,
+        "chunks": half_done_chunks()
+    });
+    write_state(&path, &v0.to_string());
+
+    let summary = download(&url, &path).await.unwrap();
+    assert!(summary.resumed && summary.multipart);
+    assert_eq!(summary.bytes_downloaded, 2048);
+    // 续传前已完成的数据块不计入本次下载
+    assert_eq!(summary.chunks, 2);
+
+    let downloaded = std::fs::read(&path).unwrap();
+    assert!(downloaded[..2048].iter().all(|&b| b == 0xFF));
+    assert_eq!(downloaded[2048..], body[2048..]);
+}
+
+#[tokio::test]
+async fn migrated_state_is_saved_in_current_format() {
+    let body = test_body(4096);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=3072-4095"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let v0 = serde_json::json!({
+        "url": url,
+        "total_size": 4096,
+        "etag": null,
+        "chunks": half_done_chunks()
+    });
+    write_state(&path, &v0.to_string());
+
+    assert!(download(&url, &path).await.is_err());
+
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(get_state_path(&path)).unwrap()).unwrap();
+    assert_eq!(saved["version"], 1);
+    assert!(saved["last_modified"].is_null());
+    assert_eq!(saved["chunks"][2]["completed"], true);
+    assert_eq!(saved["chunks"][3]["completed"], false);
+    // 本次完成的数据块记录了内容的哈希，迁移前完成的数据块没有
+    assert_eq!(saved["chunks"][2]["hash"], chunk_hash(&body[2048..3072]));
+    assert!(saved["chunks"][0].get("hash").is_none());
+}
+
+async fn download_verified(url: &str, path: &Path) -> Result<DownloadSummary, DownloadError> {
+    download_multipart(
+        &Client::new(),
+        url,
+        url,
+        path,
+        4096,
+        None,
+        None,
+        None,
+        &HttpOptions {
+            verify_resume: true,
+            ..options()
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+async fn verify_resume_redownloads_only_corrupted_chunks() {
+    let body = test_body(4096);
+    let server = serve(&body).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let mut chunks = half_done_chunks();
+    chunks[0]["hash"] = chunk_hash(&body[..1024]).into();
+    chunks[1]["hash"] = chunk_hash(&body[1024..2048]).into();
+    let state = serde_json::json!({
+        "version": 1,
+        "url": url,
+        "total_size": 4096,
+        "etag": null,
+        "last_modified": null,
+        "chunks": chunks
+    });
+    std::fs::write(get_state_path(&path), state.to_string()).unwrap();
+    // 第二个数据块在崩溃时只写入了一部分
+    let mut part = body[..2048].to_vec();
+    part[1500..2048].fill(0);
+    part.resize(4096, 0);
+    std::fs::write(get_part_path(&path), part).unwrap();
+
+    let summary = download_verified(&url, &path).await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert_eq!(summary.bytes_downloaded, 3072);
+}
+
+#[tokio::test]
+async fn verify_resume_redownloads_chunks_without_hash() {
+    let body = test_body(4096);
+    let server = serve(&body).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    // 旧版本的状态文件没有记录哈希，已完成部分的内容 (0xFF) 也无法确认
+    write_state(&path, &valid_state(&url));
+
+    let summary = download_verified(&url, &path).await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert_eq!(summary.bytes_downloaded, 4096);
+}
+
+#[tokio::test]
+async fn unknown_state_version_restarts_download() {
+    let body = test_body(4096);
+    let server = serve(&body).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let future = serde_json::json!({
+        "version": 99,
+        "url": url,
+        "total_size": 4096,
+        "etag": null,
+        "chunks": half_done_chunks()
+    });
+    write_state(&path, &future.to_string());
+
+    download(&url, &path).await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[tokio::test]
+async fn corrupt_state_file_restarts_download() {
+    let body = test_body(4096);
+    let server = serve(&body).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    write_state(&path, "{\"url\": \"truncated");
+
+    download(&url, &path).await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[tokio::test]
+async fn resume_uses_recorded_resolved_url() {
+    let body = test_body(4096);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/recorded"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .expect(2)
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let state = serde_json::json!({
+        "version": 1,
+        "url": url,
+        "total_size": 4096,
+        "etag": null,
+        "last_modified": null,
+        "resolved_url": format!("{}/recorded", server.uri()),
+        "chunks": half_done_chunks()
+    });
+    write_state(&file, &state.to_string());
+
+    // 本次探测解析出了另一个地址，续传仍然发往状态文件记录的地址
+    download_multipart(
+        &Client::new(),
+        &url,
+        &format!("{}/fresh", server.uri()),
+        &file,
+        4096,
+        None,
+        None,
+        None,
+        &options(),
+    )
+    .await
+    .unwrap();
+
+    let downloaded = std::fs::read(&file).unwrap();
+    assert!(downloaded[..2048].iter().all(|&b| b == 0xFF));
+    assert_eq!(downloaded[2048..], body[2048..]);
+}
+
+#[tokio::test]
+async fn truncated_part_file_restarts_download() {
+    let body = test_body(4096);
+    let server = serve(&body).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    // 状态文件记录所有数据块都已完成，但 .part 文件在此期间被截断了
+    let state = serde_json::json!({
+        "version": 1,
+        "url": url,
+        "total_size": 4096,
+        "etag": null,
+        "last_modified": null,
+        "chunks": [
+            { "start": 0, "end": 2047, "completed": true },
+            { "start": 2048, "end": 4095, "completed": true }
+        ]
+    });
+    std::fs::write(get_state_path(&path), state.to_string()).unwrap();
+    std::fs::write(get_part_path(&path), vec![0xFFu8; 3000]).unwrap();
+
+    let summary = download(&url, &path).await.unwrap();
+
+    // 已完成的数据块不在 .part 文件中，不能信任状态文件
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(!summary.resumed);
+    assert_eq!(
+        summary.restart_reason.as_deref(),
+        Some("the .part file is shorter (3000 bytes) than its completed chunks (4096 bytes)")
+    );
+}
+
+/// 截断的 .part 文件在续传前就能发现；下载过程中服务器返回的短数据块则要在数据块完成前拦住，
+/// 否则状态文件会把全零的区域记录为已完成
+#[tokio::test]
+async fn short_chunk_is_not_recorded_as_completed() {
+    let body = test_body(4096);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=1024-2047"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 1024-2047/4096")
+                .set_body_bytes(body[1024..1424].to_vec()),
+        )
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let err = download(&url, &path).await.unwrap_err();
+
+    assert!(matches!(err, DownloadError::ChunkDownloadFailed), "{}", err);
+    assert!(!path.exists());
+    let state: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(get_state_path(&path)).unwrap()).unwrap();
+    let completed: Vec<bool> = state["chunks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["completed"].as_bool().unwrap())
+        .collect();
+    assert_eq!(completed, [true, false, true, true]);
+
+    // 下次运行只重新下载这个数据块
+    let summary = download(&url, &path).await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(summary.resumed);
+    assert_eq!(summary.bytes_downloaded, 1024);
+}
+
+/// 所有数据块都写完之后 .part 文件被截断 (例如磁盘上的短写入)，不能当作下载成功
+#[tokio::test]
+async fn part_file_truncated_after_all_chunks_is_reported() {
+    let server = serve(&test_body(4096)).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    // 最后一次进度上报发生在最后一个数据块写入之后
+    let part_path = get_part_path(&path);
+    let options = HttpOptions {
+        on_progress: Some(ProgressCallback::new(move |downloaded, _| {
+            if downloaded == 4096 {
+                let part = std::fs::OpenOptions::new().write(true).open(&part_path);
+                part.unwrap().set_len(3000).unwrap();
+            }
+        })),
+        ..options()
+    };
+
+    let err = download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(
+        err,
+        DownloadError::SizeMismatch {
+            expected: 4096,
+            actual: 3000
+        }
+    ));
+    assert!(!path.exists());
+    assert!(get_part_path(&path).exists());
+    assert!(!get_state_path(&path).exists());
+}
+
+#[tokio::test]
+async fn stream_ending_early_keeps_part_file_for_resume() {
+    let body = test_body(4096);
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+
+    let err = download_from_reader(&body[..3000], &path, Some(4096), 0, &options())
+        .await
+        .unwrap_err();
+
+    assert!(
+        matches!(
+            err,
+            DownloadError::SizeMismatch {
+                expected: 4096,
+                actual: 3000
+            }
+        ),
+        "{}",
+        err
+    );
+    assert!(!path.exists());
+    assert_eq!(std::fs::read(get_part_path(&path)).unwrap(), &body[..3000]);
+
+    // 从 .part 文件末尾继续即可完成
+    let summary = download_from_reader(&body[3000..], &path, Some(4096), 3000, &options())
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(summary.resumed);
+}
+
+#[tokio::test]
+async fn deleted_part_file_restarts_download() {
+    let body = test_body(4096);
+    let server = MockServer::start().await;
+    // 第一次运行时最后一个数据块失败，留下状态文件和 .part 文件
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=3072-4095"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    assert!(download(&url, &path).await.is_err());
+    assert!(get_state_path(&path).exists());
+
+    // 两次运行之间用户删除了 .part 文件，状态文件仍在
+    std::fs::remove_file(get_part_path(&path)).unwrap();
+    let summary = download(&url, &path).await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(!summary.resumed);
+    assert_eq!(summary.bytes_downloaded, 4096);
+    assert_eq!(
+        summary.restart_reason.as_deref(),
+        Some("the .part file is missing")
+    );
+}
+
+#[tokio::test]
+async fn mmap_falls_back_to_positional_writes_for_short_part_file() {
+    let body = test_body(4096);
+    let server = serve(&body).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    // 只有前半部分写入了 .part 文件，映射整个文件会越过文件末尾
+    let state = serde_json::json!({
+        "version": 1,
+        "url": url,
+        "total_size": 4096,
+        "etag": null,
+        "last_modified": null,
+        "chunks": [
+            { "start": 0, "end": 2047, "completed": true },
+            { "start": 2048, "end": 4095, "completed": false }
+        ]
+    });
+    std::fs::write(get_state_path(&path), state.to_string()).unwrap();
+    std::fs::write(get_part_path(&path), &body[..2048]).unwrap();
+
+    let warnings = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorded = warnings.clone();
+    let options = HttpOptions {
+        mmap: true,
+        on_event: Some(EventCallback::new(move |event| {
+            if let DownloadEvent::Warning(message) = event {
+                recorded.lock().unwrap().push(message.clone());
+            }
+        })),
+        ..options()
+    };
+    download_multipart(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        4096,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(
+        warnings
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|m| m.contains("内存映射"))
+    );
+}
+
+fn valid_state(url: &str) -> String {
+    serde_json::json!({
+        "version": 1,
+        "url": url,
+        "total_size": 4096,
+        "etag": null,
+        "last_modified": null,
+        "chunks": half_done_chunks()
+    })
+    .to_string()
+}
+
+async fn download_with_resume(
+    url: &str,
+    path: &Path,
+    resume: ResumeMode,
+) -> Result<DownloadSummary, DownloadError> {
+    let options = HttpOptions {
+        resume,
+        ..options()
+    };
+    download_multipart(
+        &Client::new(),
+        url,
+        url,
+        path,
+        4096,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn restart_mode_ignores_valid_state() {
+    let body = test_body(4096);
+    let server = serve(&body).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    write_state(&path, &valid_state(&url));
+
+    download_with_resume(&url, &path, ResumeMode::Restart)
+        .await
+        .unwrap();
+
+    // 已完成部分 (0xFF) 也被重新下载
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+}
+
+#[tokio::test]
+async fn require_mode_fails_without_saved_progress() {
+    let server = serve(&test_body(4096)).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+
+    let result = download_with_resume(&url, &path, ResumeMode::Require).await;
+
+    assert!(matches!(result, Err(DownloadError::CannotResume(_))));
+    assert!(!get_part_path(&path).exists());
+    assert!(server.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn require_mode_keeps_mismatched_state() {
+    let server = serve(&test_body(4096)).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    // 状态文件记录的是另一个 URL，无法续传
+    write_state(&path, &valid_state("http://example.com/other.bin"));
+
+    let result = download_with_resume(&url, &path, ResumeMode::Require).await;
+
+    assert!(
+        matches!(&result, Err(DownloadError::CannotResume(reason)) if reason.contains("URL changed from http://example.com/other.bin")),
+        "{:?}",
+        result
+    );
+    assert!(get_state_path(&path).exists());
+    assert!(get_part_path(&path).exists());
+}
+
+#[tokio::test]
+async fn require_mode_resumes_valid_state() {
+    let body = test_body(4096);
+    let server = serve(&body).await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    write_state(&path, &valid_state(&url));
+
+    let summary = download_with_resume(&url, &path, ResumeMode::Require)
+        .await
+        .unwrap();
+    assert_eq!(summary.restart_reason, None);
+
+    let downloaded = std::fs::read(&path).unwrap();
+    assert!(downloaded[..2048].iter().all(|&b| b == 0xFF));
+    assert_eq!(downloaded[2048..], body[2048..]);
+}
+
+/// 大小未知的流式下载留下的状态文件：已下载前 2048 字节 (.part 中填充 0xFF)
+fn write_stream_state(path: &Path, url: &str) {
+    let state = serde_json::json!({
+        "version": 1,
+        "url": url,
+        "resolved_url": url,
+        "etag": "\"v1\"",
+        "last_modified": null,
+        "downloaded": 2048
+    });
+    std::fs::write(get_state_path(path), state.to_string()).unwrap();
+    std::fs::write(get_part_path(path), vec![0xFFu8; 2048]).unwrap();
+}
+
+async fn download_stream(url: &str, path: &Path) -> Result<DownloadSummary, DownloadError> {
+    download_sequential(
+        &Client::new(),
+        url,
+        url,
+        path,
+        None,
+        Some("\"v1\"".into()),
+        None,
+        None,
+        &options(),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn unknown_size_stream_resumes_from_saved_offset() {
+    let body = test_body(4096);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=2048-"))
+        .and(header("If-Range", "\"v1\""))
+        .and(header("Accept-Encoding", "identity"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 2048-4095/*")
+                .set_body_bytes(body[2048..].to_vec()),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stream.bin");
+    let url = format!("{}/stream.bin", server.uri());
+    write_stream_state(&path, &url);
+
+    let summary = download_stream(&url, &path).await.unwrap();
+    assert!(summary.resumed && !summary.multipart);
+    assert_eq!((summary.bytes_downloaded, summary.total_size), (2048, 4096));
+
+    let downloaded = std::fs::read(&path).unwrap();
+    assert!(downloaded[..2048].iter().all(|&b| b == 0xFF));
+    assert_eq!(downloaded[2048..], body[2048..]);
+    assert!(!get_state_path(&path).exists());
+}
+
+#[tokio::test]
+async fn unknown_size_stream_restarts_when_range_is_ignored() {
+    let body = test_body(4096);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(body.clone()))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stream.bin");
+    let url = format!("{}/stream.bin", server.uri());
+    write_stream_state(&path, &url);
+
+    let summary = download_stream(&url, &path).await.unwrap();
+
+    // 200 响应本身就是完整的文件，直接用它从头写入，不再发送第二个请求
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert_eq!(
+        summary.restart_reason.as_deref(),
+        Some("the server did not continue from byte 2048 (HTTP 200 OK)")
+    );
+}
+
+#[tokio::test]
+async fn stalled_resume_request_times_out() {
+    // 接受连接后既不读取请求也不响应
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/stream.bin", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        let (_socket, _) = listener.accept().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    });
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stream.bin");
+    write_stream_state(&path, &url);
+    let options = HttpOptions {
+        read_timeout: Some(Duration::from_millis(200)),
+        ..options()
+    };
+
+    let err = download_sequential(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        None,
+        Some("\"v1\"".into()),
+        None,
+        None,
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, DownloadError::ReadTimeout(_)), "{}", err);
+    // 进度没有丢失，下次仍可续传
+    assert!(get_state_path(&path).exists());
+}
+
+/// 发送响应头和前 1000 字节后停滞的服务器 (不带 Content-Length，大小未知)
+async fn stalling_stream_server() -> String {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = [0u8; 4096];
+        let _ = socket.read(&mut request).await;
+        socket
+            .write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        socket.write_all(&test_body(1000)).await.unwrap();
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    });
+    format!("http://{}/stream.bin", addr)
+}
+
+#[tokio::test]
+async fn interrupted_unknown_size_stream_keeps_progress() {
+    let url = stalling_stream_server().await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("stream.bin");
+    let options = HttpOptions {
+        read_timeout: Some(Duration::from_millis(200)),
+        ..options()
+    };
+
+    let result = download_sequential(
+        &Client::new(),
+        &url,
+        &url,
+        &path,
+        None,
+        None,
+        None,
+        None,
+        &options,
+    )
+    .await;
+
+    assert!(matches!(result, Err(DownloadError::ReadTimeout(_))));
+    let saved: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(get_state_path(&path)).unwrap()).unwrap();
+    assert_eq!(saved["url"], url);
+    assert_eq!(saved["downloaded"], 1000);
+    assert_eq!(std::fs::metadata(get_part_path(&path)).unwrap().len(), 1000);
+}
+
+#[tokio::test]
+async fn state_dir_keeps_state_out_of_download_dir() {
+    let body = test_body(4096);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=3072-4095"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let state_dir = dir.path().join("state");
+    let path = dir.path().join("out").join("file.bin");
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        state_dir: Some(state_dir.clone()),
+        ..options()
+    };
+    let client = Client::new();
+    let download =
+        || download_multipart(&client, &url, &url, &path, 4096, None, None, None, &options);
+
+    assert!(download().await.is_err());
+
+    let state_path = resolve_state_path(&path, &url, &options);
+    assert!(state_path.starts_with(&state_dir));
+    assert!(state_path.exists());
+    assert!(!get_state_path(&path).exists());
+
+    // 续传时找到同一个状态文件，成功后将其删除
+    server.reset().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+    download().await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(!state_path.exists());
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+}
+
+#[tokio::test]
+async fn no_state_never_touches_state_file() {
+    let body = test_body(4096);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=3072-4095"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    // 已有的 (有效的) 状态文件既不用于续传，也不会被覆盖或删除
+    let saved = valid_state(&url);
+    write_state(&path, &saved);
+    let options = HttpOptions {
+        no_state: true,
+        ..options()
+    };
+    let client = Client::new();
+    let download =
+        || download_multipart(&client, &url, &url, &path, 4096, None, None, None, &options);
+
+    assert!(download().await.is_err());
+    assert_eq!(
+        std::fs::read_to_string(get_state_path(&path)).unwrap(),
+        saved
+    );
+
+    // 没有跨运行的进度，再次运行时下载全部数据块
+    server.reset().await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+    let summary = download().await.unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert!(!summary.resumed);
+    assert_eq!(server.received_requests().await.unwrap().len(), 4);
+    assert_eq!(
+        std::fs::read_to_string(get_state_path(&path)).unwrap(),
+        saved
+    );
+}
+
+#[tokio::test]
+async fn temp_dir_holds_part_and_state_until_completion() {
+    let body = test_body(4096);
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=3072-4095"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(RangeResponder::new(body.clone()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let temp_dir = dir.path().join("scratch");
+    let out = dir.path().join("out");
+    std::fs::create_dir(&out).unwrap();
+    let path = out.join("file.bin");
+    let url = format!("{}/file.bin", server.uri());
+    let options = HttpOptions {
+        temp_dir: Some(temp_dir.clone()),
+        ..options()
+    };
+    let client = Client::new();
+    let download =
+        || download_multipart(&client, &url, &url, &path, 4096, None, None, None, &options);
+
+    assert!(download().await.is_err());
+
+    // 未完成的下载只留在临时目录中，状态文件就在 .part 文件旁边
+    let part_path = resolve_part_path(&path, &options);
+    let state_path = resolve_state_path(&path, &url, &options);
+    assert!(part_path.starts_with(&temp_dir) && part_path.exists());
+    assert_eq!(state_path.parent(), part_path.parent());
+    assert!(state_path.exists());
+    assert_eq!(std::fs::read_dir(&out).unwrap().count(), 0);
+
+    let summary = download().await.unwrap();
+
+    assert!(summary.resumed);
+    assert_eq!(std::fs::read(&path).unwrap(), body);
+    assert_eq!(std::fs::read_dir(&temp_dir).unwrap().count(), 0);
+    assert_eq!(std::fs::read_dir(&out).unwrap().count(), 1);
+}
@@ -1,8 +1,21 @@
+use base64::prelude::{Engine as _, BASE64_STANDARD};
 use regex::Regex;
-use reqwest::header::CONTENT_DISPOSITION;
-use reqwest::Client;
+use reqwest::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_DISPOSITION,
+    CONTENT_ENCODING, COOKIE, PROXY_AUTHORIZATION, RANGE, RETRY_AFTER,
+};
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 
 // --- chunk_utils ---
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -10,111 +23,1244 @@ pub struct ChunkState {
     pub start: u64,
     pub end: u64,
     pub completed: bool,
+    /// 数据块完成时写入的数据的 [`chunk_hash`]，续传时可据此校验数据是否完好。
+    /// 旧版本保存的状态文件没有此字段
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<u64>,
 }
 
-pub fn create_chunks(total_size: u64, is_multipart: bool) -> Vec<ChunkState> {
+/// 多线程模式下默认的数据块大小 (1MB)
+pub const DEFAULT_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// 为了达到最少数据块数而缩小数据块时的下限 (256KB)，更小的数据块只会增加请求次数
+pub const MIN_CHUNK_SIZE: u64 = 256 * 1024;
+
+/// 多线程模式下数据块大小的选取方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkStrategy {
+    /// 所有数据块大小相同，最后一块可能较小
+    Fixed(u64),
+    /// 第一个数据块为 `start` 字节，之后每块翻倍，直到 `max` 字节。
+    /// 较小的前几个数据块能更早地显示进度和估算剩余时间，也适合限制了首个范围请求大小的服务器
+    Exponential { start: u64, max: u64 },
+}
+
+impl ChunkStrategy {
+    /// 必要时缩小数据块，使 `total_size` 字节至少划分为 `min_chunks` 块，让较小的文件也能并行下载。
+    /// 缩小后的数据块不小于 [`MIN_CHUNK_SIZE`]，原本就更小的数据块保持不变；
+    /// `Exponential` 的 `start` 和 `max` 都不超过缩小后的大小
+    pub fn with_min_chunks(self, total_size: u64, min_chunks: u64) -> Self {
+        let limit = total_size.div_ceil(min_chunks.max(1)).max(MIN_CHUNK_SIZE);
+        match self {
+            Self::Fixed(size) => Self::Fixed(size.min(limit)),
+            Self::Exponential { start, max } => Self::Exponential {
+                start: start.min(limit),
+                max: max.min(limit),
+            },
+        }
+    }
+}
+
+impl Default for ChunkStrategy {
+    fn default() -> Self {
+        Self::Fixed(DEFAULT_CHUNK_SIZE)
+    }
+}
+
+impl From<u64> for ChunkStrategy {
+    fn from(chunk_size: u64) -> Self {
+        Self::Fixed(chunk_size)
+    }
+}
+
+/// 将文件划分为若干数据块。
+///
+/// 单线程模式下整个文件为一个数据块；多线程模式下按 `strategy` 切分 (传入字节数时为
+/// [`ChunkStrategy::Fixed`])，最后一块可能较小。数据块大小必须大于 0，
+/// `Exponential` 的 `max` 小于 `start` 时按 `start` 处理。
+/// 空文件 (`total_size == 0`) 不需要任何数据块，返回空列表。
+pub fn create_chunks(
+    total_size: u64,
+    is_multipart: bool,
+    strategy: impl Into<ChunkStrategy>,
+) -> Vec<ChunkState> {
+    if total_size == 0 {
+        return Vec::new();
+    }
     if !is_multipart {
         return vec![ChunkState {
             start: 0,
             end: total_size - 1,
             completed: false,
+            hash: None,
         }];
     }
-    let chunk_size = 1 * 1024 * 1024; // 1MB
+    let (mut chunk_size, max) = match strategy.into() {
+        ChunkStrategy::Fixed(size) => (size, size),
+        ChunkStrategy::Exponential { start, max } => (start, max.max(start)),
+    };
     let mut chunks = Vec::new();
     let mut start = 0;
     while start < total_size {
-        let end = (start + chunk_size - 1).min(total_size - 1);
+        let end = start.saturating_add(chunk_size - 1).min(total_size - 1);
         chunks.push(ChunkState {
             start,
             end,
             completed: false,
+            hash: None,
         });
         start = end + 1;
+        chunk_size = chunk_size.saturating_mul(2).min(max);
     }
     chunks
 }
 
+/// 检查数据块列表是否按顺序、无间隙、无重叠地恰好覆盖 `[0, total_size)`。
+///
+/// 用于校验从状态文件中恢复的数据块布局，防止损坏或不兼容的状态文件导致文件内容缺失或错位。
+pub fn validate_chunks(chunks: &[ChunkState], total_size: u64) -> bool {
+    let mut expected_start = 0;
+    for chunk in chunks {
+        if chunk.start != expected_start || chunk.end < chunk.start || chunk.end >= total_size {
+            return false;
+        }
+        expected_start = chunk.end + 1;
+    }
+    expected_start == total_size
+}
+
 // --- http_utils ---
+/// 从 `Content-Range` 响应头中提取文件总大小。
+///
+/// 支持 `bytes 0-1/12345` 和 416 响应使用的 `bytes */12345` 两种形式，各部分之间允许有空白。
+/// 总大小未知 (`bytes 0-1/*`)、格式错误或范围本身不合法 (起点大于终点、终点超出总大小) 时返回 `None`。
 pub fn parse_content_range(range_str: &str) -> Option<u64> {
-    let re = Regex::new(r"bytes \d+-\d+/(\d+)").unwrap();
-    re.captures(range_str)
-        .and_then(|cap| cap.get(1)?.as_str().parse().ok())
+    // 多线程下载时每个数据块响应都会经过这里，正则只编译一次
+    static RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?i)^\s*bytes\s+(?:(\d+)\s*-\s*(\d+)|\*)\s*/\s*(\d+|\*)\s*$").unwrap()
+    });
+    let cap = RE.captures(range_str)?;
+    let total: u64 = cap.get(3)?.as_str().parse().ok()?;
+    if let (Some(start), Some(end)) = (cap.get(1), cap.get(2)) {
+        let start: u64 = start.as_str().parse().ok()?;
+        let end: u64 = end.as_str().parse().ok()?;
+        if start > end || end >= total {
+            return None;
+        }
+    }
+    Some(total)
+}
+
+/// 从 `Content-Range` 响应头中提取本次响应的起始字节偏移，总大小可以未知 (`bytes 100-199/*`)。
+/// 格式错误或范围不合法时返回 `None`。
+pub fn content_range_start(range_str: &str) -> Option<u64> {
+    content_range_bounds(range_str).map(|(start, _)| start)
+}
+
+/// `Content-Range` 中给出的范围 (起点和终点，包含两端)，总大小可以未知
+fn content_range_bounds(range_str: &str) -> Option<(u64, u64)> {
+    // 多线程下载时每个数据块响应都会经过这里，正则只编译一次
+    static RE: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?i)^\s*bytes\s+(\d+)\s*-\s*(\d+)\s*/\s*(\d+|\*)\s*$").unwrap()
+    });
+    let cap = RE.captures(range_str)?;
+    let start: u64 = cap.get(1)?.as_str().parse().ok()?;
+    let end: u64 = cap.get(2)?.as_str().parse().ok()?;
+    if start > end {
+        return None;
+    }
+    if let Ok(total) = cap.get(3)?.as_str().parse::<u64>() {
+        if end >= total {
+            return None;
+        }
+    }
+    Some((start, end))
+}
+
+/// 从 `Content-Range` 响应头中提取本次响应包含的字节数 (`bytes 100-199/1000` 为 100)。
+/// 格式错误、范围不合法或没有给出范围 (`bytes */1000`) 时返回 `None`。
+pub fn content_range_len(range_str: &str) -> Option<u64> {
+    content_range_bounds(range_str).map(|(start, end)| end - start + 1)
+}
+
+/// 发往 `target_url` 的请求应携带的请求头，`url` 是用户给出的原始地址。
+///
+/// 与 reqwest 跟随重定向时的处理一致：目标与原始地址不同源时去掉认证信息和 Cookie，
+/// 避免把凭据泄露给另一台服务器 (例如预签名的对象存储地址或镜像站点)。
+pub fn target_headers(url: &str, target_url: &str, headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    let same_origin = match (reqwest::Url::parse(url), reqwest::Url::parse(target_url)) {
+        (Ok(a), Ok(b)) => a.origin() == b.origin(),
+        _ => false,
+    };
+    if !same_origin {
+        headers.remove(AUTHORIZATION);
+        headers.remove(COOKIE);
+        headers.remove(PROXY_AUTHORIZATION);
+    }
+    headers
+}
+
+/// 响应使用的内容编码，没有编码或为 `identity` 时返回 `None`
+pub fn content_encoding(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty() && v != "identity")
+}
+
+/// 提取 Content-Type 的 MIME 本体用于比较：去掉 `; charset=...` 等参数并统一为小写，
+/// 例如 `Application/Octet-Stream; charset=binary` 归一化为 `application/octet-stream`。
+pub fn mime_essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+// --- size_utils ---
+
+/// 解析人类可读的大小字符串，例如 `4M`、`16MB`、`512k`、`1GiB` 或纯字节数 `1048576`。
+///
+/// 单位按 1024 进制计算，不区分大小写。无法解析或结果溢出时返回 `None`。
+pub fn parse_size(input: &str) -> Option<u64> {
+    let s = input.trim();
+    let digits_end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits_end);
+    let number: u64 = number.parse().ok()?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        "T" | "TB" | "TIB" => 1024 * 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.checked_mul(multiplier)
+}
+
+// --- header_utils ---
+
+/// 解析 `Key: Value` 形式的请求头，例如 `Authorization: Bearer xxx`
+pub fn parse_header(input: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = input
+        .split_once(':')
+        .ok_or_else(|| format!("expected 'Key: Value', got '{}'", input))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("invalid header name '{}'", name.trim()))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|_| format!("invalid value for header '{}'", name))?;
+    Ok((name, value))
+}
+
+// --- auth_utils ---
+
+/// 访问受保护资源时使用的认证方式，会以 `Authorization` 请求头附加到所有请求上。
+///
+/// `Debug` 输出中不包含密码和令牌，避免凭据意外出现在日志里。
+#[derive(Clone, PartialEq, Eq)]
+pub enum Auth {
+    /// HTTP Basic 认证
+    Basic {
+        username: String,
+        password: Option<String>,
+    },
+    /// Bearer 令牌认证，例如 OAuth 访问令牌
+    Bearer(String),
+}
+
+impl Auth {
+    /// 解析 `user:pass` 形式的 Basic 认证信息，没有冒号时视为只有用户名
+    pub fn basic(input: &str) -> Auth {
+        match input.split_once(':') {
+            Some((username, password)) => Auth::Basic {
+                username: username.to_string(),
+                password: Some(password.to_string()),
+            },
+            None => Auth::Basic {
+                username: input.to_string(),
+                password: None,
+            },
+        }
+    }
+
+    /// 生成 `Authorization` 请求头的值，并标记为敏感信息
+    pub fn header_value(&self) -> Result<HeaderValue, String> {
+        let value = match self {
+            Auth::Basic { username, password } => {
+                let credentials = format!("{}:{}", username, password.as_deref().unwrap_or(""));
+                format!("Basic {}", BASE64_STANDARD.encode(credentials))
+            }
+            Auth::Bearer(token) => format!("Bearer {}", token),
+        };
+        let mut value = HeaderValue::from_str(&value).map_err(|_| {
+            "credentials contain characters that are not allowed in a header".to_string()
+        })?;
+        value.set_sensitive(true);
+        Ok(value)
+    }
+}
+
+impl fmt::Debug for Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Auth::Basic { username, .. } => f
+                .debug_struct("Basic")
+                .field("username", username)
+                .field("password", &"<redacted>")
+                .finish(),
+            Auth::Bearer(_) => f.debug_tuple("Bearer").field(&"<redacted>").finish(),
+        }
+    }
+}
+
+// --- cookie_utils ---
+
+/// Netscape 格式 cookie 文件 (curl 和浏览器扩展导出的 `cookies.txt`) 中的一条 cookie
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetscapeCookie {
+    pub domain: String,
+    /// 是否同时发送给子域名 (第二列为 `TRUE`)
+    pub include_subdomains: bool,
+    pub path: String,
+    /// 是否只通过 HTTPS 发送
+    pub secure: bool,
+    /// 过期时间 (Unix 时间戳，秒)，为 `None` 时是会话 cookie
+    pub expires: Option<u64>,
+    pub name: String,
+    pub value: String,
+}
+
+impl NetscapeCookie {
+    /// 这条 cookie 所属的地址，加入 cookie jar 时用它确定作用的域名和路径
+    pub fn url(&self) -> String {
+        format!("https://{}{}", self.domain, self.path)
+    }
+
+    /// 等价的 `Set-Cookie` 响应头的值。只有允许子域名时才带 `Domain` 属性，否则只发送给该主机
+    pub fn set_cookie(&self) -> String {
+        let mut set_cookie = format!("{}={}; Path={}", self.name, self.value, self.path);
+        if self.include_subdomains {
+            set_cookie.push_str(&format!("; Domain={}", self.domain));
+        }
+        if self.secure {
+            set_cookie.push_str("; Secure");
+        }
+        set_cookie
+    }
+
+    /// cookie 在 `now` 时是否已经过期，会话 cookie 永不过期
+    pub fn is_expired(&self, now: std::time::SystemTime) -> bool {
+        let now = now
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// 解析 Netscape 格式的 cookie 文件。
+///
+/// 每行七个以制表符分隔的字段：域名、是否包含子域名、路径、是否仅限 HTTPS、过期时间 (0 表示会话
+/// cookie)、名称和值。空行和 `#` 开头的注释行被忽略，`#HttpOnly_` 前缀表示 HttpOnly cookie，按普通
+/// cookie 处理。格式错误时返回带行号的错误信息。
+pub fn parse_cookie_file(contents: &str) -> Result<Vec<NetscapeCookie>, String> {
+    let flag = |value: &str, line: usize| match value.to_ascii_uppercase().as_str() {
+        "TRUE" => Ok(true),
+        "FALSE" => Ok(false),
+        _ => Err(format!(
+            "line {}: expected TRUE or FALSE, got '{}'",
+            line, value
+        )),
+    };
+    let mut cookies = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim_end_matches('\r');
+        let line = match line.strip_prefix("#HttpOnly_") {
+            Some(rest) => rest,
+            None if line.trim().is_empty() || line.starts_with('#') => continue,
+            None => line,
+        };
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [domain, include_subdomains, path, secure, expires, name, value] = fields[..] else {
+            return Err(format!(
+                "line {}: expected 7 tab-separated fields, got {}",
+                line_no,
+                fields.len()
+            ));
+        };
+        let expires: u64 = expires
+            .parse()
+            .map_err(|_| format!("line {}: invalid expiry '{}'", line_no, expires))?;
+        cookies.push(NetscapeCookie {
+            domain: domain.trim_start_matches('.').to_string(),
+            include_subdomains: flag(include_subdomains, line_no)?,
+            path: path.to_string(),
+            secure: flag(secure, line_no)?,
+            expires: (expires != 0).then_some(expires),
+            name: name.to_string(),
+            value: value.to_string(),
+        });
+    }
+    Ok(cookies)
+}
+
+// --- rate_limit_utils ---
+
+/// 基于令牌桶的异步限速器，可在多个并发任务之间共享以限制总吞吐量。
+///
+/// 令牌桶初始为空，最多累积 1 秒的额度，因此下载速度从一开始就不会超过上限。
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    /// 创建一个每秒最多放行 `bytes_per_sec` 字节的限速器，`bytes_per_sec` 必须大于 0。
+    pub fn new(bytes_per_sec: u64) -> Self {
+        RateLimiter {
+            bytes_per_sec: bytes_per_sec as f64,
+            bucket: Mutex::new(Bucket {
+                available: 0.0,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// 申请 `amount` 字节的额度，额度不足时等待直到可以放行。
+    ///
+    /// 等待期间持有内部锁，因此多个任务会按申请顺序依次放行。
+    pub async fn acquire(&self, amount: u64) {
+        let mut bucket = self.bucket.lock().await;
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.last_refill = now;
+        bucket.available =
+            (bucket.available + elapsed * self.bytes_per_sec).min(self.bytes_per_sec);
+        bucket.available -= amount as f64;
+        if bucket.available < 0.0 {
+            // 欠下的额度会在下一次申请时按经过的时间 (包括这次等待) 补回
+            let wait = Duration::from_secs_f64(-bucket.available / self.bytes_per_sec);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+// --- retry_utils ---
+
+/// 第 `attempt` 次失败 (从 1 开始) 之后的重试等待时间：从 `initial` 开始每次翻倍，最多为 `max`。
+///
+/// `jitter` 为 `true` 时在 [一半, 全部] 之间随机取值，避免大量客户端在同一时刻一起重试。
+pub fn retry_delay(initial: Duration, attempt: u32, max: Duration, jitter: bool) -> Duration {
+    let delay = initial
+        .checked_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+        .unwrap_or(max)
+        .min(max);
+    if !jitter {
+        return delay;
+    }
+    // 标准库的 RandomState 每次创建都使用新的随机密钥，足以满足打散重试时间的需要
+    use std::hash::{BuildHasher, Hasher};
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    let half = delay / 2;
+    let spread = (delay - half).as_nanos() as u64;
+    half + Duration::from_nanos(random.checked_rem(spread + 1).unwrap_or(0))
+}
+
+/// 响应状态是否可能是暂时的、值得重试。
+///
+/// 大多数 4xx (例如 403、404) 以及 501、505 说明请求本身无法被满足，重试也不会有不同的结果；
+/// 408、425、429、其余的 5xx 以及 CDN 返回的非标准状态码视为暂时性错误
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    match status.as_u16() {
+        408 | 425 | 429 => true,
+        501 | 505 => false,
+        code => !(400..500).contains(&code),
+    }
+}
+
+/// 429 和 503 响应中 `Retry-After` 要求的等待时间，其他状态或没有该响应头时返回 `None`
+pub fn retry_after(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+        return None;
+    }
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, std::time::SystemTime::now())
+}
+
+/// 解析 `Retry-After` 的值：秒数 (`120`) 或 HTTP 日期 (`Wed, 21 Oct 2015 07:28:00 GMT`)。
+/// 日期相对于 `now` 换算为等待时间，已经过去的日期表示可以立即重试
+pub fn parse_retry_after(value: &str, now: std::time::SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) {
+        return Some(Duration::from_secs(value.parse().unwrap_or(u64::MAX)));
+    }
+    let date = parse_http_date(value)?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
 }
 
 // --- path_utils ---
+
+/// `file://` 地址对应的本地路径，其他协议或无法表示为本地路径的地址返回 `None`。
+///
+/// 路径中的百分号编码会被解码，例如 `file:///tmp/a%20b.bin` 对应 `/tmp/a b.bin`。
+pub fn file_url_path(url: &str) -> Option<PathBuf> {
+    let url = reqwest::Url::parse(url).ok()?;
+    if url.scheme() != "file" {
+        return None;
+    }
+    url.to_file_path().ok()
+}
+
+/// 默认的状态文件路径：目标文件旁边的 `<文件名>.rdownload`
 pub fn get_state_path(path: &Path) -> PathBuf {
     let mut state_path = path.as_os_str().to_owned();
     state_path.push(".rdownload");
     PathBuf::from(state_path)
 }
 
-// --- filename_utils ---
-pub async fn get_filename_from_url(client: &Client, url: &str) -> Option<String> {
-    let res = client.head(url).send().await.ok()?;
-    if let Some(content_disposition) = res.headers().get(CONTENT_DISPOSITION) {
-        let re = Regex::new(r#"filename="?([^"\s]+)"?"#).unwrap();
-        if let Some(caps) = re.captures(content_disposition.to_str().ok()?) {
-            return Some(caps.get(1)?.as_str().to_string());
+/// 状态文件放在指定目录 (而不是目标文件旁边) 时使用的文件名。
+///
+/// 文件名由目标文件名和 "最终路径 + URL" 的 SHA-256 摘要前缀组成，同一个下载每次得到
+/// 相同的名字，不同目录下的同名文件也不会互相覆盖。
+pub fn state_file_name(path: &Path, url: &str) -> String {
+    format!("{}.rdownload", digest_file_name(path, Some(url)))
+}
+
+/// 在临时目录 `dir` 中下载 `path` 时使用的基础路径 (不含扩展名)，.part 文件和状态文件都以它命名。
+///
+/// 文件名由目标文件名和最终路径的摘要组成，不同目录下的同名文件不会互相覆盖
+pub fn temp_base_path(dir: &Path, path: &Path) -> PathBuf {
+    dir.join(digest_file_name(path, None))
+}
+
+/// `<目标文件名>-<最终路径 (和 URL) 的 SHA-256 摘要前缀>`
+fn digest_file_name(path: &Path, url: Option<&str>) -> String {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(absolute.to_string_lossy().as_bytes());
+    if let Some(url) = url {
+        hasher.update(b"\n");
+        hasher.update(url.as_bytes());
+    }
+    let digest: String = hasher.finalize()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let name = path
+        .file_name()
+        .map(|name| sanitize_filename(&name.to_string_lossy()))
+        .unwrap_or_else(|| DEFAULT_FILENAME.to_string());
+    format!("{}-{}", name, digest)
+}
+
+/// 操作系统的用户缓存目录下供 rdownloader 使用的子目录，无法确定时返回 `None`。
+///
+/// Linux 等平台为 `$XDG_CACHE_HOME` 或 `~/.cache`，macOS 为 `~/Library/Caches`，
+/// Windows 为 `%LOCALAPPDATA%`。
+pub fn default_state_dir() -> Option<PathBuf> {
+    let env_dir = |name| {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    let cache = if cfg!(windows) {
+        env_dir("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        env_dir("HOME").map(|home| home.join("Library").join("Caches"))
+    } else {
+        env_dir("XDG_CACHE_HOME").or_else(|| env_dir("HOME").map(|home| home.join(".cache")))
+    }?;
+    Some(cache.join("rdownloader"))
+}
+
+/// 目录是否可以创建文件。通过实际创建并删除一个临时文件来判断，
+/// 只读挂载、权限不足或配额已满时返回 `false`
+pub fn dir_is_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".rdownload-write-test-{}", std::process::id()));
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            true
         }
+        Err(_) => false,
     }
-    get_filename_from_path(url)
 }
 
+/// 下载过程中数据实际写入的临时文件路径 (`<文件名>.part`)。
+///
+/// 只有在所有数据都成功写入后，该文件才会被重命名为最终路径，
+/// 因此最终路径上的文件总是完整的。
+pub fn get_part_path(path: &Path) -> PathBuf {
+    let mut part_path = path.as_os_str().to_owned();
+    part_path.push(".part");
+    PathBuf::from(part_path)
+}
+
+// --- file_utils ---
+
+/// 将 `from` 移动到 `to`，见 [`move_file_with`]
+pub fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    move_file_with(from, to, |from, to| std::fs::rename(from, to))
+}
+
+/// 用 `rename` 将 `from` 移动到 `to`；两者不在同一个文件系统上 (`EXDEV`) 时改为复制后删除。
+///
+/// 复制的数据先写入 `to` 旁边的 .part 文件，再在目标文件系统内重命名，
+/// 因此复制中途失败也不会在 `to` 留下不完整的文件。`rename` 参数便于测试时模拟跨设备的情况
+pub fn move_file_with(
+    from: &Path,
+    to: &Path,
+    rename: impl Fn(&Path, &Path) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    match rename(from, to) {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            let staging = get_part_path(to);
+            if let Err(e) = std::fs::copy(from, &staging) {
+                let _ = std::fs::remove_file(&staging);
+                return Err(e);
+            }
+            std::fs::rename(&staging, to)?;
+            std::fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// 将 `data` 完整写入文件的 `offset` 处。
+///
+/// 写入不依赖文件的当前读写位置，因此多个线程可以共用同一个文件句柄，并发写入不同的区域，
+/// 不必为每次写入重新打开文件再定位。
+pub fn write_at(file: &File, offset: u64, data: &[u8]) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.write_all_at(data, offset)
+    }
+    #[cfg(windows)]
+    {
+        // seek_write 会移动文件的读写位置，但这里的写入都显式指定偏移，不受影响
+        use std::os::windows::fs::FileExt;
+        let (mut data, mut offset) = (data, offset);
+        while !data.is_empty() {
+            match file.seek_write(data, offset) {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    data = &data[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 从文件的 `offset` 处读满 `buf`，与 [`write_at`] 一样不依赖文件的当前读写位置。
+///
+/// 文件在 `buf` 读满之前结束时返回 [`std::io::ErrorKind::UnexpectedEof`]。
+pub fn read_exact_at(file: &File, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileExt;
+        file.read_exact_at(buf, offset)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileExt;
+        let (mut buf, mut offset) = (buf, offset);
+        while !buf.is_empty() {
+            match file.seek_read(buf, offset) {
+                Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 以原子方式替换文件内容：先写入同目录下的临时文件再重命名，
+/// 进程在写入途中被中止时 `path` 要么保持原内容，要么已是完整的新内容，不会只写入一半
+pub fn write_file_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    std::fs::write(&tmp_path, contents)?;
+    std::fs::rename(&tmp_path, path)
+}
+
+// --- checksum_utils ---
+
+/// 计算数据块内容的哈希 (xxh3)，只用于发现写入中断或损坏的数据，不具备抗碰撞的安全性
+pub fn chunk_hash(data: &[u8]) -> u64 {
+    xxhash_rust::xxh3::xxh3_64(data)
+}
+
+/// 支持的校验和算法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Md5,
+}
+
+/// 期望的文件校验和，格式为 `<算法>:<十六进制摘要>`，例如 `sha256:abcd...`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    /// 小写的十六进制摘要
+    pub expected: String,
+}
+
+impl FromStr for Checksum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, digest) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected '<algorithm>:<hex digest>', got '{}'", s))?;
+        let (algorithm, digest_len) = match name.trim().to_ascii_lowercase().as_str() {
+            "sha256" => (ChecksumAlgorithm::Sha256, 64),
+            "md5" => (ChecksumAlgorithm::Md5, 32),
+            other => {
+                return Err(format!(
+                    "unsupported checksum algorithm '{}' (supported: sha256, md5)",
+                    other
+                ));
+            }
+        };
+        let expected = digest.trim().to_ascii_lowercase();
+        if expected.len() != digest_len || !expected.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!(
+                "{} digest must be {} hex characters",
+                name, digest_len
+            ));
+        }
+        Ok(Checksum {
+            algorithm,
+            expected,
+        })
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.algorithm {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Md5 => "md5",
+        };
+        write!(f, "{}:{}", name, self.expected)
+    }
+}
+
+/// 以流式方式读取文件并计算其摘要，返回小写十六进制字符串
+pub fn compute_checksum(path: &Path, algorithm: ChecksumAlgorithm) -> std::io::Result<String> {
+    fn digest_file<D: Digest>(mut file: File) -> std::io::Result<Vec<u8>> {
+        let mut hasher = D::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        Ok(hasher.finalize().to_vec())
+    }
+
+    let file = File::open(path)?;
+    let digest = match algorithm {
+        ChecksumAlgorithm::Sha256 => digest_file::<sha2::Sha256>(file)?,
+        ChecksumAlgorithm::Md5 => digest_file::<md5::Md5>(file)?,
+    };
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// --- filename_utils ---
+
+/// 从服务器推断文件名：优先使用 `Content-Disposition` 头，其次是 URL 路径的最后一段。
+///
+/// 先发送 HEAD 请求；部分服务器不支持 HEAD (返回 405 等)，或只在 GET 响应中
+/// 带上 `Content-Disposition`，此时改用与调度器探测相同的 `Range: bytes=0-1`
+/// GET 请求再读取一次，只会下载最多两个字节。
+pub async fn get_filename_from_url(
+    client: &Client,
+    url: &str,
+    headers: &HeaderMap,
+) -> Option<String> {
+    let filename = match head_disposition(client, url, headers).await {
+        Some(filename) => Some(filename),
+        None => client
+            .get(url)
+            .headers(headers.clone())
+            .header(RANGE, "bytes=0-1")
+            .header(ACCEPT_ENCODING, "identity")
+            .send()
+            .await
+            .ok()
+            .filter(|res| res.status().is_success())
+            .and_then(|res| disposition_filename(res.headers())),
+    };
+    // Content-Disposition 来自服务器，不可信，必须清理后才能使用
+    filename
+        .map(|filename| sanitize_filename(&filename))
+        .or_else(|| get_filename_from_path(url))
+}
+
+/// 只通过 HEAD 请求读取 `Content-Disposition` 中的文件名 (已清理)。
+///
+/// 用于调用方已经发送过探测 GET 请求、但其响应中没有该头的情况。
+pub async fn get_filename_from_head(
+    client: &Client,
+    url: &str,
+    headers: &HeaderMap,
+) -> Option<String> {
+    head_disposition(client, url, headers)
+        .await
+        .map(|filename| sanitize_filename(&filename))
+}
+
+async fn head_disposition(client: &Client, url: &str, headers: &HeaderMap) -> Option<String> {
+    match client.head(url).headers(headers.clone()).send().await {
+        Ok(res) if res.status().is_success() => disposition_filename(res.headers()),
+        _ => None,
+    }
+}
+
+fn disposition_filename(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CONTENT_DISPOSITION)
+        .and_then(|v| parse_content_disposition(&String::from_utf8_lossy(v.as_bytes())))
+}
+
+/// 从 `Content-Disposition` 头中提取文件名。
+///
+/// 优先使用 RFC 5987 形式的 `filename*=UTF-8''na%C3%AFve.txt` (支持非 ASCII 文件名)，
+/// 其次才是普通的 `filename="..."`。
+pub fn parse_content_disposition(value: &str) -> Option<String> {
+    let extended = Regex::new(r"(?i)(?:^|;)\s*filename\*\s*=\s*([^;]+)").unwrap();
+    if let Some(filename) = extended
+        .captures(value)
+        .and_then(|caps| decode_rfc5987(caps.get(1)?.as_str().trim()))
+    {
+        return Some(filename);
+    }
+
+    let plain = Regex::new(r#"(?i)(?:^|;)\s*filename\s*=\s*(?:"([^"]*)"|([^;\s]+))"#).unwrap();
+    let caps = plain.captures(value)?;
+    let filename = caps.get(1).or_else(|| caps.get(2))?.as_str().trim();
+    (!filename.is_empty()).then(|| filename.to_string())
+}
+
+/// 解码 RFC 5987 扩展参数值，格式为 `charset'language'percent-encoded`
+fn decode_rfc5987(value: &str) -> Option<String> {
+    let mut parts = value.trim_matches('"').splitn(3, '\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let encoded = parts.next()?;
+    let bytes: Vec<u8> = percent_encoding::percent_decode_str(encoded).collect();
+    let decoded = match charset.to_ascii_lowercase().as_str() {
+        "utf-8" => String::from_utf8(bytes).ok()?,
+        // ISO-8859-1 的每个字节都直接对应同值的 Unicode 码点
+        "iso-8859-1" => bytes.into_iter().map(char::from).collect(),
+        _ => return None,
+    };
+    (!decoded.is_empty()).then_some(decoded)
+}
+
+/// 从 URL 路径的最后一段推断文件名 (已清理)。
+///
+/// 按 URL 解析后只使用路径部分，查询字符串和片段 (例如 `?token=abc&exp=123`) 不会进入文件名；
+/// 路径段经过百分号解码，因此 `my%20file.txt` 得到 `my file.txt`；解码结果不是合法的 UTF-8 时
+/// 保留原来的编码形式。解码出的 `/`、`\\` 和 `..` 由 [`sanitize_filename`] 清理，不会引入目录层级，
+/// 清理后为空时得到 [`DEFAULT_FILENAME`]。路径以 `/` 结尾时使用上一段，没有路径时使用主机名。
+/// 无法解析为 URL 的字符串按本地路径处理。
 pub fn get_filename_from_path(url: &str) -> Option<String> {
-    Path::new(url)
-        .file_name()
-        .and_then(|s| s.to_str())
-        .map(String::from)
+    let Ok(parsed) = reqwest::Url::parse(url) else {
+        return Path::new(url)
+            .file_name()
+            .and_then(|s| s.to_str())
+            .map(sanitize_filename);
+    };
+    let segment = parsed
+        .path_segments()
+        .and_then(|mut segments| segments.rfind(|s| !s.is_empty()));
+    let name = match segment {
+        Some(segment) => percent_encoding::percent_decode_str(segment)
+            .decode_utf8()
+            .map_or_else(|_| segment.to_string(), |decoded| decoded.into_owned()),
+        None => parsed.host_str()?.to_string(),
+    };
+    Some(sanitize_filename(&name))
+}
+
+/// 文件名被完全清理掉时使用的安全默认值
+pub const DEFAULT_FILENAME: &str = "download";
+
+/// 将来自服务器或 URL 的文件名清理为可以安全拼接到输出目录上的单个文件名。
+///
+/// - 去掉所有目录部分 (`/` 和 `\\`)，防止 `../../etc/passwd` 之类的路径穿越；
+/// - 将 Windows 上非法的字符 (`<>:"|?*`) 和控制字符替换为 `_`；
+/// - 去掉首尾空白和结尾的 `.`，因此 `.` 和 `..` 会被清理为空；
+/// - 为 Windows 保留设备名 (如 `CON`、`NUL`) 加上 `_` 前缀。
+///
+/// 清理后为空时返回 [`DEFAULT_FILENAME`]。
+pub fn sanitize_filename(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let replaced: String = base
+        .chars()
+        .map(|c| {
+            if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let cleaned = replaced.trim().trim_end_matches(['.', ' ']);
+    if cleaned.is_empty() {
+        return DEFAULT_FILENAME.to_string();
+    }
+
+    const RESERVED: [&str; 22] = [
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    let stem = cleaned.split('.').next().unwrap_or(cleaned);
+    if RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem)) {
+        return format!("_{}", cleaned);
+    }
+    cleaned.to_string()
+}
+
+// --- output_template ---
+
+/// 输出模板中可以使用的占位符
+pub const TEMPLATE_PLACEHOLDERS: [&str; 4] = ["filename", "host", "date", "ext"];
+
+enum TemplatePart<'a> {
+    Literal(&'a str),
+    Placeholder(&'a str),
+}
+
+fn parse_output_template(template: &str) -> Result<Vec<TemplatePart<'_>>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        if open > 0 {
+            parts.push(TemplatePart::Literal(&rest[..open]));
+        }
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("输出模板 '{}' 中的 '{{' 没有闭合", template))?;
+        let name = &rest[open + 1..open + close];
+        if !TEMPLATE_PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "输出模板中有未知的占位符 '{{{}}}'，可用的占位符: {}",
+                name,
+                TEMPLATE_PLACEHOLDERS
+                    .map(|p| format!("{{{}}}", p))
+                    .join(" ")
+            ));
+        }
+        parts.push(TemplatePart::Placeholder(name));
+        rest = &rest[open + close + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Literal(rest));
+    }
+    if template.ends_with('/') || template.split('/').all(str::is_empty) {
+        return Err(format!("输出模板 '{}' 必须以文件名结尾", template));
+    }
+    Ok(parts)
+}
+
+/// 检查输出模板的语法和占位符，便于在开始下载之前报告错误
+pub fn validate_output_template(template: &str) -> Result<(), String> {
+    parse_output_template(template).map(|_| ())
+}
+
+/// 展开输出模板，返回相对于输出目录的路径。
+///
+/// 占位符: `{filename}` 为推断出的文件名，`{host}` 为 URL 的主机名，
+/// `{date}` 为当前日期 (UTC，`YYYY-MM-DD`)，`{ext}` 为文件扩展名 (不含 `.`，没有时为空)。
+/// 占位符的值都会经过清理，不会引入额外的目录层级；模板中的 `/` 用于分隔目录，
+/// 空的目录层级会被忽略，因此结果总是一个相对路径。
+pub fn expand_output_template(
+    template: &str,
+    url: &str,
+    filename: &str,
+) -> Result<PathBuf, String> {
+    let filename = sanitize_filename(filename);
+    let mut expanded = String::new();
+    for part in parse_output_template(template)? {
+        match part {
+            TemplatePart::Literal(text) => expanded.push_str(text),
+            TemplatePart::Placeholder("filename") => expanded.push_str(&filename),
+            TemplatePart::Placeholder("host") => {
+                let host = reqwest::Url::parse(url)
+                    .ok()
+                    .and_then(|u| u.host_str().map(sanitize_filename))
+                    .unwrap_or_else(|| "unknown-host".to_string());
+                expanded.push_str(&host);
+            }
+            TemplatePart::Placeholder("date") => expanded.push_str(&current_date()),
+            TemplatePart::Placeholder(_) => {
+                let ext = Path::new(&filename)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("");
+                expanded.push_str(ext);
+            }
+        }
+    }
+    let path: PathBuf = expanded.split('/').filter(|s| !s.is_empty()).collect();
+    if path.as_os_str().is_empty() {
+        return Err(format!("输出模板 '{}' 展开后为空", template));
+    }
+    Ok(path)
+}
+
+/// 当前日期 (UTC)，格式为 `YYYY-MM-DD`
+fn current_date() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// 将时间格式化为 HTTP 日期 (RFC 9110 的 IMF-fixdate)，例如 `Sun, 06 Nov 1994 08:49:37 GMT`，
+/// 用于 `If-Modified-Since` 等请求头。早于 1970 年的时间按 1970-01-01 处理
+pub fn http_date(time: std::time::SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let days = secs / 86_400;
+    let (year, month, day) = civil_from_days(days as i64);
+    let rem = secs % 86_400;
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        // 1970-01-01 是星期四
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// 解析 HTTP 日期。除 IMF-fixdate 外也接受 RFC 9110 要求兼容的两种旧格式：
+/// `Sunday, 06-Nov-94 08:49:37 GMT` (RFC 850) 和 `Sun Nov  6 08:49:37 1994` (asctime)
+pub fn parse_http_date(value: &str) -> Option<std::time::SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let parts: Vec<&str> = value
+        .split([' ', ',', '-'])
+        .filter(|part| !part.is_empty())
+        .collect();
+    // 星期几不参与计算
+    let (day, month, year, time) = match parts[..] {
+        [_, day, month, year, time, "GMT"] => (day, month, year, time),
+        [_, month, day, time, year] => (day, month, year, time),
+        _ => return None,
+    };
+    let day: u32 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u32 + 1;
+    let mut year: i64 = year.parse().ok()?;
+    // RFC 850 的两位年份：看起来超过 50 年后的按上个世纪处理
+    if year < 100 {
+        year += if year < 70 { 2000 } else { 1900 };
+    }
+    let mut clock = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    if clock.next().is_some() || day == 0 || day > 31 || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    let secs = days * 86_400 + hour * 3600 + minute * 60 + second;
+    Some(std::time::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// 解析 FTP `MDTM` 响应中的修改时间 (RFC 3659)，例如 `20241014083000` 或带小数秒的
+/// `20241014083000.123`，时间为 UTC。小数秒被忽略
+pub fn parse_mdtm(value: &str) -> Option<std::time::SystemTime> {
+    let digits = value.split('.').next()?;
+    if digits.len() != 14 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let field = |range: std::ops::Range<usize>| digits[range].parse::<u32>().ok();
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    let (hour, minute, second) = (field(8..10)?, field(10..12)?, field(12..14)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    let days = u64::try_from(days_from_civil(i64::from(year), month, day)).ok()?;
+    let secs = days * 86_400 + u64::from(hour * 3600 + minute * 60 + second);
+    Some(std::time::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// 将公历日期转换为 1970-01-01 起的天数，是 [`civil_from_days`] 的逆运算
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// 将 1970-01-01 起的天数转换为公历日期 (Howard Hinnant 的 civil_from_days 算法)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
 }
 
 // --- resolve_final_path ---
 
+/// 输出路径是否表示一个目录 (已存在的目录，或以 '/' 结尾)，即是否需要从服务器推断文件名。
+/// 未提供输出路径时使用当前工作目录，同样需要推断文件名。
+pub fn output_needs_filename(output_path: Option<&Path>) -> bool {
+    output_path.is_none_or(|path| path.is_dir() || path.to_string_lossy().ends_with('/'))
+}
+
 /// 根据用户提供的可选输出路径和 URL，解析出最终应保存的完整文件路径。
 ///
 /// # 逻辑:
 /// 1. 如果提供了 `output_path`:
 ///    - 如果它指向一个已存在的目录，或以 '/' 结尾，则视为目录。
-///      程序会尝试从 URL 自动推断文件名，然后与目录拼接。
+///      程序会推断文件名，然后与目录拼接。
 ///    - 否则，直接将其作为完整的文件路径。
 /// 2. 如果未提供 `output_path`:
-///    - 使用当前工作目录，并尝试从 URL 自动推断文件名。
+///    - 使用当前工作目录，并推断文件名。
+///
+/// 提供了 `template` 时，输出路径总是视为目录，最终路径为该目录下按
+/// [`expand_output_template`] 展开的相对路径，中间目录会自动创建。
+///
+/// 推断文件名时优先使用 `probed_filename` (调用方从探测响应的 `Content-Disposition`
+/// 中得到的文件名)；没有时才发送 HEAD 请求读取 `Content-Disposition`，最后回退到 URL
+/// 路径的最后一段。
 ///
-/// 在需要创建目录的情况下，此函数会自动创建。
+/// 在需要创建目录的情况下，此函数会自动创建；目录已被其他进程同时创建时视为成功。
+/// 如果路径中应为目录的部分已经作为普通文件存在，返回说明是哪个路径的错误。
+/// `headers` 会附加到用于推断文件名的 HEAD 请求上。
 pub async fn resolve_final_path(
     client: &Client,
     url: &str,
     output_path: Option<PathBuf>,
+    headers: &HeaderMap,
+    probed_filename: Option<String>,
+    template: Option<&str>,
 ) -> Result<PathBuf, Box<dyn std::error::Error>> {
-    let mut final_path: PathBuf;
+    let final_path =
+        plan_final_path(client, url, output_path, headers, probed_filename, template).await?;
+    if let Some(parent) = final_path.parent() {
+        if !parent.as_os_str().is_empty() {
+            // create_dir_all 在目录已存在 (包括并发创建) 时直接成功，不需要事先检查
+            std::fs::create_dir_all(parent).map_err(|e| {
+                check_parent_dirs(&final_path)
+                    .err()
+                    .unwrap_or_else(|| Box::new(e))
+            })?;
+        }
+    }
+    Ok(final_path)
+}
 
-    if let Some(path) = output_path {
-        if path.is_dir() || path.to_string_lossy().ends_with('/') {
-            final_path = path;
-            if !final_path.exists() {
-                std::fs::create_dir_all(&final_path)?;
-            }
-            let filename = get_filename_from_url(client, url)
-                .await
-                .or_else(|| get_filename_from_path(url))
-                .ok_or("无法从 URL 确定文件名，请使用 -o 指定完整路径")?;
-            final_path.push(filename);
-        } else {
-            final_path = path;
-            if let Some(parent) = final_path.parent() {
-                if !parent.exists() {
-                    std::fs::create_dir_all(parent)?;
-                }
+/// 检查 `path` 的各级父目录中已存在的部分都是目录，否则返回指出冲突路径的错误。
+/// 不存在的部分不做检查，由调用方在需要时创建。
+fn check_parent_dirs(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    for ancestor in path.ancestors().skip(1) {
+        if ancestor.as_os_str().is_empty() {
+            break;
+        }
+        match std::fs::metadata(ancestor) {
+            Ok(metadata) if metadata.is_dir() => break,
+            Ok(_) => {
+                return Err(format!(
+                    "{} 已存在但不是目录，无法在其中保存文件",
+                    ancestor.display()
+                )
+                .into());
             }
+            Err(_) => continue,
         }
-    } else {
-        final_path = std::env::current_dir()?;
-        let filename = get_filename_from_url(client, url)
-            .await
-            .or_else(|| get_filename_from_path(url))
-            .ok_or("无法从 URL 确定文件名，请使用 -o 指定完整路径")?;
-        final_path.push(filename);
+    }
+    Ok(())
+}
+
+/// 与 [`resolve_final_path`] 相同，但不创建任何目录，只计算最终路径 (例如用于 dry-run)。
+/// 路径中应为目录的部分已经作为普通文件存在时同样返回错误。
+pub async fn plan_final_path(
+    client: &Client,
+    url: &str,
+    output_path: Option<PathBuf>,
+    headers: &HeaderMap,
+    probed_filename: Option<String>,
+    template: Option<&str>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let mut final_path = match output_path {
+        Some(path) if template.is_none() && !output_needs_filename(Some(&path)) => {
+            check_parent_dirs(&path)?;
+            return Ok(path);
+        }
+        Some(dir) => dir,
+        None => std::env::current_dir()?,
+    };
+    let filename = match probed_filename {
+        Some(filename) => Some(filename),
+        None => get_filename_from_head(client, url, headers).await,
+    };
+    let filename = filename
+        .or_else(|| get_filename_from_path(url))
+        .ok_or("无法从 URL 确定文件名，请使用 -o 指定完整路径")?;
+    match template {
+        Some(template) => final_path.push(expand_output_template(template, url, &filename)?),
+        None => final_path.push(sanitize_filename(&filename)),
     }
 
+    check_parent_dirs(&final_path)?;
     Ok(final_path)
 }
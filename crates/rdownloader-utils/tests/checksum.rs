@@ -0,0 +1,44 @@
+use rdownloader_utils::{compute_checksum, Checksum, ChecksumAlgorithm};
+
+#[test]
+fn parses_algorithm_prefix() {
+    let sha: Checksum = "sha256:E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855"
+        .parse()
+        .unwrap();
+    assert_eq!(sha.algorithm, ChecksumAlgorithm::Sha256);
+    assert_eq!(
+        sha.expected,
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+
+    let md5: Checksum = "md5:d41d8cd98f00b204e9800998ecf8427e".parse().unwrap();
+    assert_eq!(md5.algorithm, ChecksumAlgorithm::Md5);
+}
+
+#[test]
+fn rejects_unknown_algorithm_and_bad_digest() {
+    assert!("crc32:deadbeef".parse::<Checksum>().is_err());
+    assert!("sha256:abcd".parse::<Checksum>().is_err());
+    assert!("md5:zz1d8cd98f00b204e9800998ecf8427e"
+        .parse::<Checksum>()
+        .is_err());
+    assert!("d41d8cd98f00b204e9800998ecf8427e"
+        .parse::<Checksum>()
+        .is_err());
+}
+
+#[test]
+fn computes_known_digests() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("abc.txt");
+    std::fs::write(&path, b"abc").unwrap();
+
+    assert_eq!(
+        compute_checksum(&path, ChecksumAlgorithm::Sha256).unwrap(),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        compute_checksum(&path, ChecksumAlgorithm::Md5).unwrap(),
+        "900150983cd24fb0d6963f7d28e17f72"
+    );
+}
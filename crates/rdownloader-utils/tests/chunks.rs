@@ -0,0 +1,154 @@
+use rdownloader_utils::{
+    create_chunks, parse_size, validate_chunks, ChunkState, ChunkStrategy, DEFAULT_CHUNK_SIZE,
+    MIN_CHUNK_SIZE,
+};
+
+const MB: u64 = 1024 * 1024;
+
+#[test]
+fn ten_megabytes_with_four_megabyte_chunks_yields_three_chunks() {
+    let chunks = create_chunks(10 * MB, true, 4 * MB);
+    let bounds: Vec<(u64, u64)> = chunks.iter().map(|c| (c.start, c.end)).collect();
+    assert_eq!(
+        bounds,
+        vec![(0, 4 * MB - 1), (4 * MB, 8 * MB - 1), (8 * MB, 10 * MB - 1)]
+    );
+    assert!(chunks.iter().all(|c| !c.completed));
+}
+
+#[test]
+fn default_chunk_size_is_one_megabyte() {
+    assert_eq!(DEFAULT_CHUNK_SIZE, MB);
+    assert_eq!(create_chunks(3 * MB, true, DEFAULT_CHUNK_SIZE).len(), 3);
+}
+
+#[test]
+fn min_chunks_shrink_chunks_down_to_the_floor() {
+    let fixed = ChunkStrategy::Fixed(4 * MB);
+    assert_eq!(
+        fixed.with_min_chunks(10 * MB, 8),
+        ChunkStrategy::Fixed(10 * MB / 8)
+    );
+    // 已经足够多的数据块保持不变，缩小时不低于 MIN_CHUNK_SIZE
+    assert_eq!(fixed.with_min_chunks(100 * MB, 8), fixed);
+    assert_eq!(
+        fixed.with_min_chunks(MB, 64),
+        ChunkStrategy::Fixed(MIN_CHUNK_SIZE)
+    );
+    assert_eq!(
+        ChunkStrategy::Fixed(1024).with_min_chunks(MB, 64),
+        ChunkStrategy::Fixed(1024)
+    );
+    assert_eq!(
+        ChunkStrategy::Exponential {
+            start: 512 * 1024,
+            max: 8 * MB,
+        }
+        .with_min_chunks(2 * MB, 8),
+        ChunkStrategy::Exponential {
+            start: MIN_CHUNK_SIZE,
+            max: MIN_CHUNK_SIZE,
+        }
+    );
+    let chunks = create_chunks(3 * MB + 1, true, fixed.with_min_chunks(3 * MB + 1, 8));
+    assert!(chunks.len() >= 8);
+    assert!(validate_chunks(&chunks, 3 * MB + 1));
+}
+
+#[test]
+fn sequential_mode_ignores_chunk_size() {
+    let chunks = create_chunks(10 * MB, false, 4 * MB);
+    assert_eq!(chunks.len(), 1);
+    assert_eq!((chunks[0].start, chunks[0].end), (0, 10 * MB - 1));
+}
+
+#[test]
+fn exponential_chunks_double_up_to_the_maximum() {
+    let strategy = ChunkStrategy::Exponential {
+        start: 256 * 1024,
+        max: MB,
+    };
+    let sizes: Vec<u64> = create_chunks(4 * MB, true, strategy)
+        .iter()
+        .map(|c| c.end - c.start + 1)
+        .collect();
+    assert_eq!(sizes, vec![256 * 1024, 512 * 1024, MB, MB, MB, 256 * 1024]);
+    assert_eq!(
+        create_chunks(10 * MB, true, ChunkStrategy::Fixed(4 * MB)).len(),
+        create_chunks(10 * MB, true, 4 * MB).len()
+    );
+    assert_eq!(
+        ChunkStrategy::default(),
+        ChunkStrategy::Fixed(DEFAULT_CHUNK_SIZE)
+    );
+}
+
+#[test]
+fn parse_size_accepts_human_units() {
+    assert_eq!(parse_size("4M"), Some(4 * MB));
+    assert_eq!(parse_size("16m"), Some(16 * MB));
+    assert_eq!(parse_size("512K"), Some(512 * 1024));
+    assert_eq!(parse_size("2MiB"), Some(2 * MB));
+    assert_eq!(parse_size("1G"), Some(1024 * MB));
+    assert_eq!(parse_size("1048576"), Some(MB));
+}
+
+#[test]
+fn parse_size_rejects_malformed_input() {
+    assert_eq!(parse_size(""), None);
+    assert_eq!(parse_size("M"), None);
+    assert_eq!(parse_size("4X"), None);
+    assert_eq!(parse_size("-4M"), None);
+    assert_eq!(parse_size("99999999999999T"), None);
+}
+
+#[test]
+fn zero_byte_file_yields_no_chunks() {
+    assert!(create_chunks(0, true, DEFAULT_CHUNK_SIZE).is_empty());
+    assert!(create_chunks(0, false, DEFAULT_CHUNK_SIZE).is_empty());
+}
+
+fn chunk(start: u64, end: u64) -> ChunkState {
+    ChunkState {
+        start,
+        end,
+        completed: false,
+        hash: None,
+    }
+}
+
+#[test]
+fn validate_accepts_generated_layouts() {
+    for total in [1, 1023, 1024, 10 * MB + 17] {
+        assert!(validate_chunks(&create_chunks(total, true, 1024), total));
+        assert!(validate_chunks(&create_chunks(total, false, 1024), total));
+        for (start, max) in [(1, 1), (1, 64), (100, 1024), (1024, 512)] {
+            let chunks = create_chunks(total, true, ChunkStrategy::Exponential { start, max });
+            assert!(validate_chunks(&chunks, total));
+        }
+    }
+    assert!(validate_chunks(&[], 0));
+}
+
+#[test]
+fn validate_rejects_gap() {
+    assert!(!validate_chunks(&[chunk(0, 99), chunk(101, 199)], 200));
+    // 开头缺失
+    assert!(!validate_chunks(&[chunk(1, 199)], 200));
+    // 结尾缺失
+    assert!(!validate_chunks(&[chunk(0, 99), chunk(100, 198)], 200));
+}
+
+#[test]
+fn validate_rejects_overlap() {
+    assert!(!validate_chunks(&[chunk(0, 100), chunk(100, 199)], 200));
+    assert!(!validate_chunks(&[chunk(100, 199), chunk(0, 99)], 200));
+}
+
+#[test]
+fn validate_rejects_out_of_range() {
+    assert!(!validate_chunks(&[chunk(0, 99), chunk(100, 200)], 200));
+    assert!(!validate_chunks(&[chunk(0, 199)], 100));
+    assert!(!validate_chunks(&[chunk(10, 5)], 11));
+    assert!(!validate_chunks(&[], 200));
+}
@@ -0,0 +1,146 @@
+use rdownloader_utils::{
+    file_url_path, get_part_path, move_file_with, read_exact_at, write_at, write_file_atomic,
+};
+use std::fs::File;
+use std::sync::Arc;
+
+#[test]
+fn concurrent_positional_writes_land_at_their_offsets() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.part");
+    const CHUNK: usize = 4096;
+    const CHUNKS: usize = 16;
+    let file = File::create(&path).unwrap();
+    file.set_len((CHUNK * CHUNKS) as u64).unwrap();
+    let file = Arc::new(file);
+
+    // 倒序启动，写入顺序与文件中的位置无关
+    let handles: Vec<_> = (0..CHUNKS)
+        .rev()
+        .map(|i| {
+            let file = file.clone();
+            std::thread::spawn(move || {
+                write_at(&file, (i * CHUNK) as u64, &[i as u8; CHUNK]).unwrap();
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let data = std::fs::read(&path).unwrap();
+    assert_eq!(data.len(), CHUNK * CHUNKS);
+    for (i, chunk) in data.chunks(CHUNK).enumerate() {
+        assert!(chunk.iter().all(|&b| b == i as u8), "chunk {}", i);
+    }
+}
+
+#[test]
+fn positional_reads_fail_past_end_of_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin");
+    std::fs::write(&path, b"0123456789").unwrap();
+    let file = File::open(&path).unwrap();
+
+    let mut buf = [0; 4];
+    read_exact_at(&file, 3, &mut buf).unwrap();
+    assert_eq!(&buf, b"3456");
+    let err = read_exact_at(&file, 8, &mut buf).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[cfg(unix)]
+#[test]
+fn file_urls_map_to_decoded_local_paths() {
+    assert_eq!(
+        file_url_path("file:///tmp/a%20b.bin"),
+        Some("/tmp/a b.bin".into())
+    );
+    assert_eq!(file_url_path("https://example.com/a.bin"), None);
+    assert_eq!(file_url_path("not a url"), None);
+}
+
+#[test]
+fn interleaved_writes_from_many_threads_do_not_race() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.part");
+    const THREADS: usize = 8;
+    const BLOCK: usize = 64;
+    const ROUNDS: usize = 200;
+    let file = Arc::new(File::create(&path).unwrap());
+
+    // 每个线程写入间隔排列的小块：第 r 轮写到第 r * THREADS + t 块，
+    // 各线程的写入在时间上相互穿插，若依赖共享的读写位置就会写错地方
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let file = file.clone();
+            std::thread::spawn(move || {
+                for r in 0..ROUNDS {
+                    let offset = ((r * THREADS + t) * BLOCK) as u64;
+                    write_at(&file, offset, &[t as u8 + 1; BLOCK]).unwrap();
+                    std::thread::yield_now();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    let data = std::fs::read(&path).unwrap();
+    assert_eq!(data.len(), THREADS * ROUNDS * BLOCK);
+    for (i, block) in data.chunks(BLOCK).enumerate() {
+        let expected = (i % THREADS) as u8 + 1;
+        assert!(block.iter().all(|&b| b == expected), "block {}", i);
+    }
+}
+
+#[test]
+fn atomic_write_replaces_contents_without_leaving_temp_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.bin.rdownload");
+    std::fs::write(&path, b"old state that is longer than the new one").unwrap();
+
+    write_file_atomic(&path, b"new state").unwrap();
+
+    assert_eq!(std::fs::read(&path).unwrap(), b"new state");
+    let names: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["file.bin.rdownload"]);
+}
+
+#[test]
+fn cross_device_move_falls_back_to_copy() {
+    let dir = tempfile::tempdir().unwrap();
+    let from = dir.path().join("tmp").join("file.bin.part");
+    let to = dir.path().join("out").join("file.bin");
+    std::fs::create_dir_all(from.parent().unwrap()).unwrap();
+    std::fs::create_dir_all(to.parent().unwrap()).unwrap();
+    std::fs::write(&from, b"payload").unwrap();
+
+    // 模拟两个目录位于不同的文件系统：直接重命名返回 EXDEV，只有目标目录内的重命名可以成功
+    let cross_device = |a: &std::path::Path, b: &std::path::Path| {
+        if a.parent() == b.parent() {
+            std::fs::rename(a, b)
+        } else {
+            Err(std::io::Error::from(std::io::ErrorKind::CrossesDevices))
+        }
+    };
+    move_file_with(&from, &to, cross_device).unwrap();
+
+    assert_eq!(std::fs::read(&to).unwrap(), b"payload");
+    assert!(!from.exists());
+    assert!(!get_part_path(&to).exists());
+
+    // 其他错误不会触发复制
+    std::fs::write(&from, b"again").unwrap();
+    let denied = |_: &std::path::Path, _: &std::path::Path| {
+        Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied))
+    };
+    let err = move_file_with(&from, &to, denied).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+    assert!(from.exists());
+    assert_eq!(std::fs::read(&to).unwrap(), b"payload");
+}
@@ -0,0 +1,224 @@
+use rdownloader_utils::{
+    expand_output_template, get_filename_from_path, parse_content_disposition, sanitize_filename,
+    state_file_name, validate_output_template, DEFAULT_FILENAME,
+};
+use std::path::PathBuf;
+
+#[test]
+fn decodes_rfc5987_utf8_filename() {
+    assert_eq!(
+        parse_content_disposition("attachment; filename*=UTF-8''na%C3%AFve.txt").as_deref(),
+        Some("naïve.txt")
+    );
+}
+
+#[test]
+fn prefers_extended_filename_over_plain() {
+    let header = r#"attachment; filename="naive.txt"; filename*=UTF-8''na%C3%AFve.txt"#;
+    assert_eq!(
+        parse_content_disposition(header).as_deref(),
+        Some("naïve.txt")
+    );
+}
+
+#[test]
+fn decodes_iso_8859_1_and_language_tag() {
+    assert_eq!(
+        parse_content_disposition("attachment; filename*=iso-8859-1'en'%A3%20rates.txt").as_deref(),
+        Some("£ rates.txt")
+    );
+}
+
+#[test]
+fn falls_back_to_plain_filename() {
+    assert_eq!(
+        parse_content_disposition(r#"attachment; filename="my report.pdf""#).as_deref(),
+        Some("my report.pdf")
+    );
+    assert_eq!(
+        parse_content_disposition("attachment; filename=data.csv; size=10").as_deref(),
+        Some("data.csv")
+    );
+    // 无法解码的扩展形式会回退到普通形式
+    assert_eq!(
+        parse_content_disposition("attachment; filename*=KOI8-R''%C6; filename=fallback.txt")
+            .as_deref(),
+        Some("fallback.txt")
+    );
+}
+
+#[test]
+fn returns_none_without_filename() {
+    assert_eq!(parse_content_disposition("inline"), None);
+    assert_eq!(
+        parse_content_disposition(r#"attachment; filename="""#),
+        None
+    );
+}
+
+#[test]
+fn sanitize_strips_directory_components() {
+    assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+    assert_eq!(sanitize_filename("..\\..\\Windows\\win.ini"), "win.ini");
+    assert_eq!(sanitize_filename("/absolute/path.txt"), "path.txt");
+}
+
+#[test]
+fn sanitize_rejects_dot_names() {
+    assert_eq!(sanitize_filename(".."), DEFAULT_FILENAME);
+    assert_eq!(sanitize_filename("."), DEFAULT_FILENAME);
+    assert_eq!(sanitize_filename("foo/.."), DEFAULT_FILENAME);
+    assert_eq!(sanitize_filename("dir/"), DEFAULT_FILENAME);
+    assert_eq!(sanitize_filename("   "), DEFAULT_FILENAME);
+}
+
+#[test]
+fn sanitize_replaces_illegal_windows_characters() {
+    assert_eq!(
+        sanitize_filename("a<b>c:d\"e|f?g*h.txt"),
+        "a_b_c_d_e_f_g_h.txt"
+    );
+    assert_eq!(sanitize_filename("tab\there.txt"), "tab_here.txt");
+    assert_eq!(sanitize_filename("report.pdf. "), "report.pdf");
+}
+
+#[test]
+fn sanitize_prefixes_reserved_device_names() {
+    assert_eq!(sanitize_filename("CON"), "_CON");
+    assert_eq!(sanitize_filename("nul.txt"), "_nul.txt");
+    assert_eq!(sanitize_filename("console.txt"), "console.txt");
+}
+
+#[test]
+fn sanitize_keeps_ordinary_names() {
+    assert_eq!(sanitize_filename("naïve file.tar.gz"), "naïve file.tar.gz");
+    assert_eq!(sanitize_filename(".bashrc"), ".bashrc");
+}
+
+#[test]
+fn url_derived_names_are_sanitized() {
+    assert_eq!(
+        get_filename_from_path("https://example.com/a/b%3Ac.txt").as_deref(),
+        Some("b_c.txt")
+    );
+    assert_eq!(
+        get_filename_from_path("https://example.com/a/..%2F..%2Fevil.txt").as_deref(),
+        Some("evil.txt")
+    );
+    assert_eq!(
+        get_filename_from_path("https://example.com/dl/..\\..\\evil.txt").as_deref(),
+        Some("evil.txt")
+    );
+}
+
+#[test]
+fn url_derived_names_are_percent_decoded() {
+    assert_eq!(
+        get_filename_from_path("https://host/files/my%20file.txt").as_deref(),
+        Some("my file.txt")
+    );
+    // 解码出的路径分隔符和 .. 不会引入目录层级
+    assert_eq!(
+        get_filename_from_path("https://host/a%5C..%5Cb.txt").as_deref(),
+        Some("b.txt")
+    );
+    assert_eq!(
+        get_filename_from_path("https://host/dir/%2e%2e%2e").as_deref(),
+        Some("download")
+    );
+    assert_eq!(
+        get_filename_from_path("https://host/dir/%2F").as_deref(),
+        Some("download")
+    );
+    // 不是 UTF-8 的编码保持原样
+    assert_eq!(
+        get_filename_from_path("https://host/caf%E9.txt").as_deref(),
+        Some("caf%E9.txt")
+    );
+}
+
+#[test]
+fn url_derived_names_drop_query_and_fragment() {
+    assert_eq!(
+        get_filename_from_path("https://host/file.zip?token=abc&exp=123").as_deref(),
+        Some("file.zip")
+    );
+    assert_eq!(
+        get_filename_from_path("https://host/dl/report.pdf#page=2").as_deref(),
+        Some("report.pdf")
+    );
+    assert_eq!(
+        get_filename_from_path("https://host/na%C3%AFve%20file.txt?x=a/b.bin").as_deref(),
+        Some("naïve file.txt")
+    );
+    assert_eq!(
+        get_filename_from_path("https://host/releases/?sort=date").as_deref(),
+        Some("releases")
+    );
+    assert_eq!(
+        get_filename_from_path("https://example.com?page=1").as_deref(),
+        Some("example.com")
+    );
+}
+
+#[test]
+fn expands_output_template_placeholders() {
+    let path = expand_output_template(
+        "{host}/{ext}/{filename}",
+        "https://cdn.example.com/files/report.pdf",
+        "report.pdf",
+    )
+    .unwrap();
+    assert_eq!(path, PathBuf::from("cdn.example.com/pdf/report.pdf"));
+}
+
+#[test]
+fn output_template_date_is_iso_formatted() {
+    let path = expand_output_template("{date}_{filename}", "https://example.com/a", "a").unwrap();
+    let name = path.to_str().unwrap();
+    let date = name.strip_suffix("_a").unwrap();
+    let parts: Vec<&str> = date.split('-').collect();
+    assert_eq!(parts.len(), 3);
+    assert_eq!(parts[0].len(), 4);
+    assert!(parts.iter().all(|p| p.chars().all(|c| c.is_ascii_digit())));
+}
+
+#[test]
+fn output_template_result_is_always_relative() {
+    // `{ext}` 为空时不会产生以 '/' 开头的绝对路径
+    let path =
+        expand_output_template("{ext}/{filename}", "https://example.com/x", "README").unwrap();
+    assert_eq!(path, PathBuf::from("README"));
+    let path = expand_output_template("/{filename}", "https://example.com/x", "a/../../b").unwrap();
+    assert_eq!(path, PathBuf::from("b"));
+}
+
+#[test]
+fn rejects_unknown_or_malformed_template() {
+    assert!(validate_output_template("{date}/{name}")
+        .unwrap_err()
+        .contains("{name}"));
+    assert!(validate_output_template("{date/{filename}").is_err());
+    assert!(validate_output_template("{filename").is_err());
+    assert!(validate_output_template("{date}/").is_err());
+    assert!(validate_output_template("{date}/{filename}").is_ok());
+}
+
+#[test]
+fn state_file_name_is_stable_and_unique() {
+    let path = std::path::Path::new("/downloads/file.iso");
+    let name = state_file_name(path, "https://example.com/file.iso");
+    assert_eq!(name, state_file_name(path, "https://example.com/file.iso"));
+    assert!(name.starts_with("file.iso-") && name.ends_with(".rdownload"));
+    assert_ne!(
+        name,
+        state_file_name(path, "https://mirror.example.com/file.iso")
+    );
+    assert_ne!(
+        name,
+        state_file_name(
+            std::path::Path::new("/other/file.iso"),
+            "https://example.com/file.iso"
+        )
+    );
+}
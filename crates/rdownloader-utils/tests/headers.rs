@@ -0,0 +1,182 @@
+use rdownloader_utils::{
+    content_range_len, content_range_start, http_date, mime_essence, parse_content_range,
+    parse_cookie_file, parse_header, Auth, NetscapeCookie,
+};
+use std::time::{Duration, UNIX_EPOCH};
+
+#[test]
+fn parses_key_value_header() {
+    let (name, value) = parse_header("Referer:  https://example.com/page ").unwrap();
+    assert_eq!(name, "referer");
+    assert_eq!(value, "https://example.com/page");
+}
+
+#[test]
+fn keeps_colons_inside_value() {
+    let (name, value) = parse_header("Authorization: Basic dXNlcjpwYXNz:x").unwrap();
+    assert_eq!(name, "authorization");
+    assert_eq!(value, "Basic dXNlcjpwYXNz:x");
+}
+
+#[test]
+fn rejects_malformed_headers() {
+    assert!(parse_header("NoColonHere").is_err());
+    assert!(parse_header("Bad Name: value").is_err());
+    assert!(parse_header("X-Test: line\nbreak").is_err());
+}
+
+#[test]
+fn mime_essence_ignores_case_and_parameters() {
+    assert_eq!(
+        mime_essence("Application/Octet-Stream; charset=binary"),
+        "application/octet-stream"
+    );
+    assert_eq!(mime_essence(" text/HTML ;charset=UTF-8"), "text/html");
+    assert_eq!(mime_essence("application/zip"), "application/zip");
+}
+
+#[test]
+fn basic_auth_is_base64_encoded() {
+    let value = Auth::basic("user:pa:ss").header_value().unwrap();
+    // "user:pa:ss" 的 base64 编码，密码中的冒号保持原样
+    assert_eq!(value, "Basic dXNlcjpwYTpzcw==");
+    assert!(value.is_sensitive());
+
+    let no_password = Auth::basic("user").header_value().unwrap();
+    assert_eq!(no_password, "Basic dXNlcjo=");
+}
+
+#[test]
+fn bearer_auth_header() {
+    let value = Auth::Bearer("abc.def".into()).header_value().unwrap();
+    assert_eq!(value, "Bearer abc.def");
+    assert!(value.is_sensitive());
+    assert!(Auth::Bearer("bad\ntoken".into()).header_value().is_err());
+}
+
+#[test]
+fn auth_debug_output_hides_secrets() {
+    let basic = format!("{:?}", Auth::basic("alice:hunter2"));
+    assert!(basic.contains("alice"));
+    assert!(!basic.contains("hunter2"));
+    let bearer = format!("{:?}", Auth::Bearer("secret-token".into()));
+    assert!(!bearer.contains("secret-token"));
+}
+
+#[test]
+fn content_range_with_known_total() {
+    assert_eq!(parse_content_range("bytes 0-1/12345"), Some(12345));
+    assert_eq!(parse_content_range("bytes 100-199/200"), Some(200));
+    assert_eq!(parse_content_range("  bytes  0 - 1 / 12345 "), Some(12345));
+    assert_eq!(parse_content_range("Bytes 0-1/12345"), Some(12345));
+}
+
+#[test]
+fn content_range_unsatisfied_form() {
+    assert_eq!(parse_content_range("bytes */12345"), Some(12345));
+    assert_eq!(parse_content_range("bytes * / 0"), Some(0));
+}
+
+#[test]
+fn content_range_with_unknown_total() {
+    assert_eq!(parse_content_range("bytes 0-1/*"), None);
+    assert_eq!(parse_content_range("bytes */*"), None);
+}
+
+#[test]
+fn malformed_content_range_is_rejected() {
+    for value in [
+        "",
+        "bytes",
+        "bytes 0-1",
+        "bytes 0-1/",
+        "bytes 0-/100",
+        "bytes -1/100",
+        "bytes 0-1/abc",
+        "items 0-1/100",
+        "bytes 0-1/100 extra",
+        "bytes 5-2/100",
+        "bytes 0-100/100",
+        "bytes 0-1/99999999999999999999999",
+    ] {
+        assert_eq!(parse_content_range(value), None, "{:?}", value);
+    }
+}
+
+#[test]
+fn content_range_start_allows_unknown_total() {
+    assert_eq!(content_range_start("bytes 100-199/*"), Some(100));
+    assert_eq!(content_range_start("bytes 0-1/2"), Some(0));
+    assert_eq!(content_range_start("bytes 5-4/*"), None);
+    assert_eq!(content_range_start("bytes 0-10/10"), None);
+    assert_eq!(content_range_start("bytes */100"), None);
+    assert_eq!(content_range_len("bytes 100-199/*"), Some(100));
+    assert_eq!(content_range_len("bytes 0-0/1"), Some(1));
+    assert_eq!(content_range_len("bytes 0-10/10"), None);
+    assert_eq!(content_range_len("bytes */100"), None);
+}
+
+#[test]
+fn http_date_uses_imf_fixdate() {
+    let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+    assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    assert_eq!(http_date(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+    let leap_day = UNIX_EPOCH + Duration::from_secs(951_782_400);
+    assert_eq!(http_date(leap_day), "Tue, 29 Feb 2000 00:00:00 GMT");
+}
+
+#[test]
+fn parses_netscape_cookie_file() {
+    let contents = "# Netscape HTTP Cookie File\n\
+        \n\
+        .example.com\tTRUE\t/\tTRUE\t2000000000\tsession\tabc\n\
+        #HttpOnly_files.example.com\tFALSE\t/dl\tFALSE\t0\ttoken\tx=y\r\n";
+    let cookies = parse_cookie_file(contents).unwrap();
+    assert_eq!(
+        cookies,
+        [
+            NetscapeCookie {
+                domain: "example.com".into(),
+                include_subdomains: true,
+                path: "/".into(),
+                secure: true,
+                expires: Some(2_000_000_000),
+                name: "session".into(),
+                value: "abc".into(),
+            },
+            NetscapeCookie {
+                domain: "files.example.com".into(),
+                include_subdomains: false,
+                path: "/dl".into(),
+                secure: false,
+                expires: None,
+                name: "token".into(),
+                value: "x=y".into(),
+            },
+        ]
+    );
+    assert_eq!(
+        cookies[0].set_cookie(),
+        "session=abc; Path=/; Domain=example.com; Secure"
+    );
+    assert_eq!(cookies[1].set_cookie(), "token=x=y; Path=/dl");
+    assert_eq!(cookies[1].url(), "https://files.example.com/dl");
+}
+
+#[test]
+fn cookie_expiry_is_checked_against_now() {
+    let mut cookie =
+        parse_cookie_file("example.com\tFALSE\t/\tFALSE\t100\ta\tb").unwrap()[0].clone();
+    assert!(cookie.is_expired(UNIX_EPOCH + Duration::from_secs(100)));
+    assert!(!cookie.is_expired(UNIX_EPOCH + Duration::from_secs(99)));
+    cookie.expires = None;
+    assert!(!cookie.is_expired(UNIX_EPOCH + Duration::from_secs(u32::MAX as u64)));
+}
+
+#[test]
+fn malformed_cookie_lines_report_line_number() {
+    let err = parse_cookie_file("# comment\nexample.com\tFALSE\t/").unwrap_err();
+    assert!(err.starts_with("line 2:"), "{}", err);
+    assert!(parse_cookie_file("example.com\tMAYBE\t/\tFALSE\t0\ta\tb").is_err());
+    assert!(parse_cookie_file("example.com\tFALSE\t/\tFALSE\tsoon\ta\tb").is_err());
+}
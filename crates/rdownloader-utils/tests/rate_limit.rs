@@ -0,0 +1,32 @@
+use rdownloader_utils::RateLimiter;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[tokio::test(start_paused = true)]
+async fn limits_throughput_to_configured_rate() {
+    let limiter = RateLimiter::new(1000);
+    let start = Instant::now();
+    for _ in 0..6 {
+        limiter.acquire(500).await;
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(2990), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(3100), "{:?}", elapsed);
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_is_shared_across_tasks() {
+    let limiter = Arc::new(RateLimiter::new(1000));
+    let start = Instant::now();
+    let tasks: Vec<_> = (0..4)
+        .map(|_| {
+            let limiter = Arc::clone(&limiter);
+            tokio::spawn(async move { limiter.acquire(1000).await })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert!(start.elapsed() >= Duration::from_millis(3990));
+}
@@ -0,0 +1,128 @@
+use rdownloader_utils::{
+    http_date, is_retryable_status, parse_http_date, parse_mdtm, parse_retry_after, retry_after,
+    retry_delay,
+};
+use reqwest::header::{HeaderMap, HeaderValue, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[test]
+fn delay_doubles_up_to_the_cap() {
+    let initial = Duration::from_secs(1);
+    let max = Duration::from_secs(10);
+    let delays: Vec<_> = (1..=6)
+        .map(|attempt| retry_delay(initial, attempt, max, false))
+        .collect();
+    assert_eq!(
+        delays,
+        [1, 2, 4, 8, 10, 10].map(Duration::from_secs).to_vec()
+    );
+    // 次数很大时不会溢出
+    assert_eq!(retry_delay(initial, 200, max, false), max);
+}
+
+#[test]
+fn jitter_stays_between_half_and_full_delay() {
+    let initial = Duration::from_millis(400);
+    for attempt in 1..=5 {
+        let full = retry_delay(initial, attempt, Duration::from_secs(5), false);
+        for _ in 0..50 {
+            let delay = retry_delay(initial, attempt, Duration::from_secs(5), true);
+            assert!(delay >= full / 2 && delay <= full, "{:?}", delay);
+        }
+    }
+    assert_eq!(
+        retry_delay(Duration::ZERO, 1, Duration::from_secs(5), true),
+        Duration::ZERO
+    );
+}
+
+#[test]
+fn only_transient_statuses_are_retryable() {
+    for code in [408, 425, 429, 500, 502, 503, 504, 618] {
+        assert!(
+            is_retryable_status(StatusCode::from_u16(code).unwrap()),
+            "{}",
+            code
+        );
+    }
+    for code in [400, 401, 403, 404, 410, 416, 501, 505] {
+        assert!(
+            !is_retryable_status(StatusCode::from_u16(code).unwrap()),
+            "{}",
+            code
+        );
+    }
+}
+
+#[test]
+fn retry_after_accepts_seconds_and_http_dates() {
+    let now = UNIX_EPOCH + Duration::from_secs(1_445_412_400);
+    assert_eq!(
+        parse_retry_after(" 120 ", now),
+        Some(Duration::from_secs(120))
+    );
+    // 2015-10-21 07:28:00 UTC，比 now 晚 80 秒
+    for date in [
+        "Wed, 21 Oct 2015 07:28:00 GMT",
+        "Wednesday, 21-Oct-15 07:28:00 GMT",
+        "Wed Oct 21 07:28:00 2015",
+    ] {
+        assert_eq!(
+            parse_retry_after(date, now),
+            Some(Duration::from_secs(80)),
+            "{}",
+            date
+        );
+    }
+    // 已经过去的日期表示立即重试
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("soon", now), None);
+    assert_eq!(parse_retry_after("-5", now), None);
+    assert_eq!(
+        parse_retry_after("Wed, 32 Oct 2015 07:28:00 GMT", now),
+        None
+    );
+}
+
+#[test]
+fn http_dates_round_trip() {
+    for secs in [0, 784_111_777, 951_782_400, 4_102_444_799] {
+        let time = UNIX_EPOCH + Duration::from_secs(secs);
+        assert_eq!(parse_http_date(&http_date(time)), Some(time));
+    }
+}
+
+#[test]
+fn mdtm_timestamps_are_parsed() {
+    let time = Some(UNIX_EPOCH + Duration::from_secs(784_111_777));
+    assert_eq!(parse_mdtm("19941106084937"), time);
+    assert_eq!(parse_mdtm("19941106084937.123"), time);
+    assert_eq!(parse_mdtm("1994110608493"), None);
+    assert_eq!(parse_mdtm("19941306084937"), None);
+    assert_eq!(parse_mdtm("Sun, 06 Nov 1994"), None);
+}
+
+#[test]
+fn retry_after_is_only_honored_for_rate_limits_and_unavailability() {
+    let mut headers = HeaderMap::new();
+    headers.insert(RETRY_AFTER, HeaderValue::from_static("7"));
+    let seven = Some(Duration::from_secs(7));
+    assert_eq!(retry_after(StatusCode::TOO_MANY_REQUESTS, &headers), seven);
+    assert_eq!(
+        retry_after(StatusCode::SERVICE_UNAVAILABLE, &headers),
+        seven
+    );
+    assert_eq!(retry_after(StatusCode::BAD_GATEWAY, &headers), None);
+    assert_eq!(
+        retry_after(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new()),
+        None
+    );
+    let later = http_date(SystemTime::now() + Duration::from_secs(30));
+    headers.insert(RETRY_AFTER, HeaderValue::from_str(&later).unwrap());
+    let wait = retry_after(StatusCode::SERVICE_UNAVAILABLE, &headers).unwrap();
+    assert!(wait > Duration::from_secs(25) && wait <= Duration::from_secs(30));
+}
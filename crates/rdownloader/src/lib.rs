@@ -1,13 +1,114 @@
-use rdownloader_dispatcher::{dispatch, DispatchError};
-use rdownloader_utils::resolve_final_path;
-use reqwest::Client;
+use rdownloader_dispatcher::{
+    dispatch_probed, dispatch_to_writer, probe_url, DispatchError, HttpOptions,
+};
+pub use rdownloader_dispatcher::{
+    CancellationToken, ChunkProgressCallback, ChunkReport, ChunkStatus, ConnectionLimit,
+    DownloadEvent, DownloadMode, DownloadSummary, EventCallback, OverwritePolicy, PauseHandle,
+    Probe, ProgressBar, ProgressCallback, ResumeMode, TransferMode,
+};
+use rdownloader_utils::{
+    output_needs_filename, parse_cookie_file, plan_final_path, resolve_final_path,
+    validate_output_template,
+};
+pub use rdownloader_utils::{Auth, Checksum};
+use reqwest::cookie::Jar;
+use reqwest::header::{HeaderMap, AUTHORIZATION};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use std::fmt;
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// 默认的连接超时
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// 空闲连接在连接池中保留的默认时长，与 reqwest 的默认值一致
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// 默认的 TCP keep-alive 探测间隔。reqwest 默认不开启，长时间的下载中 NAT 或防火墙
+/// 可能悄悄丢弃看起来空闲的连接
+pub const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+/// 默认最多跟随的重定向次数，与 reqwest 的默认值一致
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+/// 默认的 `User-Agent`。reqwest 默认不发送 `User-Agent`，部分 CDN 会拒绝这样的请求
+pub const DEFAULT_USER_AGENT: &str = concat!("rdownloader/", env!("CARGO_PKG_VERSION"));
 
 // 定义一个公开的、更简洁的错误类型，对用户隐藏内部复杂的错误细节
 #[derive(Debug)]
 pub enum DownloadError {
     Dispatch(DispatchError),
     Path(Box<dyn std::error::Error>),
+    Client(reqwest::Error), // 根据配置构建 HTTP 客户端失败 (例如代理地址无效)
+    TimedOut(Duration),     // 超过了 DownloadOptions::timeout 设置的总时长
+    Auth(String),           // 认证信息无法用作请求头
+    Cookies(String),        // cookie 文件无法读取或格式错误
+    Certificate(String),    // CA 证书文件无法读取或不是有效的 PEM 证书
+    LocalAddress(IpAddr),   // 本地地址与 DownloadOptions::ip_version 要求的协议版本不一致
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Dispatch(e) => write!(f, "{}", e),
+            DownloadError::Path(e) => write!(f, "could not resolve the output path: {}", e),
+            DownloadError::Client(e) => write!(f, "could not configure the HTTP client: {}", e),
+            DownloadError::Auth(msg) => write!(f, "invalid credentials: {}", msg),
+            DownloadError::Cookies(msg) => write!(f, "could not load cookies: {}", msg),
+            DownloadError::Certificate(msg) => {
+                write!(f, "could not load CA certificate: {}", msg)
+            }
+            DownloadError::LocalAddress(addr) => write!(
+                f,
+                "local address {} does not match the requested IP version",
+                addr
+            ),
+            DownloadError::TimedOut(timeout) => write!(
+                f,
+                "download did not finish within {:?}; run it again to resume",
+                timeout
+            ),
+        }
+    }
+}
+
+impl DownloadError {
+    /// 下载是否因 [`DownloadOptions::cancel`] 被触发而中止。
+    /// 此时 .part 文件和状态文件都会保留，再次以相同参数调用即可续传。
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, DownloadError::Dispatch(e) if e.is_cancelled())
+    }
+
+    /// 错误类别的简短标识 (例如 `"network"`、`"checksum_mismatch"`)，
+    /// 便于脚本按类别处理错误，而不必解析错误信息的文字
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DownloadError::Dispatch(e) => e.kind(),
+            DownloadError::Path(_) => "path",
+            DownloadError::Client(_) => "client",
+            DownloadError::TimedOut(_) => "timeout",
+            DownloadError::Auth(_) => "auth",
+            DownloadError::Cookies(_) => "cookies",
+            DownloadError::Certificate(_) => "certificate",
+            DownloadError::LocalAddress(_) => "local_address",
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DownloadError::Dispatch(e) => e.source(),
+            DownloadError::Path(e) => Some(e.as_ref()),
+            DownloadError::Client(e) => Some(e),
+            DownloadError::TimedOut(_)
+            | DownloadError::Auth(_)
+            | DownloadError::Cookies(_)
+            | DownloadError::Certificate(_)
+            | DownloadError::LocalAddress(_) => None,
+        }
+    }
 }
 
 impl From<DispatchError> for DownloadError {
@@ -22,6 +123,480 @@ impl From<Box<dyn std::error::Error>> for DownloadError {
     }
 }
 
+/// 客户端使用的 HTTP 协议版本
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// HTTPS 连接通过 ALPN 与服务器协商 (支持时使用 HTTP/2)，明文连接使用 HTTP/1.1
+    #[default]
+    Auto,
+    /// 只使用 HTTP/1.1，每个并发的数据块请求占用一个连接
+    Http1,
+    /// 直接以 HTTP/2 连接 (prior knowledge，不经过协商)，所有数据块请求复用同一个连接的多路流。
+    /// 服务器不支持 HTTP/2 时请求会失败
+    Http2,
+}
+
+/// 连接服务器时使用的 IP 协议版本
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpVersion {
+    /// 使用域名解析出的所有地址
+    #[default]
+    Any,
+    /// 只连接 IPv4 地址，例如 IPv6 路由有问题时
+    V4,
+    /// 只连接 IPv6 地址
+    V6,
+}
+
+/// 下载任务的可选配置，所有字段都有合理的默认值。
+///
+/// 通常只需要修改关心的字段，其余使用 `..Default::default()` 填充。
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// 复用一个已配置好的 HTTP 客户端 (超时、代理、连接池等)。
+    /// 为 `None` 时根据下面的客户端配置字段新建一个客户端；
+    /// 设置了此字段时，这些客户端配置字段将被忽略。
+    pub client: Option<Client>,
+    /// 代理服务器地址，例如 `http://host:port`，应用于所有请求。
+    /// 为 `None` 时沿用 reqwest 的默认行为，即读取 `HTTP_PROXY`/`HTTPS_PROXY` 环境变量。
+    pub proxy: Option<String>,
+    /// 建立连接的超时时间，默认 30 秒，为 `None` 时不限制
+    pub connect_timeout: Option<Duration>,
+    /// 读取超时，默认 60 秒，为 `None` 时不限制。
+    /// 等待响应头或下一段数据超过该时长时放弃本次请求，数据块会自动重试。
+    pub read_timeout: Option<Duration>,
+    /// HTTP 协议版本，默认自动协商。使用 HTTP/2 时 `concurrency` 是同一个连接上同时进行的流数，
+    /// 流的开销远小于连接，高延迟链路上可以适当调大；但部分服务器按流限速，此时 HTTP/1.1
+    /// 的多个连接反而更快
+    pub http_version: HttpVersion,
+    /// 只通过 IPv4 或 IPv6 连接服务器，默认不限制。与 `local_address` 同时设置时两者的协议版本必须一致
+    pub ip_version: IpVersion,
+    /// 连接使用的本地地址，例如在多网卡的主机上指定从哪个网卡发出请求。
+    /// 设置后只会连接与它协议版本相同的服务器地址
+    pub local_address: Option<IpAddr>,
+    /// 每个主机在连接池中最多保留的空闲连接数，为 `None` 时等于 `concurrency`。
+    /// 多线程下载时每个数据块都是一个新请求，空闲连接足够多时后续数据块可以直接复用已建立的
+    /// 连接，省去 TCP 和 TLS 握手；数据块较小或延迟较高时这对吞吐量的影响最明显
+    pub pool_max_idle_per_host: Option<usize>,
+    /// 空闲连接在连接池中保留的时长，默认 90 秒，为 `None` 时一直保留
+    pub pool_idle_timeout: Option<Duration>,
+    /// TCP keep-alive 探测间隔，默认 60 秒，为 `None` 时不开启。
+    /// 避免下载过程中暂时空闲的连接 (例如限速或暂停时) 被中间设备断开
+    pub tcp_keepalive: Option<Duration>,
+    /// 整个下载任务 (探测和所有数据块) 的最长时间，默认不限制。
+    /// 超时后会像取消一样保存进度并返回 [`DownloadError::TimedOut`]，之后可以续传。
+    pub timeout: Option<Duration>,
+    /// 最多跟随的重定向次数，默认 10，为 0 时不跟随任何重定向 (3xx 响应视为失败)。
+    /// 数据块请求直接发往探测时解析出的最终地址，不会重复经过重定向。
+    pub max_redirects: usize,
+    /// 所有请求 (探测、数据块、文件名探测) 使用的 `User-Agent`，默认为 [`DEFAULT_USER_AGENT`]，
+    /// 为 `None` 时不发送。`headers` 中的 `User-Agent` 优先
+    pub user_agent: Option<String>,
+    /// 发送给下载地址所在主机的 cookie，每项为 `name=value`。
+    /// 客户端总是启用 cookie 存储，服务器在探测时设置的 cookie 也会随之后的数据块请求发送
+    pub cookies: Vec<String>,
+    /// Netscape 格式的 cookie 文件 (例如浏览器导出的 `cookies.txt`)，按其中记录的域名和路径发送，
+    /// 已过期的 cookie 被忽略
+    pub cookie_file: Option<PathBuf>,
+    /// 额外信任的根证书 (PEM 格式，可以包含多个证书)，用于使用私有 CA 的内部服务器。
+    /// 系统默认信任的证书仍然有效
+    pub ca_cert: Option<PathBuf>,
+    /// 不校验服务器的 TLS 证书 (包括过期、自签名和主机名不匹配的证书)，只应在测试环境中使用。
+    /// 开启时会记录一条警告，并通过 `on_event` 上报 [`DownloadEvent::Warning`]
+    pub insecure: bool,
+    /// 多线程模式下每个数据块的大小 (字节)，默认 1MB
+    pub chunk_size: u64,
+    /// 设置后数据块从 `chunk_size` 开始逐块翻倍，直到该大小 (字节)，
+    /// 前几个较小的数据块能更早地显示进度。默认不增长
+    pub max_chunk_size: Option<u64>,
+    /// 多线程下载时至少划分的数据块数，按 `chunk_size` 划分的块数不足时缩小数据块 (不小于 256KB)，
+    /// 让较小的文件也能充分并行。默认 (`None`) 等于 `concurrency`，`Some(1)` 表示总是使用 `chunk_size`
+    pub min_chunks: Option<usize>,
+    /// 多线程模式下的并发连接数，默认 8。启用自适应并发时为上限
+    pub concurrency: usize,
+    /// 启用自适应并发：从该并发数开始，顺利时逐步增加到 `concurrency`，出现错误或 429 时减半。
+    /// 默认不启用
+    pub initial_concurrency: Option<usize>,
+    /// 单线程/多线程模式的选择方式，默认根据文件大小和服务器能力自动选择
+    pub mode: DownloadMode,
+    /// 自动模式下，文件大于该大小 (字节) 时才使用多线程，默认 1MB
+    pub min_multipart_size: u64,
+    /// 与 URL 内容相同的镜像地址。主地址探测失败时依次尝试镜像；
+    /// 下载过程中某个数据块多次失败时，也会改用下一个镜像继续下载。
+    /// 大小或 ETag 与主文件不一致的镜像会被跳过。[`download_to_writer`] 不使用镜像。
+    pub mirrors: Vec<String>,
+    /// 只下载文件中的这一段字节 (包含两端)，例如 `0..=1048575` 下载前 1MB，
+    /// 保存的文件内容就是这一段数据。服务器必须支持 Range，范围超出文件末尾时返回错误。
+    /// 设置后不再区分单线程或多线程，只发起一个范围请求，也不使用镜像和续传
+    pub byte_range: Option<RangeInclusive<u64>>,
+    /// 存在未完成的下载 (`.rdownload` 状态文件) 时的处理方式，默认在状态文件通过校验时续传。
+    /// 校验包括 URL、文件大小、ETag (没有 ETag 时为 Last-Modified) 和数据块布局，
+    /// 任一项不一致时 [`ResumeMode::Auto`] 会从头下载，[`ResumeMode::Require`] 则返回错误。
+    pub resume: ResumeMode,
+    /// 最终文件已经存在时的处理方式，默认返回错误，可以选择跳过或覆盖
+    pub overwrite: OverwritePolicy,
+    /// 条件下载：文件已存在时以它的修改时间发送 `If-Modified-Since`，
+    /// 服务器返回 304 时跳过下载 (视为成功)，否则下载并覆盖。
+    /// 与 [`OverwritePolicy::Skip`] 同时使用时仍然直接跳过，不发送请求
+    pub if_newer: bool,
+    /// 条件下载：文件已存在时发送 `If-None-Match`，处理方式同 [`DownloadOptions::if_newer`]
+    pub if_none_match: Option<String>,
+    /// 下载完成后校验的文件摘要，例如 `"sha256:abcd...".parse()`
+    pub checksum: Option<Checksum>,
+    /// 附加到所有请求 (探测、数据块、文件名探测) 上的自定义请求头
+    pub headers: HeaderMap,
+    /// 认证信息 (Basic 或 Bearer)，以 `Authorization` 请求头附加到所有请求上，
+    /// 会覆盖 `headers` 中的 `Authorization`。凭据不会写入状态文件或日志。
+    pub auth: Option<Auth>,
+    /// 单个数据块的最大尝试次数 (包含第一次请求)，默认 3
+    pub chunk_max_attempts: u32,
+    /// 探测请求遇到网络错误或非 2xx 响应后的最大重试次数 (不含第一次请求)，默认 2
+    pub probe_retries: u32,
+    /// 探测请求第一次重试前的等待时间，之后每次翻倍，默认 1 秒
+    pub probe_retry_delay: Duration,
+    /// 探测请求重试等待时间的上限，默认 30 秒
+    pub probe_max_retry_delay: Duration,
+    /// 随机缩短探测请求的重试等待时间 (最多一半)，避免大量客户端同时重试，默认关闭
+    pub probe_retry_jitter: bool,
+    /// 遇到任何错误都重试，包括 403、404 等通常不会自行恢复的 HTTP 状态，默认只重试暂时性的错误
+    pub retry_all_errors: bool,
+    /// 最大下载速度 (字节/秒)，为 `None` 时不限速
+    pub max_speed: Option<u64>,
+    /// 允许下载的最大文件大小 (字节)，超过时返回错误，为 `None` 时不限制。
+    /// 大小已知时在开始下载前检查，大小未知时在写入的数据超过限制时中止
+    pub max_size: Option<u64>,
+    /// 进度回调，参数为 (已下载字节数, 总大小)。
+    /// 提供回调时不再在终端绘制进度条，便于嵌入 GUI 或服务端程序。
+    pub on_progress: Option<ProgressCallback>,
+    /// 由调用方创建的进度条，例如多个下载共用一个 `MultiProgress` 时各自的进度条。
+    /// 提供时总是使用它显示进度，不受 `show_progress` 影响，样式由调用方决定
+    pub progress_bar: Option<ProgressBar>,
+    /// 数据块状态回调，定期收到所有数据块 (等待中、下载中、已完成、失败) 的快照，
+    /// 便于排查卡住或反复失败的数据块。只在可续传的下载中调用
+    pub on_chunk_progress: Option<ChunkProgressCallback>,
+    /// 结构化事件回调 (探测结果、状态信息、警告、下载结果)。状态信息和警告同时通过 `log`
+    /// 以 `info`/`warn` 级别记录，库本身不会把它们打印到终端，需要显示时在回调中处理
+    pub on_event: Option<EventCallback>,
+    /// 是否在终端显示进度条，默认关闭。
+    /// 作为库使用时默认不会向终端输出任何内容，命令行工具会开启此选项。
+    pub show_progress: bool,
+    /// 取消令牌，在其他任务中调用 `cancel()` 即可中止正在进行的下载，
+    /// 此时返回的错误满足 [`DownloadError::is_cancelled`]
+    pub cancel: Option<CancellationToken>,
+    /// 暂停控制，在其他任务中调用 `pause()` / `resume()` 即可暂停和继续多线程或可续传的下载。
+    /// 暂停期间 [`DownloadOptions::timeout`] 仍然计时
+    pub pause: Option<PauseHandle>,
+    /// 多个下载共用的请求数上限，例如同时调用多次 [`download_with`] 时，把同一个
+    /// `ConnectionLimit::new(16)` 的克隆传给每个下载，所有下载合计最多 16 个请求同时进行。
+    /// 与 `concurrency` 同时生效，为 `None` 时不限制
+    pub connection_limit: Option<ConnectionLimit>,
+    /// 跳过开始下载前的磁盘剩余空间检查，默认进行检查
+    pub skip_space_check: bool,
+    /// 不校验各个数据块的 Content-Type 是否与探测时一致，默认进行校验。
+    /// 服务器对不同范围返回不同的 Content-Type 时可以开启
+    pub skip_content_check: bool,
+    /// 输出文件名模板，例如 `"{date}/{host}/{filename}"`。设置后输出路径总是视为目录，
+    /// 文件保存到该目录下按模板展开的位置，中间目录会自动创建。
+    /// 可用的占位符见 [`rdownloader_utils::expand_output_template`]，未知的占位符会返回错误。
+    pub output_template: Option<String>,
+    /// 存放 `.rdownload` 状态文件的目录，默认放在目标文件旁边；
+    /// 目标目录不可写时自动改用操作系统的缓存目录。状态文件在下载成功后删除
+    pub state_dir: Option<PathBuf>,
+    /// 下载期间存放 `.part` 文件 (未指定 `state_dir` 时还有状态文件) 的目录，例如本地的快速磁盘。
+    /// 完成后移动到最终路径，跨文件系统时自动改为复制后删除。默认放在目标文件旁边
+    pub temp_dir: Option<PathBuf>,
+    /// 不读写 `.rdownload` 状态文件，适用于只读目录或不希望留下任何附属文件的场合。
+    /// 本次运行内照常按数据块下载，但中断后无法续传
+    pub no_state: bool,
+    /// 单线程流式下载时按 `Content-Encoding` 自动解压 gzip、deflate 和 br 编码的数据，默认开启
+    pub decompress: bool,
+    /// 多线程下载时通过内存映射写入文件，无法映射时自动改用普通写入，默认关闭
+    pub mmap: bool,
+    /// 流式下载顺序写入文件时的写缓冲区大小 (字节)，默认 256KB，为 0 时不缓冲
+    pub write_buffer_size: usize,
+    /// 续传前重新校验已完成的数据块，只跳过与记录的哈希一致的数据块，默认关闭
+    pub verify_resume: bool,
+    /// 下载完成后把 `.gz`/`.tgz` 文件解压到去掉压缩扩展名的路径 (`.tgz` 解压为 `.tar`)，默认关闭。
+    /// 解压得到的文件同样遵守 `overwrite`，[`DownloadSummary::path`] 指向解压得到的文件
+    pub unpack: bool,
+    /// 解压成功后删除下载的压缩文件，默认保留
+    pub remove_compressed: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        // 默认值与下载执行层保持一致
+        let http = HttpOptions::default();
+        DownloadOptions {
+            client: None,
+            proxy: None,
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            read_timeout: http.read_timeout,
+            http_version: HttpVersion::Auto,
+            ip_version: IpVersion::Any,
+            local_address: None,
+            pool_max_idle_per_host: None,
+            pool_idle_timeout: Some(DEFAULT_POOL_IDLE_TIMEOUT),
+            tcp_keepalive: Some(DEFAULT_TCP_KEEPALIVE),
+            timeout: None,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            user_agent: Some(DEFAULT_USER_AGENT.to_string()),
+            cookies: Vec::new(),
+            cookie_file: None,
+            ca_cert: None,
+            insecure: false,
+            chunk_size: http.chunk_size,
+            max_chunk_size: http.max_chunk_size,
+            min_chunks: http.min_chunks,
+            concurrency: http.concurrency,
+            initial_concurrency: http.initial_concurrency,
+            mode: http.mode,
+            min_multipart_size: http.min_multipart_size,
+            mirrors: http.mirrors,
+            byte_range: http.byte_range,
+            resume: http.resume,
+            overwrite: http.overwrite,
+            if_newer: http.if_newer,
+            if_none_match: http.if_none_match,
+            checksum: http.checksum,
+            headers: http.headers,
+            auth: None,
+            chunk_max_attempts: http.chunk_max_attempts,
+            probe_retries: http.probe_retries,
+            probe_retry_delay: http.probe_retry_delay,
+            probe_max_retry_delay: http.probe_max_retry_delay,
+            probe_retry_jitter: http.probe_retry_jitter,
+            retry_all_errors: http.retry_all_errors,
+            max_speed: http.max_speed,
+            max_size: http.max_size,
+            on_progress: http.on_progress,
+            progress_bar: http.progress_bar,
+            on_chunk_progress: http.on_chunk_progress,
+            on_event: http.on_event,
+            show_progress: false,
+            cancel: http.cancel,
+            pause: http.pause,
+            connection_limit: http.connection_limit,
+            skip_space_check: http.skip_space_check,
+            skip_content_check: http.skip_content_check,
+            output_template: None,
+            state_dir: http.state_dir,
+            temp_dir: http.temp_dir,
+            no_state: http.no_state,
+            decompress: http.decompress,
+            mmap: http.mmap,
+            write_buffer_size: http.write_buffer_size,
+            verify_resume: http.verify_resume,
+            unpack: http.unpack,
+            remove_compressed: http.remove_compressed,
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// 返回调用方提供的客户端，或根据客户端配置字段构建一个新的客户端。
+    /// `--cookie` 给出的 cookie 作用于 `url` 所在的主机
+    fn build_client(&self, url: &str) -> Result<Client, DownloadError> {
+        if let Some(client) = &self.client {
+            // reqwest::Client 内部是引用计数的，克隆代价很低，且共享同一个连接池
+            return Ok(client.clone());
+        }
+        let mut builder = Client::builder();
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy).map_err(DownloadError::Client)?);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.read_timeout(timeout);
+        }
+        builder = builder
+            .pool_max_idle_per_host(self.pool_max_idle_per_host.unwrap_or(self.concurrency))
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .tcp_keepalive(self.tcp_keepalive);
+        builder = match self.http_version {
+            HttpVersion::Auto => builder,
+            HttpVersion::Http1 => builder.http1_only(),
+            HttpVersion::Http2 => builder.http2_prior_knowledge(),
+        };
+        // 连接器只会连接与本地地址协议版本相同的服务器地址，
+        // 因此限制协议版本时绑定到该版本的未指定地址即可
+        builder = builder.local_address(match (self.ip_version, self.local_address) {
+            (IpVersion::V4, Some(addr)) if !addr.is_ipv4() => {
+                return Err(DownloadError::LocalAddress(addr))
+            }
+            (IpVersion::V6, Some(addr)) if !addr.is_ipv6() => {
+                return Err(DownloadError::LocalAddress(addr))
+            }
+            (_, Some(addr)) => Some(addr),
+            (IpVersion::Any, None) => None,
+            (IpVersion::V4, None) => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+            (IpVersion::V6, None) => Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+        });
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        builder = builder.cookie_provider(Arc::new(self.cookie_jar(url)?));
+        if let Some(path) = &self.ca_cert {
+            let certificate_error = |e: &dyn fmt::Display| {
+                DownloadError::Certificate(format!("{}: {}", path.display(), e))
+            };
+            let pem = std::fs::read(path).map_err(|e| certificate_error(&e))?;
+            let certificates =
+                reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| certificate_error(&e))?;
+            if certificates.is_empty() {
+                return Err(certificate_error(&"no certificates found"));
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if self.insecure {
+            let message = "已禁用 TLS 证书校验，连接可能被中间人窃听或篡改";
+            log::warn!("{}", message);
+            if let Some(on_event) = &self.on_event {
+                on_event.emit(&DownloadEvent::Warning(message.to_string()));
+            }
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        builder = builder.redirect(match self.max_redirects {
+            0 => Policy::none(),
+            n => Policy::limited(n),
+        });
+        builder.build().map_err(DownloadError::Client)
+    }
+
+    /// 由 `cookies` 和 `cookie_file` 组成的 cookie jar，reqwest 按域名和路径决定每个请求带哪些 cookie
+    fn cookie_jar(&self, url: &str) -> Result<Jar, DownloadError> {
+        let jar = Jar::default();
+        // 地址无效时没有可用的主机，留给之后的请求报告错误
+        if let Ok(url) = Url::parse(url) {
+            for cookie in &self.cookies {
+                jar.add_cookie_str(cookie, &url);
+            }
+        }
+        if let Some(path) = &self.cookie_file {
+            let contents = std::fs::read_to_string(path)
+                .map_err(|e| DownloadError::Cookies(format!("{}: {}", path.display(), e)))?;
+            let cookies = parse_cookie_file(&contents)
+                .map_err(|e| DownloadError::Cookies(format!("{}: {}", path.display(), e)))?;
+            let now = SystemTime::now();
+            for cookie in cookies.iter().filter(|cookie| !cookie.is_expired(now)) {
+                if let Ok(url) = Url::parse(&cookie.url()) {
+                    jar.add_cookie_str(&cookie.set_cookie(), &url);
+                }
+            }
+        }
+        Ok(jar)
+    }
+
+    /// 所有请求共用的请求头：自定义请求头加上认证信息
+    fn request_headers(&self) -> Result<HeaderMap, DownloadError> {
+        let mut headers = self.headers.clone();
+        if let Some(auth) = &self.auth {
+            headers.insert(
+                AUTHORIZATION,
+                auth.header_value().map_err(DownloadError::Auth)?,
+            );
+        }
+        Ok(headers)
+    }
+
+    fn http_options(&self, headers: HeaderMap) -> HttpOptions {
+        HttpOptions {
+            chunk_size: self.chunk_size,
+            max_chunk_size: self.max_chunk_size,
+            min_chunks: self.min_chunks,
+            concurrency: self.concurrency,
+            initial_concurrency: self.initial_concurrency,
+            mode: self.mode,
+            min_multipart_size: self.min_multipart_size,
+            mirrors: self.mirrors.clone(),
+            byte_range: self.byte_range.clone(),
+            resume: self.resume,
+            overwrite: self.overwrite,
+            if_newer: self.if_newer,
+            if_none_match: self.if_none_match.clone(),
+            checksum: self.checksum.clone(),
+            headers,
+            chunk_max_attempts: self.chunk_max_attempts,
+            probe_retries: self.probe_retries,
+            probe_retry_delay: self.probe_retry_delay,
+            probe_max_retry_delay: self.probe_max_retry_delay,
+            probe_retry_jitter: self.probe_retry_jitter,
+            retry_all_errors: self.retry_all_errors,
+            max_speed: self.max_speed,
+            max_size: self.max_size,
+            on_progress: self.on_progress.clone(),
+            progress_bar: self.progress_bar.clone(),
+            on_chunk_progress: self.on_chunk_progress.clone(),
+            on_event: self.on_event.clone(),
+            state_dir: self.state_dir.clone(),
+            temp_dir: self.temp_dir.clone(),
+            no_state: self.no_state,
+            decompress: self.decompress,
+            mmap: self.mmap,
+            write_buffer_size: self.write_buffer_size,
+            verify_resume: self.verify_resume,
+            unpack: self.unpack,
+            remove_compressed: self.remove_compressed,
+            quiet: !self.show_progress,
+            cancel: self.cancel.clone(),
+            pause: self.pause.clone(),
+            connection_limit: self.connection_limit.clone(),
+            skip_space_check: self.skip_space_check,
+            skip_content_check: self.skip_content_check,
+            read_timeout: self.read_timeout,
+            connect_timeout: self.connect_timeout,
+            insecure: self.insecure,
+            ..HttpOptions::default()
+        }
+    }
+}
+
+/// 正在计时的总超时，见 [`DownloadOptions::timeout`]
+struct Deadline {
+    timeout: Duration,
+    timer: tokio::task::JoinHandle<()>,
+}
+
+impl DownloadOptions {
+    /// 总超时通过取消令牌实现，这样超时后同样会保存进度，可以在之后续传
+    fn start_deadline(&self, http_options: &mut HttpOptions) -> Option<Deadline> {
+        let timeout = self.timeout?;
+        let token = match &self.cancel {
+            // 子令牌会随调用方的令牌一起被取消，但超时只取消子令牌
+            Some(parent) => parent.child_token(),
+            None => CancellationToken::new(),
+        };
+        http_options.cancel = Some(token.clone());
+        let timer = tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            token.cancel();
+        });
+        Some(Deadline { timeout, timer })
+    }
+
+    /// 停止计时，并把因超时触发的取消转换为 [`DownloadError::TimedOut`]
+    fn finish_deadline<T>(
+        &self,
+        deadline: Option<Deadline>,
+        result: Result<T, DispatchError>,
+    ) -> Result<T, DownloadError> {
+        if let Some(Deadline { timeout, timer }) = deadline {
+            // 调用方自己取消的情况仍按取消处理
+            let timed_out =
+                timer.is_finished() && !self.cancel.as_ref().is_some_and(|t| t.is_cancelled());
+            timer.abort();
+            if timed_out && result.as_ref().is_err_and(|e| e.is_cancelled()) {
+                return Err(DownloadError::TimedOut(timeout));
+            }
+        }
+        Ok(result?)
+    }
+}
+
 /// rDownloader 的高级公共 API。
 ///
 /// 封装了所有内部逻辑，提供一个简单的函数来启动下载。
@@ -29,21 +604,211 @@ impl From<Box<dyn std::error::Error>> for DownloadError {
 /// # 参数
 /// * `url`: 要下载的文件的 URL。
 /// * `output`: 一个可选的输出路径。可以是目录，也可以是完整的文件路径。
-///           如果为 `None`，则下载到当前工作目录。
-pub async fn download(url: &str, output: Option<String>) -> Result<(), DownloadError> {
-    let client = Client::new();
+///   如果为 `None`，则下载到当前工作目录。
+///
+/// 成功时返回 [`DownloadSummary`]，包含保存路径、文件大小、本次下载的字节数和用时等信息。
+/// `output` 为目录或 `None` 时文件名由服务器响应或 URL 决定，实际的保存路径见
+/// [`DownloadSummary::path`]。
+pub async fn download(url: &str, output: Option<String>) -> Result<DownloadSummary, DownloadError> {
+    download_with(url, output, &DownloadOptions::default()).await
+}
+
+/// 与 [`download`] 相同，但允许通过 [`DownloadOptions`] 调整下载行为。
+///
+/// # 示例
+/// ```no_run
+/// # async fn run() -> Result<(), rdownloader::DownloadError> {
+/// use rdownloader::{download_with, DownloadOptions};
+///
+/// let options = DownloadOptions {
+///     chunk_size: 16 * 1024 * 1024,
+///     concurrency: 4,
+///     ..Default::default()
+/// };
+/// download_with("https://example.com/file.iso", Some("downloads/".into()), &options).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn download_with(
+    url: &str,
+    output: Option<String>,
+    options: &DownloadOptions,
+) -> Result<DownloadSummary, DownloadError> {
+    let client = options.build_client(url)?;
 
     // 将 Option<String> 转换为 Option<PathBuf>
     let output_path_buf = output.map(PathBuf::from);
 
+    let headers = options.request_headers()?;
+    let mut http_options = options.http_options(headers.clone());
+
+    // 输出为目录时需要从服务器推断文件名：先探测一次，文件名和下载所需的信息
+    // (大小、ETag 等) 都来自同一个响应，调度器不会再重复探测
+    let template = options.output_template.as_deref();
+    if let Some(template) = template {
+        // 在发出任何请求之前报告模板错误
+        validate_output_template(template).map_err(|e| DownloadError::Path(e.into()))?;
+    }
+    let probe = if template.is_some() || output_needs_filename(output_path_buf.as_deref()) {
+        match probe_url(&client, url, &http_options).await {
+            Ok(probe) => Some(probe),
+            // 主地址探测失败时，交给调度器依次尝试镜像，文件名则回退到 HEAD 请求和 URL 路径
+            Err(e) if !e.is_cancelled() && !http_options.mirrors.is_empty() => None,
+            Err(e) => return Err(e.into()),
+        }
+    } else {
+        None
+    };
+    let probed_filename = probe.as_ref().and_then(|probe| probe.filename.clone());
+
     // 解析最终的保存路径
-    let final_path = resolve_final_path(&client, url, output_path_buf).await?;
+    let final_path = resolve_final_path(
+        &client,
+        url,
+        output_path_buf,
+        &headers,
+        probed_filename,
+        template,
+    )
+    .await?;
 
     log::info!("准备下载: {}", url);
     log::info!("保存路径: {}", final_path.display());
 
+    let deadline = options.start_deadline(&mut http_options);
+
     // 调用调度器执行下载
-    dispatch(&client, url, &final_path).await?;
+    let result = dispatch_probed(&client, url, &final_path, probe, &http_options).await;
+    options.finish_deadline(deadline, result)
+}
+
+/// 只探测不下载：返回文件大小、ETag、Content-Type、Range 支持和最终地址等信息。
+///
+/// 与 [`download_with`] 使用相同的请求头、认证、代理和重试设置，但不解析保存路径，也不会创建任何文件。
+/// 多个文件的结果可以用 [`ProbeTotal`] 汇总，在开始下载前得到总大小。
+///
+/// # 示例
+/// ```no_run
+/// # async fn run() -> Result<(), rdownloader::DownloadError> {
+/// use rdownloader::{probe, DownloadOptions, ProbeTotal};
+///
+/// let options = DownloadOptions::default();
+/// let mut probes = Vec::new();
+/// for url in ["https://example.com/a.iso", "https://example.com/b.iso"] {
+///     probes.push(probe(url, &options).await?);
+/// }
+/// let total = ProbeTotal::of(&probes);
+/// println!("共 {} 个文件，{:?} 字节", total.files, total.size());
+/// # Ok(())
+/// # }
+/// ```
+pub async fn probe(url: &str, options: &DownloadOptions) -> Result<Probe, DownloadError> {
+    let client = options.build_client(url)?;
+    let http_options = options.http_options(options.request_headers()?);
+    Ok(probe_url(&client, url, &http_options).await?)
+}
+
+/// 一组探测结果的合计，例如批量下载开始前显示的总大小
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProbeTotal {
+    /// 文件数
+    pub files: usize,
+    /// 大小已知的文件的总大小 (字节)
+    pub known_size: u64,
+    /// 大小未知的文件数
+    pub unknown: usize,
+}
+
+impl ProbeTotal {
+    pub fn of<'a>(probes: impl IntoIterator<Item = &'a Probe>) -> Self {
+        let mut total = ProbeTotal::default();
+        for probe in probes {
+            total.add(probe);
+        }
+        total
+    }
+
+    pub fn add(&mut self, probe: &Probe) {
+        self.files += 1;
+        match probe.size {
+            Some(size) => self.known_size += size,
+            None => self.unknown += 1,
+        }
+    }
+
+    /// 所有文件的总大小；有文件大小未知时为 `None`
+    pub fn size(&self) -> Option<u64> {
+        (self.unknown == 0).then_some(self.known_size)
+    }
+}
+
+/// [`plan`] 的结果：按当前选项下载时文件会保存到哪里、以什么方式下载
+#[derive(Debug, Clone)]
+pub struct DownloadPlan {
+    /// 最终的保存路径
+    pub path: PathBuf,
+    /// 探测得到的大小、ETag、Content-Type 等信息
+    pub probe: Probe,
+    /// 将会使用的下载方式
+    pub mode: TransferMode,
+}
+
+/// 只探测不下载：返回 [`download_with`] 以相同参数下载时的保存路径和下载方式。
+///
+/// 不会创建任何文件、目录或状态文件，适合在脚本中预先检查 URL 和输出路径。
+pub async fn plan(
+    url: &str,
+    output: Option<String>,
+    options: &DownloadOptions,
+) -> Result<DownloadPlan, DownloadError> {
+    let client = options.build_client(url)?;
+    let headers = options.request_headers()?;
+    let http_options = options.http_options(headers.clone());
+
+    let template = options.output_template.as_deref();
+    if let Some(template) = template {
+        validate_output_template(template).map_err(|e| DownloadError::Path(e.into()))?;
+    }
+    let probe = probe_url(&client, url, &http_options).await?;
+    let path = plan_final_path(
+        &client,
+        url,
+        output.map(PathBuf::from),
+        &headers,
+        probe.filename.clone(),
+        template,
+    )
+    .await?;
+    let mode = probe.transfer_mode(&http_options);
+    Ok(DownloadPlan { path, probe, mode })
+}
+
+/// 将下载内容写入任意 [`Write`]，例如标准输出或内存缓冲区，不会在磁盘上创建任何文件。
+///
+/// 这种模式按数据到达的顺序流式写出，不支持多线程、断点续传和校验和；
+/// 其余选项 (请求头、限速、进度、超时、取消等) 照常生效。
+///
+/// # 示例
+/// ```no_run
+/// # async fn run() -> Result<(), rdownloader::DownloadError> {
+/// use rdownloader::{download_to_writer, DownloadOptions};
+///
+/// let mut buffer = Vec::new();
+/// download_to_writer("https://example.com/data.json", &mut buffer, &DownloadOptions::default())
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn download_to_writer<W: Write + ?Sized>(
+    url: &str,
+    writer: &mut W,
+    options: &DownloadOptions,
+) -> Result<(), DownloadError> {
+    let client = options.build_client(url)?;
+    log::info!("准备下载: {} (写入数据流)", url);
 
-    Ok(())
+    let mut http_options = options.http_options(options.request_headers()?);
+    let deadline = options.start_deadline(&mut http_options);
+    let result = dispatch_to_writer(&client, url, writer, &http_options).await;
+    options.finish_deadline(deadline, result)
 }
@@ -0,0 +1,825 @@
+use rdownloader::{
+    download_to_writer, download_with, plan, probe, Auth, CancellationToken, DownloadError,
+    DownloadEvent, DownloadOptions, EventCallback, HttpVersion, IpVersion, ProbeTotal,
+    TransferMode, DEFAULT_USER_AGENT,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn requests_go_through_configured_proxy() {
+    // 模拟服务器充当 HTTP 代理：目标主机并不存在，只有经过代理的请求才能成功
+    let proxy = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"via proxy".to_vec()))
+        .mount(&proxy)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("file.txt");
+    let options = DownloadOptions {
+        proxy: Some(proxy.uri()),
+        ..Default::default()
+    };
+
+    download_with(
+        "http://rdownloader.invalid/file.txt",
+        Some(output.to_string_lossy().into_owned()),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&output).unwrap(), b"via proxy");
+}
+
+#[tokio::test]
+async fn invalid_proxy_is_reported() {
+    let options = DownloadOptions {
+        proxy: Some("not a proxy url".into()),
+        ..Default::default()
+    };
+    let err = download_with("http://rdownloader.invalid/file.txt", None, &options)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, DownloadError::Client(_)));
+}
+
+#[tokio::test]
+async fn cancelled_download_is_reported() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"some data".to_vec()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("file.txt");
+    let cancel = CancellationToken::new();
+    cancel.cancel();
+    let options = DownloadOptions {
+        cancel: Some(cancel),
+        ..Default::default()
+    };
+
+    let err = download_with(
+        &format!("{}/file.txt", server.uri()),
+        Some(output.to_string_lossy().into_owned()),
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(err.is_cancelled());
+    assert!(!output.exists());
+}
+
+#[tokio::test]
+async fn overall_timeout_is_reported() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_bytes(b"slow".to_vec())
+                .set_delay(Duration::from_secs(30)),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("file.txt");
+    let options = DownloadOptions {
+        timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+
+    let err = download_with(
+        &format!("{}/file.txt", server.uri()),
+        Some(output.to_string_lossy().into_owned()),
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, DownloadError::TimedOut(_)));
+}
+
+#[tokio::test]
+async fn download_to_writer_fills_buffer() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"streamed".to_vec()))
+        .mount(&server)
+        .await;
+
+    let mut buffer = Vec::new();
+    download_to_writer(
+        &format!("{}/file.txt", server.uri()),
+        &mut buffer,
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(buffer, b"streamed");
+}
+
+#[tokio::test]
+async fn basic_auth_is_sent_on_every_request() {
+    let server = MockServer::start().await;
+    // 只有带正确认证信息的请求才能成功，其余请求都会得到 404
+    Mock::given(header("Authorization", "Basic dXNlcjpwYXNz"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Disposition", "attachment; filename=\"secret.txt\"")
+                .set_body_bytes(b"top secret".to_vec()),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = DownloadOptions {
+        auth: Some(Auth::basic("user:pass")),
+        ..Default::default()
+    };
+
+    // 输出为目录，因此文件名探测请求也必须带上认证信息
+    download_with(
+        &format!("{}/download", server.uri()),
+        Some(format!("{}/", dir.path().display())),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        std::fs::read(dir.path().join("secret.txt")).unwrap(),
+        b"top secret"
+    );
+}
+
+/// 只对带有 `user_agent` 的请求返回文件，以目录作为输出，让文件名探测请求也经过检查
+async fn download_with_user_agent(user_agent: &str, options: &DownloadOptions) {
+    let server = MockServer::start().await;
+    Mock::given(header("User-Agent", user_agent))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Disposition", "attachment; filename=\"ua.txt\"")
+                .set_body_bytes(b"hello".to_vec()),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    download_with(
+        &format!("{}/download", server.uri()),
+        Some(format!("{}/", dir.path().display())),
+        options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(std::fs::read(dir.path().join("ua.txt")).unwrap(), b"hello");
+}
+
+#[tokio::test]
+async fn default_user_agent_is_sent() {
+    assert!(DEFAULT_USER_AGENT.starts_with("rdownloader/"));
+    download_with_user_agent(DEFAULT_USER_AGENT, &DownloadOptions::default()).await;
+}
+
+#[tokio::test]
+async fn custom_user_agent_is_sent() {
+    let options = DownloadOptions {
+        user_agent: Some("my-agent/1.0".into()),
+        ..Default::default()
+    };
+    download_with_user_agent("my-agent/1.0", &options).await;
+}
+
+/// 只对带有 `cookie` 的请求返回文件，输出为目录，因此文件名探测请求也必须带上 cookie
+async fn download_with_cookie(cookie: &str, options: &DownloadOptions) {
+    let server = MockServer::start().await;
+    Mock::given(header("Cookie", cookie))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Disposition", "attachment; filename=\"member.txt\"")
+                .set_body_bytes(b"members only".to_vec()),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    download_with(
+        &format!("{}/download", server.uri()),
+        Some(format!("{}/", dir.path().display())),
+        options,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        std::fs::read(dir.path().join("member.txt")).unwrap(),
+        b"members only"
+    );
+}
+
+#[tokio::test]
+async fn cookies_are_sent_on_every_request() {
+    let options = DownloadOptions {
+        cookies: vec!["session=abc".into()],
+        ..Default::default()
+    };
+    download_with_cookie("session=abc", &options).await;
+}
+
+#[tokio::test]
+async fn cookie_file_is_loaded_and_expired_cookies_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let jar = dir.path().join("cookies.txt");
+    std::fs::write(
+        &jar,
+        "# Netscape HTTP Cookie File\n\
+         127.0.0.1\tFALSE\t/\tFALSE\t0\tsession\tfrom-file\n\
+         127.0.0.1\tFALSE\t/\tFALSE\t1\told\texpired\n",
+    )
+    .unwrap();
+    let options = DownloadOptions {
+        cookie_file: Some(jar),
+        ..Default::default()
+    };
+    download_with_cookie("session=from-file", &options).await;
+}
+
+#[tokio::test]
+async fn unreadable_cookie_file_is_reported() {
+    let options = DownloadOptions {
+        cookie_file: Some("/nonexistent/cookies.txt".into()),
+        ..Default::default()
+    };
+    let err = download_with("http://127.0.0.1:1/file", None, &options)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), "cookies");
+}
+
+#[tokio::test]
+async fn missing_ca_certificate_is_reported() {
+    let options = DownloadOptions {
+        ca_cert: Some("/nonexistent/ca.pem".into()),
+        ..Default::default()
+    };
+    let err = download_with("http://127.0.0.1:1/file", None, &options)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), "certificate");
+}
+
+#[tokio::test]
+async fn file_without_certificates_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let pem = dir.path().join("ca.pem");
+    std::fs::write(&pem, "not a certificate\n").unwrap();
+    let options = DownloadOptions {
+        ca_cert: Some(pem),
+        ..Default::default()
+    };
+    let err = download_with("http://127.0.0.1:1/file", None, &options)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), "certificate");
+}
+
+#[tokio::test]
+async fn ip_version_and_local_address_limit_connections() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"hello".to_vec()))
+        .mount(&server)
+        .await;
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("{}/hello.txt", server.uri());
+    let download = |options: DownloadOptions, name: &str| {
+        let output = dir.path().join(name).to_string_lossy().into_owned();
+        let url = url.clone();
+        async move { download_with(&url, Some(output), &options).await }
+    };
+
+    // 模拟服务器只监听 IPv4 的回环地址
+    let ipv4 = DownloadOptions {
+        ip_version: IpVersion::V4,
+        local_address: Some("127.0.0.1".parse().unwrap()),
+        ..Default::default()
+    };
+    download(ipv4, "v4.txt").await.unwrap();
+    assert_eq!(std::fs::read(dir.path().join("v4.txt")).unwrap(), b"hello");
+
+    let ipv6 = DownloadOptions {
+        ip_version: IpVersion::V6,
+        probe_retries: 0,
+        ..Default::default()
+    };
+    let err = download(ipv6, "v6.txt").await.unwrap_err();
+    assert_eq!(err.kind(), "network");
+
+    let mismatched = DownloadOptions {
+        ip_version: IpVersion::V6,
+        local_address: Some("127.0.0.1".parse().unwrap()),
+        ..Default::default()
+    };
+    let err = download(mismatched, "mismatched.txt").await.unwrap_err();
+    assert_eq!(err.kind(), "local_address");
+}
+
+#[tokio::test]
+async fn insecure_mode_is_reported_as_warning() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"plain".to_vec()))
+        .mount(&server)
+        .await;
+
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let recorded = warnings.clone();
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("plain.txt");
+    let options = DownloadOptions {
+        insecure: true,
+        on_event: Some(EventCallback::new(move |event| {
+            if let DownloadEvent::Warning(message) = event {
+                recorded.lock().unwrap().push(message.clone());
+            }
+        })),
+        ..Default::default()
+    };
+    download_with(
+        &format!("{}/plain", server.uri()),
+        Some(output.display().to_string()),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(&output).unwrap(), b"plain");
+    assert!(warnings
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|message| message.contains("TLS")));
+}
+
+#[tokio::test]
+async fn summary_reports_resolved_path_for_directory_output() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Disposition", "attachment; filename=\"report.csv\"")
+                .set_body_bytes(b"a,b\n".to_vec()),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let summary = download_with(
+        &format!("{}/export?id=7", server.uri()),
+        Some(format!("{}/", dir.path().display())),
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(summary.path, dir.path().join("report.csv"));
+    assert_eq!(std::fs::read(&summary.path).unwrap(), b"a,b\n");
+}
+
+#[tokio::test]
+async fn file_in_place_of_output_directory_is_reported() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"data".to_vec()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let blocker = dir.path().join("downloads");
+    std::fs::write(&blocker, b"not a directory").unwrap();
+    let url = format!("{}/file.bin", server.uri());
+
+    for output in [
+        format!("{}/", blocker.display()),
+        format!("{}/nested/file.bin", blocker.display()),
+    ] {
+        let err = download_with(&url, Some(output.clone()), &DownloadOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), "path", "{}", output);
+        assert!(
+            err.to_string().contains(&blocker.display().to_string()),
+            "{}",
+            err
+        );
+        let err = plan(&url, Some(output.clone()), &DownloadOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), "path", "{}", output);
+    }
+    assert_eq!(std::fs::read(&blocker).unwrap(), b"not a directory");
+}
+
+#[tokio::test]
+async fn http2_prior_knowledge_sends_connection_preface() {
+    use tokio::io::AsyncReadExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut preface = [0u8; 24];
+        socket.read_exact(&mut preface).await.unwrap();
+        preface
+    });
+
+    let options = DownloadOptions {
+        http_version: HttpVersion::Http2,
+        probe_retries: 0,
+        ..Default::default()
+    };
+    // 服务器读完连接前言就断开，下载本身会失败
+    let dir = tempfile::tempdir().unwrap();
+    let _ = download_with(
+        &format!("http://{}/file.bin", addr),
+        Some(dir.path().join("file.bin").display().to_string()),
+        &options,
+    )
+    .await;
+    assert_eq!(&server.await.unwrap(), b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+}
+
+#[tokio::test]
+async fn multipart_download_works_over_http2_and_http1_only() {
+    let body: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    let server = MockServer::start().await;
+    let len = body.len();
+    Mock::given(method("GET"))
+        .respond_with(move |request: &wiremock::Request| {
+            let range = request
+                .headers
+                .get("Range")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("bytes="))
+                .and_then(|v| v.split_once('-'))
+                .map(|(start, end)| {
+                    let start: usize = start.parse().unwrap();
+                    let end = end.parse::<usize>().map_or(len - 1, |end| end.min(len - 1));
+                    (start, end)
+                });
+            match range {
+                Some((start, end)) => ResponseTemplate::new(206)
+                    .insert_header(
+                        "Content-Range",
+                        format!("bytes {}-{}/{}", start, end, len).as_str(),
+                    )
+                    .set_body_bytes(body[start..=end].to_vec()),
+                None => ResponseTemplate::new(200).set_body_bytes(body.clone()),
+            }
+        })
+        .mount(&server)
+        .await;
+
+    for version in [HttpVersion::Http2, HttpVersion::Http1] {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("data.bin");
+        let options = DownloadOptions {
+            http_version: version,
+            ..Default::default()
+        };
+        let summary = download_with(
+            &format!("{}/data.bin", server.uri()),
+            Some(output.display().to_string()),
+            &options,
+        )
+        .await
+        .unwrap();
+        assert!(summary.multipart, "{:?}", version);
+        assert_eq!(std::fs::read(&output).unwrap().len(), len, "{:?}", version);
+    }
+}
+
+#[tokio::test]
+async fn bearer_token_is_sent() {
+    let server = MockServer::start().await;
+    Mock::given(header("Authorization", "Bearer abc123"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"ok".to_vec()))
+        .mount(&server)
+        .await;
+
+    let mut buffer = Vec::new();
+    let options = DownloadOptions {
+        auth: Some(Auth::Bearer("abc123".into())),
+        ..Default::default()
+    };
+    download_to_writer(&format!("{}/file", server.uri()), &mut buffer, &options)
+        .await
+        .unwrap();
+
+    assert_eq!(buffer, b"ok");
+}
+
+#[tokio::test]
+async fn redirects_can_be_disabled() {
+    let server = MockServer::start().await;
+    Mock::given(path("/old"))
+        .respond_with(
+            ResponseTemplate::new(301)
+                .insert_header("Location", format!("{}/new", server.uri()).as_str()),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(path("/new"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"moved".to_vec()))
+        .expect(0)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = DownloadOptions {
+        max_redirects: 0,
+        ..Default::default()
+    };
+    let result = download_with(
+        &format!("{}/old", server.uri()),
+        Some(dir.path().join("file.txt").display().to_string()),
+        &options,
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert!(!dir.path().join("file.txt").exists());
+}
+
+#[tokio::test]
+async fn filename_falls_back_to_get_when_head_is_rejected() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(405))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Disposition", "attachment; filename=\"report.pdf\"")
+                .set_body_bytes(b"pdf data".to_vec()),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    download_with(
+        &format!("{}/download?id=42", server.uri()),
+        Some(format!("{}/", dir.path().display())),
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        std::fs::read(dir.path().join("report.pdf")).unwrap(),
+        b"pdf data"
+    );
+}
+
+#[tokio::test]
+async fn get_disposition_is_used_when_head_lacks_it() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Disposition", "attachment; filename=\"data.csv\"")
+                .set_body_bytes(b"a,b".to_vec()),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    download_with(
+        &format!("{}/export", server.uri()),
+        Some(format!("{}/", dir.path().display())),
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(dir.path().join("data.csv")).unwrap(), b"a,b");
+}
+
+#[tokio::test]
+async fn filename_and_size_come_from_a_single_probe() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Disposition", "attachment; filename=\"notes.txt\"")
+                .set_body_bytes(b"hello".to_vec()),
+        )
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    download_with(
+        &format!("{}/get?id=1", server.uri()),
+        Some(format!("{}/", dir.path().display())),
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        std::fs::read(dir.path().join("notes.txt")).unwrap(),
+        b"hello"
+    );
+    // 一次探测 + 一次单线程下载，没有单独的文件名请求
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn head_is_used_when_probe_lacks_disposition() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Disposition", "attachment; filename=\"head.bin\""),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"body".to_vec()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    download_with(
+        &format!("{}/get", server.uri()),
+        Some(format!("{}/", dir.path().display())),
+        &DownloadOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(std::fs::read(dir.path().join("head.bin")).unwrap(), b"body");
+}
+
+#[tokio::test]
+async fn output_template_creates_intermediate_directories() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_bytes(b"archive".to_vec()))
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = DownloadOptions {
+        output_template: Some("{host}/{ext}/{filename}".into()),
+        ..Default::default()
+    };
+    download_with(
+        &format!("{}/pkg/data.tar", server.uri()),
+        Some(dir.path().display().to_string()),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    let saved = dir.path().join("127.0.0.1").join("tar").join("data.tar");
+    assert_eq!(std::fs::read(saved).unwrap(), b"archive");
+}
+
+#[tokio::test]
+async fn unknown_template_placeholder_is_rejected_before_requests() {
+    let server = MockServer::start().await;
+    let dir = tempfile::tempdir().unwrap();
+    let options = DownloadOptions {
+        output_template: Some("{year}/{filename}".into()),
+        ..Default::default()
+    };
+
+    let err = download_with(
+        &format!("{}/file.txt", server.uri()),
+        Some(dir.path().display().to_string()),
+        &options,
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(err, DownloadError::Path(_)));
+    assert!(server.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn plan_reports_path_and_mode_without_writing() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("Range", "bytes=0-1"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 0-1/4194304")
+                .insert_header("ETag", "\"v1\"")
+                .insert_header("Content-Type", "application/x-tar")
+                .insert_header("Content-Disposition", "attachment; filename=\"data.tar\"")
+                .set_body_bytes(b"ab".to_vec()),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let options = DownloadOptions {
+        output_template: Some("{host}/{filename}".into()),
+        ..Default::default()
+    };
+    let plan = plan(
+        &format!("{}/download?id=1", server.uri()),
+        Some(dir.path().display().to_string()),
+        &options,
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(plan.path, dir.path().join("127.0.0.1").join("data.tar"));
+    assert_eq!(plan.mode, TransferMode::Multipart);
+    assert_eq!(plan.probe.size, Some(4194304));
+    assert_eq!(plan.probe.etag.as_deref(), Some("\"v1\""));
+    assert_eq!(
+        plan.probe.content_type.as_deref(),
+        Some("application/x-tar")
+    );
+    // 模板中的目录不会被创建
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[tokio::test]
+async fn probes_are_totalled_before_downloading() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/a.bin"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 0-1/1000")
+                .insert_header("ETag", "\"a\"")
+                .set_body_bytes(b"ab".to_vec()),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/b.bin"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 0-1/24")
+                .set_body_bytes(b"ab".to_vec()),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/live"))
+        .respond_with(
+            ResponseTemplate::new(206)
+                .insert_header("Content-Range", "bytes 0-1/*")
+                .set_body_bytes(b"ab".to_vec()),
+        )
+        .mount(&server)
+        .await;
+    let options = DownloadOptions::default();
+
+    let a = probe(&format!("{}/a.bin", server.uri()), &options)
+        .await
+        .unwrap();
+    assert_eq!(a.size, Some(1000));
+    assert!(a.supports_range);
+    assert_eq!(a.etag.as_deref(), Some("\"a\""));
+    assert_eq!(a.resolved_url, format!("{}/a.bin", server.uri()));
+    let b = probe(&format!("{}/b.bin", server.uri()), &options)
+        .await
+        .unwrap();
+
+    let total = ProbeTotal::of([&a, &b]);
+    assert_eq!(total.files, 2);
+    assert_eq!(total.size(), Some(1024));
+
+    // 有文件大小未知时只能给出已知部分的合计
+    let live = probe(&format!("{}/live", server.uri()), &options)
+        .await
+        .unwrap();
+    let total = ProbeTotal::of([&a, &b, &live]);
+    assert_eq!((total.known_size, total.unknown), (1024, 1));
+    assert_eq!(total.size(), None);
+}